@@ -24,6 +24,8 @@ pub struct AvcParams {
     pub picture_width: usize,
     pub picture_height: usize,
     pub rate_control: RateControl,
+    pub profile: Profile,
+    pub level: Level,
     pub preset: Preset,
     pub tune: Option<Tune>,
     pub gop_size: Option<usize>,
@@ -35,6 +37,41 @@ pub enum RateControl {
     ConstantQuality { crf: usize },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Baseline,
+    Main,
+    High,
+}
+
+/// H.264 level, as ten times the level number (eg. 41 for level 4.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Level(pub u8);
+
+impl Level {
+    /// Picks the lowest level which can carry a stream at this resolution
+    /// and frame rate, going by the max macroblock processing rate
+    pub fn for_picture(width: usize, height: usize, frame_rate: usize) -> Level {
+        let macroblocks = ((width + 15) / 16) * ((height + 15) / 16);
+        let macroblocks_per_sec = macroblocks * frame_rate;
+
+        let levels = [
+            (Level(30), 40_500),
+            (Level(31), 108_000),
+            (Level(32), 216_000),
+            (Level(41), 245_760),
+            (Level(42), 522_240),
+            (Level(50), 589_824),
+            (Level(51), 983_040),
+        ];
+
+        levels.iter()
+            .find(|(_, max_rate)| macroblocks_per_sec <= *max_rate)
+            .map(|(level, _)| *level)
+            .unwrap_or(Level(52))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Preset {
     Ultrafast,
@@ -83,6 +120,13 @@ impl AvcEncoder {
             Preset::Veryslow => "veryslow",
         });
 
+        // profile
+        opts.set("profile", match params.profile {
+            Profile::Baseline => "baseline",
+            Profile::Main => "main",
+            Profile::High => "high",
+        });
+
         // tune
         if let Some(tune) = params.tune {
             opts.set("tune", match tune {
@@ -123,8 +167,12 @@ impl AvcEncoder {
         // set codec context params
         unsafe {
             let avctx = &mut *ctx.as_mut_ptr();
-            avctx.profile = ff::FF_PROFILE_H264_HIGH as i32;
-            avctx.level = 41;
+            avctx.profile = match params.profile {
+                Profile::Baseline => ff::FF_PROFILE_H264_BASELINE as i32,
+                Profile::Main => ff::FF_PROFILE_H264_MAIN as i32,
+                Profile::High => ff::FF_PROFILE_H264_HIGH as i32,
+            };
+            avctx.level = params.level.0.into();
             avctx.width = params.picture_width.try_into().expect("picture_width too large");
             avctx.height = params.picture_height.try_into().expect("picture_height too large");
             avctx.colorspace = params.color_space;
@@ -196,6 +244,18 @@ impl AvcEncoder {
         Ok(())
     }
 
+    /// Signals the end of the stream, after which the encoder gives up any
+    /// packets it is holding back. No more frames can be sent afterwards
+    pub fn send_eof(&mut self) -> Result<(), AvError> {
+        let rc = unsafe { ff::avcodec_send_frame(self.ctx.as_mut_ptr(), ptr::null()) };
+
+        if rc < 0 {
+            return Err(AvError(rc));
+        }
+
+        Ok(())
+    }
+
     pub fn recv_packet(&mut self) -> Result<AvPacket, AvError> {
         unsafe {
             let mut packet = MaybeUninit::<ff::AVPacket>::uninit();
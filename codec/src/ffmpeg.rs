@@ -15,7 +15,7 @@ mod packet;
 mod pixfmt;
 mod scale;
 
-pub use format::InputContainer;
+pub use format::{InputContainer, InputStream};
 pub use frame::{AvFrame, PictureSettings, PictureData, PictureDataMut};
 pub use ioctx::{AvIoError, IoReader, AvIoReader};
 pub use packet::{AvPacket, AvPacketRef, PacketInfo};
@@ -34,6 +34,10 @@ impl AvError {
     pub fn again(&self) -> bool {
         self.0 == -(ff::EAGAIN as c_int)
     }
+
+    pub fn eof(&self) -> bool {
+        self.0 == EOF
+    }
 }
 
 impl Display for AvError {
@@ -11,6 +11,7 @@ use mixlab_util::time::{MediaDuration, MediaTime, TimeBase};
 
 use crate::ffmpeg::{AvIoError, AvPacket, AvError, EOF};
 use crate::ffmpeg::codec::AvCodecParameters;
+use crate::ffmpeg::media::MediaType;
 use crate::ffmpeg::ioctx::{IoReader, AvIoReader};
 
 pub struct InputContainer<R: IoReader> {
@@ -48,6 +49,46 @@ impl<R: IoReader> InputContainer<R> {
         unsafe { &*(self.ctx.ptr as *const _) }
     }
 
+    /// Reads ahead into the input to fill in stream parameters which the
+    /// container does not record up front, such as frame rates
+    pub fn find_stream_info(&mut self) -> Result<(), AvIoError<R>> {
+        let rc = unsafe {
+            ff::avformat_find_stream_info(self.ctx.ptr, ptr::null_mut())
+        };
+
+        self.io.check_error(rc)
+    }
+
+    pub fn format_name(&self) -> Option<&'static str> {
+        let iformat = self.as_underlying().iformat;
+
+        if iformat.is_null() {
+            return None;
+        }
+
+        let long_name = unsafe { (*iformat).long_name };
+
+        if long_name.is_null() {
+            return None;
+        }
+
+        let long_name = unsafe { CStr::from_ptr(long_name) };
+        Some(long_name.to_str().expect("utf8 format name"))
+    }
+
+    /// Returns `None` if the duration of the input is not known
+    pub fn duration(&self) -> Option<MediaDuration> {
+        let duration = self.as_underlying().duration;
+
+        // AV_NOPTS_VALUE:
+        if duration == i64::min_value() {
+            return None;
+        }
+
+        // container duration is always in AV_TIME_BASE units:
+        Some(MediaDuration::new(duration, 1_000_000))
+    }
+
     pub fn streams(&self) -> &[InputStream] {
         let underlying = self.as_underlying();
 
@@ -125,8 +166,36 @@ impl InputStream {
         Some(long_name.to_str().expect("utf8 codec name"))
     }
 
-    pub fn duration(&self) -> MediaDuration {
-        self.time_base().scale_duration(self.as_underlying().duration)
+    /// Short name of the codec, eg. "h264". Unlike `codec_name`, this is
+    /// known even if there is no decoder available for the codec
+    pub fn codec_short_name(&self) -> &'static str {
+        let codec_id = self.codec_parameters().codec_id;
+        let name = unsafe { CStr::from_ptr(ff::avcodec_get_name(codec_id)) };
+        name.to_str().expect("utf8 codec name")
+    }
+
+    /// Average frame rate of a video stream, if known
+    pub fn frame_rate(&self) -> Option<f64> {
+        let rate = self.as_underlying().avg_frame_rate;
+
+        if rate.num == 0 || rate.den == 0 {
+            return None;
+        }
+
+        Some(rate.num as f64 / rate.den as f64)
+    }
+
+    /// Returns `None` if the container does not record a duration for this
+    /// stream
+    pub fn duration(&self) -> Option<MediaDuration> {
+        let duration = self.as_underlying().duration;
+
+        // AV_NOPTS_VALUE:
+        if duration == i64::min_value() {
+            return None;
+        }
+
+        Some(self.time_base().scale_duration(duration))
     }
 
     pub fn time_base(&self) -> TimeBase {
@@ -134,6 +203,10 @@ impl InputStream {
         TimeBase::new(underlying.time_base.num, underlying.time_base.den)
     }
 
+    pub fn is_media_type<Mt: MediaType>(&self) -> bool {
+        self.codec_parameters().codec_type == Mt::FFMPEG_MEDIA_TYPE
+    }
+
     pub fn codec_parameters(&self) -> AvCodecParameters<'_> {
         unsafe { AvCodecParameters::from_raw(&*self.as_underlying().codecpar) }
     }
@@ -212,8 +285,8 @@ mod tests {
         eprintln!("streams:");
 
         for stream in fmt.streams() {
-            let secs = (stream.duration().as_rational() * 1_000).to_integer() as f64 / 1_000.0;
-            eprintln!("  - {:?}: {:?}, {:.3} secs", stream.id(), stream.codec_name(), secs);
+            let secs = stream.duration().map(|duration| (duration.as_rational() * 1_000).to_integer() as f64 / 1_000.0);
+            eprintln!("  - {:?}: {:?}, {:?} secs", stream.id(), stream.codec_name(), secs);
         }
 
         panic!("OK")
@@ -5,7 +5,7 @@ use std::ptr;
 
 use ffmpeg_dev::sys as ff;
 
-use crate::ffmpeg::media::{MediaType, Video};
+use crate::ffmpeg::media::{MediaType, Audio, Video};
 use crate::ffmpeg::{AvError, PixelFormat, ColorFormat};
 
 #[derive(Debug)]
@@ -303,6 +303,73 @@ impl AvFrame<Video> {
     }
 }
 
+impl AvFrame<Audio> {
+    pub fn sample_rate(&self) -> usize {
+        self.as_underlying().sample_rate.try_into().expect("sample_rate >= 0")
+    }
+
+    pub fn channels(&self) -> usize {
+        self.as_underlying().channels.try_into().expect("channels >= 0")
+    }
+
+    /// Number of samples per channel in this frame
+    pub fn sample_count(&self) -> usize {
+        self.as_underlying().nb_samples.try_into().expect("nb_samples >= 0")
+    }
+
+    /// Copies samples out of the frame as interleaved f32, converting from
+    /// whichever sample format the decoder produced. Returns None if the
+    /// sample format is not supported.
+    pub fn interleaved_samples(&self) -> Option<Vec<f32>> {
+        let underlying = self.as_underlying();
+        let format = underlying.format as ff::AVSampleFormat;
+
+        let planar = unsafe { ff::av_sample_fmt_is_planar(format) } != 0;
+        let packed_format = unsafe { ff::av_get_packed_sample_fmt(format) };
+        let bytes_per_sample: usize = unsafe { ff::av_get_bytes_per_sample(format) }
+            .try_into().ok()?;
+
+        let read_sample: fn(*const u8) -> f32 = match packed_format {
+            ff::AVSampleFormat_AV_SAMPLE_FMT_U8 => |ptr| unsafe {
+                (*ptr as f32 - 128.0) / 128.0
+            },
+            ff::AVSampleFormat_AV_SAMPLE_FMT_S16 => |ptr| unsafe {
+                ptr::read_unaligned(ptr as *const i16) as f32 / 32768.0
+            },
+            ff::AVSampleFormat_AV_SAMPLE_FMT_S32 => |ptr| unsafe {
+                ptr::read_unaligned(ptr as *const i32) as f32 / 2147483648.0
+            },
+            ff::AVSampleFormat_AV_SAMPLE_FMT_FLT => |ptr| unsafe {
+                ptr::read_unaligned(ptr as *const f32)
+            },
+            ff::AVSampleFormat_AV_SAMPLE_FMT_DBL => |ptr| unsafe {
+                ptr::read_unaligned(ptr as *const f64) as f32
+            },
+            _ => { return None; }
+        };
+
+        let channels = self.channels();
+        let sample_count = self.sample_count();
+        let mut samples = Vec::with_capacity(channels * sample_count);
+
+        for i in 0..sample_count {
+            for ch in 0..channels {
+                let ptr = unsafe {
+                    if planar {
+                        (*underlying.extended_data.add(ch)).add(i * bytes_per_sample)
+                    } else {
+                        (*underlying.extended_data).add((i * channels + ch) * bytes_per_sample)
+                    }
+                };
+
+                samples.push(read_sample(ptr));
+            }
+        }
+
+        Some(samples)
+    }
+}
+
 type PlanarData = [*mut u8; ff::AV_NUM_DATA_POINTERS as usize];
 type PlanarStride = [c_int; ff::AV_NUM_DATA_POINTERS as usize];
 
@@ -383,4 +450,12 @@ impl PictureSettings {
             pixel_format: PixelFormat::yuv420p(),
         }
     }
+
+    pub fn rgb24(width: usize, height: usize) -> Self {
+        PictureSettings {
+            width,
+            height,
+            pixel_format: PixelFormat::rgb24(),
+        }
+    }
 }
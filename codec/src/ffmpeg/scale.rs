@@ -12,6 +12,9 @@ pub struct SwsContext {
     output: PictureSettings,
 }
 
+// contexts are never shared, but may be moved between threads:
+unsafe impl Send for SwsContext {}
+
 impl SwsContext {
     pub fn new(input: PictureSettings, output: PictureSettings) -> Self {
         let input_width: i32 = input.width.try_into().expect("input_width too large");
@@ -0,0 +1,276 @@
+// Streaming band-limited resampler, after Julius O. Smith's "Digital Audio
+// Resampling" algorithm: a Kaiser-windowed sinc is tabulated once, and each
+// output sample is computed by convolving the input with the table at the
+// exact fractional position of that output sample in the input.
+//
+// Input position is tracked exactly as a rational number, so there is no
+// drift no matter how long the stream runs.
+
+use std::f64::consts::PI;
+
+// number of zero crossings of the sinc on each side of the filter centre:
+const ZERO_CROSSINGS: usize = 16;
+
+// filter table resolution, in entries per zero crossing:
+const TABLE_RESOLUTION: usize = 512;
+
+const KAISER_BETA: f64 = 8.6;
+
+// pull cutoff in slightly from nyquist so the transition band of the filter
+// does not let through aliasing:
+const ROLLOFF: f64 = 0.95;
+
+pub trait Sample: Copy {
+    fn to_f32(self) -> f32;
+    fn from_f32(sample: f32) -> Self;
+}
+
+impl Sample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        sample
+    }
+}
+
+impl Sample for i16 {
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
+    }
+
+    fn from_f32(sample: f32) -> Self {
+        let sample = sample * 32768.0;
+
+        if sample >= i16::max_value() as f32 {
+            i16::max_value()
+        } else if sample <= i16::min_value() as f32 {
+            i16::min_value()
+        } else {
+            sample.round() as i16
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Resampler {
+    channels: usize,
+    input_rate: u64,
+    output_rate: u64,
+    state: Option<FilterState>,
+}
+
+#[derive(Debug)]
+struct FilterState {
+    table: Vec<f32>,
+    cutoff: f64,
+    // filter half width in input samples:
+    half_width: usize,
+    // per channel input history:
+    history: Vec<Vec<f32>>,
+    // position of next output sample in history, in units of
+    // 1 / output_rate input samples:
+    position: u64,
+}
+
+impl Resampler {
+    /// Creates a resampler for interleaved audio with `channels` channels.
+    /// When `input_rate == output_rate`, samples are passed through untouched.
+    pub fn new(channels: usize, input_rate: usize, output_rate: usize) -> Self {
+        assert!(channels > 0, "resampler must have at least one channel");
+        assert!(input_rate > 0 && output_rate > 0, "resampler rates must be non-zero");
+
+        let divisor = gcd(input_rate as u64, output_rate as u64);
+        let input_rate = input_rate as u64 / divisor;
+        let output_rate = output_rate as u64 / divisor;
+
+        let state = if input_rate == output_rate {
+            None
+        } else {
+            // when downsampling, the filter must cut off at the output
+            // nyquist rather than the input nyquist:
+            let cutoff = f64::min(1.0, output_rate as f64 / input_rate as f64) * ROLLOFF;
+            let half_width = (ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+
+            Some(FilterState {
+                table: make_table(),
+                cutoff,
+                half_width,
+                // prime history with silence so the first output sample lines
+                // up exactly with the first input sample:
+                history: vec![vec![0.0; half_width]; channels],
+                position: half_width as u64 * output_rate,
+            })
+        };
+
+        Resampler {
+            channels,
+            input_rate,
+            output_rate,
+            state,
+        }
+    }
+
+    /// Resamples interleaved `input`, appending any interleaved output
+    /// samples that can be produced so far to `output`.
+    pub fn process<S: Sample>(&mut self, input: &[S], output: &mut Vec<S>) {
+        let channels = self.channels;
+
+        let state = match &mut self.state {
+            Some(state) => state,
+            None => {
+                output.extend_from_slice(input);
+                return;
+            }
+        };
+
+        for frame in input.chunks_exact(channels) {
+            for (history, sample) in state.history.iter_mut().zip(frame) {
+                history.push(sample.to_f32());
+            }
+        }
+
+        let available = state.history[0].len() as u64;
+
+        loop {
+            let index = state.position / self.output_rate;
+
+            if index + state.half_width as u64 >= available {
+                break;
+            }
+
+            let fraction = (state.position % self.output_rate) as f64 / self.output_rate as f64;
+
+            for history in &state.history {
+                output.push(S::from_f32(state.convolve(history, index as usize, fraction)));
+            }
+
+            state.position += self.input_rate;
+        }
+
+        // discard history which no future output sample can depend on
+        let index = (state.position / self.output_rate) as usize;
+        let consumed = index.saturating_sub(state.half_width);
+
+        if consumed > 0 {
+            for history in &mut state.history {
+                history.drain(0..consumed);
+            }
+
+            state.position -= consumed as u64 * self.output_rate;
+        }
+    }
+}
+
+impl FilterState {
+    fn convolve(&self, history: &[f32], index: usize, fraction: f64) -> f32 {
+        let mut acc = 0.0f64;
+
+        let start = index + 1 - self.half_width;
+        let end = index + self.half_width;
+
+        for i in start..=end {
+            let distance = (i as f64 - index as f64 - fraction).abs();
+            acc += history[i] as f64 * self.filter(distance);
+        }
+
+        acc as f32
+    }
+
+    fn filter(&self, distance: f64) -> f64 {
+        let offset = distance * self.cutoff * TABLE_RESOLUTION as f64;
+        let table_index = offset as usize;
+
+        if table_index + 1 >= self.table.len() {
+            return 0.0;
+        }
+
+        // linearly interpolate between table entries:
+        let t = offset - table_index as f64;
+        let a = self.table[table_index] as f64;
+        let b = self.table[table_index + 1] as f64;
+
+        (a + (b - a) * t) * self.cutoff
+    }
+}
+
+fn make_table() -> Vec<f32> {
+    let len = ZERO_CROSSINGS * TABLE_RESOLUTION + 1;
+    let i0_beta = bessel_i0(KAISER_BETA);
+
+    (0..len).map(|i| {
+        let x = i as f64 / TABLE_RESOLUTION as f64;
+
+        let sinc = if i == 0 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+
+        let w = x / ZERO_CROSSINGS as f64;
+        let window = bessel_i0(KAISER_BETA * (1.0 - w * w).max(0.0).sqrt()) / i0_beta;
+
+        (sinc * window) as f32
+    }).collect()
+}
+
+// zeroth order modified bessel function of the first kind, for kaiser window
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+
+    for k in 1..50 {
+        term *= (half_x / k as f64) * (half_x / k as f64);
+        sum += term;
+
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+
+    sum
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let t = b;
+        b = a % b;
+        a = t;
+    }
+
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resampler;
+
+    #[test]
+    fn passthrough_when_rates_match() {
+        let mut resampler = Resampler::new(2, 44100, 44100);
+        let input = vec![1i16, 2, 3, 4, 5, 6];
+        let mut output = Vec::new();
+        resampler.process(&input, &mut output);
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn output_length_follows_rate_ratio() {
+        let mut resampler = Resampler::new(2, 48000, 44100);
+        let mut output = Vec::<f32>::new();
+
+        // feed one second of stereo audio in uneven chunks:
+        let input = (0..48000 * 2).map(|i| ((i / 2) as f32 * 0.01).sin() * 0.5).collect::<Vec<_>>();
+
+        for chunk in input.chunks(2 * 1000 + 6) {
+            resampler.process(chunk, &mut output);
+        }
+
+        // the filter holds back half its width of samples at the end:
+        let frames = output.len() / 2;
+        assert!(frames <= 44100 && frames > 44100 - 64, "frames = {}", frames);
+        assert!(output.iter().all(|sample| sample.abs() < 0.6));
+    }
+}
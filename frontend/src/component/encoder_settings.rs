@@ -0,0 +1,206 @@
+use std::fmt::{self, Display};
+
+use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties, Callback};
+use yew::events::ChangeData;
+use yew_components::Select;
+
+use mixlab_protocol::{StreamEncoderSettings, EncoderPreset, EncoderProfile};
+
+/// Form fields for the encoder settings shared by modules which stream or
+/// record their output
+pub struct EncoderSettings {
+    props: EncoderSettingsProps,
+}
+
+#[derive(Properties, Clone, Debug)]
+pub struct EncoderSettingsProps {
+    pub settings: StreamEncoderSettings,
+    pub disabled: bool,
+    pub onchange: Callback<StreamEncoderSettings>,
+}
+
+impl Component for EncoderSettings {
+    type Properties = EncoderSettingsProps;
+    type Message = ();
+
+    fn create(props: Self::Properties, _: ComponentLink<Self>) -> Self {
+        EncoderSettings { props }
+    }
+
+    fn update(&mut self, _: Self::Message) -> ShouldRender {
+        false
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        true
+    }
+
+    fn view(&self) -> Html {
+        let settings = &self.props.settings;
+        let disabled = self.props.disabled;
+
+        let presets = vec![
+            SelectablePreset(EncoderPreset::Ultrafast),
+            SelectablePreset(EncoderPreset::Superfast),
+            SelectablePreset(EncoderPreset::Veryfast),
+            SelectablePreset(EncoderPreset::Faster),
+            SelectablePreset(EncoderPreset::Fast),
+            SelectablePreset(EncoderPreset::Medium),
+            SelectablePreset(EncoderPreset::Slow),
+            SelectablePreset(EncoderPreset::Slower),
+            SelectablePreset(EncoderPreset::Veryslow),
+        ];
+
+        let profiles = vec![
+            SelectableProfile(EncoderProfile::Baseline),
+            SelectableProfile(EncoderProfile::Main),
+            SelectableProfile(EncoderProfile::High),
+        ];
+
+        html! {
+            <div class="encoder-settings">
+                <label class="form-field">
+                    <span class="form-field-label">{"Width"}</span>
+                    <input type="text"
+                        disabled={disabled}
+                        onchange={self.callback(number(|width, settings| settings.width = width))}
+                        value={settings.width}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Height"}</span>
+                    <input type="text"
+                        disabled={disabled}
+                        onchange={self.callback(number(|height, settings| settings.height = height))}
+                        value={settings.height}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Frame Rate (fps)"}</span>
+                    <input type="text"
+                        disabled={disabled}
+                        onchange={self.callback(number(|frame_rate, settings| settings.frame_rate = frame_rate))}
+                        value={settings.frame_rate}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Video Bitrate (kbps)"}</span>
+                    <input type="text"
+                        disabled={disabled}
+                        onchange={self.callback(number(|kbps, settings| settings.video_bitrate_kbps = kbps))}
+                        value={settings.video_bitrate_kbps}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Keyframe Interval (secs)"}</span>
+                    <input type="text"
+                        disabled={disabled}
+                        onchange={self.callback(number(|secs, settings| settings.keyframe_interval_secs = secs))}
+                        value={settings.keyframe_interval_secs}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Preset"}</span>
+                    <Select<SelectablePreset>
+                        disabled={disabled}
+                        selected={SelectablePreset(settings.preset)}
+                        options={presets}
+                        on_change={self.callback(|SelectablePreset(preset), settings: &mut StreamEncoderSettings| {
+                            settings.preset = preset;
+                        })}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Profile"}</span>
+                    <Select<SelectableProfile>
+                        disabled={disabled}
+                        selected={SelectableProfile(settings.profile)}
+                        options={profiles}
+                        on_change={self.callback(|SelectableProfile(profile), settings: &mut StreamEncoderSettings| {
+                            settings.profile = profile;
+                        })}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Audio Bitrate (kbps)"}</span>
+                    <input type="text"
+                        disabled={disabled}
+                        onchange={self.callback(number(|kbps, settings| settings.audio_bitrate_kbps = kbps))}
+                        value={settings.audio_bitrate_kbps}
+                    />
+                </label>
+            </div>
+        }
+    }
+}
+
+impl EncoderSettings {
+    fn callback<Ev>(&self, f: impl Fn(Ev, &mut StreamEncoderSettings) + 'static) -> Callback<Ev> {
+        let settings = self.props.settings.clone();
+
+        self.props.onchange.reform(move |ev| {
+            let mut settings = settings.clone();
+            f(ev, &mut settings);
+            settings
+        })
+    }
+}
+
+// unparseable input leaves settings unchanged:
+fn number(f: impl Fn(usize, &mut StreamEncoderSettings))
+    -> impl Fn(ChangeData, &mut StreamEncoderSettings)
+{
+    move |change, settings| {
+        if let ChangeData::Value(value) = change {
+            if let Ok(number) = value.trim().parse() {
+                f(number, settings);
+            }
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+#[derive(PartialEq, Clone)]
+struct SelectablePreset(EncoderPreset);
+
+impl Display for SelectablePreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let SelectablePreset(preset) = self;
+        let name = match preset {
+            EncoderPreset::Ultrafast => "Ultrafast",
+            EncoderPreset::Superfast => "Superfast",
+            EncoderPreset::Veryfast => "Veryfast",
+            EncoderPreset::Faster => "Faster",
+            EncoderPreset::Fast => "Fast",
+            EncoderPreset::Medium => "Medium",
+            EncoderPreset::Slow => "Slow",
+            EncoderPreset::Slower => "Slower",
+            EncoderPreset::Veryslow => "Veryslow",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(PartialEq, Clone)]
+struct SelectableProfile(EncoderProfile);
+
+impl Display for SelectableProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let SelectableProfile(profile) = self;
+        let name = match profile {
+            EncoderProfile::Baseline => "Baseline",
+            EncoderProfile::Main => "Main",
+            EncoderProfile::High => "High",
+        };
+        write!(f, "{}", name)
+    }
+}
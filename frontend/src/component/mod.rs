@@ -1,4 +1,5 @@
 pub mod drag_target;
+pub mod encoder_settings;
 pub mod midi_target;
 pub mod pure_module;
 pub mod scroll_target;
@@ -7,6 +7,7 @@ mod module;
 mod service;
 mod session;
 mod sidebar;
+mod snapshots;
 mod util;
 mod workspace;
 
@@ -1,24 +1,36 @@
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::collections::BTreeMap;
 use std::num::NonZeroUsize;
 use std::rc::Rc;
 
 use gloo_events::EventListener;
+use js_sys::Promise;
 use wasm_bindgen::{JsCast, JsValue};
-use web_sys::{File, XmlHttpRequest, ProgressEvent};
-use yew::events::ChangeData;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, File, XmlHttpRequest, ProgressEvent};
+use yew::events::{ChangeData, InputData};
 use yew::{html, Callback, Component, ComponentLink, Html, ShouldRender, Properties, NodeRef};
 
 use mixlab_protocol as protocol;
+use mixlab_protocol::{MediaId, MediaOp, MediaOpError, MediaSearchResults, MediaInfo, MediaStreamKind, Microseconds};
 
 use crate::session::SessionRef;
 use crate::util::{self, notify, Sequence};
 
 pub struct MediaLibrary {
     link: ComponentLink<Self>,
+    session: SessionRef,
     upload_seq: Sequence,
     uploads: BTreeMap<NonZeroUsize, InProgressUpload>,
     library: Option<Rc<protocol::MediaLibrary>>,
+    query: String,
+    // results for the current query, None while not searching:
+    search: Option<Rc<MediaSearchResults>>,
+    error: Option<String>,
     _notify: notify::Handle,
+    _search_notify: notify::Handle,
+    _error_notify: notify::Handle,
 }
 
 #[derive(Properties, Clone)]
@@ -30,6 +42,12 @@ pub enum LibraryMsg {
     Update(Rc<protocol::MediaLibrary>),
     SelectFiles(Vec<File>),
     Upload(NonZeroUsize, UploadEvent),
+    Search(String),
+    SearchResults(Rc<MediaSearchResults>),
+    Error(Rc<MediaOpError>),
+    Rename(MediaId, String),
+    SetTags(MediaId, String),
+    Delete(MediaId),
 }
 
 impl Component for MediaLibrary {
@@ -38,13 +56,21 @@ impl Component for MediaLibrary {
 
     fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
         let notify = props.session.listen_media(link.callback(LibraryMsg::Update));
+        let search_notify = props.session.listen_media_search(link.callback(LibraryMsg::SearchResults));
+        let error_notify = props.session.listen_media_errors(link.callback(LibraryMsg::Error));
 
         MediaLibrary {
             link,
+            session: props.session,
             upload_seq: Sequence::new(),
             uploads: BTreeMap::new(),
             library: None,
+            query: String::new(),
+            search: None,
+            error: None,
             _notify: notify,
+            _search_notify: search_notify,
+            _error_notify: error_notify,
         }
     }
 
@@ -56,6 +82,55 @@ impl Component for MediaLibrary {
         match msg {
             LibraryMsg::Update(library) => {
                 self.library = Some(library);
+
+                // library has changed, so search results may have too:
+                if !self.query.trim().is_empty() {
+                    self.session.update_media(MediaOp::Search(self.query.clone()));
+                }
+
+                true
+            }
+            LibraryMsg::Search(query) => {
+                if query.trim().is_empty() {
+                    self.search = None;
+                } else {
+                    self.session.update_media(MediaOp::Search(query.clone()));
+                }
+
+                self.query = query;
+                true
+            }
+            LibraryMsg::SearchResults(results) => {
+                // ignore results for queries which have since changed:
+                if results.query == self.query {
+                    self.search = Some(results);
+                    true
+                } else {
+                    false
+                }
+            }
+            LibraryMsg::Error(error) => {
+                self.error = Some(format_error(&error, self.library.as_deref()));
+                true
+            }
+            LibraryMsg::Rename(id, name) => {
+                self.error = None;
+                self.session.update_media(MediaOp::Rename(id, name));
+                true
+            }
+            LibraryMsg::SetTags(id, tags) => {
+                let tags = tags.split(',')
+                    .map(|tag| tag.trim().to_owned())
+                    .filter(|tag| !tag.is_empty())
+                    .collect();
+
+                self.error = None;
+                self.session.update_media(MediaOp::SetTags(id, tags));
+                true
+            }
+            LibraryMsg::Delete(id) => {
+                self.error = None;
+                self.session.update_media(MediaOp::Delete(id));
                 true
             }
             LibraryMsg::SelectFiles(files) => {
@@ -66,8 +141,7 @@ impl Component for MediaLibrary {
 
                     let task = UploadTask::start(file,
                         self.link.callback(move |ev|
-                            LibraryMsg::Upload(id, ev))
-                    ).expect("UploadTask::start");
+                            LibraryMsg::Upload(id, ev)));
 
                     self.uploads.insert(id, InProgressUpload {
                         filename,
@@ -92,6 +166,13 @@ impl Component for MediaLibrary {
                         self.uploads.remove(&id);
                         true
                     }
+                    UploadEvent::Failed(reason) => {
+                        if let Some(upload) = self.uploads.remove(&id) {
+                            self.error = Some(format!("Could not upload {}: {}", upload.filename, reason));
+                        }
+
+                        true
+                    }
                 }
             }
         }
@@ -102,7 +183,18 @@ impl Component for MediaLibrary {
             <div class="media-library">
                 <div class="media-library-main-button-row">
                     <UploadButton on_file_upload={self.link.callback(LibraryMsg::SelectFiles)} />
+                    <input type="text"
+                        class="media-library-search"
+                        placeholder="Search"
+                        value={&self.query}
+                        oninput={self.link.callback(|ev: InputData| LibraryMsg::Search(ev.value))}
+                    />
                 </div>
+                { if let Some(error) = &self.error {
+                    html! { <div class="media-library-error">{error}</div> }
+                } else {
+                    html! {}
+                } }
                 { if self.uploads.is_empty() {
                     html! {}
                 } else {
@@ -145,19 +237,21 @@ impl Component for MediaLibrary {
                     html! {
                         <table class="media-library-table">
                             <tr class="table-heading">
+                                <th></th>
                                 <th>{"Name"}</th>
+                                <th>{"Tags"}</th>
                                 <th>{"Kind"}</th>
+                                <th>{"Duration"}</th>
+                                <th>{"Streams"}</th>
                                 <th>{"Size"}</th>
+                                <th></th>
                             </tr>
-                            { for library.items.iter().map(|item| {
-                                html! {
-                                    <tr>
-                                        <td>{&item.name}</td>
-                                        <td>{&item.kind}</td>
-                                        <td>{format_size(item.size)}</td>
-                                    </tr>
-                                }
-                            }) }
+                            { for library.items.iter()
+                                .filter(|item| match &self.search {
+                                    Some(search) => search.items.contains(&item.id),
+                                    None => true,
+                                })
+                                .map(|item| self.view_item(item)) }
                         </table>
                     }
                 } else {
@@ -168,6 +262,124 @@ impl Component for MediaLibrary {
     }
 }
 
+impl MediaLibrary {
+    fn view_item(&self, item: &protocol::MediaItem) -> Html {
+        let id = item.id;
+
+        html! {
+            <tr>
+                <td>{view_preview(item)}</td>
+                <td>
+                    <input type="text"
+                        class="media-library-name"
+                        value={&item.name}
+                        onchange={self.link.callback(move |change| {
+                            if let ChangeData::Value(name) = change {
+                                LibraryMsg::Rename(id, name)
+                            } else {
+                                unreachable!()
+                            }
+                        })}
+                    />
+                </td>
+                <td>
+                    <input type="text"
+                        class="media-library-tags"
+                        placeholder="tag, tag"
+                        value={item.tags.join(", ")}
+                        onchange={self.link.callback(move |change| {
+                            if let ChangeData::Value(tags) = change {
+                                LibraryMsg::SetTags(id, tags)
+                            } else {
+                                unreachable!()
+                            }
+                        })}
+                    />
+                </td>
+                <td>{item.info.as_ref().map(|info| info.format.as_str()).unwrap_or(item.kind.as_str())}</td>
+                <td>{item.info.as_ref().and_then(|info| info.duration).map(format_duration).unwrap_or_default()}</td>
+                <td>{item.info.as_ref().map(format_streams).unwrap_or_default()}</td>
+                <td>{format_size(item.size)}</td>
+                <td>
+                    <button
+                        class="media-library-delete"
+                        onclick={self.link.callback(move |_| LibraryMsg::Delete(id))}
+                    >
+                        {"Delete"}
+                    </button>
+                </td>
+            </tr>
+        }
+    }
+}
+
+fn view_preview(item: &protocol::MediaItem) -> Html {
+    // prefer a poster frame, falling back to the waveform for audio-only media
+    let preview = if item.thumbnail {
+        Some("thumbnail")
+    } else if item.waveform {
+        Some("waveform")
+    } else {
+        None
+    };
+
+    match preview {
+        Some(preview) => {
+            let src = format!("{}/_media/{}/{}", util::origin(), item.id.0, preview);
+            html! { <img class="media-library-preview" src={src} /> }
+        }
+        None => html! { <div class="media-library-preview" /> },
+    }
+}
+
+fn format_error(error: &MediaOpError, library: Option<&protocol::MediaLibrary>) -> String {
+    let name = |id: &MediaId| library
+        .and_then(|library| library.items.iter().find(|item| item.id == *id))
+        .map(|item| item.name.clone())
+        .unwrap_or_else(|| "Media".to_owned());
+
+    match error {
+        MediaOpError::NotFound(id) => format!("{} no longer exists", name(id)),
+        MediaOpError::InUse(id) => format!("{} is in use and cannot be deleted", name(id)),
+        MediaOpError::InvalidName => "Name must not be empty".to_owned(),
+    }
+}
+
+fn format_duration(duration: Microseconds) -> String {
+    let secs = duration.0 / 1_000_000;
+    format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+// eg. "h264 1920x1080 30fps, aac 48000Hz 2ch"
+fn format_streams(info: &MediaInfo) -> String {
+    info.streams.iter()
+        .filter(|stream| stream.kind != MediaStreamKind::Other)
+        .map(|stream| {
+            let mut desc = stream.codec.clone();
+
+            if let Some((width, height)) = stream.resolution {
+                desc += &format!(" {}x{}", width, height);
+            }
+
+            if let Some(frame_rate) = stream.frame_rate {
+                // eg. 30fps, 29.97fps
+                desc += &format!(" {}fps", (frame_rate * 100.0).round() / 100.0);
+            }
+
+            if let Some(sample_rate) = stream.sample_rate {
+                desc += &format!(" {}Hz", sample_rate);
+            }
+
+            if let Some(channels) = stream.channels {
+                desc += &format!(" {}ch", channels);
+            }
+
+            desc
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 fn format_size(bytes: usize) -> String {
     const KIB: usize = 1024;
     const MIB: usize = 1024 * 1024;
@@ -277,58 +489,89 @@ impl UploadProgress {
     }
 }
 
+// files are sent in chunks of this size, so a dropped connection costs at
+// most one chunk's progress:
+const UPLOAD_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+// consecutive failed requests tolerated before giving up on an upload, and
+// how long to wait before each retry:
+const UPLOAD_MAX_RETRIES: usize = 20;
+const UPLOAD_RETRY_DELAY_MS: i32 = 3000;
+
 struct UploadTask {
-    xhr: XmlHttpRequest,
-    _progress_event: EventListener,
-    _load_event: EventListener,
+    state: Rc<UploadState>,
+}
+
+#[derive(Default)]
+struct UploadState {
+    cancelled: Cell<bool>,
+    // request in flight, aborted if the task is dropped:
+    xhr: RefCell<Option<XmlHttpRequest>>,
 }
 
 pub enum UploadEvent {
     Progress(UploadProgress),
     Complete,
+    Failed(String),
 }
 
-impl UploadTask {
-    fn start(file: File, callback: Callback<UploadEvent>) -> Result<UploadTask, JsValue> {
-        crate::log!("origin: {:?}", util::origin());
-        let url = util::origin() + "/_upload/" + &file.name();
+enum UploadFailure {
+    Cancelled,
+    Failed(String),
+}
 
-        let mut kind = file.type_();
-        if kind == "" {
-            kind = "application/octet-stream".to_string();
-        }
+impl From<JsValue> for UploadFailure {
+    fn from(e: JsValue) -> Self {
+        UploadFailure::Failed(format!("{:?}", e))
+    }
+}
 
-        let xhr = XmlHttpRequest::new()?;
-        let upload = xhr.upload()?;
+struct Response {
+    // zero if the request did not complete, eg. because the network dropped:
+    status: u16,
+    text: String,
+}
 
-        let progress_event = EventListener::new(&upload, "progress", {
-            let callback = callback.clone();
-            move |ev| {
-                if let Some(ev) = ev.dyn_ref::<ProgressEvent>() {
-                    let uploaded = ev.loaded() as u64;
-                    let total = ev.total() as u64;
-                    callback.emit(UploadEvent::Progress(UploadProgress {
-                        uploaded,
-                        total,
-                    }));
+impl Response {
+    fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+
+    // client errors other than conflicts won't be fixed by trying again
+    fn is_retryable(&self) -> bool {
+        self.status == 0 || self.status == 409 || self.status >= 500
+    }
+
+    fn offset(&self) -> Result<u64, UploadFailure> {
+        self.text.trim().parse()
+            .map_err(|_| UploadFailure::Failed(format!("invalid upload offset from server: {:?}", self.text)))
+    }
+
+    fn reason(&self) -> String {
+        if self.text.is_empty() {
+            format!("server responded with status {}", self.status)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+impl UploadTask {
+    fn start(file: File, callback: Callback<UploadEvent>) -> UploadTask {
+        let state = Rc::new(UploadState::default());
+
+        wasm_bindgen_futures::spawn_local({
+            let state = state.clone();
+            async move {
+                match upload(&state, &file, &callback).await {
+                    Ok(()) => callback.emit(UploadEvent::Complete),
+                    Err(UploadFailure::Cancelled) => {}
+                    Err(UploadFailure::Failed(reason)) => callback.emit(UploadEvent::Failed(reason)),
                 }
             }
         });
 
-        let load_event = EventListener::new(&upload, "load", {
-            let callback = callback.clone();
-            move |_| { callback.emit(UploadEvent::Complete); }
-        });
-
-        xhr.open("POST", &url)?;
-        xhr.override_mime_type(&kind)?;
-        xhr.send_with_opt_blob(Some(&file))?;
-
-        Ok(UploadTask {
-            xhr,
-            _progress_event: progress_event,
-            _load_event: load_event,
-        })
+        UploadTask { state }
     }
 }
 
@@ -337,9 +580,156 @@ impl Drop for UploadTask {
         /// https://developer.mozilla.org/en-US/docs/Web/API/XMLHttpRequest/readyState
         const DONE: u16 = 4;
 
-        if self.xhr.ready_state() != DONE {
-            // nothing we can do in drop if abort fails
-            let _ = self.xhr.abort();
+        self.state.cancelled.set(true);
+
+        let xhr = self.state.xhr.borrow_mut().take();
+
+        if let Some(xhr) = xhr {
+            if xhr.ready_state() != DONE {
+                // nothing we can do in drop if abort fails
+                let _ = xhr.abort();
+            }
         }
     }
 }
+
+// uploads a file using the server's resumable upload protocol, retrying
+// failed requests from wherever the server got to
+async fn upload(state: &UploadState, file: &File, callback: &Callback<UploadEvent>) -> Result<(), UploadFailure> {
+    let mut kind = file.type_();
+    if kind == "" {
+        kind = "application/octet-stream".to_string();
+    }
+
+    let total = file.size() as u64;
+
+    let create_url = format!("{}/_uploads/{}", util::origin(), String::from(js_sys::encode_uri_component(&file.name())));
+
+    let created = request(state, "POST", &create_url, &[
+        ("content-type", &kind),
+        ("upload-length", &total.to_string()),
+    ], None, None).await?;
+
+    if !created.is_success() {
+        return Err(UploadFailure::Failed(created.reason()));
+    }
+
+    let upload_url = format!("{}/_uploads/{}", util::origin(), created.text.trim());
+
+    let mut offset = 0;
+    let mut failures = 0;
+
+    while offset < total {
+        let end = cmp::min(offset + UPLOAD_CHUNK_SIZE, total);
+        let chunk = file.slice_with_f64_and_f64(offset as f64, end as f64)?;
+
+        let on_progress = Callback::from({
+            let callback = callback.clone();
+            move |loaded: f64| {
+                callback.emit(UploadEvent::Progress(UploadProgress {
+                    uploaded: offset + loaded as u64,
+                    total,
+                }))
+            }
+        });
+
+        let response = request(state, "PUT", &upload_url, &[
+            ("upload-offset", &offset.to_string()),
+        ], Some(&chunk), Some(on_progress)).await?;
+
+        if response.is_success() {
+            offset = response.offset()?;
+            failures = 0;
+            continue;
+        }
+
+        failures += 1;
+
+        if !response.is_retryable() || failures > UPLOAD_MAX_RETRIES {
+            let _ = request(state, "DELETE", &upload_url, &[], None, None).await;
+            return Err(UploadFailure::Failed(response.reason()));
+        }
+
+        sleep(UPLOAD_RETRY_DELAY_MS).await;
+
+        // part of the chunk may have been received before the request failed,
+        // so ask the server where to resume from
+        let status = request(state, "GET", &upload_url, &[], None, None).await?;
+
+        if status.is_success() {
+            offset = status.offset()?;
+        } else if !status.is_retryable() {
+            return Err(UploadFailure::Failed(status.reason()));
+        }
+    }
+
+    let finalized = request(state, "POST", &format!("{}/finalize", upload_url), &[], None, None).await?;
+
+    if finalized.is_success() {
+        Ok(())
+    } else {
+        Err(UploadFailure::Failed(finalized.reason()))
+    }
+}
+
+async fn request(
+    state: &UploadState,
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<&Blob>,
+    on_progress: Option<Callback<f64>>,
+) -> Result<Response, UploadFailure> {
+    if state.cancelled.get() {
+        return Err(UploadFailure::Cancelled);
+    }
+
+    let xhr = XmlHttpRequest::new()?;
+    xhr.open(method, url)?;
+
+    for (name, value) in headers {
+        xhr.set_request_header(name, value)?;
+    }
+
+    let mut listeners = Vec::new();
+
+    if let Some(on_progress) = on_progress {
+        listeners.push(EventListener::new(&xhr.upload()?, "progress", move |ev| {
+            if let Some(ev) = ev.dyn_ref::<ProgressEvent>() {
+                on_progress.emit(ev.loaded());
+            }
+        }));
+    }
+
+    // loadend fires however the request ends, including errors and aborts
+    let done = Promise::new(&mut |resolve, _reject| {
+        listeners.push(EventListener::once(&xhr, "loadend", move |_| {
+            let _ = resolve.call0(&JsValue::NULL);
+        }));
+    });
+
+    xhr.send_with_opt_blob(body)?;
+    *state.xhr.borrow_mut() = Some(xhr.clone());
+
+    let _ = JsFuture::from(done).await;
+
+    state.xhr.borrow_mut().take();
+
+    if state.cancelled.get() {
+        return Err(UploadFailure::Cancelled);
+    }
+
+    Ok(Response {
+        status: xhr.status().unwrap_or(0),
+        text: xhr.response_text().ok().flatten().unwrap_or_default(),
+    })
+}
+
+async fn sleep(millis: i32) {
+    let timeout = Promise::new(&mut |resolve, _reject| {
+        let _ = web_sys::window().unwrap()
+            .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, millis);
+    });
+
+    let _ = JsFuture::from(timeout).await;
+}
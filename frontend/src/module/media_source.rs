@@ -1,10 +1,11 @@
 use std::fmt::{self, Display};
 use std::rc::Rc;
 
-use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties};
+use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties, Callback};
+use yew::events::ChangeData;
 use yew_components::Select;
 
-use mixlab_protocol::{ModuleId, ModuleParams, MediaSourceParams, MediaLibrary, MediaId};
+use mixlab_protocol::{ModuleId, ModuleParams, MediaSourceParams, MediaSourceIndication, MediaLibrary, MediaId, Microseconds, PlaybackMode};
 
 use crate::util::notify;
 use crate::session::SessionRef;
@@ -15,6 +16,7 @@ pub struct MediaSourceProps {
     pub id: ModuleId,
     pub module: ComponentLink<Window>,
     pub params: MediaSourceParams,
+    pub indication: MediaSourceIndication,
     pub session: SessionRef,
 }
 
@@ -88,16 +90,142 @@ impl Component for MediaSource {
             }
         });
 
+        let modes = vec![
+            SelectableMode(PlaybackMode::Loop),
+            SelectableMode(PlaybackMode::OneShot),
+        ];
+
+        let indication = &self.props.indication;
+
+        let position = format!("{} / {}",
+            indication.playhead.map(format_time).unwrap_or_else(|| "-".to_owned()),
+            indication.duration.map(format_time).unwrap_or_else(|| "-".to_owned()));
+
         html! {
-            <Select<MediaSourceItem>
-                options={options}
-                selected={selected}
-                on_change={self.link.callback(MediaSourceMsg::ChangeSource)}
-            />
+            <>
+                <Select<MediaSourceItem>
+                    options={options}
+                    selected={selected}
+                    on_change={self.link.callback(MediaSourceMsg::ChangeSource)}
+                />
+
+                <div class="media-source-transport">
+                    <button
+                        onclick={self.callback(|_, params| {
+                            MediaSourceParams { playing: !params.playing, ..params }
+                        })}
+                    >
+                        {if self.props.params.playing { "Pause" } else { "Play" }}
+                    </button>
+
+                    <span class="media-source-position">{position}</span>
+                </div>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Mode"}</span>
+                    <Select<SelectableMode>
+                        selected={SelectableMode(self.props.params.mode)}
+                        options={modes}
+                        on_change={self.callback(|SelectableMode(mode), params| {
+                            MediaSourceParams { mode, ..params }
+                        })}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"In (secs)"}</span>
+                    <input type="text"
+                        onchange={self.callback(seconds(|in_point, params| {
+                            MediaSourceParams { in_point, ..params }
+                        }))}
+                        value={self.props.params.in_point.map(format_seconds).unwrap_or_default()}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Out (secs)"}</span>
+                    <input type="text"
+                        onchange={self.callback(seconds(|out_point, params| {
+                            MediaSourceParams { out_point, ..params }
+                        }))}
+                        value={self.props.params.out_point.map(format_seconds).unwrap_or_default()}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Seek (secs)"}</span>
+                    <input type="text"
+                        onchange={self.callback(seconds(|position, params| {
+                            match position {
+                                Some(seek_position) => MediaSourceParams {
+                                    seek_position,
+                                    seek_seq: params.seek_seq + 1,
+                                    ..params
+                                },
+                                None => params,
+                            }
+                        }))}
+                        value={format_seconds(self.props.params.seek_position)}
+                    />
+                </label>
+            </>
+        }
+    }
+}
+
+impl MediaSource {
+    fn callback<Ev>(&self, f: impl Fn(Ev, MediaSourceParams) -> MediaSourceParams + 'static)
+        -> Callback<Ev>
+    {
+        let params = self.props.params.clone();
+
+        self.props.module.callback(move |ev| {
+            WindowMsg::UpdateParams(
+                ModuleParams::MediaSource(f(ev, params.clone())))
+        })
+    }
+}
+
+// empty or unparseable input is None:
+fn seconds<T>(f: impl Fn(Option<Microseconds>, MediaSourceParams) -> T)
+    -> impl Fn(ChangeData, MediaSourceParams) -> T
+{
+    move |change, params| {
+        if let ChangeData::Value(value) = change {
+            let micros = value.trim().parse::<f64>().ok()
+                .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                .map(|secs| Microseconds((secs * 1_000_000.0) as u64));
+
+            f(micros, params)
+        } else {
+            unreachable!()
         }
     }
 }
 
+fn format_seconds(time: Microseconds) -> String {
+    format!("{:.3}", time.0 as f64 / 1_000_000.0)
+}
+
+fn format_time(time: Microseconds) -> String {
+    let tenths = time.0 / 100_000;
+    format!("{}:{:02}.{}", tenths / 600, (tenths / 10) % 60, tenths % 10)
+}
+
+#[derive(PartialEq, Clone)]
+struct SelectableMode(PlaybackMode);
+
+impl Display for SelectableMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let SelectableMode(mode) = self;
+        let name = match mode {
+            PlaybackMode::Loop => "Loop",
+            PlaybackMode::OneShot => "One-shot",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 #[derive(Clone)]
 pub struct MediaSourceItem {
     id: MediaId,
@@ -8,6 +8,7 @@ pub mod monitor;
 pub mod oscillator;
 pub mod output_device;
 pub mod plotter;
+pub mod recorder;
 pub mod stream_input;
 pub mod stream_output;
 pub mod trigger;
@@ -0,0 +1,219 @@
+use std::fmt::{self, Display};
+
+use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties, Callback};
+use yew::events::ChangeData;
+use yew_components::Select;
+
+use mixlab_protocol::{ModuleId, ModuleParams, RecorderParams, RecorderIndication, RecordingTarget, Microseconds};
+
+use crate::component::encoder_settings::EncoderSettings;
+use crate::workspace::{Window, WindowMsg};
+
+#[derive(Properties, Clone, Debug)]
+pub struct RecorderProps {
+    pub id: ModuleId,
+    pub module: ComponentLink<Window>,
+    pub params: RecorderParams,
+    pub indication: RecorderIndication,
+}
+
+pub struct Recorder {
+    props: RecorderProps,
+}
+
+impl Component for Recorder {
+    type Properties = RecorderProps;
+    type Message = ();
+
+    fn create(props: Self::Properties, _: ComponentLink<Self>) -> Self {
+        Self { props }
+    }
+
+    fn update(&mut self, _msg: Self::Message) -> ShouldRender {
+        false
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        true
+    }
+
+    fn view(&self) -> Html {
+        let indication = &self.props.indication;
+        let recording = indication.recording;
+
+        let targets = vec![
+            SelectableTarget(RecordingTarget::File),
+            SelectableTarget(RecordingTarget::MediaLibrary),
+        ];
+
+        let path_label = match self.props.params.target {
+            RecordingTarget::File => "Path",
+            RecordingTarget::MediaLibrary => "Name",
+        };
+
+        html! {
+            <>
+                <div class="status-light-bar">
+                    <div class={recording_class(recording)}>{"REC"}</div>
+                    <div class={warning_class(indication.error.is_some())}>{"ERROR"}</div>
+                </div>
+
+                <div class="recorder-transport">
+                    { if recording {
+                        html! {
+                            <>
+                                <button
+                                    onclick={self.callback(|_, params| {
+                                        RecorderParams { stop_seq: params.seq, ..params }
+                                    })}
+                                >
+                                    {"Stop"}
+                                </button>
+                                <button
+                                    onclick={self.callback(|_, params| {
+                                        RecorderParams { split_seq: params.seq, ..params }
+                                    })}
+                                >
+                                    {"Split"}
+                                </button>
+                            </>
+                        }
+                    } else {
+                        html! {
+                            <button
+                                onclick={self.callback(|_, params| {
+                                    RecorderParams { start_seq: params.seq, ..params }
+                                })}
+                            >
+                                {"Record"}
+                            </button>
+                        }
+                    } }
+                </div>
+
+                { if let Some(file) = &indication.file {
+                    html! {
+                        <div class="recorder-file">
+                            <div class="recorder-file-path">{&file.name}</div>
+                            <div class="recorder-file-stats">
+                                {format!("{} - {}", format_duration(file.duration), format_bytes(file.bytes_written))}
+                            </div>
+                        </div>
+                    }
+                } else {
+                    html! {}
+                } }
+
+                { if let Some(error) = &indication.error {
+                    html! { <div class="recorder-error">{error}</div> }
+                } else {
+                    html! {}
+                } }
+
+                { if indication.dropped_ticks > 0 {
+                    html! {
+                        <div class="recorder-error">
+                            {format!("Encoder lagging, {} ticks padded with silence", indication.dropped_ticks)}
+                        </div>
+                    }
+                } else {
+                    html! {}
+                } }
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Record To"}</span>
+                    <Select<SelectableTarget>
+                        disabled={recording}
+                        selected={SelectableTarget(self.props.params.target)}
+                        options={targets}
+                        on_change={self.callback(|SelectableTarget(target), params| {
+                            RecorderParams { target, ..params }
+                        })}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{path_label}</span>
+                    <input type="text"
+                        disabled={recording}
+                        onchange={self.callback(|change, params| {
+                            if let ChangeData::Value(path) = change {
+                                RecorderParams { path, ..params }
+                            } else {
+                                unreachable!()
+                            }
+                        })}
+                        value={&self.props.params.path}
+                    />
+                </label>
+
+                <EncoderSettings
+                    settings={self.props.params.encoder.clone()}
+                    disabled={recording}
+                    onchange={self.callback(|encoder, params| {
+                        RecorderParams { encoder, ..params }
+                    })}
+                />
+            </>
+        }
+    }
+}
+
+impl Recorder {
+    fn callback<Ev>(&self, f: impl Fn(Ev, RecorderParams) -> RecorderParams + 'static)
+        -> Callback<Ev>
+    {
+        let params = self.props.params.clone();
+
+        self.props.module.callback(move |ev| {
+            let updated_params = f(ev, {
+                let mut params = params.clone();
+                params.seq += 1;
+                params
+            });
+
+            WindowMsg::UpdateParams(
+                ModuleParams::Recorder(updated_params))
+        })
+    }
+}
+
+fn format_duration(duration: Microseconds) -> String {
+    let secs = duration.0 / 1_000_000;
+    let tenths = (duration.0 / 100_000) % 10;
+    format!("{}:{:02}:{:02}.{}", secs / 3600, (secs / 60) % 60, secs % 60, tenths)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    format!("{:.1} MiB", mb)
+}
+
+#[derive(PartialEq, Clone)]
+struct SelectableTarget(RecordingTarget);
+
+impl Display for SelectableTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let SelectableTarget(target) = self;
+        let name = match target {
+            RecordingTarget::File => "File",
+            RecordingTarget::MediaLibrary => "Media Library",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn recording_class(recording: bool) -> &'static str {
+    match recording {
+        false => "status-light",
+        true => "status-light status-light-red-active",
+    }
+}
+
+fn warning_class(is_warning: bool) -> &'static str {
+    match is_warning {
+        false => "status-light",
+        true => "status-light status-light-red-active",
+    }
+}
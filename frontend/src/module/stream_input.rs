@@ -1,12 +1,15 @@
 use std::fmt::{self, Display};
+use std::rc::Rc;
 
 use derive_more::{From, Into};
 use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties, Callback};
 use yew_components::Select;
 use yew::events::ChangeData;
 
-use mixlab_protocol::{ModuleId, ModuleParams, StreamInputParams, StreamProtocol};
+use mixlab_protocol::{ModuleId, ModuleParams, StreamInputParams, StreamProtocol, LiveSource};
 
+use crate::session::SessionRef;
+use crate::util::notify;
 use crate::workspace::{Window, WindowMsg};
 
 #[derive(Properties, Clone, Debug)]
@@ -14,22 +17,40 @@ pub struct StreamInputProps {
     pub id: ModuleId,
     pub module: ComponentLink<Window>,
     pub params: StreamInputParams,
+    pub session: SessionRef,
 }
 
 pub struct StreamInput {
     props: StreamInputProps,
+    live_sources: Rc<Vec<LiveSource>>,
+    _notify: notify::Handle,
+}
+
+pub enum StreamInputMsg {
+    LiveSources(Rc<Vec<LiveSource>>),
 }
 
 impl Component for StreamInput {
     type Properties = StreamInputProps;
-    type Message = ();
+    type Message = StreamInputMsg;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let notify = props.session.listen_live_sources(link.callback(StreamInputMsg::LiveSources));
 
-    fn create(props: Self::Properties, _: ComponentLink<Self>) -> Self {
-        Self { props }
+        Self {
+            props,
+            live_sources: Rc::new(Vec::new()),
+            _notify: notify,
+        }
     }
 
-    fn update(&mut self, _msg: Self::Message) -> ShouldRender {
-        false
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            StreamInputMsg::LiveSources(sources) => {
+                self.live_sources = sources;
+                true
+            }
+        }
     }
 
     fn change(&mut self, props: Self::Properties) -> ShouldRender {
@@ -38,8 +59,35 @@ impl Component for StreamInput {
     }
 
     fn view(&self) -> Html {
+        let sources = self.live_sources.iter()
+            .cloned()
+            .map(LiveSourceItem)
+            .collect::<Vec<_>>();
+
+        let selected = sources.iter()
+            .find(|LiveSourceItem(source)| {
+                Some(source.protocol) == self.props.params.protocol &&
+                    Some(&source.channel_name) == self.props.params.mountpoint.as_ref()
+            })
+            .cloned();
+
         html! {
             <>
+                <label class="form-field">
+                    <span class="form-field-label">{"Live Sources"}</span>
+                    <Select<LiveSourceItem>
+                        selected={selected}
+                        options={sources}
+                        on_change={self.callback(move |LiveSourceItem(source), params| {
+                            StreamInputParams {
+                                protocol: Some(source.protocol),
+                                mountpoint: Some(source.channel_name),
+                                ..params
+                            }
+                        })}
+                    />
+                </label>
+
                 <label class="form-field">
                     <span class="form-field-label">{"Protocol"}</span>
                     <Select<DisplayProtocol>
@@ -66,6 +114,32 @@ impl Component for StreamInput {
                         value={self.props.params.mountpoint.as_ref().map(String::as_str).unwrap_or("")}
                     />
                 </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Secret"}</span>
+                    // the server never sends the secret back, only whether
+                    // one is set:
+                    <input type="password"
+                        onchange={self.callback(text(move |secret, params| {
+                            StreamInputParams {
+                                secret: secret.map(str::to_owned),
+                                has_secret: secret.is_some(),
+                                ..params
+                            }
+                        }))}
+                        placeholder={if self.props.params.has_secret { "(unchanged)" } else { "" }}
+                        value=""
+                    />
+                </label>
+
+                <button
+                    disabled={!self.props.params.has_secret}
+                    onclick={self.callback(move |_, params| {
+                        StreamInputParams { secret: None, has_secret: false, ..params }
+                    })}
+                >
+                    {"Clear Secret"}
+                </button>
             </>
         }
     }
@@ -112,3 +186,39 @@ impl Display for DisplayProtocol {
         }
     }
 }
+
+#[derive(Clone)]
+pub struct LiveSourceItem(LiveSource);
+
+impl PartialEq for LiveSourceItem {
+    fn eq(&self, other: &LiveSourceItem) -> bool {
+        self.0.protocol == other.0.protocol &&
+            self.0.channel_name == other.0.channel_name
+    }
+}
+
+impl Display for LiveSourceItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let source = &self.0;
+
+        write!(f, "{} ({})", source.channel_name, DisplayProtocol(source.protocol))?;
+
+        if !source.connected {
+            return write!(f, " - offline");
+        }
+
+        if let Some(codec) = &source.codec {
+            write!(f, " - {}", codec)?;
+        }
+
+        if let Some((width, height)) = source.resolution {
+            write!(f, ", {}x{}", width, height)?;
+        }
+
+        if let Some(bitrate) = source.bitrate_kbps {
+            write!(f, ", {} kbps", bitrate)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,8 +1,9 @@
 use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties, Callback};
 use yew::events::ChangeData;
 
-use mixlab_protocol::{ModuleId, ModuleParams, StreamOutputParams, StreamOutputLiveStatus, StreamOutputIndication};
+use mixlab_protocol::{ModuleId, ModuleParams, StreamOutputParams, StreamOutputLiveStatus, StreamOutputIndication, StreamDestination};
 
+use crate::component::encoder_settings::EncoderSettings;
 use crate::workspace::{Window, WindowMsg};
 
 #[derive(Properties, Clone, Debug)]
@@ -37,7 +38,9 @@ impl Component for StreamOutput {
     fn view(&self) -> Html {
         let is_conn_active = match self.props.indication.live {
             StreamOutputLiveStatus::Offline => false,
-            StreamOutputLiveStatus::Connecting | StreamOutputLiveStatus::Live => true,
+            StreamOutputLiveStatus::Connecting |
+            StreamOutputLiveStatus::Reconnecting { .. } |
+            StreamOutputLiveStatus::Live => true,
         };
 
         html! {
@@ -69,31 +72,138 @@ impl Component for StreamOutput {
                     }
                 } }
 
+                { for self.props.params.destinations.iter().enumerate().map(|(index, destination)| {
+                    self.view_destination(index, destination, is_conn_active)
+                }) }
+
+                <button
+                    disabled={is_conn_active}
+                    onclick={self.callback(move |_, mut params| {
+                        params.destinations.push(StreamDestination::default());
+                        params
+                    })}
+                >
+                    {"Add Destination"}
+                </button>
+
                 <label class="form-field">
-                    <span class="form-field-label">{"RTMP URL"}</span>
+                    <span class="form-field-label">{"Max Reconnect Attempts"}</span>
                     <input type="text"
-                        onchange={self.callback(text(move |rtmp_url, params| {
-                            StreamOutputParams { rtmp_url, ..params }
+                        disabled={is_conn_active}
+                        onchange={self.callback(number(|max_attempts, mut params| {
+                            params.reconnect.max_attempts = max_attempts;
+                            params
                         }))}
-                        value={&self.props.params.rtmp_url}
+                        value={self.props.params.reconnect.max_attempts}
                     />
                 </label>
 
                 <label class="form-field">
-                    <span class="form-field-label">{"Stream Key"}</span>
+                    <span class="form-field-label">{"Max Reconnect Delay (secs)"}</span>
                     <input type="text"
-                        onchange={self.callback(text(move |rtmp_stream_key, params| {
-                            StreamOutputParams { rtmp_stream_key, ..params }
+                        disabled={is_conn_active}
+                        onchange={self.callback(number(|max_delay_secs, mut params| {
+                            params.reconnect.max_delay_secs = max_delay_secs;
+                            params
                         }))}
-                        value={&self.props.params.rtmp_stream_key}
+                        value={self.props.params.reconnect.max_delay_secs}
                     />
                 </label>
+
+                { self.view_encoder(is_conn_active) }
             </>
         }
     }
 }
 
 impl StreamOutput {
+    fn view_encoder(&self, is_conn_active: bool) -> Html {
+        html! {
+            <div class="stream-output-encoder">
+                <EncoderSettings
+                    settings={self.props.params.encoder.clone()}
+                    disabled={is_conn_active}
+                    onchange={self.callback(|encoder, params| {
+                        StreamOutputParams { encoder, ..params }
+                    })}
+                />
+
+                { if let Some(error) = &self.props.indication.encoder_error {
+                    html! { <div class="stream-output-error">{error}</div> }
+                } else {
+                    html! {}
+                } }
+            </div>
+        }
+    }
+
+    fn view_destination(&self, index: usize, destination: &StreamDestination, is_conn_active: bool) -> Html {
+        let indication = self.props.indication.destinations.get(index);
+
+        let live = indication.map(|ind| ind.live)
+            .unwrap_or(StreamOutputLiveStatus::Offline);
+
+        let error = indication.and_then(|ind| ind.error.clone());
+
+        html! {
+            <div class="stream-output-destination">
+                <div class="status-light-bar">
+                    <div class={live_class(live)}>{"LIVE"}</div>
+                    <div class={warning_class(error.is_some())}>{"ERROR"}</div>
+                    <button
+                        disabled={is_conn_active}
+                        onclick={self.callback(move |_, mut params| {
+                            params.destinations.remove(index);
+                            params
+                        })}
+                    >
+                        {"Remove"}
+                    </button>
+                </div>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"RTMP URL"}</span>
+                    <input type="text"
+                        disabled={is_conn_active}
+                        onchange={self.callback(text(move |rtmp_url, mut params| {
+                            params.destinations[index].rtmp_url = rtmp_url;
+                            params
+                        }))}
+                        value={&destination.rtmp_url}
+                    />
+                </label>
+
+                <label class="form-field">
+                    <span class="form-field-label">{"Stream Key"}</span>
+                    <input type="text"
+                        disabled={is_conn_active}
+                        onchange={self.callback(text(move |rtmp_stream_key, mut params| {
+                            params.destinations[index].rtmp_stream_key = rtmp_stream_key;
+                            params
+                        }))}
+                        value={&destination.rtmp_stream_key}
+                    />
+                </label>
+
+                { if let StreamOutputLiveStatus::Reconnecting { attempt } = live {
+                    html! {
+                        <div class="stream-output-reconnecting">
+                            {format!("Reconnecting, attempt {} of {}", attempt, self.props.params.reconnect.max_attempts)}
+                        </div>
+                    }
+                } else {
+                    html! {}
+                } }
+
+                { if let Some(error) = error {
+                    html! { <div class="stream-output-error">{error}</div> }
+                } else {
+                    html! {}
+                } }
+            </div>
+        }
+    }
+
     fn callback<Ev>(&self, f: impl Fn(Ev, StreamOutputParams) -> StreamOutputParams + 'static)
         -> Callback<Ev>
     {
@@ -124,10 +234,27 @@ fn text<T>(f: impl Fn(String, StreamOutputParams) -> T)
     }
 }
 
+// unparseable input leaves params unchanged:
+fn number(f: impl Fn(usize, StreamOutputParams) -> StreamOutputParams)
+    -> impl Fn(ChangeData, StreamOutputParams) -> StreamOutputParams
+{
+    move |change, params| {
+        if let ChangeData::Value(value) = change {
+            match value.trim().parse() {
+                Ok(number) => f(number, params),
+                Err(_) => params,
+            }
+        } else {
+            unreachable!()
+        }
+    }
+}
+
 fn live_class(live_status: StreamOutputLiveStatus) -> &'static str {
     match live_status {
         StreamOutputLiveStatus::Offline => "status-light",
         StreamOutputLiveStatus::Connecting => "status-light status-light-green",
+        StreamOutputLiveStatus::Reconnecting { .. } => "status-light status-light-red",
         StreamOutputLiveStatus::Live => "status-light status-light-green-active",
     }
 }
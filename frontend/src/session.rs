@@ -1,12 +1,12 @@
 use std::cell::RefCell;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
 
 use yew::services::websocket::{WebSocketService, WebSocketStatus, WebSocketTask};
 use yew::format::Binary;
 use yew::Callback;
 
-use mixlab_protocol::{ServerMessage, ServerUpdate, ClientMessage, ClientSequence, ModuleId, ModuleParams, WindowGeometry, InputId, OutputId, Indication, Terminal, WorkspaceOp, WorkspaceMessage};
+use mixlab_protocol::{ServerMessage, ServerUpdate, ClientMessage, ClientSequence, ModuleId, ModuleParams, ParamsVersion, Rejection, WindowGeometry, InputId, OutputId, Indication, Terminal, Modulation, WorkspaceOp, WorkspaceMessage, MediaOp, SnapshotOp};
 
 use crate::util;
 use crate::util::notify::{self, Notify};
@@ -31,6 +31,11 @@ struct Notifiers {
     workspace: Notify<()>,
     performance: Notify<Rc<mixlab_protocol::PerformanceInfo>>,
     media: Notify<Rc<mixlab_protocol::MediaLibrary>>,
+    media_search: Notify<Rc<mixlab_protocol::MediaSearchResults>>,
+    media_errors: Notify<Rc<mixlab_protocol::MediaOpError>>,
+    live_sources: Notify<Rc<Vec<mixlab_protocol::LiveSource>>>,
+    snapshots: Notify<Rc<Vec<mixlab_protocol::SnapshotInfo>>>,
+    snapshot_errors: Notify<Rc<mixlab_protocol::SnapshotOpError>>,
 }
 
 pub type SessionRef = Rc<Session>;
@@ -48,6 +53,11 @@ impl Session {
                 workspace: Notify::new(),
                 performance: Notify::new(),
                 media: Notify::new(),
+                media_search: Notify::new(),
+                media_errors: Notify::new(),
+                live_sources: Notify::new(),
+                snapshots: Notify::new(),
+                snapshot_errors: Notify::new(),
             },
         });
 
@@ -113,6 +123,27 @@ impl Session {
                 *self.state.borrow_mut() = Some(Rc::new(RefCell::new(state.into())));
                 self.notify.workspace.broadcast(());
             }
+            ServerMessage::Rejected(seq, rejection) => {
+                crate::log!("op {:?} rejected: {:?}", seq, rejection);
+
+                let state = self.state.borrow().as_ref().cloned()
+                    .expect("PROTOCOL VIOLATION: received Rejected before WorkspaceState");
+
+                let mut state = state.borrow_mut();
+
+                match rejection {
+                    // our change was optimistically applied to local state,
+                    // take the server's params in its place. this is rendered
+                    // along with everything else once the Sync for the op
+                    // arrives:
+                    Rejection::StaleParams(id, server_params, version) => {
+                        if let Some(params) = state.modules.get_mut(&id) {
+                            *params = server_params;
+                            state.param_versions.insert(id, version);
+                        }
+                    }
+                }
+            }
             ServerMessage::Sync(seq) => {
                 self.sync(seq);
 
@@ -131,16 +162,19 @@ impl Session {
                     let mut state = state.borrow_mut();
 
                     match op {
-                        ServerUpdate::CreateModule { id, params, geometry, indication, inputs, outputs } => {
+                        ServerUpdate::CreateModule { id, params, geometry, indication, inputs, outputs, modulation } => {
                             state.modules.insert(id, params);
+                            state.param_versions.remove(&id);
                             state.geometry.insert(id, geometry);
                             state.indications.insert(id, indication);
                             state.inputs.insert(id, inputs);
                             state.outputs.insert(id, outputs);
+                            state.modulation.insert(id, modulation);
                         }
-                        ServerUpdate::UpdateModuleParams(id, new_params) => {
+                        ServerUpdate::UpdateModuleParams(id, new_params, version) => {
                             if let Some(params) = state.modules.get_mut(&id) {
                                 *params = new_params;
+                                state.param_versions.insert(id, version);
                             }
                         }
                         ServerUpdate::UpdateWindowGeometry(id, new_geometry) => {
@@ -148,6 +182,12 @@ impl Session {
                                 *geometry = new_geometry;
                             }
                         }
+                        ServerUpdate::UpdateModulation(id, modulation, inputs) => {
+                            if state.modules.contains_key(&id) {
+                                state.modulation.insert(id, modulation);
+                                state.inputs.insert(id, inputs);
+                            }
+                        }
                         ServerUpdate::UpdateModuleIndication(id, new_indication) => {
                             if let Some(indication) = state.indications.get_mut(&id) {
                                 *indication = new_indication;
@@ -155,10 +195,12 @@ impl Session {
                         }
                         ServerUpdate::DeleteModule(id) => {
                             state.modules.remove(&id);
+                            state.param_versions.remove(&id);
                             state.geometry.remove(&id);
                             state.indications.remove(&id);
                             state.inputs.remove(&id);
                             state.outputs.remove(&id);
+                            state.modulation.remove(&id);
                         }
                         ServerUpdate::CreateConnection(input, output) => {
                             state.connections.insert(input, output);
@@ -166,6 +208,9 @@ impl Session {
                         ServerUpdate::DeleteConnection(input) => {
                             state.connections.remove(&input);
                         }
+                        ServerUpdate::UpdateFeedback(feedback) => {
+                            state.feedback = feedback.into_iter().collect();
+                        }
                     }
                 }
 
@@ -183,6 +228,21 @@ impl Session {
                 crate::log!("Receiving media library!");
                 self.notify.media.broadcast(Rc::new(library));
             }
+            ServerMessage::MediaSearch(results) => {
+                self.notify.media_search.broadcast(Rc::new(results));
+            }
+            ServerMessage::MediaOpFailed(error) => {
+                self.notify.media_errors.broadcast(Rc::new(error));
+            }
+            ServerMessage::LiveSources(sources) => {
+                self.notify.live_sources.broadcast(Rc::new(sources));
+            }
+            ServerMessage::Snapshots(snapshots) => {
+                self.notify.snapshots.broadcast(Rc::new(snapshots));
+            }
+            ServerMessage::SnapshotOpFailed(error) => {
+                self.notify.snapshot_errors.broadcast(Rc::new(error));
+            }
         }
     }
 
@@ -203,6 +263,14 @@ impl Session {
         self.send_message(msg);
     }
 
+    pub fn update_media(&self, op: MediaOp) {
+        self.send_message(ClientMessage::Media(op));
+    }
+
+    pub fn update_snapshot(&self, op: SnapshotOp) {
+        self.send_message(ClientMessage::Snapshot(op));
+    }
+
     pub fn listen_performance(&self, callback: Callback<Rc<mixlab_protocol::PerformanceInfo>>) -> notify::Handle {
         self.notify.performance.subscribe(callback)
     }
@@ -211,6 +279,26 @@ impl Session {
         self.notify.media.subscribe(callback)
     }
 
+    pub fn listen_media_search(&self, callback: Callback<Rc<mixlab_protocol::MediaSearchResults>>) -> notify::Handle {
+        self.notify.media_search.subscribe(callback)
+    }
+
+    pub fn listen_media_errors(&self, callback: Callback<Rc<mixlab_protocol::MediaOpError>>) -> notify::Handle {
+        self.notify.media_errors.subscribe(callback)
+    }
+
+    pub fn listen_live_sources(&self, callback: Callback<Rc<Vec<mixlab_protocol::LiveSource>>>) -> notify::Handle {
+        self.notify.live_sources.subscribe(callback)
+    }
+
+    pub fn listen_snapshots(&self, callback: Callback<Rc<Vec<mixlab_protocol::SnapshotInfo>>>) -> notify::Handle {
+        self.notify.snapshots.subscribe(callback)
+    }
+
+    pub fn listen_snapshot_errors(&self, callback: Callback<Rc<mixlab_protocol::SnapshotOpError>>) -> notify::Handle {
+        self.notify.snapshot_errors.subscribe(callback)
+    }
+
     fn send_message(&self, msg: ClientMessage) {
         let packet = bincode::serialize(&msg)
             .expect("bincode::serialize");
@@ -228,22 +316,31 @@ pub type WorkspaceStateRef = Rc<RefCell<WorkspaceState>>;
 pub struct WorkspaceState {
     // modules uses BTreeMap for consistent iteration order:
     pub modules: BTreeMap<ModuleId, ModuleParams>,
+    // version of the params last received from the server for each module,
+    // which is sent back with our own updates:
+    pub param_versions: HashMap<ModuleId, ParamsVersion>,
     pub geometry: HashMap<ModuleId, WindowGeometry>,
     pub connections: HashMap<InputId, OutputId>,
+    pub feedback: HashSet<InputId>,
     pub indications: HashMap<ModuleId, Indication>,
     pub inputs: HashMap<ModuleId, Vec<Terminal>>,
     pub outputs: HashMap<ModuleId, Vec<Terminal>>,
+    // modules without modulation may not have an entry:
+    pub modulation: HashMap<ModuleId, Vec<Modulation>>,
 }
 
 impl From<mixlab_protocol::WorkspaceState> for WorkspaceState {
     fn from(wstate: mixlab_protocol::WorkspaceState) -> WorkspaceState {
         WorkspaceState {
             modules: wstate.modules.into_iter().collect(),
+            param_versions: wstate.param_versions.into_iter().collect(),
             geometry: wstate.geometry.into_iter().collect(),
             indications: wstate.indications.into_iter().collect(),
             connections: wstate.connections.into_iter().collect(),
+            feedback: wstate.feedback.into_iter().collect(),
             inputs: wstate.inputs.into_iter().collect(),
             outputs: wstate.outputs.into_iter().collect(),
+            modulation: wstate.modulation.into_iter().collect(),
         }
     }
 }
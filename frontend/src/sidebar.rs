@@ -5,6 +5,7 @@ use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties};
 use mixlab_protocol::{PerformanceInfo, PerformanceAccount, TemporalWarningStatus, ModuleId};
 
 use crate::session::{SessionRef, WorkspaceStateRef};
+use crate::snapshots::Snapshots;
 use crate::util::notify;
 
 pub struct Sidebar {
@@ -56,6 +57,7 @@ impl Component for Sidebar {
             <div class="sidebar">
                 <div class="sidebar-title">{"Mixlab"}</div>
                 {self.view_perf_info()}
+                <Snapshots session={self.props.session.clone()} />
             </div>
         }
     }
@@ -88,11 +90,9 @@ impl Sidebar {
 
             let tick_budget = perf_info.tick_budget.0 as f64;
 
-            let total_tick_time: u64 = perf_info.accounts.iter()
-                .map(|(_, metric)| metric.last.0)
-                .sum();
-
-            let total_tick_percent = (total_tick_time as f64 / tick_budget) * 100.0;
+            // modules run in parallel, so the accounts can add up to more
+            // than the tick actually took:
+            let total_tick_percent = (perf_info.tick_time.0 as f64 / tick_budget) * 100.0;
 
             let mut sorted_accounts = perf_info.accounts.clone();
             sorted_accounts.sort_by(|(_, a), (_, b)| b.last.cmp(&a.last));
@@ -126,6 +126,18 @@ impl Sidebar {
                             }
                         }) }
                     </table>
+                    <table class="perf-info-accounts-table perf-info-workers-table">
+                        { for perf_info.workers.iter().enumerate().map(|(index, metric)| {
+                            let percent = (metric.last.0 as f64 / tick_budget) * 100.0;
+
+                            html! {
+                                <tr>
+                                    <td class="perf-info-account perf-info-account-worker">{format!("Worker {}", index + 1)}</td>
+                                    <td class="perf-info-metric">{format!("{:2.1}%", percent)}</td>
+                                </tr>
+                            }
+                        }) }
+                    </table>
                 </div>
             }
         } else {
@@ -0,0 +1,170 @@
+use std::rc::Rc;
+
+use yew::events::InputData;
+use yew::{html, Component, ComponentLink, Html, ShouldRender, Properties};
+
+use mixlab_protocol::{Microseconds, SnapshotId, SnapshotInfo, SnapshotKind, SnapshotOp, SnapshotOpError};
+
+use crate::session::SessionRef;
+use crate::util::notify;
+
+pub struct Snapshots {
+    link: ComponentLink<Self>,
+    session: SessionRef,
+    snapshots: Option<Rc<Vec<SnapshotInfo>>>,
+    name: String,
+    // in seconds, as typed:
+    crossfade: String,
+    error: Option<String>,
+    _notify: notify::Handle,
+    _error_notify: notify::Handle,
+}
+
+#[derive(Properties, Clone, Debug)]
+pub struct SnapshotsProps {
+    pub session: SessionRef,
+}
+
+pub enum SnapshotsMsg {
+    Update(Rc<Vec<SnapshotInfo>>),
+    Error(Rc<SnapshotOpError>),
+    Name(String),
+    Crossfade(String),
+    Save(SnapshotKind),
+    Recall(SnapshotId),
+    Delete(SnapshotId),
+}
+
+impl Component for Snapshots {
+    type Properties = SnapshotsProps;
+    type Message = SnapshotsMsg;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let notify = props.session.listen_snapshots(link.callback(SnapshotsMsg::Update));
+        let error_notify = props.session.listen_snapshot_errors(link.callback(SnapshotsMsg::Error));
+
+        Snapshots {
+            link,
+            session: props.session,
+            snapshots: None,
+            name: String::new(),
+            crossfade: String::new(),
+            error: None,
+            _notify: notify,
+            _error_notify: error_notify,
+        }
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            SnapshotsMsg::Update(snapshots) => {
+                self.snapshots = Some(snapshots);
+                true
+            }
+            SnapshotsMsg::Error(error) => {
+                self.error = Some(match &*error {
+                    SnapshotOpError::NotFound(_) => "Snapshot no longer exists".to_owned(),
+                    SnapshotOpError::InvalidName => "Snapshot name cannot be blank".to_owned(),
+                });
+                true
+            }
+            SnapshotsMsg::Name(name) => {
+                self.name = name;
+                false
+            }
+            SnapshotsMsg::Crossfade(crossfade) => {
+                self.crossfade = crossfade;
+                false
+            }
+            SnapshotsMsg::Save(kind) => {
+                self.error = None;
+                self.session.update_snapshot(SnapshotOp::Save(self.name.clone(), kind));
+                self.name.clear();
+                true
+            }
+            SnapshotsMsg::Recall(id) => {
+                // blank or invalid crossfade times recall immediately:
+                let crossfade = self.crossfade.trim().parse::<f64>().ok()
+                    .filter(|secs| *secs > 0.0)
+                    .map(|secs| Microseconds((secs * 1_000_000.0) as u64));
+
+                self.error = None;
+                self.session.update_snapshot(SnapshotOp::Recall(id, crossfade));
+                true
+            }
+            SnapshotsMsg::Delete(id) => {
+                self.error = None;
+                self.session.update_snapshot(SnapshotOp::Delete(id));
+                true
+            }
+        }
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <div class="snapshots">
+                <div class="snapshots-heading">{"Snapshots"}</div>
+                <input type="text"
+                    class="snapshots-name"
+                    placeholder="Name"
+                    value={&self.name}
+                    oninput={self.link.callback(|ev: InputData| SnapshotsMsg::Name(ev.value))}
+                />
+                <div class="snapshots-button-row">
+                    <button onclick={self.link.callback(|_| SnapshotsMsg::Save(SnapshotKind::Workspace))}>
+                        {"Save patch"}
+                    </button>
+                    <button onclick={self.link.callback(|_| SnapshotsMsg::Save(SnapshotKind::Scene))}>
+                        {"Save scene"}
+                    </button>
+                </div>
+                <label class="snapshots-crossfade">
+                    {"Scene crossfade (s)"}
+                    <input type="number"
+                        min="0"
+                        step="0.1"
+                        value={&self.crossfade}
+                        oninput={self.link.callback(|ev: InputData| SnapshotsMsg::Crossfade(ev.value))}
+                    />
+                </label>
+                { if let Some(error) = &self.error {
+                    html! { <div class="snapshots-error">{error}</div> }
+                } else {
+                    html! {}
+                } }
+                <table class="snapshots-table">
+                    { for self.snapshots.iter().flat_map(|snapshots| snapshots.iter()).map(|snapshot| {
+                        let id = snapshot.id;
+
+                        let kind = match snapshot.kind {
+                            SnapshotKind::Workspace => "Patch",
+                            SnapshotKind::Scene => "Scene",
+                        };
+
+                        html! {
+                            <tr>
+                                <td class="snapshots-recall"
+                                    title="Recall"
+                                    onclick={self.link.callback(move |_| SnapshotsMsg::Recall(id))}
+                                >
+                                    {&snapshot.name}
+                                    <div class="snapshots-kind">{kind}</div>
+                                </td>
+                                <td class="snapshots-delete"
+                                    title="Delete"
+                                    onclick={self.link.callback(move |_| SnapshotsMsg::Delete(id))}
+                                >
+                                    {"×"}
+                                </td>
+                            </tr>
+                        }
+                    }) }
+                </table>
+            </div>
+        }
+    }
+}
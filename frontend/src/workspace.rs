@@ -1,11 +1,13 @@
 use std::collections::{BTreeMap, HashSet};
 use std::mem;
 
-use wasm_bindgen::JsCast;
-use web_sys::{CanvasRenderingContext2d, HtmlElement, HtmlCanvasElement, MouseEvent, Element};
+use gloo_events::EventListener;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{CanvasRenderingContext2d, HtmlElement, HtmlCanvasElement, KeyboardEvent, MouseEvent, Element};
 use yew::{html, Callback, Component, ComponentLink, Html, ShouldRender, Properties, NodeRef};
+use yew::events::ChangeData;
 
-use mixlab_protocol::{ModuleId, TerminalId, InputId, OutputId, ModuleParams, OscillatorParams, Waveform, WorkspaceOp, WindowGeometry, Coords, Indication, OutputDeviceParams, FmSineParams, AmplifierParams, GateState, LineType, EnvelopeParams, MixerParams, StreamInputParams, EqThreeParams, StreamOutputParams, VideoMixerParams, MediaSourceParams};
+use mixlab_protocol::{ModuleId, TerminalId, InputId, OutputId, ModuleParams, OscillatorParams, Waveform, WorkspaceOp, WindowGeometry, Coords, Indication, OutputDeviceParams, FmSineParams, AmplifierParams, GateState, LineType, EnvelopeParams, MixerParams, StreamInputParams, EqThreeParams, StreamOutputParams, VideoMixerParams, MediaSourceParams, RecorderParams, Modulation};
 
 use crate::component::midi_target::MidiUiMode;
 use crate::module::amplifier::Amplifier;
@@ -18,6 +20,7 @@ use crate::module::monitor::Monitor;
 use crate::module::oscillator::Oscillator;
 use crate::module::output_device::OutputDevice;
 use crate::module::plotter::Plotter;
+use crate::module::recorder::Recorder;
 use crate::module::stream_input::StreamInput;
 use crate::module::stream_output::StreamOutput;
 use crate::module::trigger::Trigger;
@@ -33,6 +36,7 @@ pub struct Workspace {
     gen_z_index: Sequence,
     mouse: MouseMode,
     window_refs: BTreeMap<ModuleId, WindowRef>,
+    _keydown: EventListener,
 }
 
 #[derive(Properties, Clone)]
@@ -65,7 +69,10 @@ pub enum WorkspaceMsg {
     ClearTerminal(TerminalId),
     DeleteWindow(ModuleId),
     UpdateModuleParams(ModuleId, ModuleParams),
+    UpdateModulation(ModuleId, Vec<Modulation>),
     CreateModule(ModuleParams, Coords),
+    Undo,
+    Redo,
 }
 
 impl Component for Workspace {
@@ -73,6 +80,20 @@ impl Component for Workspace {
     type Properties = WorkspaceProps;
 
     fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let window = web_sys::window().expect("web_sys::window");
+
+        let keydown = EventListener::new(&window, "keydown", {
+            let link = link.clone();
+            move |ev| {
+                if let Some(ev) = ev.dyn_ref::<KeyboardEvent>() {
+                    if let Some(msg) = history_shortcut(ev) {
+                        ev.prevent_default();
+                        link.send_message(msg);
+                    }
+                }
+            }
+        });
+
         let mut workspace = Workspace {
             link,
             props,
@@ -80,6 +101,7 @@ impl Component for Workspace {
             gen_z_index: Sequence::new(),
             mouse: MouseMode::Normal,
             window_refs: BTreeMap::new(),
+            _keydown: keydown,
         };
 
         workspace.update_state();
@@ -274,9 +296,13 @@ impl Component for Workspace {
                     if mem::discriminant(&*module_params) == mem::discriminant(&params) {
                         *module_params = params.clone();
 
+                        let version = state.param_versions.get(&module)
+                            .copied()
+                            .unwrap_or_default();
+
                         self.props.app.send_message(
                             AppMsg::ClientUpdate(
-                                WorkspaceOp::UpdateModuleParams(module, params)));
+                                WorkspaceOp::UpdateModuleParams(module, params, version)));
 
                         true
                     } else {
@@ -286,6 +312,23 @@ impl Component for Workspace {
                     false
                 }
             }
+            WorkspaceMsg::UpdateModulation(module, modulation) => {
+                let mut state = self.props.state.borrow_mut();
+
+                if state.modules.contains_key(&module) {
+                    // inputs change along with modulation, so the window is
+                    // re-rendered once the server sends them back:
+                    state.modulation.insert(module, modulation.clone());
+
+                    self.props.app.send_message(
+                        AppMsg::ClientUpdate(
+                            WorkspaceOp::UpdateModulation(module, modulation)));
+
+                    true
+                } else {
+                    false
+                }
+            }
             WorkspaceMsg::CreateModule(module, coords) => {
                 self.mouse = MouseMode::Normal;
 
@@ -300,6 +343,16 @@ impl Component for Workspace {
 
                 true
             }
+            WorkspaceMsg::Undo => {
+                self.mouse = MouseMode::Normal;
+                self.props.app.send_message(AppMsg::ClientUpdate(WorkspaceOp::Undo));
+                true
+            }
+            WorkspaceMsg::Redo => {
+                self.mouse = MouseMode::Normal;
+                self.props.app.send_message(AppMsg::ClientUpdate(WorkspaceOp::Redo));
+                true
+            }
         };
 
         fn drag_event(state: &mut WorkspaceState, window_refs: &BTreeMap<ModuleId, WindowRef>, drag: &mut Drag, ev: MouseEvent) -> ShouldRender {
@@ -328,12 +381,14 @@ impl Component for Workspace {
     }
 
     fn view(&self) -> Html {
-        let mut connections: Vec<(Coords, Coords)> = vec![];
+        let mut connections: Vec<(Coords, Coords, bool)> = vec![];
+
+        let state = self.props.state.borrow();
 
-        for (input, output) in &self.props.state.borrow().connections {
+        for (input, output) in &state.connections {
             if let Some(input_coords) = self.screen_coords_for_terminal(TerminalId::Input(*input)) {
                 if let Some(output_coords) = self.screen_coords_for_terminal(TerminalId::Output(*output)) {
-                    connections.push((output_coords, input_coords));
+                    connections.push((output_coords, input_coords, state.feedback.contains(input)));
                 }
             }
         }
@@ -341,8 +396,8 @@ impl Component for Workspace {
         if let MouseMode::Connect(terminal_id, _, Some(to_coords)) = &self.mouse {
             if let Some(start_coords) = self.screen_coords_for_terminal(*terminal_id) {
                 let pair = match terminal_id {
-                    TerminalId::Input(_) => (*to_coords, start_coords),
-                    TerminalId::Output(_) => (start_coords, *to_coords),
+                    TerminalId::Input(_) => (*to_coords, start_coords, false),
+                    TerminalId::Output(_) => (start_coords, *to_coords, false),
                 };
 
                 connections.push(pair);
@@ -366,6 +421,7 @@ impl Component for Workspace {
                     let geometry = state.geometry.get(id);
                     let workspace = self.link.clone();
                     let indication = state.indications.get(id);
+                    let modulation = state.modulation.get(id).cloned().unwrap_or_default();
 
                     if let (Some(module), Some(geometry)) = (module, geometry) {
                         let name = format!("{:?}", module).chars().take_while(|c| c.is_alphanumeric()).collect::<String>();
@@ -377,6 +433,7 @@ impl Component for Workspace {
                             workspace={workspace}
                             geometry={geometry}
                             indication={indication.cloned()}
+                            modulation={modulation}
                             session={self.props.session.clone()}
                         /> }
                     } else {
@@ -408,7 +465,13 @@ impl Workspace {
 
         for id in state.modules.keys() {
             if deleted_windows.remove(id) {
-                // cool, nothing changes with this module
+                // the module already has a window, but its inputs change
+                // along with its modulation:
+                if let (Some(refs), Some(inputs)) = (self.window_refs.get_mut(id), state.inputs.get(id)) {
+                    if !same_terminals(&refs.inputs, inputs) {
+                        refs.inputs = make_terminal_refs(inputs, TerminalType::Input);
+                    }
+                }
             } else {
                 // this module was not present before, create a window ref for it
                 let inputs = state.inputs.get(id);
@@ -422,22 +485,29 @@ impl Workspace {
                     };
 
                     self.window_refs.insert(*id, refs);
-
-                    fn make_terminal_refs(terminals: &[mixlab_protocol::Terminal], terminal_type: TerminalType) -> Vec<TerminalRef> {
-                        terminals.iter()
-                            .cloned()
-                            .map(|terminal| TerminalRef {
-                                node: NodeRef::default(),
-                                label: terminal.label().map(String::from),
-                                line_type: terminal.line_type(),
-                                terminal_type,
-                            })
-                            .collect()
-                    }
                 }
             }
         }
 
+        fn make_terminal_refs(terminals: &[mixlab_protocol::Terminal], terminal_type: TerminalType) -> Vec<TerminalRef> {
+            terminals.iter()
+                .cloned()
+                .map(|terminal| TerminalRef {
+                    node: NodeRef::default(),
+                    label: terminal.label().map(String::from),
+                    line_type: terminal.line_type(),
+                    terminal_type,
+                })
+                .collect()
+        }
+
+        fn same_terminals(refs: &[TerminalRef], terminals: &[mixlab_protocol::Terminal]) -> bool {
+            refs.len() == terminals.len() && refs.iter().zip(terminals).all(|(terminal_ref, terminal)| {
+                terminal_ref.label.as_ref().map(String::as_str) == terminal.label()
+                    && terminal_ref.line_type == terminal.line_type()
+            })
+        }
+
         for deleted_window in deleted_windows {
             self.window_refs.remove(&deleted_window);
         }
@@ -484,6 +554,7 @@ impl Workspace {
             ("Monitor", ModuleParams::Monitor(())),
             ("Video Mixer", ModuleParams::VideoMixer(VideoMixerParams::default())),
             ("Media Source", ModuleParams::MediaSource(MediaSourceParams::default())),
+            ("Recorder", ModuleParams::Recorder(RecorderParams::default())),
         ];
 
         html! {
@@ -491,6 +562,17 @@ impl Workspace {
                 style={format!("left:{}px; top:{}px;", coords.x, coords.y)}
                 onmousedown={stop_propagation()}
             >
+                <div class="context-menu-heading">{"Edit"}</div>
+                <div class="context-menu-item"
+                    onmousedown={self.link.callback(|_| WorkspaceMsg::Undo)}
+                >
+                    {"Undo"}
+                </div>
+                <div class="context-menu-item"
+                    onmousedown={self.link.callback(|_| WorkspaceMsg::Redo)}
+                >
+                    {"Redo"}
+                </div>
                 <div class="context-menu-heading">{"Add module"}</div>
                 { for items.iter().map(|(label, params)| {
                     let params = params.clone();
@@ -509,10 +591,38 @@ impl Workspace {
     }
 }
 
+// ctrl+z (cmd+z on mac) undoes, and ctrl+shift+z or ctrl+y redoes. text
+// fields keep their own undo:
+fn history_shortcut(ev: &KeyboardEvent) -> Option<WorkspaceMsg> {
+    if !(ev.ctrl_key() || ev.meta_key()) {
+        return None;
+    }
+
+    let in_text_field = ev.target()
+        .and_then(|target| target.dyn_into::<Element>().ok())
+        .map(|element| {
+            let tag = element.tag_name();
+            tag == "INPUT" || tag == "TEXTAREA"
+        })
+        .unwrap_or(false);
+
+    if in_text_field {
+        return None;
+    }
+
+    match ev.key().as_str() {
+        "z" | "Z" if ev.shift_key() => Some(WorkspaceMsg::Redo),
+        "z" | "Z" => Some(WorkspaceMsg::Undo),
+        "y" | "Y" => Some(WorkspaceMsg::Redo),
+        _ => None,
+    }
+}
+
 pub struct Window {
     link: ComponentLink<Self>,
     props: WindowProps,
     midi_mode: MidiUiMode,
+    show_modulation: bool,
 }
 
 pub enum WindowMsg {
@@ -520,7 +630,9 @@ pub enum WindowMsg {
     TerminalMouseDown(MouseEvent, TerminalId, TerminalRef),
     Delete,
     UpdateParams(ModuleParams),
+    UpdateModulation(Vec<Modulation>),
     SetMidiMode(MidiUiMode),
+    ToggleModulation,
 }
 
 #[derive(Properties, Clone, Debug)]
@@ -532,6 +644,7 @@ pub struct WindowProps {
     pub workspace: ComponentLink<Workspace>,
     pub refs: WindowRef,
     pub indication: Option<Indication>,
+    pub modulation: Vec<Modulation>,
     pub session: SessionRef,
 }
 
@@ -574,6 +687,7 @@ impl Component for Window {
             link,
             props,
             midi_mode: MidiUiMode::Normal,
+            show_modulation: false,
         }
     }
 
@@ -610,10 +724,20 @@ impl Component for Window {
 
                 false
             }
+            WindowMsg::UpdateModulation(modulation) => {
+                self.props.workspace.send_message(
+                    WorkspaceMsg::UpdateModulation(self.props.id, modulation));
+
+                false
+            }
             WindowMsg::SetMidiMode(new_midi_mode) => {
                 self.midi_mode = new_midi_mode;
                 true
             }
+            WindowMsg::ToggleModulation => {
+                self.show_modulation = !self.show_modulation;
+                true
+            }
         }
     }
 
@@ -643,6 +767,7 @@ impl Component for Window {
                         {&self.props.name}
                     </div>
                     {self.view_custom_title_buttons()}
+                    {self.view_modulation_button()}
                     <div class="module-window-title-button module-window-title-delete" onmousedown={self.link.callback(|_| WindowMsg::Delete)}>
                         {"×"}
                     </div>
@@ -653,6 +778,7 @@ impl Component for Window {
                     </div>
                     <div class="module-window-params">
                         {self.view_params()}
+                        {self.view_modulation()}
                     </div>
                     <div class="module-window-outputs">
                         {self.view_outputs()}
@@ -689,6 +815,121 @@ impl Window {
             _ => html! {},
         }
     }
+    fn view_modulation_button(&self) -> Html {
+        if self.props.module.modulatable_params().is_empty() {
+            return html! {};
+        }
+
+        let class = if self.show_modulation {
+            "module-window-title-button module-window-title-midi-btn module-window-title-midi-btn-active"
+        } else {
+            "module-window-title-button module-window-title-midi-btn"
+        };
+
+        html! {
+            <div class={class} onmousedown={self.link.callback(|_| WindowMsg::ToggleModulation)}>
+                {"CV"}
+            </div>
+        }
+    }
+
+    fn view_modulation(&self) -> Html {
+        if !self.show_modulation {
+            return html! {};
+        }
+
+        let modulation = &self.props.modulation;
+
+        html! {
+            <div class="module-window-modulation">
+                { for self.props.module.modulatable_params().into_iter().enumerate().map(|(index, param)| {
+                    let slot = modulation.iter().position(|m| m.param == index);
+
+                    // toggling adds modulation over the param's whole range,
+                    // or removes it along with its control input:
+                    let toggled = match slot {
+                        Some(slot) => {
+                            let mut modulation = modulation.clone();
+                            modulation.remove(slot);
+                            modulation
+                        }
+                        None => {
+                            let mut modulation = modulation.clone();
+                            modulation.push(param.modulation(index));
+                            modulation
+                        }
+                    };
+
+                    html! {
+                        <div class="module-window-modulation-param">
+                            <label>
+                                <input type="checkbox"
+                                    checked={slot.is_some()}
+                                    onclick={self.link.callback(move |_| WindowMsg::UpdateModulation(toggled.clone()))}
+                                />
+                                {&param.name}
+                            </label>
+                            { match slot {
+                                Some(slot) => self.view_modulation_controls(slot),
+                                None => html! {},
+                            } }
+                        </div>
+                    }
+                }) }
+            </div>
+        }
+    }
+
+    fn view_modulation_controls(&self, slot: usize) -> Html {
+        let modulation = &self.props.modulation;
+        let current = &modulation[slot];
+
+        let update = |change: fn(&mut Modulation, f64)| {
+            let modulation = modulation.clone();
+
+            self.link.callback(move |ev| {
+                let mut modulation = modulation.clone();
+
+                if let ChangeData::Value(value_str) = ev {
+                    if let Ok(value) = value_str.parse() {
+                        change(&mut modulation[slot], value);
+                    }
+                }
+
+                WindowMsg::UpdateModulation(modulation)
+            })
+        };
+
+        html! {
+            <>
+                <label>
+                    <div>{"Depth"}</div>
+                    <input type="range"
+                        min={0}
+                        max={1}
+                        step={0.01}
+                        onchange={update(|m, depth| m.depth = depth)}
+                        value={current.depth}
+                    />
+                </label>
+                <label>
+                    <div>{"Min"}</div>
+                    <input type="number"
+                        onchange={update(|m, min| m.min = min)}
+                        value={current.min}
+                    />
+                </label>
+                <label>
+                    <div>{"Max"}</div>
+                    <input type="number"
+                        onchange={update(|m, max| m.max = max)}
+                        value={current.max}
+                    />
+                </label>
+            </>
+        }
+    }
+
     fn view_inputs(&self) -> Html {
         self.view_terminals(
             self.props.refs.inputs.iter()
@@ -762,7 +1003,7 @@ impl Window {
                 html! { <Mixer id={self.props.id} module={self.link.clone()} params={params} midi_mode={self.midi_mode} /> }
             }
             ModuleParams::StreamInput(params) => {
-                html! { <StreamInput id={self.props.id} module={self.link.clone()} params={params} /> }
+                html! { <StreamInput id={self.props.id} module={self.link.clone()} params={params} session={self.props.session.clone()} /> }
             }
             ModuleParams::StreamOutput(params) => {
                 if let Some(Indication::StreamOutput(indication)) = &self.props.indication {
@@ -785,7 +1026,18 @@ impl Window {
                 html! { <VideoMixer id={self.props.id} module={self.link.clone()} params={params} midi_mode={self.midi_mode} /> }
             }
             ModuleParams::MediaSource(params) => {
-                html! { <MediaSource id={self.props.id} module={self.link.clone()} params={params} session={self.props.session.clone()} /> }
+                if let Some(Indication::MediaSource(indication)) = &self.props.indication {
+                    html! { <MediaSource id={self.props.id} module={self.link.clone()} params={params} indication={indication} session={self.props.session.clone()} /> }
+                } else {
+                    unreachable!()
+                }
+            }
+            ModuleParams::Recorder(params) => {
+                if let Some(Indication::Recorder(indication)) = &self.props.indication {
+                    html! { <Recorder id={self.props.id} module={self.link.clone()} params={params} indication={indication} /> }
+                } else {
+                    unreachable!()
+                }
             }
         }
     }
@@ -855,6 +1107,9 @@ impl Component for Terminal {
     }
 }
 
+const LINE_COLOR: &str = "#000000";
+const FEEDBACK_LINE_COLOR: &str = "#c08020";
+
 pub struct Connections {
     canvas: NodeRef,
     props: ConnectionsProps,
@@ -862,7 +1117,8 @@ pub struct Connections {
 
 #[derive(Properties, Clone, PartialEq, Eq)]
 pub struct ConnectionsProps {
-    connections: Vec<(Coords, Coords)>,
+    // start, end, and whether the connection closes a feedback loop:
+    connections: Vec<(Coords, Coords, bool)>,
 }
 
 impl Component for Connections {
@@ -912,12 +1168,12 @@ impl Component for Connections {
 
             // plan multi-segment lines for all connections
             let lines = self.props.connections.iter()
-                .map(|(a, b)| plan_line_points(*a, *b))
+                .map(|(a, b, feedback)| (plan_line_points(*a, *b), *feedback))
                 .collect::<Vec<_>>();
 
             // calculate required canvas size for all points
             let Coords { x: width, y: height } = lines.iter()
-                .flat_map(|segments| segments)
+                .flat_map(|(segments, _)| segments)
                 .fold(Coords { x: 0, y: 0 }, |area, point| {
                     Coords {
                         x: max(area.x, point.x),
@@ -931,7 +1187,11 @@ impl Component for Connections {
             // draw lines
             ctx.clear_rect(0f64, 0f64, width as f64, height as f64);
 
-            for points in lines {
+            for (points, feedback) in lines {
+                // feedback connections are a tick behind, draw them apart:
+                let color = if feedback { FEEDBACK_LINE_COLOR } else { LINE_COLOR };
+                ctx.set_stroke_style(&JsValue::from_str(color));
+
                 ctx.begin_path();
 
                 ctx.move_to(points[0].x as f64, points[0].y as f64);
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::ffi::CString;
 
 use bytes::{Bytes, BytesMut};
+use bytes::BufMut;
 use bytes::buf::BufMutExt;
 use mse_fmp4::aac::{AacProfile, SamplingFrequency, ChannelConfiguration};
 use mse_fmp4::fmp4::{
@@ -21,6 +22,22 @@ pub struct Mp4Mux {
     video_time: MediaTime,
 }
 
+/// Collects random access points while writing a fragmented MP4 file, and
+/// produces the 'mfra' box which goes at the very end of the file so that
+/// players can seek without scanning every fragment
+#[derive(Debug, Default)]
+pub struct Mp4Index {
+    audio: Vec<IndexEntry>,
+    video: Vec<IndexEntry>,
+}
+
+#[derive(Debug)]
+struct IndexEntry {
+    // in mux timescale:
+    time: u64,
+    moof_offset: u64,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct AdtsFrame(pub Bytes);
 
@@ -40,6 +57,9 @@ pub enum TrackData {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Mp4Params<'a> {
     pub timescale: u32,
+    // of the audio track. must be one of the sampling frequencies AAC
+    // supports, see `sampling_frequency`:
+    pub sample_rate: u32,
     pub width: u32,
     pub height: u32,
     pub dcr: Cow<'a, [u8]>,
@@ -64,6 +84,90 @@ impl Mp4Mux {
 
         to_bytes(media)
     }
+
+    /// Like `write_track`, also recording the fragment in `index` if it is
+    /// a random access point. `offset` is the position in the file at which
+    /// the returned bytes will be written
+    pub fn write_track_indexed(&mut self, duration: MediaDuration, data: &TrackData, offset: u64, index: &mut Mp4Index) -> Bytes {
+        let timescale = i64::from(self.timescale);
+
+        match data {
+            TrackData::Audio(_) => {
+                // every audio frame is a sync sample, one entry a second
+                // is plenty though:
+                let time = self.audio_time.round_to_base(timescale) as u64;
+
+                let due = index.audio.last()
+                    .map(|last| time >= last.time + u64::from(self.timescale))
+                    .unwrap_or(true);
+
+                if due {
+                    index.audio.push(IndexEntry { time, moof_offset: offset });
+                }
+            }
+            TrackData::Video(frame) => {
+                if frame.is_key_frame {
+                    let time = self.video_time.round_to_base(timescale) as u64;
+                    index.video.push(IndexEntry { time, moof_offset: offset });
+                }
+            }
+        }
+
+        self.write_track(duration, data)
+    }
+}
+
+impl Mp4Index {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serializes the index as an 'mfra' box, see ISO/IEC 14496-12 8.8.9
+    pub fn finish(&self) -> Bytes {
+        let mut body = BytesMut::new();
+        write_tfra(&mut body, AUDIO_TRACK, &self.audio);
+        write_tfra(&mut body, VIDEO_TRACK, &self.video);
+
+        // mfro box, which carries the size of the enclosing mfra box so that
+        // readers can find it by seeking from the end of the file:
+        let mfro_size = 16;
+        let mfra_size = 8 + body.len() as u32 + mfro_size;
+
+        let mut mfra = BytesMut::with_capacity(mfra_size as usize);
+        mfra.put_u32(mfra_size);
+        mfra.put_slice(b"mfra");
+        mfra.put_slice(&body);
+        mfra.put_u32(mfro_size);
+        mfra.put_slice(b"mfro");
+        mfra.put_u32(0); // version and flags
+        mfra.put_u32(mfra_size);
+
+        mfra.freeze()
+    }
+}
+
+fn write_tfra(out: &mut BytesMut, track_id: u32, entries: &[IndexEntry]) {
+    // version 1 for 64 bit time and offset. traf, trun and sample numbers
+    // are all one byte wide, and always 1 since we write one sample per
+    // fragment:
+    let entry_size = 8 + 8 + 1 + 1 + 1;
+    let size = 8 + 4 + 4 + 4 + 4 + entries.len() * entry_size;
+
+    out.put_u32(size as u32);
+    out.put_slice(b"tfra");
+    out.put_u8(1); // version
+    out.put_slice(&[0, 0, 0]); // flags
+    out.put_u32(track_id);
+    out.put_u32(0); // reserved, and length_size_of_traf/trun/sample_num = 0
+    out.put_u32(entries.len() as u32);
+
+    for entry in entries {
+        out.put_u64(entry.time);
+        out.put_u64(entry.moof_offset);
+        out.put_u8(1); // traf_number
+        out.put_u8(1); // trun_number
+        out.put_u8(1); // sample_number
+    }
 }
 
 fn to_bytes(segment: impl WriteTo) -> Bytes {
@@ -134,9 +238,8 @@ fn make_init_segment(
                                     sample_entries: vec![
                                         SampleEntry::Aac(AacSampleEntry {
                                             esds_box: Mpeg4EsDescriptorBox {
-                                                // TODO set these from ADTS header - or are they always constant?
                                                 profile: AacProfile::Lc,
-                                                frequency: SamplingFrequency::Hz44100,
+                                                frequency: sampling_frequency(params.sample_rate),
                                                 channel_configuration: ChannelConfiguration::TwoChannels,
                                             },
                                         }),
@@ -222,6 +325,26 @@ fn make_init_segment(
     }
 }
 
+/// Panics if `sample_rate` is not a sampling frequency AAC supports
+fn sampling_frequency(sample_rate: u32) -> SamplingFrequency {
+    match sample_rate {
+        96000 => SamplingFrequency::Hz96000,
+        88200 => SamplingFrequency::Hz88200,
+        64000 => SamplingFrequency::Hz64000,
+        48000 => SamplingFrequency::Hz48000,
+        44100 => SamplingFrequency::Hz44100,
+        32000 => SamplingFrequency::Hz32000,
+        24000 => SamplingFrequency::Hz24000,
+        22050 => SamplingFrequency::Hz22050,
+        16000 => SamplingFrequency::Hz16000,
+        12000 => SamplingFrequency::Hz12000,
+        11025 => SamplingFrequency::Hz11025,
+        8000 => SamplingFrequency::Hz8000,
+        7350 => SamplingFrequency::Hz7350,
+        _ => panic!("unsupported AAC sample rate: {}", sample_rate),
+    }
+}
+
 fn make_media_segment(
     mux: &mut Mp4Mux,
     duration: MediaDuration,
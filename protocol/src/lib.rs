@@ -15,18 +15,33 @@ pub enum ServerMessage<'a> {
     WorkspaceState(WorkspaceState),
     Update(ServerUpdate),
     Sync(ClientSequence),
+    // sent to a client in place of the updates an op of its would have
+    // caused, ahead of the Sync for that op:
+    Rejected(ClientSequence, Rejection),
     Performance(Cow<'a, PerformanceInfo>),
     MediaLibrary(MediaLibrary),
+    MediaSearch(MediaSearchResults),
+    MediaOpFailed(MediaOpError),
+    LiveSources(Vec<LiveSource>),
+    Snapshots(Vec<SnapshotInfo>),
+    SnapshotOpFailed(SnapshotOpError),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WorkspaceState {
     pub modules: Vec<(ModuleId, ModuleParams)>,
+    // modules which are not listed are at the default version:
+    pub param_versions: Vec<(ModuleId, ParamsVersion)>,
     pub geometry: Vec<(ModuleId, WindowGeometry)>,
     pub indications: Vec<(ModuleId, Indication)>,
     pub connections: Vec<(InputId, OutputId)>,
+    // connections which close a cycle in the graph. these carry the signal
+    // from the previous tick rather than the current one:
+    pub feedback: Vec<InputId>,
     pub inputs: Vec<(ModuleId, Vec<Terminal>)>,
     pub outputs: Vec<(ModuleId, Vec<Terminal>)>,
+    // modules which are not listed have no modulation:
+    pub modulation: Vec<(ModuleId, Vec<Modulation>)>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -35,7 +50,11 @@ pub struct PerformanceInfo {
     pub lag: Option<TemporalWarningStatus>,
     pub tick_rate: usize,
     pub tick_budget: Microseconds,
+    // wall clock time the last tick took:
+    pub tick_time: Microseconds,
     pub accounts: Vec<(PerformanceAccount, PerformanceMetric)>,
+    // time each engine worker thread spent running modules in the last tick:
+    pub workers: Vec<PerformanceMetric>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
@@ -55,7 +74,7 @@ pub struct PerformanceMetric {
     pub last: Microseconds,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Default)]
 pub struct Microseconds(pub u64);
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -63,7 +82,7 @@ pub struct MediaLibrary {
     pub items: Vec<MediaItem>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MediaId(pub i64);
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -72,11 +91,91 @@ pub struct MediaItem {
     pub name: String,
     pub kind: String,
     pub size: usize,
+    pub tags: Vec<String>,
+    // None if the media has not been probed yet, or could not be:
+    pub info: Option<MediaInfo>,
+    // whether previews are available from /_media/<id>/thumbnail and
+    // /_media/<id>/waveform. previews are generated in the background, so
+    // may become available some time after the media is added:
+    pub thumbnail: bool,
+    pub waveform: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MediaInfo {
+    pub format: String,
+    pub duration: Option<Microseconds>,
+    pub streams: Vec<MediaStreamInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MediaStreamInfo {
+    pub kind: MediaStreamKind,
+    pub codec: String,
+    // video only:
+    pub resolution: Option<(u32, u32)>,
+    pub frame_rate: Option<f64>,
+    // audio only:
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaStreamKind {
+    Video,
+    Audio,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MediaSearchResults {
+    pub query: String,
+    // matching items, in library order:
+    pub items: Vec<MediaId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum MediaOpError {
+    NotFound(MediaId),
+    // media is loaded into a media source and cannot be deleted:
+    InUse(MediaId),
+    InvalidName,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SnapshotId(pub i64);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotKind {
+    // the whole patch - modules, their params, window geometry and
+    // connections. recalling replaces the workspace:
+    Workspace,
+    // only the params of modules with numeric params, such as mixers and
+    // EQ. recalling updates whichever of the modules still exist, and leaves
+    // everything else alone:
+    Scene,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotInfo {
+    pub id: SnapshotId,
+    pub name: String,
+    pub kind: SnapshotKind,
+    // unix time:
+    pub created_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SnapshotOpError {
+    NotFound(SnapshotId),
+    InvalidName,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientMessage {
     Workspace(WorkspaceMessage),
+    Media(MediaOp),
+    Snapshot(SnapshotOp),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -88,18 +187,69 @@ pub struct WorkspaceMessage {
     pub op: WorkspaceOp,
 }
 
+// the params of every module are versioned, with a new version each time they
+// change. clients send the version they last saw along with their own changes
+// so that changes made concurrently by someone else can be detected:
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ParamsVersion(pub u64);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Rejection {
+    // params were changed by someone else since the version an update was
+    // based on. carries the params and version now current, which the client
+    // should take in place of its own change:
+    StaleParams(ModuleId, ModuleParams, ParamsVersion),
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum WorkspaceOp {
     CreateModule(ModuleParams, WindowGeometry),
-    UpdateModuleParams(ModuleId, ModuleParams),
+    // updates are rejected if the params have been changed by another client
+    // since the version given:
+    UpdateModuleParams(ModuleId, ModuleParams, ParamsVersion),
     UpdateWindowGeometry(ModuleId, WindowGeometry),
+    // replaces the modulation of a module. control inputs of modulation which
+    // is removed or now modulates a different param are disconnected:
+    UpdateModulation(ModuleId, Vec<Modulation>),
     DeleteModule(ModuleId),
     CreateConnection(InputId, OutputId),
     DeleteConnection(InputId),
+    // undo reverts the last op sent by this session and redo reapplies the
+    // last op undone. history is kept per session, so undo never reverts
+    // changes made by other clients:
+    Undo,
+    Redo,
+}
+
+// unlike workspace ops, media ops are not sequenced. every client is sent the
+// updated library once an op has been applied:
+#[derive(Serialize, Deserialize, Debug)]
+pub enum MediaOp {
+    Rename(MediaId, String),
+    Delete(MediaId),
+    SetTags(MediaId, Vec<String>),
+    // matches items whose name or tags contain every whitespace separated
+    // term of the query. results are sent back to the searching client only:
+    Search(String),
+}
+
+// like media ops, snapshot ops are not sequenced. every client is sent the
+// updated list of snapshots when one is saved or deleted, and changes made by
+// recalling one are sent as server updates:
+#[derive(Serialize, Deserialize, Debug)]
+pub enum SnapshotOp {
+    // saves the current state of the workspace, replacing any snapshot of
+    // the same name:
+    Save(String, SnapshotKind),
+    // numeric params of a scene may be crossfaded to over the duration given.
+    // everything else changes at once:
+    Recall(SnapshotId, Option<Microseconds>),
+    Delete(SnapshotId),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ServerUpdate {
+    // new modules start at the default params version:
     CreateModule {
         id: ModuleId,
         params: ModuleParams,
@@ -107,13 +257,20 @@ pub enum ServerUpdate {
         indication: Indication,
         inputs: Vec<Terminal>,
         outputs: Vec<Terminal>,
+        modulation: Vec<Modulation>,
     },
-    UpdateModuleParams(ModuleId, ModuleParams),
+    UpdateModuleParams(ModuleId, ModuleParams, ParamsVersion),
     UpdateWindowGeometry(ModuleId, WindowGeometry),
+    // carries the module's inputs, which include a control input for each
+    // modulation:
+    UpdateModulation(ModuleId, Vec<Modulation>, Vec<Terminal>),
     UpdateModuleIndication(ModuleId, Indication),
     DeleteModule(ModuleId),
     CreateConnection(InputId, OutputId),
     DeleteConnection(InputId),
+    // replaces the set of feedback connections, see WorkspaceState::feedback.
+    // sent whenever it changes:
+    UpdateFeedback(Vec<InputId>),
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
@@ -202,6 +359,7 @@ pub enum ModuleParams {
     Oscillator(OscillatorParams),
     OutputDevice(OutputDeviceParams),
     Plotter(()),
+    Recorder(RecorderParams),
     StereoPanner(()),
     StereoSplitter(()),
     StreamInput(StreamInputParams),
@@ -210,18 +368,201 @@ pub enum ModuleParams {
     VideoMixer(VideoMixerParams),
 }
 
+impl ModuleParams {
+    /// Continuously variable params, such as levels, gains and frequencies,
+    /// in an order fixed by the kind of module and its shape (for example the
+    /// number of mixer channels). Params of the same kind and shape can be
+    /// interpolated between by pairing these up.
+    pub fn numeric_params_mut(&mut self) -> Vec<&mut f64> {
+        match self {
+            ModuleParams::Amplifier(params) => vec![&mut params.amplitude, &mut params.mod_depth],
+            ModuleParams::Envelope(params) => vec![
+                &mut params.attack_ms,
+                &mut params.decay_ms,
+                &mut params.sustain_amplitude,
+                &mut params.release_ms,
+            ],
+            ModuleParams::EqThree(params) => vec![
+                &mut params.gain_lo.0,
+                &mut params.gain_mid.0,
+                &mut params.gain_hi.0,
+            ],
+            ModuleParams::FmSine(params) => vec![&mut params.freq_lo, &mut params.freq_hi],
+            ModuleParams::Mixer(params) => params.channels.iter_mut()
+                .flat_map(|channel| vec![&mut channel.gain.0, &mut channel.fader])
+                .collect(),
+            ModuleParams::Oscillator(params) => vec![&mut params.freq],
+            ModuleParams::VideoMixer(params) => vec![&mut params.fader],
+            ModuleParams::MediaSource(_) |
+            ModuleParams::Monitor(()) |
+            ModuleParams::OutputDevice(_) |
+            ModuleParams::Plotter(()) |
+            ModuleParams::Recorder(_) |
+            ModuleParams::StereoPanner(()) |
+            ModuleParams::StereoSplitter(()) |
+            ModuleParams::StreamInput(_) |
+            ModuleParams::StreamOutput(_) |
+            ModuleParams::Trigger(_) => Vec::new(),
+        }
+    }
+
+    /// Params which can be modulated by a control signal, see `Modulation`.
+    /// These are the numeric params, in the same order as
+    /// `numeric_params_mut`, described along with the range each is usually
+    /// set within.
+    pub fn modulatable_params(&self) -> Vec<ModulatableParam> {
+        match self {
+            ModuleParams::Amplifier(_) => vec![
+                ModulatableParam::new("Amplitude", 0.0, 1.0),
+                ModulatableParam::new("Mod Depth", 0.0, 1.0),
+            ],
+            ModuleParams::Envelope(_) => vec![
+                ModulatableParam::new("Attack", 5.0, 500.0),
+                ModulatableParam::new("Decay", 5.0, 1000.0),
+                ModulatableParam::new("Sustain", 0.0, 1.0),
+                ModulatableParam::new("Release", 5.0, 5000.0),
+            ],
+            ModuleParams::EqThree(_) => vec![
+                ModulatableParam::new("Lo", -24.0, 6.0),
+                ModulatableParam::new("Mid", -24.0, 6.0),
+                ModulatableParam::new("Hi", -24.0, 6.0),
+            ],
+            ModuleParams::FmSine(_) => vec![
+                ModulatableParam::new("Freq Lo", 0.0, 20000.0),
+                ModulatableParam::new("Freq Hi", 0.0, 20000.0),
+            ],
+            ModuleParams::Mixer(params) => (1..=params.channels.len())
+                .flat_map(|channel| vec![
+                    ModulatableParam::new(&format!("Gain {}", channel), -24.0, 6.0),
+                    ModulatableParam::new(&format!("Fader {}", channel), 0.0, 1.0),
+                ])
+                .collect(),
+            ModuleParams::Oscillator(_) => vec![ModulatableParam::new("Freq", 0.0, 20000.0)],
+            ModuleParams::VideoMixer(_) => vec![ModulatableParam::new("Crossfade", 0.0, 1.0)],
+            ModuleParams::MediaSource(_) |
+            ModuleParams::Monitor(()) |
+            ModuleParams::OutputDevice(_) |
+            ModuleParams::Plotter(()) |
+            ModuleParams::Recorder(_) |
+            ModuleParams::StereoPanner(()) |
+            ModuleParams::StereoSplitter(()) |
+            ModuleParams::StreamInput(_) |
+            ModuleParams::StreamOutput(_) |
+            ModuleParams::Trigger(_) => Vec::new(),
+        }
+    }
+
+    /// Params as they may be sent to clients or exported, with any secrets
+    /// left out. Whether a secret is set is kept, see `restore_secrets`
+    pub fn redacted(&self) -> ModuleParams {
+        let mut redacted = self.clone();
+
+        if let ModuleParams::StreamInput(params) = &mut redacted {
+            params.has_secret = params.secret.is_some();
+            params.secret = None;
+        }
+
+        redacted
+    }
+
+    /// Puts back secrets left out of params by `redacted`, taking them from
+    /// the module's `current` params. Used on params coming from clients,
+    /// which only ever see redacted params
+    pub fn restore_secrets(&mut self, current: Option<&ModuleParams>) {
+        if let ModuleParams::StreamInput(params) = self {
+            if params.secret.is_none() && params.has_secret {
+                params.secret = match current {
+                    Some(ModuleParams::StreamInput(current)) => current.secret.clone(),
+                    _ => None,
+                };
+            }
+
+            params.has_secret = params.secret.is_some();
+        }
+    }
+
+    /// Lowers encoder frame rates to what a project running at `tick_rate`
+    /// can produce. Used on newly created modules, whose params are made by
+    /// clients without knowing the tick rate
+    pub fn fit_tick_rate(&mut self, tick_rate: usize) {
+        match self {
+            ModuleParams::Recorder(params) => params.encoder.fit_tick_rate(tick_rate),
+            ModuleParams::StreamOutput(params) => params.encoder.fit_tick_rate(tick_rate),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModulatableParam {
+    pub name: String,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl ModulatableParam {
+    fn new(name: &str, min: f64, max: f64) -> Self {
+        ModulatableParam { name: name.to_string(), min, max }
+    }
+
+    /// Modulation of this param, the `index`th of its module, over its whole
+    /// usual range
+    pub fn modulation(&self, index: usize) -> Modulation {
+        Modulation {
+            param: index,
+            depth: 1.0,
+            min: self.min,
+            max: self.max,
+        }
+    }
+
+    pub fn control_label(&self) -> String {
+        format!("{} CV", self.name)
+    }
+}
+
+/// Patches a mono control signal, such as an envelope or a low frequency
+/// oscillator, into a numeric param of a module. Each modulation adds a
+/// control input to the module, after the module's own inputs.
+///
+/// A full scale signal moves the param `depth` times the width of `min..max`
+/// away from the value it is set to, and the result is kept within
+/// `min..max`. The signal is read once per tick.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Modulation {
+    // index into the module's modulatable params:
+    pub param: usize,
+    pub depth: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Modulation {
+    /// Value of the param when set to `value`, under a control signal `cv`
+    pub fn apply(&self, value: f64, cv: f64) -> f64 {
+        let (min, max) = if self.min <= self.max {
+            (self.min, self.max)
+        } else {
+            (self.max, self.min)
+        };
+
+        (value + cv * self.depth * (max - min)).max(min).min(max)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Indication {
     Amplifier(()),
     Envelope(()),
     EqThree(()),
     FmSine(()),
-    MediaSource(()),
+    MediaSource(MediaSourceIndication),
     Mixer(()),
     Monitor(MonitorIndication),
     Oscillator(()),
     OutputDevice(OutputDeviceIndication),
     Plotter(PlotterIndication),
+    Recorder(RecorderIndication),
     StereoPanner(()),
     StereoSplitter(()),
     StreamInput(()),
@@ -350,6 +691,14 @@ pub struct MixerChannelParams {
 pub struct StreamInputParams {
     pub protocol: Option<StreamProtocol>,
     pub mountpoint: Option<String>,
+    // when set, sources must present this secret to connect. for RTMP this
+    // is the stream key, for Icecast the basic auth password. the secret
+    // never leaves the server, see `ModuleParams::redacted`:
+    #[serde(default)]
+    pub secret: Option<String>,
+    // whether a secret is set. this is all clients get to know of it:
+    #[serde(default)]
+    pub has_secret: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
@@ -359,14 +708,88 @@ pub enum StreamProtocol {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LiveSource {
+    pub channel_name: String,
+    pub protocol: StreamProtocol,
+    // a Stream Input module is listening on this mountpoint:
+    pub listening: bool,
+    // a source client is connected and publishing to this mountpoint:
+    pub connected: bool,
+    pub codec: Option<String>,
+    pub resolution: Option<(u32, u32)>,
+    pub bitrate_kbps: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(from = "SerializedStreamOutputParams", into = "SerializedStreamOutputParams")]
 pub struct StreamOutputParams {
     // TODO this is an awful hack to encode one-time impulses into params
     // figure out a nicer way of doing this
     pub seq: u64,
     pub connect_seq: u64,
     pub disconnect_seq: u64,
-    pub rtmp_url: String,
-    pub rtmp_stream_key: String,
+    // all destinations share the one encode:
+    pub destinations: Vec<StreamDestination>,
+    pub encoder: StreamEncoderSettings,
+    pub reconnect: ReconnectPolicy,
+}
+
+// workspaces saved before stream outputs had multiple destinations have a
+// single rtmp_url and rtmp_stream_key instead, which are moved over into
+// `destinations` on load. they are always serialized as None now, but must
+// stay part of the serialized form as bincode is not self-describing:
+#[derive(Serialize, Deserialize)]
+struct SerializedStreamOutputParams {
+    seq: u64,
+    connect_seq: u64,
+    disconnect_seq: u64,
+    #[serde(default)]
+    destinations: Vec<StreamDestination>,
+    #[serde(default)]
+    encoder: StreamEncoderSettings,
+    #[serde(default)]
+    reconnect: ReconnectPolicy,
+    #[serde(default)]
+    rtmp_url: Option<String>,
+    #[serde(default)]
+    rtmp_stream_key: Option<String>,
+}
+
+impl From<SerializedStreamOutputParams> for StreamOutputParams {
+    fn from(params: SerializedStreamOutputParams) -> Self {
+        let mut destinations = params.destinations;
+
+        if destinations.is_empty() && (params.rtmp_url.is_some() || params.rtmp_stream_key.is_some()) {
+            destinations.push(StreamDestination {
+                rtmp_url: params.rtmp_url.unwrap_or_default(),
+                rtmp_stream_key: params.rtmp_stream_key.unwrap_or_default(),
+            });
+        }
+
+        StreamOutputParams {
+            seq: params.seq,
+            connect_seq: params.connect_seq,
+            disconnect_seq: params.disconnect_seq,
+            destinations,
+            encoder: params.encoder,
+            reconnect: params.reconnect,
+        }
+    }
+}
+
+impl From<StreamOutputParams> for SerializedStreamOutputParams {
+    fn from(params: StreamOutputParams) -> Self {
+        SerializedStreamOutputParams {
+            seq: params.seq,
+            connect_seq: params.connect_seq,
+            disconnect_seq: params.disconnect_seq,
+            destinations: params.destinations,
+            encoder: params.encoder,
+            reconnect: params.reconnect,
+            rtmp_url: None,
+            rtmp_stream_key: None,
+        }
+    }
 }
 
 impl Default for StreamOutputParams {
@@ -375,22 +798,215 @@ impl Default for StreamOutputParams {
             seq: 1,
             connect_seq: 0,
             disconnect_seq: 0,
-            rtmp_url: "".to_owned(),
-            rtmp_stream_key: "".to_owned(),
+            destinations: vec![StreamDestination::default()],
+            encoder: StreamEncoderSettings::default(),
+            reconnect: ReconnectPolicy::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct StreamDestination {
+    pub rtmp_url: String,
+    pub rtmp_stream_key: String,
+}
+
+// applies to destinations which drop after going live. the delay between
+// attempts doubles each time, starting from one second
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    // zero disables reconnecting:
+    pub max_attempts: usize,
+    pub max_delay_secs: usize,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 10,
+            max_delay_secs: 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct StreamEncoderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub frame_rate: usize,
+    pub video_bitrate_kbps: usize,
+    pub keyframe_interval_secs: usize,
+    pub preset: EncoderPreset,
+    pub profile: EncoderProfile,
+    pub audio_bitrate_kbps: usize,
+}
+
+impl Default for StreamEncoderSettings {
+    fn default() -> Self {
+        StreamEncoderSettings {
+            width: 1120,
+            height: 700,
+            frame_rate: 60,
+            video_bitrate_kbps: 1500,
+            keyframe_interval_secs: 1,
+            preset: EncoderPreset::Slow,
+            profile: EncoderProfile::High,
+            audio_bitrate_kbps: 160,
+        }
+    }
+}
+
+impl StreamEncoderSettings {
+    pub const MAX_WIDTH: usize = 3840;
+    pub const MAX_HEIGHT: usize = 2160;
+
+    /// Video is produced once per engine tick at most, so the frame rate can
+    /// be no higher than the tick rate of the project
+    pub fn validate(&self, tick_rate: usize) -> Result<(), String> {
+        if self.width < 16 || self.width > Self::MAX_WIDTH || self.height < 16 || self.height > Self::MAX_HEIGHT {
+            return Err(format!("Resolution must be between 16x16 and {}x{}", Self::MAX_WIDTH, Self::MAX_HEIGHT));
+        }
+
+        // chroma planes are subsampled by two in each direction:
+        if self.width % 2 != 0 || self.height % 2 != 0 {
+            return Err("Resolution must have even width and height".to_owned());
+        }
+
+        if self.frame_rate < 1 || self.frame_rate > tick_rate {
+            return Err(format!("Frame rate must be between 1 and {} fps", tick_rate));
+        }
+
+        if self.video_bitrate_kbps < 100 || self.video_bitrate_kbps > 50_000 {
+            return Err("Video bitrate must be between 100 and 50000 kbps".to_owned());
+        }
+
+        if self.keyframe_interval_secs < 1 || self.keyframe_interval_secs > 10 {
+            return Err("Keyframe interval must be between 1 and 10 seconds".to_owned());
         }
+
+        if self.audio_bitrate_kbps < 32 || self.audio_bitrate_kbps > 320 {
+            return Err("Audio bitrate must be between 32 and 320 kbps".to_owned());
+        }
+
+        Ok(())
+    }
+
+    pub fn fit_tick_rate(&mut self, tick_rate: usize) {
+        self.frame_rate = self.frame_rate.min(tick_rate);
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecorderParams {
+    // see StreamOutputParams:
+    pub seq: u64,
+    pub start_seq: u64,
+    pub stop_seq: u64,
+    pub split_seq: u64,
+    #[serde(default)]
+    pub target: RecordingTarget,
+    // resolved relative to the directory containing the project. existing
+    // files are never overwritten, a number is added to the name instead.
+    // when recording to the media library, names the library item instead:
+    pub path: String,
+    #[serde(default)]
+    pub encoder: StreamEncoderSettings,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingTarget {
+    File,
+    MediaLibrary,
+}
+
+impl Default for RecordingTarget {
+    fn default() -> Self {
+        RecordingTarget::File
+    }
+}
+
+impl Default for RecorderParams {
+    fn default() -> Self {
+        RecorderParams {
+            seq: 1,
+            start_seq: 0,
+            stop_seq: 0,
+            split_seq: 0,
+            target: RecordingTarget::File,
+            path: "recording.mp4".to_owned(),
+            encoder: StreamEncoderSettings::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecorderIndication {
+    pub recording: bool,
+    // name, duration and size of the file currently or most recently being
+    // written:
+    pub file: Option<RecordingFile>,
+    pub error: Option<String>,
+    // ticks dropped by the current or most recent recording because the
+    // encoder could not keep up. these are filled with silence and a held
+    // frame in the recorded file:
+    pub dropped_ticks: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordingFile {
+    // file path, or media library item name:
+    pub name: String,
+    pub duration: Microseconds,
+    pub bytes_written: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncoderPreset {
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncoderProfile {
+    Baseline,
+    Main,
+    High,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct StreamOutputIndication {
+    // overall status, live if any destination is live:
     pub live: StreamOutputLiveStatus,
+    // set if any destination has failed:
     pub error: bool,
+    // status of each destination, in the same order as params:
+    pub destinations: Vec<StreamDestinationIndication>,
+    // set if the encoder settings are invalid or the encoder failed to start:
+    pub encoder_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StreamDestinationIndication {
+    pub live: StreamOutputLiveStatus,
+    pub error: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StreamOutputLiveStatus {
     Offline,
     Connecting,
+    // waiting on or making reconnect attempt number `attempt`, counting
+    // from one:
+    Reconnecting { attempt: usize },
     Live,
 }
 
@@ -419,9 +1035,55 @@ impl Default for VideoMixerParams {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+// serde(default) so that workspaces saved before transport controls existed
+// continue to load:
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
 pub struct MediaSourceParams {
     pub media_id: Option<MediaId>,
+    pub playing: bool,
+    pub mode: PlaybackMode,
+    // in and out points are relative to the start of the media:
+    pub in_point: Option<Microseconds>,
+    pub out_point: Option<Microseconds>,
+    // see StreamOutputParams - seeking is a one-time impulse, a seek is
+    // performed whenever seek_seq changes:
+    pub seek_seq: u64,
+    pub seek_position: Microseconds,
+}
+
+impl Default for MediaSourceParams {
+    fn default() -> Self {
+        MediaSourceParams {
+            media_id: None,
+            playing: true,
+            mode: PlaybackMode::Loop,
+            in_point: None,
+            out_point: None,
+            seek_seq: 0,
+            seek_position: Microseconds(0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackMode {
+    Loop,
+    OneShot,
+}
+
+impl Default for PlaybackMode {
+    fn default() -> Self {
+        PlaybackMode::Loop
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MediaSourceIndication {
+    pub playhead: Option<Microseconds>,
+    pub duration: Option<Microseconds>,
+    // set once playback reaches the out point in one-shot mode:
+    pub ended: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -430,7 +1092,7 @@ pub struct Coords {
     pub y: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct WindowGeometry {
     pub position: Coords,
     pub z_index: usize,
@@ -0,0 +1,364 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use derive_more::From;
+use structopt::StructOpt;
+use tokio::task;
+
+use mixlab_codec::ffmpeg::IoReader;
+use mixlab_protocol::MediaId;
+
+use crate::db;
+use crate::engine::{Rate, RateError};
+use crate::persist;
+use crate::project::{self, OpenError};
+use crate::project::bundle::{self, BundleError};
+use crate::project::media::{self, MediaError, MediaUpload, UploadError, UploadInfo};
+use crate::project::stream::{self, ReadStream};
+
+// files are added to the library in chunks of this size:
+const ADD_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(StructOpt)]
+pub enum MediaCommand {
+    /// List media in the library, one item per line as tab separated ID,
+    /// name, format, size in bytes and tags
+    Ls {
+        workspace_path: PathBuf,
+    },
+    /// Add files to the library. Files which can't be played are rejected
+    Add {
+        workspace_path: PathBuf,
+
+        #[structopt(required = true)]
+        files: Vec<PathBuf>,
+
+        /// MIME type of the files, guessed from their extension if not given
+        #[structopt(long)]
+        kind: Option<String>,
+
+        /// Tag the added media, may be given more than once
+        #[structopt(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// Delete media from the library. The project must not be open in a
+    /// running server
+    Rm {
+        workspace_path: PathBuf,
+
+        #[structopt(required = true)]
+        ids: Vec<i64>,
+    },
+    /// Write the contents of a media item to a file
+    Export {
+        workspace_path: PathBuf,
+        id: i64,
+        output: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum WorkspaceCommand {
+    /// Print the workspace as JSON
+    Dump {
+        workspace_path: PathBuf,
+
+        /// Write to a file rather than stdout
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Replace the workspace with a previously dumped one. The project must
+    /// not be open in a running server
+    Load {
+        workspace_path: PathBuf,
+        input: PathBuf,
+    },
+    /// Export the workspace as a standalone JSON bundle
+    Export {
+        workspace_path: PathBuf,
+        output: PathBuf,
+
+        /// Include the media referred to by Media Source modules
+        #[structopt(long)]
+        with_media: bool,
+    },
+    /// Import a bundle into the workspace alongside the modules already in
+    /// it. The project must not be open in a running server
+    Import {
+        workspace_path: PathBuf,
+        bundle: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum DbCommand {
+    /// Bring the project database's schema up to date
+    Migrate {
+        workspace_path: PathBuf,
+    },
+    /// Check the project database's schema version and integrity without
+    /// modifying it. Exits with an error if there is anything to report
+    Check {
+        workspace_path: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum SettingsCommand {
+    /// Print the sample rate and tick rate the engine runs the project at
+    Show {
+        workspace_path: PathBuf,
+    },
+    /// Change the sample rate or tick rate of the engine. The project must
+    /// not be open in a running server
+    Set {
+        workspace_path: PathBuf,
+
+        /// Samples per second, eg. 44100 or 48000
+        #[structopt(long)]
+        sample_rate: Option<usize>,
+
+        /// Ticks per second, which must divide the sample rate evenly. Higher
+        /// tick rates mean lower latency at the cost of more overhead
+        #[structopt(long)]
+        tick_rate: Option<usize>,
+    },
+}
+
+#[derive(Debug, From)]
+pub enum CliError {
+    Open(OpenError),
+    Database(rusqlite::Error),
+    Upload(UploadError),
+    Media(MediaError),
+    Bundle(BundleError),
+    Io(io::Error),
+    Json(serde_json::Error),
+    Rate(RateError),
+    #[from(ignore)]
+    MediaNotFound(MediaId),
+    CheckFailed,
+}
+
+pub async fn media(command: MediaCommand) -> Result<(), CliError> {
+    match command {
+        MediaCommand::Ls { workspace_path } => {
+            let base = project::open_base(workspace_path).await?;
+
+            for item in media::library(&base).await?.items {
+                let format = item.info.as_ref()
+                    .map(|info| info.format.as_str())
+                    .unwrap_or(item.kind.as_str());
+
+                println!("{}\t{}\t{}\t{}\t{}", item.id.0, item.name, format, item.size, item.tags.join(","));
+            }
+        }
+        MediaCommand::Add { workspace_path, files, kind, tags } => {
+            let base = project::open_base(workspace_path).await?;
+
+            for path in files {
+                let media_id = add_media(&base, &path, kind.clone(), tags.clone()).await?;
+                println!("{}\t{}", media_id.0, path.display());
+            }
+        }
+        MediaCommand::Rm { workspace_path, ids } => {
+            let base = project::open_base(workspace_path).await?;
+
+            for id in ids {
+                media::delete(&base, MediaId(id)).await?;
+            }
+
+            // streams in use are only tracked within a process, so
+            // vacuuming from here could delete those of uploads and
+            // recordings in progress elsewhere. the server vacuums the
+            // streams of deleted media once it next opens the project
+        }
+        MediaCommand::Export { workspace_path, id, output } => {
+            let base = project::open_base(workspace_path).await?;
+
+            let stream = media::open(base, MediaId(id)).await?
+                .ok_or(CliError::MediaNotFound(MediaId(id)))?;
+
+            task::spawn_blocking(move || write_stream(stream, &output)).await
+                .expect("write media task")?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn workspace(command: WorkspaceCommand) -> Result<(), CliError> {
+    match command {
+        WorkspaceCommand::Dump { workspace_path, output } => {
+            let (_, workspace) = project::open_workspace(workspace_path).await?;
+
+            task::spawn_blocking(move || -> Result<(), CliError> {
+                let mut out: Box<dyn Write> = match output {
+                    Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                    None => Box::new(io::stdout()),
+                };
+
+                serde_json::to_writer_pretty(&mut out, &workspace)?;
+                writeln!(out)?;
+                Ok(out.flush()?)
+            }).await.expect("dump workspace task")?;
+        }
+        WorkspaceCommand::Load { workspace_path, input } => {
+            let workspace = task::spawn_blocking(move || -> Result<persist::Workspace, CliError> {
+                Ok(serde_json::from_reader(BufReader::new(File::open(input)?))?)
+            }).await.expect("read workspace task")?;
+
+            let base = project::open_base(workspace_path).await?;
+            project::write_workspace(&base, &workspace).await?;
+        }
+        WorkspaceCommand::Export { workspace_path, output, with_media } => {
+            bundle::export_file(workspace_path, output, with_media).await?;
+        }
+        WorkspaceCommand::Import { workspace_path, bundle } => {
+            let count = bundle::import_file(workspace_path, bundle).await?;
+            println!("imported {} modules", count);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn db(command: DbCommand) -> Result<(), CliError> {
+    match command {
+        DbCommand::Migrate { workspace_path } => {
+            let migrated = db::migrate(project::database_path(&workspace_path)).await?;
+
+            if migrated.from_version == migrated.to_version {
+                println!("schema is up to date at version {}", format_version(migrated.to_version));
+            } else {
+                println!("migrated schema from version {} to {}",
+                    format_version(migrated.from_version),
+                    format_version(migrated.to_version));
+            }
+        }
+        DbCommand::Check { workspace_path } => {
+            let check = db::check(project::database_path(&workspace_path)).await?;
+
+            println!("schema version {}", format_version(check.schema_version));
+
+            for version in &check.pending_migrations {
+                println!("pending migration {}", version);
+            }
+
+            for problem in &check.problems {
+                println!("problem: {}", problem);
+            }
+
+            if !check.pending_migrations.is_empty() || !check.problems.is_empty() {
+                return Err(CliError::CheckFailed);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn settings(command: SettingsCommand) -> Result<(), CliError> {
+    match command {
+        SettingsCommand::Show { workspace_path } => {
+            let base = project::open_base(workspace_path).await?;
+            print_rate(base.rate());
+        }
+        SettingsCommand::Set { workspace_path, sample_rate, tick_rate } => {
+            let base = project::open_base(workspace_path).await?;
+
+            let rate = Rate::new(
+                sample_rate.unwrap_or(base.rate().sample_rate()),
+                tick_rate.unwrap_or(base.rate().tick_rate()))?;
+
+            project::write_rate(&base, rate).await?;
+            print_rate(rate);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_rate(rate: Rate) {
+    println!("sample rate {} Hz", rate.sample_rate());
+    println!("tick rate {} Hz, {} samples per tick", rate.tick_rate(), rate.samples_per_tick());
+}
+
+async fn add_media(base: &project::ProjectBaseRef, path: &Path, kind: Option<String>, tags: Vec<String>) -> Result<MediaId, CliError> {
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+
+    let kind = kind.unwrap_or_else(|| guess_kind(path).to_owned());
+
+    let mut file = File::open(path)?;
+    let mut upload = MediaUpload::new(base.clone(), UploadInfo { name, kind }).await?;
+
+    loop {
+        let (returned_file, chunk) = task::spawn_blocking(move || -> Result<_, io::Error> {
+            let mut chunk = vec![0u8; ADD_CHUNK_SIZE];
+            let len = file.read(&mut chunk)?;
+            chunk.truncate(len);
+            Ok((file, chunk))
+        }).await.expect("read file task")?;
+
+        file = returned_file;
+
+        if chunk.is_empty() {
+            break;
+        }
+
+        upload.receive_bytes(&chunk).await?;
+    }
+
+    let media_id = upload.finalize().await?;
+
+    if !tags.is_empty() {
+        media::set_tags(base, media_id, tags).await?;
+    }
+
+    Ok(media_id)
+}
+
+fn write_stream(mut stream: ReadStream, path: &Path) -> Result<(), CliError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut buff = vec![0u8; <ReadStream as IoReader>::BUFFER_SIZE];
+
+    loop {
+        let len = stream.read(&mut buff)?;
+
+        if len == 0 {
+            break;
+        }
+
+        out.write_all(&buff[..len])?;
+    }
+
+    Ok(out.flush()?)
+}
+
+// browsers tell us the MIME type of uploads, but files on disk only have
+// their extension to go by
+fn guess_kind(path: &Path) -> &'static str {
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    match extension.as_deref() {
+        Some("mp4") | Some("m4v") => "video/mp4",
+        Some("mov") => "video/quicktime",
+        Some("mkv") => "video/x-matroska",
+        Some("webm") => "video/webm",
+        Some("m4a") => "audio/mp4",
+        Some("mp3") => "audio/mpeg",
+        Some("wav") => "audio/wav",
+        Some("flac") => "audio/flac",
+        Some("ogg") => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
+
+fn format_version(version: Option<i64>) -> String {
+    version.map(|version| version.to_string()).unwrap_or_else(|| "none".to_owned())
+}
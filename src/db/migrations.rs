@@ -2,4 +2,10 @@ pub static MIGRATIONS: &'static [(i64, &'static str)] = &[
     (0, include_str!("migrations/0_init.sql")),
     (20200804, include_str!("migrations/20200804_create_media_tables.sql")),
     (20200805, include_str!("migrations/20200805_create_workspace_table.sql")),
+    (20200810, include_str!("migrations/20200810_create_media_tags_table.sql")),
+    (20200812, include_str!("migrations/20200812_add_media_probe_columns.sql")),
+    (20200815, include_str!("migrations/20200815_create_media_previews_table.sql")),
+    (20200818, include_str!("migrations/20200818_create_uploads_table.sql")),
+    (20200820, include_str!("migrations/20200820_create_snapshots_table.sql")),
+    (20200822, include_str!("migrations/20200822_create_settings_table.sql")),
 ];
@@ -0,0 +1,7 @@
+CREATE TABLE media_tags (
+    media_id INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    FOREIGN KEY (media_id) REFERENCES media (id)
+);
+
+CREATE UNIQUE INDEX media_tag_idx ON media_tags (media_id, tag);
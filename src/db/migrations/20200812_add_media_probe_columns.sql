@@ -0,0 +1,18 @@
+ALTER TABLE media ADD COLUMN probed INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE media ADD COLUMN format TEXT;
+ALTER TABLE media ADD COLUMN duration_us INTEGER;
+
+CREATE TABLE media_streams (
+    media_id INTEGER NOT NULL,
+    stream_index INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    codec TEXT NOT NULL,
+    width INTEGER,
+    height INTEGER,
+    frame_rate REAL,
+    sample_rate INTEGER,
+    channels INTEGER,
+    FOREIGN KEY (media_id) REFERENCES media (id)
+);
+
+CREATE UNIQUE INDEX media_stream_idx ON media_streams (media_id, stream_index);
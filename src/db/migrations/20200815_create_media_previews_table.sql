@@ -0,0 +1,6 @@
+CREATE TABLE media_previews (
+    media_id INTEGER PRIMARY KEY NOT NULL,
+    thumbnail BLOB,
+    waveform BLOB,
+    FOREIGN KEY (media_id) REFERENCES media (id)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE uploads (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    stream_id INTEGER NOT NULL,
+    -- total length declared by the client, if known up front:
+    length INTEGER,
+    -- unix time, used to expire abandoned uploads:
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    FOREIGN KEY (stream_id) REFERENCES streams (id),
+    CONSTRAINT non_negative_length CHECK (length >= 0)
+);
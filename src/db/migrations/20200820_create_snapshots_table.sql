@@ -0,0 +1,11 @@
+CREATE TABLE snapshots (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    -- 'workspace' or 'scene':
+    kind TEXT NOT NULL,
+    -- JSON, a persist::Workspace or persist::Scene according to kind:
+    serialized TEXT NOT NULL,
+    -- unix time:
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    CONSTRAINT valid_kind CHECK (kind IN ('workspace', 'scene'))
+);
@@ -0,0 +1,5 @@
+-- a single row, absent until the defaults are first changed:
+CREATE TABLE settings (
+    sample_rate INTEGER NOT NULL,
+    tick_rate INTEGER NOT NULL
+);
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use rusqlite::{self, Connection, Row};
+use rusqlite::{self, Connection, OpenFlags, Row};
 use tokio::task;
 
 mod migrations;
@@ -26,35 +26,46 @@ fn update_schema_version(conn: &Connection, ver: i64) -> Result<(), rusqlite::Er
     Ok(())
 }
 
-fn attach_blocking(path: PathBuf) -> Result<Connection, rusqlite::Error> {
-    let mut conn = Connection::open(&path)?;
+fn pending_migrations(schema_version: Option<i64>) -> Vec<(i64, &'static str)> {
+    let mut migrations = migrations::MIGRATIONS.to_vec();
 
-    {
-        let mut txn = conn.transaction()?;
+    // migrations should already be sorted, but we should ensure it is anyway:
+    migrations.sort_by_key(|(ver, _)| *ver);
 
-        let schema_version = schema_version(&mut txn)?;
+    // retain only migrations yet to be performed on this database
+    migrations.retain(|(ver, _)| Some(*ver) > schema_version);
 
-        let mut migrations = migrations::MIGRATIONS.to_vec();
+    migrations
+}
 
-        // migrations should already be sorted, but we should ensure it is anyway:
-        migrations.sort_by_key(|(ver, _)| *ver);
+// returns the schema versions before and after migrating
+fn migrate_blocking(conn: &mut Connection) -> Result<(Option<i64>, Option<i64>), rusqlite::Error> {
+    let mut txn = conn.transaction()?;
 
-        // retain only migrations yet to be performed on this database
-        migrations.retain(|(ver, _)| Some(*ver) > schema_version);
+    let schema_version = schema_version(&mut txn)?;
 
-        // run migrations to bring database up to date
-        for (_, sql) in &migrations {
-            txn.execute_batch(sql)?;
-        }
+    let migrations = pending_migrations(schema_version);
 
-        // update database schema version if migrations were performed
-        if let Some((ver, _)) = migrations.last() {
-            update_schema_version(&mut txn, *ver)?;
-        }
+    // run migrations to bring database up to date
+    for (_, sql) in &migrations {
+        txn.execute_batch(sql)?;
+    }
 
-        txn.commit()?;
+    // update database schema version if migrations were performed
+    if let Some((ver, _)) = migrations.last() {
+        update_schema_version(&mut txn, *ver)?;
     }
 
+    txn.commit()?;
+
+    let migrated_version = migrations.last().map(|(ver, _)| *ver).or(schema_version);
+
+    Ok((schema_version, migrated_version))
+}
+
+fn attach_blocking(path: PathBuf) -> Result<Connection, rusqlite::Error> {
+    let mut conn = Connection::open(&path)?;
+    migrate_blocking(&mut conn)?;
     Ok(conn)
 }
 
@@ -62,3 +73,60 @@ pub async fn attach(path: PathBuf) -> Result<Connection, rusqlite::Error> {
     task::spawn_blocking(|| attach_blocking(path)).await
         .expect("join blocking task")
 }
+
+pub struct Migrated {
+    pub from_version: Option<i64>,
+    pub to_version: Option<i64>,
+}
+
+/// Brings a database's schema up to date, as happens whenever a project is
+/// opened, creating the database if it does not exist
+pub async fn migrate(path: PathBuf) -> Result<Migrated, rusqlite::Error> {
+    task::spawn_blocking(move || {
+        let mut conn = Connection::open(&path)?;
+        let (from_version, to_version) = migrate_blocking(&mut conn)?;
+        Ok(Migrated { from_version, to_version })
+    }).await.expect("join blocking task")
+}
+
+pub struct Check {
+    pub schema_version: Option<i64>,
+    pub pending_migrations: Vec<i64>,
+    // problems reported by SQLite's integrity and foreign key checks:
+    pub problems: Vec<String>,
+}
+
+/// Checks a database's schema version and integrity without modifying it
+pub async fn check(path: PathBuf) -> Result<Check, rusqlite::Error> {
+    task::spawn_blocking(move || {
+        let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+        let schema_version = schema_version(&conn)?;
+
+        let pending_migrations = pending_migrations(schema_version).into_iter()
+            .map(|(ver, _)| ver)
+            .collect();
+
+        let mut problems = conn.prepare("PRAGMA integrity_check")?
+            .query_map(rusqlite::NO_PARAMS, |row| row.get::<_, String>(0))?
+            .filter(|result| result.as_ref().map(|message| message != "ok").unwrap_or(true))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let foreign_key_problems = conn.prepare("PRAGMA foreign_key_check")?
+            .query_map(rusqlite::NO_PARAMS, |row| {
+                let table = row.get::<_, String>(0)?;
+                let rowid = row.get::<_, Option<i64>>(1)?;
+                let parent = row.get::<_, String>(2)?;
+
+                Ok(match rowid {
+                    Some(rowid) => format!("row {} in {} refers to a missing row in {}", rowid, table, parent),
+                    None => format!("a row in {} refers to a missing row in {}", table, parent),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        problems.extend(foreign_key_problems);
+
+        Ok(Check { schema_version, pending_migrations, problems })
+    }).await.expect("join blocking task")
+}
@@ -1,7 +1,9 @@
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::f32;
+use std::mem;
 use std::num::NonZeroUsize;
+use std::panic;
 use std::sync::Arc;
 use std::sync::mpsc::{self, SyncSender, Receiver, RecvTimeoutError, TrySendError, TryRecvError};
 use std::thread;
@@ -12,26 +14,44 @@ use futures::stream::{Stream, StreamExt};
 use tokio::runtime;
 use tokio::sync::{oneshot, broadcast, watch};
 
-use mixlab_protocol::{ModuleId, InputId, OutputId, WorkspaceState, ServerUpdate, Indication, ClientSequence, WorkspaceMessage, WorkspaceOp, PerformanceInfo};
+use mixlab_protocol::{ModuleId, ModuleParams, InputId, OutputId, WorkspaceState, ServerUpdate, Indication, ClientSequence, WorkspaceMessage, WorkspaceOp, PerformanceInfo, Rejection};
 
+use crate::persist;
 use crate::project::ProjectBaseRef;
 use crate::util::Sequence;
 
+mod conflict;
+mod fade;
+mod graph;
+mod history;
 mod io;
 mod module;
+mod modulation;
+mod offline;
+mod pool;
+mod rate;
+mod smooth;
 mod timing;
 mod workspace;
 
+use conflict::ParamVersions;
+use fade::Fade;
+use graph::Schedule;
+use history::{Edit, History};
+use pool::{Job, WorkerPool};
 use timing::{EngineStat, TickStat};
-use workspace::SyncWorkspace;
+use workspace::{SyncWorkspace, Workspace};
 
 pub use io::{InputRef, OutputRef, Output, VideoFrame};
-pub use module::{ModuleCtx, DynModuleHost};
+pub use module::{ModuleCtx, DynModuleHost, EngineMode};
+pub use offline::{OfflineEngine, OfflineTick};
+pub use rate::{Rate, RateError};
+pub use smooth::Smoothed;
 pub use workspace::WorkspaceEmbryo;
 
 pub type Sample = f32;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct SessionId(NonZeroUsize);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -50,13 +70,15 @@ impl PartialOrd for OpClock {
 }
 
 pub const CHANNELS: usize = 2;
-pub const SAMPLE_RATE: usize = 44100;
-pub const TICKS_PER_SECOND: usize = 60;
-pub const SAMPLES_PER_TICK: usize = SAMPLE_RATE / TICKS_PER_SECOND;
 
 pub enum EngineMessage {
     ConnectSession(oneshot::Sender<(SessionId, WorkspaceState, EngineEvents)>),
+    DisconnectSession(SessionId),
     Workspace(SessionId, WorkspaceMessage),
+    ExportWorkspace(oneshot::Sender<persist::Workspace>),
+    ImportWorkspace(persist::Workspace, oneshot::Sender<Vec<ModuleId>>),
+    RecallWorkspace(persist::Workspace, oneshot::Sender<()>),
+    RecallScene(persist::Scene, Option<Duration>, oneshot::Sender<()>),
 }
 
 #[derive(Clone)]
@@ -84,10 +106,17 @@ pub fn start(tokio_runtime: runtime::Handle, workspace: WorkspaceEmbryo, base: P
                 log_tx,
                 perf_tx,
                 session_seq: Sequence::new(),
+                history: HashMap::new(),
+                param_versions: ParamVersions::new(),
+                fades: Vec::new(),
+                feedback: BTreeSet::new(),
+                pool: WorkerPool::new(base.rate()),
+                tick_start: Instant::now(),
                 workspace: workspace.spawn(base.clone()),
                 base,
             };
 
+            engine.update_feedback();
             engine.run();
         });
     });
@@ -116,6 +145,7 @@ pub type EngineEvents = broadcast::Receiver<EngineEvent>;
 pub enum EngineEvent {
     Sync(OpClock),
     ServerUpdate(ServerUpdate),
+    Rejected(OpClock, Rejection),
 }
 
 impl EngineHandle {
@@ -132,6 +162,36 @@ impl EngineHandle {
         }))
     }
 
+    pub async fn export_workspace(&self) -> Result<persist::Workspace, EngineError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx.try_send(EngineMessage::ExportWorkspace(tx))?;
+        rx.await.map_err(|_| EngineError::Stopped)
+    }
+
+    /// Merges the modules of another workspace into the live workspace,
+    /// returning the IDs they were given
+    pub async fn import_workspace(&self, workspace: persist::Workspace) -> Result<Vec<ModuleId>, EngineError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx.try_send(EngineMessage::ImportWorkspace(workspace, tx))?;
+        rx.await.map_err(|_| EngineError::Stopped)
+    }
+
+    /// Replaces the live workspace with a snapshot of it. Modules which are
+    /// unchanged in the snapshot are left running.
+    pub async fn recall_workspace(&self, workspace: persist::Workspace) -> Result<(), EngineError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx.try_send(EngineMessage::RecallWorkspace(workspace, tx))?;
+        rx.await.map_err(|_| EngineError::Stopped)
+    }
+
+    /// Applies the params of a scene to the modules of the live workspace
+    /// which still exist, optionally crossfading numeric params
+    pub async fn recall_scene(&self, scene: persist::Scene, crossfade: Option<Duration>) -> Result<(), EngineError> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx.try_send(EngineMessage::RecallScene(scene, crossfade, tx))?;
+        rx.await.map_err(|_| EngineError::Stopped)
+    }
+
     pub fn performance_info(&self) -> impl Stream<Item = Arc<PerformanceInfo>> {
         self.perf_rx.clone().filter_map(|info| future::ready(info))
     }
@@ -142,7 +202,8 @@ impl EngineSession {
         self.session_id
     }
 
-    /// TODO - maybe pass log position in here and detect conflicts?
+    /// Ops which conflict with changes made by other sessions are rejected
+    /// rather than applied, see `EngineEvent::Rejected`
     pub fn update(&self, msg: WorkspaceMessage) -> Result<(), EngineError> {
         self.send_message(EngineMessage::Workspace(self.session_id, msg))
     }
@@ -152,11 +213,27 @@ impl EngineSession {
     }
 }
 
+impl Drop for EngineSession {
+    fn drop(&mut self) {
+        // if the engine is busy the session's history is kept around until
+        // the engine stops, which is harmless
+        let _ = self.send_message(EngineMessage::DisconnectSession(self.session_id));
+    }
+}
+
 pub struct Engine {
     cmd_rx: Receiver<EngineMessage>,
     log_tx: broadcast::Sender<EngineEvent>,
     perf_tx: watch::Sender<Option<Arc<PerformanceInfo>>>,
     session_seq: Sequence,
+    history: HashMap<SessionId, History>,
+    param_versions: ParamVersions,
+    fades: Vec<Fade>,
+    // feedback connections as last sent to clients:
+    feedback: BTreeSet<InputId>,
+    pool: WorkerPool,
+    // when the period of time covered by the last tick run began:
+    tick_start: Instant,
     workspace: SyncWorkspace,
     base: ProjectBaseRef,
 }
@@ -164,15 +241,16 @@ pub struct Engine {
 impl Engine {
     fn run(&mut self) {
         let start = Instant::now();
-        let mut stat = EngineStat::new();
+        let rate = self.base.rate();
+        let mut stat = EngineStat::new(rate, self.pool.size());
         let mut tick = 0;
 
         loop {
             let this_tick = tick;
             tick += 1;
 
-            // we don't simply calculate `tick * TICK_BUDGET` here to prevent loss of precision over time:
-            let scheduled_tick_end = start + Duration::from_millis((tick * 1_000) / TICKS_PER_SECOND as u64);
+            let scheduled_tick_end = start + rate.tick_start(tick);
+            self.tick_start = start + rate.tick_start(this_tick);
 
             // run tick
             let indications = stat.record_tick(scheduled_tick_end,
@@ -184,8 +262,10 @@ impl Engine {
                 self.log_op(ServerUpdate::UpdateModuleIndication(module_id, indication));
             }
 
+            self.step_fades(this_tick, &mut stat);
+
             // send out performance metrics
-            if (this_tick % (TICKS_PER_SECOND as u64 / 2)) == 0 {
+            if (this_tick % (rate.tick_rate() as u64 / 2).max(1)) == 0 {
                 let _ = self.perf_tx.broadcast(Some(Arc::new(stat.report())));
             }
 
@@ -215,14 +295,42 @@ impl Engine {
         }
     }
 
+    // where in the next tick a change made now should take effect. changes
+    // made part way through the time covered by the last tick are placed the
+    // same way through the next one, keeping the spacing between changes
+    // rather than bunching them all up on tick boundaries:
+    fn sample_offset(&self) -> usize {
+        let rate = self.base.rate();
+        let elapsed = Instant::now().saturating_duration_since(self.tick_start);
+        let offset = elapsed.as_micros() * rate.samples_per_tick() as u128
+            / rate.tick_budget().as_micros();
+
+        (offset as usize).min(rate.samples_per_tick() - 1)
+    }
+
     fn process_message(&mut self, msg: EngineMessage, stat: &mut EngineStat) {
         match msg {
             EngineMessage::ConnectSession(tx) => {
                 let _ = tx.send(self.connect_session());
             }
+            EngineMessage::DisconnectSession(session_id) => {
+                self.history.remove(&session_id);
+            }
             EngineMessage::Workspace(session, msg) => {
                 self.client_update(session, msg, stat);
             }
+            EngineMessage::ExportWorkspace(tx) => {
+                let _ = tx.send(self.workspace.borrow().to_persist());
+            }
+            EngineMessage::ImportWorkspace(workspace, tx) => {
+                let _ = tx.send(self.import_workspace(workspace));
+            }
+            EngineMessage::RecallWorkspace(workspace, tx) => {
+                let _ = tx.send(self.recall_workspace(workspace, stat));
+            }
+            EngineMessage::RecallScene(scene, crossfade, tx) => {
+                let _ = tx.send(self.recall_scene(scene, crossfade, stat));
+            }
         }
     }
 
@@ -236,19 +344,27 @@ impl Engine {
     fn dump_state(&self) -> WorkspaceState {
         let mut state = WorkspaceState {
             modules: Vec::new(),
+            param_versions: Vec::new(),
             geometry: Vec::new(),
             indications: Vec::new(),
             connections: Vec::new(),
+            feedback: self.feedback.iter().copied().collect(),
             inputs: Vec::new(),
             outputs: Vec::new(),
+            modulation: Vec::new(),
         };
 
         let workspace = self.workspace.borrow();
 
         for (module_id, module) in &workspace.modules {
             state.modules.push((*module_id, module.params()));
+            state.param_versions.push((*module_id, self.param_versions.current(*module_id)));
             state.inputs.push((*module_id, module.inputs().to_vec()));
             state.outputs.push((*module_id, module.outputs().to_vec()));
+
+            if !module.modulation().is_empty() {
+                state.modulation.push((*module_id, module.modulation().to_vec()));
+            }
         }
 
         for (module_id, geometry) in &workspace.geometry {
@@ -266,28 +382,392 @@ impl Engine {
         state
     }
 
+    fn import_workspace(&mut self, import: persist::Workspace) -> Vec<ModuleId> {
+        let mut operations = Vec::new();
+
+        let ids = {
+            let mut workspace = self.workspace.borrow_mut();
+            let modules = import.remap_modules(&mut workspace.module_seq);
+
+            for (id, saved_module) in &modules {
+                let (mut module, indication) = module::host(saved_module.params.clone(), self.base.clone(), EngineMode::Realtime);
+                module.set_modulation(saved_module.modulation.clone());
+                let inputs = module.inputs().to_vec();
+                let outputs = module.outputs().to_vec();
+                let modulation = module.modulation().to_vec();
+                workspace.modules.insert(*id, module);
+                workspace.geometry.insert(*id, saved_module.geometry.clone());
+                workspace.indications.insert(*id, indication.clone());
+
+                operations.push(ServerUpdate::CreateModule {
+                    id: *id,
+                    params: saved_module.params.clone(),
+                    geometry: saved_module.geometry.clone(),
+                    indication,
+                    inputs,
+                    outputs,
+                    modulation,
+                });
+            }
+
+            // connect after all modules exist, as with loading a workspace
+            for (id, saved_module) in &modules {
+                for (input_idx, output_id) in saved_module.inputs.iter().enumerate() {
+                    if let Some(output_id) = output_id {
+                        let input_id = InputId(*id, input_idx);
+
+                        if workspace.connect(input_id, *output_id).is_ok() {
+                            operations.push(ServerUpdate::CreateConnection(input_id, *output_id));
+                        }
+                    }
+                }
+            }
+
+            modules.into_iter().map(|(id, _)| id).collect()
+        };
+
+        for op in operations {
+            self.log_op(op);
+        }
+
+        self.update_feedback();
+
+        ids
+    }
+
+    fn recall_workspace(&mut self, snapshot: persist::Workspace, stat: &mut EngineStat) {
+        self.fades.clear();
+
+        // modules are recreated with the IDs they had when the snapshot was
+        // taken, as with undoing a delete. modules which have since been
+        // replaced by a different kind of module are recreated too:
+        let replaced = self.workspace.borrow().modules.iter()
+            .filter(|(id, module)| {
+                match snapshot.modules.get(*id) {
+                    Some(saved) => mem::discriminant(&saved.params) != mem::discriminant(&module.params()),
+                    None => true,
+                }
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for module_id in replaced {
+            self.apply_edit(Edit::DeleteModule(module_id), None, stat);
+        }
+
+        let saved_modules = snapshot.modules.iter().collect::<BTreeMap<_, _>>();
+
+        for (module_id, saved) in &saved_modules {
+            let current = {
+                let workspace = self.workspace.borrow();
+                workspace.modules.get(*module_id)
+                    .map(|module| (module.params(), module.modulation().to_vec()))
+                    .map(|(params, modulation)| (params, workspace.geometry.get(*module_id).cloned(), modulation))
+            };
+
+            match current {
+                Some((params, geometry, modulation)) => {
+                    if !same_params(&params, &saved.params) {
+                        self.apply_edit(Edit::UpdateModuleParams(**module_id, saved.params.clone()), None, stat);
+                    }
+
+                    if geometry.map(|geometry| geometry != saved.geometry).unwrap_or(true) {
+                        self.apply_edit(Edit::UpdateWindowGeometry(**module_id, saved.geometry.clone()), None, stat);
+                    }
+
+                    if modulation != saved.modulation {
+                        self.apply_edit(Edit::UpdateModulation(**module_id, saved.modulation.clone()), None, stat);
+                    }
+                }
+                None => {
+                    self.apply_edit(Edit::CreateModule {
+                        id: **module_id,
+                        params: saved.params.clone(),
+                        geometry: saved.geometry.clone(),
+                        modulation: saved.modulation.clone(),
+                        connections: Vec::new(),
+                    }, None, stat);
+                }
+            }
+        }
+
+        // connect once all modules exist, as with loading a workspace:
+        let saved_connections = saved_modules.iter()
+            .flat_map(|(module_id, saved)| {
+                saved.inputs.iter().enumerate()
+                    .filter_map(move |(index, output_id)| {
+                        output_id.map(|output_id| (InputId(**module_id, index), output_id))
+                    })
+            })
+            .collect::<HashMap<_, _>>();
+
+        let stale_connections = self.workspace.borrow().connections.iter()
+            .filter(|(input_id, output_id)| saved_connections.get(*input_id) != Some(*output_id))
+            .map(|(input_id, _)| *input_id)
+            .collect::<Vec<_>>();
+
+        for input_id in stale_connections {
+            self.apply_edit(Edit::DeleteConnection(input_id), None, stat);
+        }
+
+        for (input_id, output_id) in saved_connections {
+            if self.workspace.borrow().connections.get(&input_id) != Some(&output_id) {
+                self.apply_edit(Edit::CreateConnection(input_id, output_id), None, stat);
+            }
+        }
+    }
+
+    fn recall_scene(&mut self, scene: persist::Scene, crossfade: Option<Duration>, stat: &mut EngineStat) {
+        let ticks = crossfade
+            .map(|duration| (duration.as_secs_f64() * self.base.rate().tick_rate() as f64).round() as usize)
+            .unwrap_or(0);
+
+        // scenes saved before they were limited to modules with numeric
+        // params have everything in them:
+        let saved_params = scene.params.into_iter()
+            .filter(|(_, params)| persist::Scene::includes(params))
+            .collect::<BTreeMap<_, _>>();
+
+        for (module_id, params) in saved_params {
+            // a new recall takes over from any fade in progress:
+            self.fades.retain(|fade| fade.module_id() != module_id);
+
+            let current = match self.workspace.borrow().modules.get(&module_id) {
+                Some(module) => module.params(),
+                // module has been deleted since the scene was saved
+                None => { continue; }
+            };
+
+            if mem::discriminant(&current) != mem::discriminant(&params) || same_params(&current, &params) {
+                continue;
+            }
+
+            match Fade::new(module_id, &current, params.clone(), ticks) {
+                Some(fade) => { self.fades.push(fade); }
+                None => { self.apply_edit(Edit::UpdateModuleParams(module_id, params), None, stat); }
+            }
+        }
+    }
+
+    fn step_fades(&mut self, tick: u64, stat: &mut EngineStat) {
+        // fades update modules every tick, but clients are only sent some of
+        // the steps so as not to flood them:
+        const UPDATES_PER_SECOND: usize = 10;
+
+        let update_interval = (self.base.rate().tick_rate() / UPDATES_PER_SECOND).max(1) as u64;
+
+        let mut finished = Vec::new();
+
+        for fade in &mut self.fades {
+            let params = fade.step();
+
+            if fade.is_finished() {
+                finished.push((fade.module_id(), fade.target().clone()));
+                continue;
+            }
+
+            // intermediate steps are not persisted:
+            let workspace = self.workspace.borrow_mut_without_sync();
+
+            if let Some(module) = workspace.modules.get_mut(&fade.module_id()) {
+                module.update(params);
+
+                // nor do they change the version of the params, so that
+                // clients can take over from a fade in progress:
+                if tick % update_interval == 0 {
+                    let version = self.param_versions.current(fade.module_id());
+
+                    let _ = self.log_tx.send(EngineEvent::ServerUpdate(
+                        ServerUpdate::UpdateModuleParams(fade.module_id(), module.params(), version)));
+                }
+            }
+        }
+
+        self.fades.retain(|fade| !fade.is_finished());
+
+        // the final step is applied as an edit so that it is persisted:
+        for (module_id, params) in finished {
+            self.apply_edit(Edit::UpdateModuleParams(module_id, params), None, stat);
+        }
+    }
+
     fn log_op(&mut self, op: ServerUpdate) {
         let _ = self.log_tx.send(EngineEvent::ServerUpdate(op));
     }
 
+    // finds which connections close cycles in the graph, and tells clients
+    // if that has changed
+    fn update_feedback(&mut self) {
+        let feedback = {
+            let workspace = self.workspace.borrow();
+            Schedule::new(&workspace.modules, &workspace.connections).feedback
+        };
+
+        if feedback != self.feedback {
+            self.log_op(ServerUpdate::UpdateFeedback(feedback.iter().copied().collect()));
+            self.feedback = feedback;
+        }
+    }
+
     fn sync_log(&mut self, clock: OpClock) {
         let _ = self.log_tx.send(EngineEvent::Sync(clock));
     }
 
+    fn reject_stale_params(&mut self, clock: OpClock, module_id: ModuleId) {
+        let params = self.workspace.borrow().modules.get(&module_id).map(|module| module.params());
+
+        // nothing to reconcile if the module has since been deleted, the
+        // client will be sent its deletion:
+        if let Some(params) = params {
+            let version = self.param_versions.current(module_id);
+            let rejection = Rejection::StaleParams(module_id, params, version);
+            let _ = self.log_tx.send(EngineEvent::Rejected(clock, rejection));
+        }
+    }
+
     fn client_update(&mut self, session_id: SessionId, msg: WorkspaceMessage, stat: &mut EngineStat) {
         let clock = OpClock(session_id, msg.sequence);
 
-        match msg.op {
-            WorkspaceOp::CreateModule(params, geometry) => {
+        let edit = match msg.op {
+            WorkspaceOp::CreateModule(mut params, geometry) => {
+                params.restore_secrets(None);
+                params.fit_tick_rate(self.base.rate().tick_rate());
+
                 // TODO - the audio engine is not actually concerned with
                 // window geometry and so should not own this data and force
                 // all accesses to it to go via the live audio thread
+                let id = ModuleId(self.workspace.borrow_mut().module_seq.next());
+                Some(Edit::CreateModule { id, params, geometry, modulation: Vec::new(), connections: Vec::new() })
+            }
+            WorkspaceOp::UpdateModuleParams(module_id, mut params, base) => {
+                if self.param_versions.accepts(module_id, base, session_id) {
+                    let current = self.workspace.borrow().modules.get(&module_id).map(|module| module.params());
+                    params.restore_secrets(current.as_ref());
+                    Some(Edit::UpdateModuleParams(module_id, params))
+                } else {
+                    self.reject_stale_params(clock, module_id);
+                    None
+                }
+            }
+            WorkspaceOp::UpdateWindowGeometry(module_id, geometry) => {
+                Some(Edit::UpdateWindowGeometry(module_id, geometry))
+            }
+            WorkspaceOp::UpdateModulation(module_id, modulation) => {
+                Some(Edit::UpdateModulation(module_id, modulation))
+            }
+            WorkspaceOp::DeleteModule(module_id) => {
+                Some(Edit::DeleteModule(module_id))
+            }
+            WorkspaceOp::CreateConnection(input_id, output_id) => {
+                Some(Edit::CreateConnection(input_id, output_id))
+            }
+            WorkspaceOp::DeleteConnection(input_id) => {
+                Some(Edit::DeleteConnection(input_id))
+            }
+            WorkspaceOp::Undo => {
+                self.undo(session_id, stat);
+                None
+            }
+            WorkspaceOp::Redo => {
+                self.redo(session_id, stat);
+                None
+            }
+        };
+
+        if let Some(edit) = edit {
+            let inverse = self.apply_edit(edit.clone(), Some(session_id), stat);
+
+            self.history.entry(session_id)
+                .or_default()
+                .record(&edit, inverse, Instant::now());
+        }
+
+        return self.sync_log(clock);
+    }
+
+    fn undo(&mut self, session_id: SessionId, stat: &mut EngineStat) {
+        loop {
+            let inverse = match self.history.get_mut(&session_id).and_then(History::take_undo) {
+                Some(inverse) => inverse,
+                None => { return; }
+            };
+
+            let redo = self.apply_edits(inverse, Some(session_id), stat);
+
+            // if nothing changed, everything the entry would have undone has
+            // since been deleted by another session. move on to the next:
+            if !redo.is_empty() {
+                self.history.entry(session_id).or_default().record_undone(redo);
+                return;
+            }
+        }
+    }
+
+    fn redo(&mut self, session_id: SessionId, stat: &mut EngineStat) {
+        loop {
+            let edits = match self.history.get_mut(&session_id).and_then(History::take_redo) {
+                Some(edits) => edits,
+                None => { return; }
+            };
+
+            let undo = self.apply_edits(edits, Some(session_id), stat);
+
+            if !undo.is_empty() {
+                self.history.entry(session_id).or_default().record_redone(undo);
+                return;
+            }
+        }
+    }
+
+    // applies edits in order, returning the edits which revert them all
+    fn apply_edits(&mut self, edits: Vec<Edit>, writer: Option<SessionId>, stat: &mut EngineStat) -> Vec<Edit> {
+        let inverses = edits.into_iter()
+            .map(|edit| self.apply_edit(edit, writer, stat))
+            .collect::<Vec<_>>();
+
+        inverses.into_iter().rev().flatten().collect()
+    }
+
+    // applies an edit to the workspace and logs the resulting updates. writer
+    // is the session making the edit, or None if made by the server itself.
+    // returns the edits which revert it, which are empty if nothing changed
+    fn apply_edit(&mut self, edit: Edit, writer: Option<SessionId>, stat: &mut EngineStat) -> Vec<Edit> {
+        let changes_graph = match edit {
+            Edit::UpdateModuleParams(..) | Edit::UpdateWindowGeometry(..) => false,
+            // changing modulation adds and removes inputs:
+            Edit::UpdateModulation(..) |
+            Edit::CreateModule { .. } |
+            Edit::DeleteModule(_) |
+            Edit::CreateConnection(..) |
+            Edit::DeleteConnection(_) => true,
+        };
+
+        let inverse = self.edit_workspace(edit, writer, stat);
+
+        if changes_graph {
+            self.update_feedback();
+        }
+
+        inverse
+    }
+
+    fn edit_workspace(&mut self, edit: Edit, writer: Option<SessionId>, stat: &mut EngineStat) -> Vec<Edit> {
+        match edit {
+            Edit::CreateModule { id, params, geometry, modulation, connections } => {
                 let op = {
                     let mut workspace = self.workspace.borrow_mut();
-                    let id = ModuleId(workspace.module_seq.next());
-                    let (module, indication) = module::host(params.clone(), self.base.clone());
+
+                    // module may have been restored already by another
+                    // session undoing its own deletion of it:
+                    if workspace.modules.contains_key(&id) {
+                        return Vec::new();
+                    }
+
+                    let (mut module, indication) = module::host(params.clone(), self.base.clone(), EngineMode::Realtime);
+                    module.set_modulation(modulation);
                     let inputs = module.inputs().to_vec();
                     let outputs = module.outputs().to_vec();
+                    let modulation = module.modulation().to_vec();
                     workspace.modules.insert(id, module);
                     workspace.geometry.insert(id, geometry.clone());
                     workspace.indications.insert(id, indication.clone());
@@ -299,43 +779,113 @@ impl Engine {
                         indication,
                         inputs,
                         outputs,
+                        modulation,
                     }
                 };
 
                 self.log_op(op);
+
+                // restored connections may replace connections made since the
+                // module was deleted, so they need reverting too:
+                let mut edits = self.apply_edits(
+                    connections.into_iter()
+                        .map(|(input_id, output_id)| Edit::CreateConnection(input_id, output_id))
+                        .collect(),
+                    writer,
+                    stat);
+
+                edits.push(Edit::DeleteModule(id));
+                edits
             }
-            WorkspaceOp::UpdateModuleParams(module_id, params) => {
-                let op = {
+            Edit::UpdateModuleParams(module_id, params) => {
+                // explicit changes take over from any fade in progress:
+                self.fades.retain(|fade| fade.module_id() != module_id);
+
+                let offset = self.sample_offset();
+
+                let result = {
                     let mut workspace = self.workspace.borrow_mut();
 
                     workspace.modules.get_mut(&module_id).map(|module| {
-                        module.update(params.clone());
-                        ServerUpdate::UpdateModuleParams(module_id, module.params())
+                        let previous = module.params();
+                        module.update_at(params, offset);
+                        (previous, module.params())
                     })
                 };
 
-                if let Some(op) = op {
-                    self.log_op(op);
+                match result {
+                    Some((previous, params)) => {
+                        let version = self.param_versions.bump(module_id, writer);
+                        self.log_op(ServerUpdate::UpdateModuleParams(module_id, params, version));
+                        vec![Edit::UpdateModuleParams(module_id, previous)]
+                    }
+                    None => Vec::new(),
                 }
             }
-            WorkspaceOp::UpdateWindowGeometry(module_id, geometry) => {
-                let op = {
+            Edit::UpdateWindowGeometry(module_id, geometry) => {
+                let previous = {
                     let mut workspace = self.workspace.borrow_mut();
 
                     workspace.geometry.get_mut(&module_id).map(|geom| {
-                        *geom = geometry.clone();
-                        ServerUpdate::UpdateWindowGeometry(module_id, geometry)
+                        mem::replace(geom, geometry.clone())
                     })
                 };
 
-                if let Some(op) = op {
-                    self.log_op(op);
+                match previous {
+                    Some(previous) => {
+                        self.log_op(ServerUpdate::UpdateWindowGeometry(module_id, geometry));
+                        vec![Edit::UpdateWindowGeometry(module_id, previous)]
+                    }
+                    None => Vec::new(),
                 }
             }
-            WorkspaceOp::DeleteModule(module_id) => {
+            Edit::UpdateModulation(module_id, modulation) => {
+                let current = self.workspace.borrow().modules.get(&module_id).map(|module| {
+                    let modulation = modulation::sanitize(&module.params(), modulation);
+                    let previous = module.modulation().to_vec();
+                    // control inputs come after the module's own inputs:
+                    let first_control = module.inputs().len() - previous.len();
+                    (modulation, previous, first_control)
+                });
+
+                let (modulation, previous, first_control) = match current {
+                    Some(current) => current,
+                    None => { return Vec::new(); }
+                };
+
+                if modulation == previous {
+                    return Vec::new();
+                }
+
+                // control inputs which are going away or will modulate a
+                // different param are disconnected first:
+                let stale_connections = previous.iter()
+                    .enumerate()
+                    .filter(|(index, m)| modulation.get(*index).map(|new| new.param != m.param).unwrap_or(true))
+                    .map(|(index, _)| Edit::DeleteConnection(InputId(module_id, first_control + index)))
+                    .collect();
+
+                let reconnect = self.apply_edits(stale_connections, writer, stat);
+
+                let op = {
+                    let mut workspace = self.workspace.borrow_mut();
+                    let module = workspace.modules.get_mut(&module_id)
+                        .expect("module exists");
+
+                    module.set_modulation(modulation);
+                    ServerUpdate::UpdateModulation(module_id, module.modulation().to_vec(), module.inputs().to_vec())
+                };
+
+                self.log_op(op);
+
+                let mut inverse = vec![Edit::UpdateModulation(module_id, previous)];
+                inverse.extend(reconnect);
+                inverse
+            }
+            Edit::DeleteModule(module_id) => {
                 let mut operations = Vec::new();
 
-                {
+                let restore = {
                     let mut workspace = self.workspace.borrow_mut();
 
                     // find any connections connected to this module's inputs or
@@ -345,30 +895,48 @@ impl Engine {
 
                     for (input, output) in &workspace.connections {
                         if input.module_id() == module_id || output.module_id() == module_id {
-                            deleted_connections.push(*input);
+                            deleted_connections.push((*input, *output));
                         }
                     }
 
-                    for deleted_connection in deleted_connections {
-                        workspace.connections.remove(&deleted_connection);
-                        operations.push(ServerUpdate::DeleteConnection(deleted_connection));
+                    for (input, _) in &deleted_connections {
+                        workspace.connections.remove(input);
+                        operations.push(ServerUpdate::DeleteConnection(*input));
                     }
 
                     // finally, delete the module:
 
-                    if workspace.modules.contains_key(&module_id) {
-                        workspace.modules.remove(&module_id);
-                        operations.push(ServerUpdate::DeleteModule(module_id));
+                    match workspace.modules.remove(&module_id) {
+                        Some(module) => {
+                            operations.push(ServerUpdate::DeleteModule(module_id));
+
+                            let geometry = workspace.geometry.remove(&module_id)
+                                .unwrap_or_default();
+
+                            workspace.indications.remove(&module_id);
+
+                            Some(Edit::CreateModule {
+                                id: module_id,
+                                params: module.params(),
+                                geometry,
+                                modulation: module.modulation().to_vec(),
+                                connections: deleted_connections,
+                            })
+                        }
+                        None => None,
                     }
-                }
+                };
 
                 for op in operations {
                     self.log_op(op);
                 }
 
+                self.param_versions.remove(module_id);
                 stat.remove_module(module_id);
+
+                restore.into_iter().collect()
             }
-            WorkspaceOp::CreateConnection(input_id, output_id) => {
+            Edit::CreateConnection(input_id, output_id) => {
                 let previous = self.workspace.borrow_mut().connect(input_id, output_id);
 
                 match previous {
@@ -378,23 +946,32 @@ impl Engine {
                         }
 
                         self.log_op(ServerUpdate::CreateConnection(input_id, output_id));
+
+                        match old_output {
+                            Some(old_output) if old_output == output_id => Vec::new(),
+                            Some(old_output) => vec![Edit::CreateConnection(input_id, old_output)],
+                            None => vec![Edit::DeleteConnection(input_id)],
+                        }
                     }
                     Err(_) => {
                         // client should have guarded against a type mismatched
                         // connection, just drop
+                        Vec::new()
                     }
                 }
             }
-            WorkspaceOp::DeleteConnection(input_id) => {
+            Edit::DeleteConnection(input_id) => {
                 let previous = self.workspace.borrow_mut().disconnect(input_id);
 
-                if let Some(_) = previous {
-                    self.log_op(ServerUpdate::DeleteConnection(input_id));
+                match previous {
+                    Some(output_id) => {
+                        self.log_op(ServerUpdate::DeleteConnection(input_id));
+                        vec![Edit::CreateConnection(input_id, output_id)]
+                    }
+                    None => Vec::new(),
                 }
             }
         }
-
-        return self.sync_log(clock);
     }
 
     fn run_tick(&mut self, tick: u64, stat: &mut TickStat) -> Vec<(ModuleId, Indication)> {
@@ -402,110 +979,185 @@ impl Engine {
         // module params or connections
         let workspace = self.workspace.borrow_mut_without_sync();
 
-        // find terminal modules - modules which do not send their output to
-        // the input of any other module
+        run_workspace_tick(workspace, &self.pool, self.base.rate(), tick, stat, |_, _| {})
+    }
+}
 
-        let mut terminal_modules = HashSet::new();
+// params have no PartialEq as not all of them can sensibly be compared, but
+// comparing their serialized form is good enough to skip redundant updates
+fn same_params(a: &ModuleParams, b: &ModuleParams) -> bool {
+    match (serde_json::to_value(a), serde_json::to_value(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
 
-        for (id, _) in &workspace.modules {
-            terminal_modules.insert(*id);
-        }
+/// Runs a single tick over every module in the workspace on the workers of
+/// `pool`. Each module runs once all of the modules feeding its inputs have,
+/// so independent branches of the graph run in parallel. Feedback connections
+/// carry what was output on the previous tick, see `Schedule`. `on_terminal`
+/// is called with the inputs of each terminal module (modules whose outputs
+/// are not connected to anything) once it has run.
+///
+/// Output is the same however modules happen to be scheduled, as a module
+/// only ever sees the outputs of the modules it depends on. Indications are
+/// returned in run order.
+fn run_workspace_tick(
+    workspace: &mut Workspace,
+    pool: &WorkerPool,
+    rate: Rate,
+    tick: u64,
+    stat: &mut TickStat,
+    mut on_terminal: impl FnMut(ModuleId, &[InputRef]),
+) -> Vec<(ModuleId, Indication)> {
+    let schedule = Schedule::new(&workspace.modules, &workspace.connections);
+
+    let mut terminal_modules = workspace.modules.keys().copied().collect::<HashSet<_>>();
+
+    for (_, output) in &workspace.connections {
+        terminal_modules.remove(&output.module_id());
+    }
 
-        for (_, output) in &workspace.connections {
-            terminal_modules.remove(&output.module_id());
-        }
+    let position = schedule.run_order.iter()
+        .enumerate()
+        .map(|(index, module_id)| (*module_id, index))
+        .collect::<HashMap<_, _>>();
 
-        // depth-first-search modules out via their inputs, starting from
-        // terminal modules
+    // a module depends on the modules feeding its inputs, other than by
+    // feedback connections
 
-        let mut topsort = Topsort {
-            modules: &workspace.modules,
-            connections: &workspace.connections,
-            run_order: Vec::new(),
-            seen: HashSet::new(),
-        };
+    #[derive(Clone, Copy)]
+    enum Source {
+        Disconnected,
+        Connected(OutputId),
+        Feedback(OutputId),
+    }
 
-        for id in terminal_modules.into_iter() {
-            traverse(id, &mut topsort);
+    let mut sources = HashMap::<ModuleId, Vec<Source>>::new();
+    let mut waiting_on = HashMap::<ModuleId, usize>::new();
+    let mut dependents = HashMap::<ModuleId, Vec<ModuleId>>::new();
+
+    for module_id in &schedule.run_order {
+        let module_sources = (0..workspace.modules[module_id].inputs().len())
+            .map(|i| InputId(*module_id, i))
+            .map(|input_id| {
+                match workspace.connections.get(&input_id) {
+                    None => Source::Disconnected,
+                    Some(output_id) if schedule.feedback.contains(&input_id) => Source::Feedback(*output_id),
+                    Some(output_id) => Source::Connected(*output_id),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let dependencies = module_sources.iter()
+            .filter_map(|source| match source {
+                Source::Connected(output_id) => Some(output_id.module_id()),
+                Source::Disconnected | Source::Feedback(_) => None,
+            })
+            .collect::<BTreeSet<_>>();
+
+        for dependency in &dependencies {
+            dependents.entry(*dependency).or_default().push(*module_id);
         }
 
-        struct Topsort<'a> {
-            modules: &'a HashMap<ModuleId, DynModuleHost>,
-            connections: &'a HashMap<InputId, OutputId>,
-            run_order: Vec<ModuleId>,
-            seen: HashSet<ModuleId>,
-        }
+        waiting_on.insert(*module_id, dependencies.len());
+        sources.insert(*module_id, module_sources);
+    }
 
-        fn traverse(module_id: ModuleId, state: &mut Topsort) {
-            if state.seen.contains(&module_id) {
-                return;
-            }
+    // run modules as soon as everything they depend on has run
 
-            state.seen.insert(module_id);
+    let t = tick * rate.samples_per_tick() as u64;
 
-            let module = &state.modules[&module_id];
+    let mut buffers = HashMap::<OutputId, Arc<Output>>::new();
+    let mut indications = Vec::new();
 
-            for i in 0..module.inputs().len() {
-                let terminal_id = InputId(module_id, i);
+    let mut ready = schedule.run_order.iter()
+        .filter(|module_id| waiting_on[*module_id] == 0)
+        .copied()
+        .collect::<Vec<_>>();
 
-                if let Some(output_id) = state.connections.get(&terminal_id) {
-                    traverse(output_id.module_id(), state);
-                }
-            }
+    let mut running = 0;
 
-            state.run_order.push(module_id);
+    loop {
+        for module_id in ready.drain(..) {
+            let module = workspace.modules.remove(&module_id)
+                .expect("module remove");
+
+            let inputs = sources[&module_id].iter()
+                .map(|source| match source {
+                    Source::Disconnected => None,
+                    Source::Connected(output_id) => buffers.get(output_id).cloned(),
+                    Source::Feedback(output_id) => workspace.delayed.get(output_id).cloned(),
+                })
+                .collect();
+
+            pool.dispatch(Job { module_id, module, t, inputs });
+            running += 1;
         }
 
-        // run modules in dependency order according to BFS above
+        if running == 0 {
+            break;
+        }
 
-        let mut buffers = HashMap::<OutputId, Output>::new();
-        let mut indications = Vec::new();
+        let done = stat.record_wait(|| pool.recv());
+        running -= 1;
 
-        for module_id in topsort.run_order.iter() {
-            let module = workspace.modules.get_mut(&module_id)
-                .expect("module get_mut");
+        let module_id = done.module_id;
+        workspace.modules.insert(module_id, done.module);
 
-            let connections = &workspace.connections;
+        let indication = match done.indication {
+            Ok(indication) => indication,
+            Err(panic) => panic::resume_unwind(panic),
+        };
 
-            let mut output_buffers = module.outputs().iter()
-                .map(|output| Output::from_line_type(output.line_type()))
-                .collect::<Vec<_>>();
+        stat.record_module(module_id, done.worker, done.elapsed);
 
-            {
-                let input_refs = module.inputs().iter()
-                    .enumerate()
-                    .map(|(i, _ty)| InputId(*module_id, i))
-                    .map(|input_id| {
-                        connections.get(&input_id)
-                            .and_then(|output_id| buffers.get(output_id))
-                            .map(|output| output.as_input_ref())
-                            .unwrap_or(InputRef::Disconnected)
-                    })
-                    .collect::<Vec<_>>();
+        if let Some(indic) = indication {
+            indications.push((position[&module_id], module_id, indic));
+        }
 
-                let mut output_refs = output_buffers.iter_mut()
-                    .map(|output| output.as_output_ref())
-                    .collect::<Vec<_>>();
+        if terminal_modules.contains(&module_id) {
+            let input_refs = sources[&module_id].iter()
+                .map(|source| {
+                    let output = match source {
+                        Source::Disconnected => None,
+                        Source::Connected(output_id) => buffers.get(output_id),
+                        Source::Feedback(output_id) => workspace.delayed.get(output_id),
+                    };
+
+                    output.map(|output| output.as_input_ref())
+                        .unwrap_or(InputRef::Disconnected(pool.zero_buffers()))
+                })
+                .collect::<Vec<_>>();
 
-                let t = tick * SAMPLES_PER_TICK as u64;
+            on_terminal(module_id, &input_refs);
+        }
 
-                let result = stat.record_module(*module_id, || {
-                    module.run_tick(t, &input_refs, &mut output_refs)
-                });
+        for (i, output) in done.outputs.into_iter().enumerate() {
+            buffers.insert(OutputId(module_id, i), Arc::new(output));
+        }
 
-                match result {
-                    None => {}
-                    Some(indic) => {
-                        indications.push((*module_id, indic));
-                    }
-                }
-            }
+        for dependent in dependents.get(&module_id).into_iter().flatten() {
+            let waiting = waiting_on.get_mut(dependent).expect("waiting_on get_mut");
+            *waiting -= 1;
 
-            for (i, output) in output_buffers.into_iter().enumerate() {
-                buffers.insert(OutputId(*module_id, i), output);
+            if *waiting == 0 {
+                ready.push(*dependent);
             }
         }
-
-        indications
     }
+
+    // hold on to whatever feeds feedback connections for the next tick:
+    let delayed = schedule.feedback.iter()
+        .filter_map(|input_id| workspace.connections.get(input_id))
+        .filter_map(|output_id| buffers.get(output_id).map(|output| (*output_id, output.clone())))
+        .collect();
+
+    workspace.delayed = delayed;
+
+    indications.sort_by_key(|(index, _, _)| *index);
+
+    indications.into_iter()
+        .map(|(_, module_id, indication)| (module_id, indication))
+        .collect()
 }
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use mixlab_protocol::{ModuleId, ParamsVersion};
+
+use crate::engine::SessionId;
+
+struct ModuleVersion {
+    version: ParamsVersion,
+    // session which made the latest run of changes to the params, or None
+    // if they were made by the server itself, eg. by recalling a snapshot:
+    writer: Option<SessionId>,
+    // version of the params from before the run of changes by the writer:
+    run_base: ParamsVersion,
+}
+
+/// Tracks the version of every module's params, so that updates based on
+/// params which have since been changed by someone else can be detected.
+///
+/// Versions are drawn from a single sequence rather than counted per module,
+/// so that a module deleted and then recreated with the same ID never reuses
+/// a version its previous incarnation had.
+pub struct ParamVersions {
+    last: u64,
+    modules: HashMap<ModuleId, ModuleVersion>,
+}
+
+impl ParamVersions {
+    pub fn new() -> Self {
+        ParamVersions {
+            last: 0,
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Modules which have never been updated are at the default version
+    pub fn current(&self, module_id: ModuleId) -> ParamsVersion {
+        self.modules.get(&module_id)
+            .map(|module| module.version)
+            .unwrap_or_default()
+    }
+
+    /// Whether an update by `session` based on version `base` of a module's
+    /// params can be applied. Besides updates based on the current version,
+    /// this accepts updates based on any version since which only `session`
+    /// has made changes - a client sends many updates while dragging a knob
+    /// before it sees the first of them come back, and those don't conflict
+    /// with each other.
+    pub fn accepts(&self, module_id: ModuleId, base: ParamsVersion, session: SessionId) -> bool {
+        match self.modules.get(&module_id) {
+            Some(module) => {
+                base == module.version
+                    || (module.writer == Some(session) && base >= module.run_base && base <= module.version)
+            }
+            None => base == ParamsVersion::default(),
+        }
+    }
+
+    /// Records a change to a module's params, returning its new version
+    pub fn bump(&mut self, module_id: ModuleId, writer: Option<SessionId>) -> ParamsVersion {
+        self.last += 1;
+
+        let version = ParamsVersion(self.last);
+
+        let module = self.modules.entry(module_id).or_insert(ModuleVersion {
+            version: ParamsVersion::default(),
+            writer: None,
+            run_base: ParamsVersion::default(),
+        });
+
+        if writer.is_none() || module.writer != writer {
+            module.writer = writer;
+            module.run_base = module.version;
+        }
+
+        module.version = version;
+        version
+    }
+
+    pub fn remove(&mut self, module_id: ModuleId) {
+        self.modules.remove(&module_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use mixlab_protocol::{ModuleId, ParamsVersion};
+
+    use crate::engine::SessionId;
+    use super::ParamVersions;
+
+    fn module(id: usize) -> ModuleId {
+        ModuleId(NonZeroUsize::new(id).unwrap())
+    }
+
+    fn session(id: usize) -> SessionId {
+        SessionId(NonZeroUsize::new(id).unwrap())
+    }
+
+    #[test]
+    fn accepts_own_run_of_changes() {
+        let mut versions = ParamVersions::new();
+        let (m, a, b) = (module(1), session(1), session(2));
+
+        assert!(versions.accepts(m, ParamsVersion::default(), a));
+
+        let v1 = versions.bump(m, Some(a));
+        let v2 = versions.bump(m, Some(a));
+
+        // a hasn't seen its own changes come back yet:
+        assert!(versions.accepts(m, ParamsVersion::default(), a));
+        assert!(versions.accepts(m, v1, a));
+        assert!(versions.accepts(m, v2, a));
+
+        // anyone else must be up to date:
+        assert!(!versions.accepts(m, v1, b));
+        assert!(versions.accepts(m, v2, b));
+    }
+
+    #[test]
+    fn foreign_writer_ends_run() {
+        let mut versions = ParamVersions::new();
+        let (m, a, b) = (module(1), session(1), session(2));
+
+        let v1 = versions.bump(m, Some(a));
+        let v2 = versions.bump(m, Some(b));
+
+        assert!(!versions.accepts(m, ParamsVersion::default(), a));
+        assert!(!versions.accepts(m, v1, a));
+        assert!(versions.accepts(m, v2, a));
+
+        // b's run started from a's change:
+        assert!(versions.accepts(m, v1, b));
+        assert!(!versions.accepts(m, ParamsVersion::default(), b));
+    }
+
+    #[test]
+    fn server_writer_ends_run() {
+        let mut versions = ParamVersions::new();
+        let (m, a) = (module(1), session(1));
+
+        let v1 = versions.bump(m, Some(a));
+        let v2 = versions.bump(m, None);
+
+        assert!(!versions.accepts(m, v1, a));
+        assert!(versions.accepts(m, v2, a));
+
+        // the server never has a run of its own:
+        let v3 = versions.bump(m, None);
+
+        assert!(!versions.accepts(m, v2, a));
+        assert!(versions.accepts(m, v3, a));
+    }
+
+    #[test]
+    fn recreated_module_starts_afresh() {
+        let mut versions = ParamVersions::new();
+        let (m, a, b) = (module(1), session(1), session(2));
+
+        let v1 = versions.bump(m, Some(a));
+        versions.remove(m);
+
+        assert_eq!(versions.current(m), ParamsVersion::default());
+        assert!(!versions.accepts(m, v1, a));
+        assert!(versions.accepts(m, ParamsVersion::default(), a));
+
+        // versions are never reused, so updates based on the params of the
+        // module deleted can't be mistaken for ones based on its successor:
+        let v2 = versions.bump(m, Some(b));
+
+        assert_ne!(v2, v1);
+        assert!(!versions.accepts(m, v1, a));
+        assert!(versions.accepts(m, v2, a));
+    }
+}
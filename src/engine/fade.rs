@@ -0,0 +1,64 @@
+use std::mem;
+
+use mixlab_protocol::{ModuleId, ModuleParams};
+
+/// Crossfades the numeric params of a module from their values at the start
+/// of the fade to those of a target, one step per tick. Params which are not
+/// numeric take their target value from the first step.
+pub struct Fade {
+    module_id: ModuleId,
+    from: Vec<f64>,
+    to: ModuleParams,
+    tick: usize,
+    ticks: usize,
+}
+
+impl Fade {
+    /// Returns None if the params can't be faded between, because they are
+    /// of different kinds or shapes, or have nothing numeric to fade
+    pub fn new(module_id: ModuleId, from: &ModuleParams, to: ModuleParams, ticks: usize) -> Option<Fade> {
+        if mem::discriminant(from) != mem::discriminant(&to) {
+            return None;
+        }
+
+        let mut current = from.clone();
+        let from = current.numeric_params_mut().into_iter()
+            .map(|value| *value)
+            .collect::<Vec<_>>();
+
+        let target_len = to.clone().numeric_params_mut().len();
+
+        if ticks == 0 || from.is_empty() || from.len() != target_len {
+            return None;
+        }
+
+        Some(Fade { module_id, from, to, tick: 0, ticks })
+    }
+
+    pub fn module_id(&self) -> ModuleId {
+        self.module_id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tick >= self.ticks
+    }
+
+    pub fn target(&self) -> &ModuleParams {
+        &self.to
+    }
+
+    /// Advances the fade by one tick, returning the params to apply
+    pub fn step(&mut self) -> ModuleParams {
+        self.tick += 1;
+
+        let t = (self.tick as f64 / self.ticks as f64).min(1.0);
+
+        let mut params = self.to.clone();
+
+        for (value, from) in params.numeric_params_mut().into_iter().zip(&self.from) {
+            *value = from + (*value - from) * t;
+        }
+
+        params
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use mixlab_protocol::{ModuleId, InputId, OutputId};
+
+use crate::engine::DynModuleHost;
+
+/// The order modules run in within a tick, and the connections which close
+/// cycles in the graph. Inputs on feedback connections are given what their
+/// output produced on the previous tick, so every module can still run after
+/// all of the modules it depends on.
+///
+/// Which connection of a cycle is considered feedback depends only on the
+/// shape of the graph and on module IDs, so the same patch always behaves the
+/// same way.
+pub struct Schedule {
+    pub run_order: Vec<ModuleId>,
+    pub feedback: BTreeSet<InputId>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Visit {
+    InProgress,
+    Done,
+}
+
+struct Traversal<'a> {
+    input_counts: &'a HashMap<ModuleId, usize>,
+    connections: &'a HashMap<InputId, OutputId>,
+    visits: HashMap<ModuleId, Visit>,
+    schedule: Schedule,
+}
+
+impl Schedule {
+    pub fn new(modules: &HashMap<ModuleId, DynModuleHost>, connections: &HashMap<InputId, OutputId>) -> Schedule {
+        let input_counts = modules.iter()
+            .map(|(module_id, module)| (*module_id, module.inputs().len()))
+            .collect();
+
+        Schedule::from_input_counts(&input_counts, connections)
+    }
+
+    // schedules a graph given only the number of inputs each module has,
+    // which is all the shape of the graph depends on:
+    fn from_input_counts(input_counts: &HashMap<ModuleId, usize>, connections: &HashMap<InputId, OutputId>) -> Schedule {
+        // depth-first-search modules out via their inputs, starting from
+        // terminal modules - modules which do not send their output to the
+        // input of any other module. modules which only feed into cycles are
+        // not reachable from any terminal module, so every other module is
+        // searched from afterwards
+
+        let mut module_ids = input_counts.keys().copied().collect::<Vec<_>>();
+        module_ids.sort();
+
+        let connected_outputs = connections.values()
+            .map(|output_id| output_id.module_id())
+            .collect::<HashSet<_>>();
+
+        let mut roots = module_ids.iter()
+            .copied()
+            .filter(|module_id| !connected_outputs.contains(module_id))
+            .collect::<Vec<_>>();
+
+        roots.extend(module_ids);
+
+        let mut traversal = Traversal {
+            input_counts,
+            connections,
+            visits: HashMap::new(),
+            schedule: Schedule {
+                run_order: Vec::new(),
+                feedback: BTreeSet::new(),
+            },
+        };
+
+        for module_id in roots {
+            if !traversal.visits.contains_key(&module_id) {
+                traversal.visit(module_id);
+            }
+        }
+
+        traversal.schedule
+    }
+}
+
+impl<'a> Traversal<'a> {
+    fn visit(&mut self, module_id: ModuleId) {
+        self.visits.insert(module_id, Visit::InProgress);
+
+        for i in 0..self.input_counts[&module_id] {
+            let input_id = InputId(module_id, i);
+
+            let source_id = match self.connections.get(&input_id) {
+                Some(output_id) => output_id.module_id(),
+                None => { continue; }
+            };
+
+            match self.visits.get(&source_id) {
+                // the module feeding this input is further up the search
+                // path, so this connection closes a cycle:
+                Some(Visit::InProgress) => {
+                    self.schedule.feedback.insert(input_id);
+                }
+                Some(Visit::Done) => {}
+                None => {
+                    self.visit(source_id);
+                }
+            }
+        }
+
+        self.visits.insert(module_id, Visit::Done);
+        self.schedule.run_order.push(module_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+    use std::num::NonZeroUsize;
+
+    use mixlab_protocol::{ModuleId, InputId, OutputId};
+
+    use super::Schedule;
+
+    fn module(id: usize) -> ModuleId {
+        ModuleId(NonZeroUsize::new(id).unwrap())
+    }
+
+    // builds a graph of modules with one input and one output each, from
+    // (from, to) pairs connecting the output of one to the input of another:
+    fn schedule(module_ids: &[usize], edges: &[(usize, usize)]) -> Schedule {
+        let input_counts = module_ids.iter()
+            .map(|id| (module(*id), 1))
+            .collect::<HashMap<_, _>>();
+
+        let connections = edges.iter()
+            .map(|(from, to)| (InputId(module(*to), 0), OutputId(module(*from), 0)))
+            .collect::<HashMap<_, _>>();
+
+        Schedule::from_input_counts(&input_counts, &connections)
+    }
+
+    fn ids(ids: &[usize]) -> Vec<ModuleId> {
+        ids.iter().copied().map(module).collect()
+    }
+
+    fn feedback(ids: &[usize]) -> BTreeSet<InputId> {
+        ids.iter().map(|id| InputId(module(*id), 0)).collect()
+    }
+
+    #[test]
+    fn chain_runs_in_dependency_order() {
+        let schedule = schedule(&[1, 2, 3], &[(3, 2), (2, 1)]);
+
+        assert_eq!(schedule.run_order, ids(&[3, 2, 1]));
+        assert!(schedule.feedback.is_empty());
+    }
+
+    #[test]
+    fn simple_cycle() {
+        // 1 -> 2 -> 3 -> 1, nothing terminal so the search starts from the
+        // lowest module ID, and the connection out of it closes the cycle:
+        let schedule = schedule(&[1, 2, 3], &[(1, 2), (2, 3), (3, 1)]);
+
+        assert_eq!(schedule.run_order, ids(&[2, 3, 1]));
+        assert_eq!(schedule.feedback, feedback(&[2]));
+    }
+
+    #[test]
+    fn cycle_feeding_terminal_module() {
+        // 1 <-> 2 -> 3, where 3 is terminal:
+        let schedule = schedule(&[1, 2, 3], &[(1, 2), (2, 1), (2, 3)]);
+
+        assert_eq!(schedule.run_order, ids(&[1, 2, 3]));
+        assert_eq!(schedule.feedback, feedback(&[1]));
+    }
+
+    #[test]
+    fn self_loop() {
+        let schedule = schedule(&[1], &[(1, 1)]);
+
+        assert_eq!(schedule.run_order, ids(&[1]));
+        assert_eq!(schedule.feedback, feedback(&[1]));
+    }
+
+    #[test]
+    fn disconnected_components() {
+        // two chains, 4 -> 1 and 3 -> 2, and a module on its own:
+        let schedule = schedule(&[1, 2, 3, 4, 5], &[(4, 1), (3, 2)]);
+
+        assert_eq!(schedule.run_order, ids(&[4, 1, 3, 2, 5]));
+        assert!(schedule.feedback.is_empty());
+    }
+
+    #[test]
+    fn schedule_is_deterministic() {
+        // hash map iteration order differs between maps, so build the same
+        // graph many times over and check nothing about the schedule changes:
+        let module_ids = [1, 2, 3, 4, 5, 6];
+        let edges = [(1, 2), (2, 3), (3, 1), (5, 5), (4, 6), (6, 4)];
+
+        let first = schedule(&module_ids, &edges);
+
+        for _ in 0..100 {
+            let again = schedule(&module_ids, &edges);
+            assert_eq!(again.run_order, first.run_order);
+            assert_eq!(again.feedback, first.feedback);
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::time::{Duration, Instant};
+
+use mixlab_protocol::{ModuleId, InputId, OutputId, ModuleParams, WindowGeometry, Modulation};
+
+// the oldest edits are forgotten once a session has this many to undo:
+const HISTORY_LIMIT: usize = 100;
+
+// consecutive updates to the params, geometry or modulation of the same module
+// less than this far apart are undone together, so that undo doesn't step back
+// through every position a knob or window was dragged through:
+const COALESCE_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// A change to the workspace which can be recorded in history. Unlike
+/// `WorkspaceOp`, creating a module specifies its ID, so that undoing the
+/// deletion of a module restores it as it was, connections and all.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    CreateModule {
+        id: ModuleId,
+        params: ModuleParams,
+        geometry: WindowGeometry,
+        modulation: Vec<Modulation>,
+        // connections to the module's inputs and from its outputs:
+        connections: Vec<(InputId, OutputId)>,
+    },
+    DeleteModule(ModuleId),
+    UpdateModuleParams(ModuleId, ModuleParams),
+    UpdateWindowGeometry(ModuleId, WindowGeometry),
+    UpdateModulation(ModuleId, Vec<Modulation>),
+    CreateConnection(InputId, OutputId),
+    DeleteConnection(InputId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CoalesceKey {
+    Params(ModuleId),
+    Geometry(ModuleId),
+    Modulation(ModuleId),
+}
+
+impl CoalesceKey {
+    fn for_edit(edit: &Edit) -> Option<CoalesceKey> {
+        match edit {
+            Edit::UpdateModuleParams(module_id, _) => Some(CoalesceKey::Params(*module_id)),
+            Edit::UpdateWindowGeometry(module_id, _) => Some(CoalesceKey::Geometry(*module_id)),
+            Edit::UpdateModulation(module_id, _) => Some(CoalesceKey::Modulation(*module_id)),
+            _ => None,
+        }
+    }
+}
+
+struct Entry {
+    // edits which revert a change, in the order they are to be applied:
+    inverse: Vec<Edit>,
+    coalesce: Option<(CoalesceKey, Instant)>,
+}
+
+/// Undo and redo stacks of a single session. Each session only ever undoes
+/// its own changes, so that collaborators don't undo each other's work.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Entry>,
+    redo: Vec<Vec<Edit>>,
+}
+
+impl History {
+    /// Records the inverse of an edit just made by the session. Anything that
+    /// could have been redone is forgotten.
+    pub fn record(&mut self, edit: &Edit, inverse: Vec<Edit>, now: Instant) {
+        // edit made no change:
+        if inverse.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+
+        let key = CoalesceKey::for_edit(edit);
+
+        if let (Some(key), Some(last)) = (key, self.undo.last_mut()) {
+            if let Some((last_key, last_time)) = &mut last.coalesce {
+                if *last_key == key && now.duration_since(*last_time) < COALESCE_INTERVAL {
+                    // the inverse already recorded restores the state from
+                    // before the first of these updates, keep that one:
+                    *last_time = now;
+                    return;
+                }
+            }
+        }
+
+        self.push_undo(Entry {
+            inverse,
+            coalesce: key.map(|key| (key, now)),
+        });
+    }
+
+    pub fn take_undo(&mut self) -> Option<Vec<Edit>> {
+        self.undo.pop().map(|entry| entry.inverse)
+    }
+
+    pub fn take_redo(&mut self) -> Option<Vec<Edit>> {
+        self.redo.pop()
+    }
+
+    /// Records the inverse of an undo, so that it can be redone
+    pub fn record_undone(&mut self, inverse: Vec<Edit>) {
+        self.redo.push(inverse);
+    }
+
+    /// Records the inverse of a redo, so that it can be undone again. Unlike
+    /// `record`, this leaves the rest of the redo stack in place.
+    pub fn record_redone(&mut self, inverse: Vec<Edit>) {
+        self.push_undo(Entry { inverse, coalesce: None });
+    }
+
+    fn push_undo(&mut self, entry: Entry) {
+        self.undo.push(entry);
+
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.remove(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+    use std::time::{Duration, Instant};
+
+    use mixlab_protocol::{ModuleId, InputId, OutputId, WindowGeometry, Coords};
+
+    use super::{Edit, History, HISTORY_LIMIT};
+
+    fn module(id: usize) -> ModuleId {
+        ModuleId(NonZeroUsize::new(id).unwrap())
+    }
+
+    fn move_window(id: usize) -> Edit {
+        Edit::UpdateWindowGeometry(module(id), WindowGeometry {
+            position: Coords { x: 0, y: 0 },
+            z_index: 0,
+        })
+    }
+
+    fn connect(id: usize) -> Edit {
+        Edit::CreateConnection(InputId(module(id), 0), OutputId(module(id), 0))
+    }
+
+    // the history doesn't look inside inverses, so tests tell them apart by
+    // the module deleted:
+    fn inverse(tag: usize) -> Vec<Edit> {
+        vec![Edit::DeleteModule(module(tag))]
+    }
+
+    fn tag(inverse: Option<Vec<Edit>>) -> Option<usize> {
+        match inverse?.as_slice() {
+            [Edit::DeleteModule(module_id)] => Some(module_id.0.get()),
+            _ => panic!("not a tagged inverse"),
+        }
+    }
+
+    fn after(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn coalesces_updates_to_same_module() {
+        let start = Instant::now();
+        let mut history = History::default();
+
+        // each update extends the window from the last, not the first:
+        history.record(&move_window(1), inverse(1), start);
+        history.record(&move_window(1), inverse(2), after(start, 600));
+        history.record(&move_window(1), inverse(3), after(start, 1200));
+
+        assert_eq!(tag(history.take_undo()), Some(1));
+        assert_eq!(tag(history.take_undo()), None);
+    }
+
+    #[test]
+    fn does_not_coalesce_outside_window() {
+        let start = Instant::now();
+        let mut history = History::default();
+
+        history.record(&move_window(1), inverse(1), start);
+        history.record(&move_window(1), inverse(2), after(start, 1000));
+        history.record(&move_window(2), inverse(3), after(start, 1100));
+
+        assert_eq!(tag(history.take_undo()), Some(3));
+        assert_eq!(tag(history.take_undo()), Some(2));
+        assert_eq!(tag(history.take_undo()), Some(1));
+    }
+
+    #[test]
+    fn forgets_oldest_beyond_limit() {
+        let start = Instant::now();
+        let mut history = History::default();
+
+        for id in 1..=(HISTORY_LIMIT + 1) {
+            history.record(&connect(id), inverse(id), start);
+        }
+
+        let mut undone = Vec::new();
+
+        while let Some(id) = tag(history.take_undo()) {
+            undone.push(id);
+        }
+
+        assert_eq!(undone.len(), HISTORY_LIMIT);
+        assert_eq!(undone.first(), Some(&(HISTORY_LIMIT + 1)));
+        assert_eq!(undone.last(), Some(&2));
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let start = Instant::now();
+        let mut history = History::default();
+
+        history.record(&connect(1), inverse(1), start);
+        history.take_undo();
+        history.record_undone(inverse(10));
+
+        // edits which change nothing leave redo alone:
+        history.record(&connect(2), Vec::new(), start);
+        assert_eq!(history.redo.len(), 1);
+
+        history.record(&connect(2), inverse(2), start);
+        assert_eq!(tag(history.take_redo()), None);
+    }
+
+    #[test]
+    fn redo_keeps_rest_of_redo_stack() {
+        let start = Instant::now();
+        let mut history = History::default();
+
+        history.record(&connect(1), inverse(1), start);
+        history.record(&connect(2), inverse(2), start);
+
+        assert_eq!(tag(history.take_undo()), Some(2));
+        history.record_undone(inverse(20));
+        assert_eq!(tag(history.take_undo()), Some(1));
+        history.record_undone(inverse(10));
+
+        assert_eq!(tag(history.take_redo()), Some(10));
+        history.record_redone(inverse(1));
+
+        assert_eq!(tag(history.take_redo()), Some(20));
+        history.record_redone(inverse(2));
+
+        assert_eq!(tag(history.take_undo()), Some(2));
+        assert_eq!(tag(history.take_undo()), Some(1));
+    }
+}
@@ -1,12 +1,25 @@
 use mixlab_protocol::LineType;
 use mixlab_util::time::MediaDuration;
 
-use crate::engine::{CHANNELS, SAMPLES_PER_TICK};
+use crate::engine::{CHANNELS, Rate};
 use crate::engine::Sample;
 use crate::video;
 
-pub static ZERO_BUFFER_STEREO: [Sample; SAMPLES_PER_TICK * CHANNELS] = [0.0; SAMPLES_PER_TICK * CHANNELS];
-pub static ZERO_BUFFER_MONO: [Sample; SAMPLES_PER_TICK] = [0.0; SAMPLES_PER_TICK];
+/// A tick's worth of silence, which disconnected inputs read as
+#[derive(Debug)]
+pub struct ZeroBuffers {
+    mono: Vec<Sample>,
+    stereo: Vec<Sample>,
+}
+
+impl ZeroBuffers {
+    pub fn new(rate: Rate) -> Self {
+        ZeroBuffers {
+            mono: vec![0.0; rate.samples_per_tick()],
+            stereo: vec![0.0; rate.samples_per_tick() * CHANNELS],
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct VideoFrame {
@@ -17,7 +30,7 @@ pub struct VideoFrame {
 }
 
 pub enum InputRef<'a> {
-    Disconnected,
+    Disconnected(&'a ZeroBuffers),
     Mono(&'a [Sample]),
     Stereo(&'a [Sample]),
     Video(Option<&'a VideoFrame>),
@@ -26,7 +39,7 @@ pub enum InputRef<'a> {
 impl<'a> InputRef<'a> {
     pub fn connected(&self) -> bool {
         match self {
-            InputRef::Disconnected => false,
+            InputRef::Disconnected(_) => false,
             InputRef::Mono(_) |
             InputRef::Stereo(_) |
             InputRef::Video(_) => true,
@@ -35,7 +48,7 @@ impl<'a> InputRef<'a> {
 
     pub fn expect_mono(&self) -> &'a [Sample] {
         match self {
-            InputRef::Disconnected => &ZERO_BUFFER_MONO,
+            InputRef::Disconnected(zero) => &zero.mono,
             InputRef::Mono(buff) => buff,
             InputRef::Stereo(_) => panic!("expected mono input, got stereo"),
             InputRef::Video(_) => panic!("expected mono input, got avc"),
@@ -44,7 +57,7 @@ impl<'a> InputRef<'a> {
 
     pub fn expect_stereo(&self) -> &'a [Sample] {
         match self {
-            InputRef::Disconnected => &ZERO_BUFFER_STEREO,
+            InputRef::Disconnected(zero) => &zero.stereo,
             InputRef::Stereo(buff) => buff,
             InputRef::Mono(_) => panic!("expected stereo input, got mono"),
             InputRef::Video(_) => panic!("expected stereo input, got avc"),
@@ -53,7 +66,7 @@ impl<'a> InputRef<'a> {
 
     pub fn expect_video(&self) -> Option<&VideoFrame> {
         match self {
-            InputRef::Disconnected => None,
+            InputRef::Disconnected(_) => None,
             InputRef::Stereo(_) => panic!("expected stereo input, got stereo"),
             InputRef::Mono(_) => panic!("expected stereo input, got mono"),
             InputRef::Video(frame) => *frame,
@@ -68,10 +81,10 @@ pub enum Output {
 }
 
 impl Output {
-    pub fn from_line_type(line_type: LineType) -> Output {
+    pub fn from_line_type(line_type: LineType, rate: Rate) -> Output {
         match line_type {
-            LineType::Mono => Output::Mono(vec![0.0; SAMPLES_PER_TICK]),
-            LineType::Stereo => Output::Stereo(vec![0.0; SAMPLES_PER_TICK * CHANNELS]),
+            LineType::Mono => Output::Mono(vec![0.0; rate.samples_per_tick()]),
+            LineType::Stereo => Output::Stereo(vec![0.0; rate.samples_per_tick() * CHANNELS]),
             LineType::Video => Output::Video(None),
         }
     }
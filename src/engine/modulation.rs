@@ -0,0 +1,67 @@
+use mixlab_protocol::{ModuleParams, Modulation, Terminal, LineType};
+
+use crate::engine::InputRef;
+
+/// Drops modulation of params the module doesn't have, and all but the first
+/// modulation of each param
+pub fn sanitize(params: &ModuleParams, modulation: Vec<Modulation>) -> Vec<Modulation> {
+    let param_count = params.modulatable_params().len();
+    let mut modulated = vec![false; param_count];
+
+    modulation.into_iter()
+        .filter(|m| m.depth.is_finite() && m.min.is_finite() && m.max.is_finite())
+        .filter(|m| {
+            match modulated.get_mut(m.param) {
+                Some(modulated) if !*modulated => {
+                    *modulated = true;
+                    true
+                }
+                _ => false,
+            }
+        })
+        .collect()
+}
+
+/// Control inputs for each modulation, which go after the module's own inputs
+pub fn terminals(params: &ModuleParams, modulation: &[Modulation]) -> Vec<Terminal> {
+    let modulatable = params.modulatable_params();
+
+    modulation.iter()
+        .map(|m| {
+            // the params a mixer has depend on its channel count, so
+            // modulation can outlive the param it was for:
+            match modulatable.get(m.param) {
+                Some(param) => LineType::Mono.labeled(&param.control_label()),
+                None => LineType::Mono.labeled("CV"),
+            }
+        })
+        .collect()
+}
+
+pub fn is_active(controls: &[InputRef]) -> bool {
+    controls.iter().any(InputRef::connected)
+}
+
+/// Applies the control signal for this tick from each connected control input
+/// to the param it modulates
+pub fn apply(params: &mut ModuleParams, modulation: &[Modulation], controls: &[InputRef]) {
+    let mut values = params.numeric_params_mut();
+
+    for (m, control) in modulation.iter().zip(controls) {
+        if !control.connected() {
+            continue;
+        }
+
+        let signal = control.expect_mono();
+
+        if signal.is_empty() {
+            continue;
+        }
+
+        let cv = signal.iter().map(|sample| *sample as f64).sum::<f64>() / signal.len() as f64;
+
+        if let Some(value) = values.get_mut(m.param) {
+            **value = m.apply(**value, cv);
+        }
+    }
+}
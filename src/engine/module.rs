@@ -4,16 +4,30 @@ use std::future::Future;
 use tokio::runtime;
 use tokio::sync::mpsc;
 
-use mixlab_protocol::{ModuleParams, Indication, Terminal};
+use mixlab_protocol::{ModuleParams, Indication, Terminal, Modulation};
 
-use crate::engine::{InputRef, OutputRef};
+use crate::engine::{InputRef, OutputRef, Rate};
+use crate::engine::modulation;
 use crate::module::{self, ModuleT};
 use crate::project::ProjectBaseRef;
 
+/// How the engine a module runs in is driven
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineMode {
+    // ticks keep pace with the wall clock, and modules must never hold them
+    // up waiting on anything:
+    Realtime,
+    // ticks run as fast as they can, and every run of the same workspace
+    // must produce the same output. modules which get their data from
+    // background threads wait for it rather than carry on without:
+    Offline,
+}
+
 #[derive(Debug)]
 pub struct ModuleCtx<M: ModuleT> {
     runtime: runtime::Handle,
     base: ProjectBaseRef,
+    mode: EngineMode,
     link: ModuleLink<M>,
 }
 
@@ -22,6 +36,16 @@ impl<M: ModuleT> ModuleCtx<M> {
         self.base.clone()
     }
 
+    /// Sample and tick rate of the engine the module runs in. This never
+    /// changes over the lifetime of a module
+    pub fn rate(&self) -> Rate {
+        self.base.rate()
+    }
+
+    pub fn mode(&self) -> EngineMode {
+        self.mode
+    }
+
     pub fn link(&self) -> ModuleLink<M> {
         self.link.clone()
     }
@@ -36,6 +60,23 @@ impl<M: ModuleT> ModuleCtx<M> {
     }
 }
 
+#[cfg(test)]
+impl<M: ModuleT> ModuleCtx<M> {
+    /// Context for a module created outside of any engine, as in unit tests.
+    /// Must be called within a tokio runtime, and events the module sends
+    /// itself are dropped
+    pub fn detached(base: ProjectBaseRef, mode: EngineMode) -> Self {
+        let (events, _) = mpsc::channel(2);
+
+        ModuleCtx {
+            runtime: runtime::Handle::current(),
+            base,
+            mode,
+            link: ModuleLink { events },
+        }
+    }
+}
+
 pub struct ModuleLink<M: ModuleT> {
     events: mpsc::Sender<M::Event>,
 }
@@ -62,32 +103,55 @@ impl<M: ModuleT> fmt::Debug for ModuleLink<M> {
 pub struct ModuleHost<M: ModuleT> {
     module: M,
     events: mpsc::Receiver<M::Event>,
+    modulation: Vec<Modulation>,
+    // the module's own inputs, followed by a control input per modulation:
+    inputs: Vec<Terminal>,
+    // params as last set, while the module itself holds modulated params:
+    unmodulated: Option<ModuleParams>,
+    // sample offset into the next tick of the last update made while
+    // modulated, which modulation is applied from:
+    update_offset: Option<usize>,
 }
 
 impl<M: ModuleT> ModuleHost<M> {
-    fn new(params: M::Params, base: ProjectBaseRef) -> (Self, M::Indication) {
+    fn new(params: M::Params, base: ProjectBaseRef, mode: EngineMode) -> (Self, M::Indication) {
         let (events_tx, events_rx) = mpsc::channel(2);
 
         let ctx = ModuleCtx {
             runtime: runtime::Handle::current(),
             base,
+            mode,
             link: ModuleLink { events: events_tx },
         };
 
         let (module, indication) = M::create(params, ctx);
 
         let host = ModuleHost {
+            inputs: module.inputs().to_vec(),
             module,
             events: events_rx,
+            modulation: Vec::new(),
+            unmodulated: None,
+            update_offset: None,
         };
 
         (host, indication)
     }
+
+    // modules may change their own inputs when their params are updated, and
+    // control inputs are labeled after the params they modulate:
+    fn update_inputs(&mut self, params: &ModuleParams) {
+        self.inputs = self.module.inputs().to_vec();
+        self.inputs.extend(modulation::terminals(params, &self.modulation));
+    }
 }
 
 pub trait DynModuleHostT {
     fn params(&self) -> ModuleParams;
     fn update(&mut self, new_params: ModuleParams) -> Option<Indication>;
+    fn update_at(&mut self, new_params: ModuleParams, offset: usize) -> Option<Indication>;
+    fn modulation(&self) -> &[Modulation];
+    fn set_modulation(&mut self, modulation: Vec<Modulation>);
     fn run_tick(&mut self, t: u64, inputs: &[InputRef], outputs: &mut [OutputRef]) -> Option<Indication>;
     fn inputs(&self) -> &[Terminal];
     fn outputs(&self) -> &[Terminal];
@@ -98,15 +162,41 @@ macro_rules! gen_dyn_module_impls {
         $(
             impl DynModuleHostT for ModuleHost<module::$mod_name::$module> {
                 fn params(&self) -> ModuleParams {
-                    ModuleParams::$module(self.module.params())
+                    self.unmodulated.clone()
+                        .unwrap_or_else(|| ModuleParams::$module(self.module.params()))
                 }
 
                 fn update(&mut self, new_params: ModuleParams) -> Option<Indication> {
-                    if let ModuleParams::$module(params) = new_params {
-                        self.module.update(params).map(Indication::$module)
+                    self.update_at(new_params, 0)
+                }
+
+                fn update_at(&mut self, new_params: ModuleParams, offset: usize) -> Option<Indication> {
+                    let indication = if let ModuleParams::$module(params) = new_params.clone() {
+                        self.module.update_at(params, offset).map(Indication::$module)
                     } else {
                         panic!("module params mismatch! module = {:?}, params = {:?}", self.module, new_params);
+                    };
+
+                    // the module takes new params straight away even while
+                    // modulated, so that its inputs always match its params.
+                    // modulation is applied over them again next tick:
+                    if let Some(unmodulated) = &mut self.unmodulated {
+                        *unmodulated = new_params.clone();
+                        self.update_offset = Some(offset);
                     }
+
+                    self.update_inputs(&new_params);
+                    indication
+                }
+
+                fn modulation(&self) -> &[Modulation] {
+                    &self.modulation
+                }
+
+                fn set_modulation(&mut self, new_modulation: Vec<Modulation>) {
+                    let params = self.params();
+                    self.modulation = modulation::sanitize(&params, new_modulation);
+                    self.update_inputs(&params);
                 }
 
                 fn run_tick(&mut self, t: u64, inputs: &[InputRef], outputs: &mut [OutputRef]) -> Option<Indication> {
@@ -114,12 +204,33 @@ macro_rules! gen_dyn_module_impls {
                         self.module.receive_event(ev);
                     }
 
+                    let own_inputs = self.module.inputs().len().min(inputs.len());
+                    let (inputs, controls) = inputs.split_at(own_inputs);
+
+                    let offset = self.update_offset.take().unwrap_or(0);
+
+                    if modulation::is_active(controls) {
+                        let mut params = self.params();
+                        self.unmodulated.get_or_insert_with(|| params.clone());
+                        modulation::apply(&mut params, &self.modulation, controls);
+
+                        if let ModuleParams::$module(params) = params {
+                            self.module.update_at(params, offset);
+                        }
+                    } else if let Some(unmodulated) = self.unmodulated.take() {
+                        // control inputs were disconnected, so the module
+                        // goes back to its params as they were set:
+                        if let ModuleParams::$module(params) = unmodulated {
+                            self.module.update_at(params, offset);
+                        }
+                    }
+
                     self.module.run_tick(t, inputs, outputs)
                         .map(Indication::$module)
                 }
 
                 fn inputs(&self) -> &[Terminal] {
-                    self.module.inputs()
+                    &self.inputs
                 }
 
                 fn outputs(&self) -> &[Terminal] {
@@ -132,11 +243,11 @@ macro_rules! gen_dyn_module_impls {
 
 macro_rules! gen_host_fn {
     ($( $mod_name:ident::$module:ident , )*) => {
-        pub fn host(params: ModuleParams, base: ProjectBaseRef) -> (DynModuleHost, Indication) {
+        pub fn host(params: ModuleParams, base: ProjectBaseRef, mode: EngineMode) -> (DynModuleHost, Indication) {
             match params {
                 $(
                     ModuleParams::$module(params) => {
-                        let (host, indication) = ModuleHost::<module::$mod_name::$module>::new(params, base);
+                        let (host, indication) = ModuleHost::<module::$mod_name::$module>::new(params, base, mode);
                         (Box::new(host) as DynModuleHost, Indication::$module(indication))
                     }
                 )*
@@ -148,4 +259,4 @@ macro_rules! gen_host_fn {
 crate::enumerate_modules!{then gen_dyn_module_impls!}
 crate::enumerate_modules!{then gen_host_fn!}
 
-pub type DynModuleHost = Box<dyn DynModuleHostT>;
+pub type DynModuleHost = Box<dyn DynModuleHostT + Send>;
@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use mixlab_protocol::ModuleId;
+
+use crate::engine::{self, EngineMode, InputRef, Rate, Sample, VideoFrame, CHANNELS};
+use crate::engine::pool::WorkerPool;
+use crate::engine::timing::EngineStat;
+use crate::engine::workspace::Workspace;
+use crate::persist;
+use crate::project::ProjectBaseRef;
+
+/// Drives a workspace tick by tick without any wall-clock scheduling,
+/// collecting whatever arrives at the inputs of terminal modules.
+pub struct OfflineEngine {
+    workspace: Workspace,
+    rate: Rate,
+    pool: WorkerPool,
+    stat: EngineStat,
+    tick: u64,
+}
+
+pub struct OfflineTick {
+    // all stereo signals going into terminal modules, summed:
+    pub audio: Vec<Sample>,
+
+    // first video signal going into a terminal module, ordered by module id:
+    pub video: Option<VideoFrame>,
+}
+
+impl OfflineEngine {
+    pub fn new(workspace: &persist::Workspace, base: ProjectBaseRef) -> Self {
+        let rate = base.rate();
+        let pool = WorkerPool::new(rate);
+
+        OfflineEngine {
+            workspace: Workspace::from_persist(workspace, base, EngineMode::Offline),
+            rate,
+            stat: EngineStat::new(rate, pool.size()),
+            pool,
+            tick: 0,
+        }
+    }
+
+    pub fn rate(&self) -> Rate {
+        self.rate
+    }
+
+    pub fn run_tick(&mut self) -> OfflineTick {
+        let this_tick = self.tick;
+        self.tick += 1;
+
+        // ticks are considered on schedule as long as we keep up with real
+        // time. this only affects performance reporting
+        let scheduled_tick_end = Instant::now() + self.rate.tick_budget();
+        let tick_len = self.rate.samples_per_tick() * CHANNELS;
+
+        let mut audio_inputs = BTreeMap::<ModuleId, Vec<Sample>>::new();
+        let mut video_inputs = BTreeMap::<ModuleId, VideoFrame>::new();
+
+        let workspace = &mut self.workspace;
+        let pool = &self.pool;
+        let rate = self.rate;
+
+        self.stat.record_tick(scheduled_tick_end, |tick_stat| {
+            engine::run_workspace_tick(workspace, pool, rate, this_tick, tick_stat, |module_id, inputs| {
+                for input in inputs {
+                    match input {
+                        InputRef::Stereo(samples) => {
+                            let buff = audio_inputs.entry(module_id)
+                                .or_insert_with(|| vec![0.0; tick_len]);
+
+                            for (out, sample) in buff.iter_mut().zip(samples.iter()) {
+                                *out += sample;
+                            }
+                        }
+                        InputRef::Video(Some(frame)) => {
+                            video_inputs.entry(module_id)
+                                .or_insert_with(|| (*frame).clone());
+                        }
+                        InputRef::Video(None) |
+                        InputRef::Mono(_) |
+                        InputRef::Disconnected(_) => {}
+                    }
+                }
+            })
+        });
+
+        // sum in module id order so that renders are deterministic
+        let mut audio = vec![0.0; tick_len];
+
+        for samples in audio_inputs.values() {
+            for (out, sample) in audio.iter_mut().zip(samples.iter()) {
+                *out += sample;
+            }
+        }
+
+        let video = video_inputs.into_iter()
+            .next()
+            .map(|(_, frame)| frame);
+
+        OfflineTick { audio, video }
+    }
+}
@@ -0,0 +1,138 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::runtime;
+
+use mixlab_protocol::{Indication, ModuleId};
+
+use crate::engine::{DynModuleHost, InputRef, Output, Rate};
+use crate::engine::io::ZeroBuffers;
+
+/// A module to run for a tick. The module is moved to whichever worker picks
+/// the job up and handed back once it has run, along with its outputs.
+pub struct Job {
+    pub module_id: ModuleId,
+    pub module: DynModuleHost,
+    pub t: u64,
+    // outputs connected to each of the module's inputs:
+    pub inputs: Vec<Option<Arc<Output>>>,
+}
+
+pub struct Done {
+    pub module_id: ModuleId,
+    pub module: DynModuleHost,
+    pub outputs: Vec<Output>,
+    pub indication: thread::Result<Option<Indication>>,
+    // index of the worker that ran the module, and how long it took:
+    pub worker: usize,
+    pub elapsed: Duration,
+}
+
+/// Threads which modules are run on. Jobs go to whichever worker is free
+/// first. The threads exit once the pool is dropped.
+pub struct WorkerPool {
+    job_tx: Sender<Job>,
+    done_rx: Receiver<Done>,
+    size: usize,
+    zero: Arc<ZeroBuffers>,
+}
+
+impl WorkerPool {
+    /// Must be called from within a tokio runtime context, which workers
+    /// enter so that modules can spawn async tasks while running
+    pub fn new(rate: Rate) -> Self {
+        let size = num_cpus::get().max(1);
+        let runtime = runtime::Handle::current();
+        let zero = Arc::new(ZeroBuffers::new(rate));
+
+        let (job_tx, job_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        for index in 0..size {
+            let runtime = runtime.clone();
+            let job_rx = job_rx.clone();
+            let done_tx = done_tx.clone();
+            let zero = zero.clone();
+
+            thread::Builder::new()
+                .name(format!("engine-worker-{}", index))
+                .spawn(move || runtime.enter(|| run_worker(index, rate, &zero, job_rx, done_tx)))
+                .expect("spawn engine worker");
+        }
+
+        WorkerPool { job_tx, done_rx, size, zero }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// What disconnected inputs read as
+    pub fn zero_buffers(&self) -> &ZeroBuffers {
+        &self.zero
+    }
+
+    pub fn dispatch(&self, job: Job) {
+        self.job_tx.send(job).expect("engine workers stopped");
+    }
+
+    /// Blocks until the next dispatched job is done
+    pub fn recv(&self) -> Done {
+        self.done_rx.recv().expect("engine workers stopped")
+    }
+}
+
+fn run_worker(index: usize, rate: Rate, zero: &ZeroBuffers, jobs: Arc<Mutex<Receiver<Job>>>, done_tx: Sender<Done>) {
+    loop {
+        // the lock is only held while waiting for the next job:
+        let job = match jobs.lock().expect("lock jobs").recv() {
+            Ok(job) => job,
+            Err(_) => { return; }
+        };
+
+        let Job { module_id, mut module, t, inputs } = job;
+
+        let mut outputs = module.outputs().iter()
+            .map(|output| Output::from_line_type(output.line_type(), rate))
+            .collect::<Vec<_>>();
+
+        let start = Instant::now();
+
+        // panics are handed back to be resumed on the engine thread, as if
+        // the module had been run there:
+        let indication = panic::catch_unwind(AssertUnwindSafe(|| {
+            let input_refs = inputs.iter()
+                .map(|input| {
+                    input.as_ref()
+                        .map(|output| output.as_input_ref())
+                        .unwrap_or(InputRef::Disconnected(zero))
+                })
+                .collect::<Vec<_>>();
+
+            let mut output_refs = outputs.iter_mut()
+                .map(|output| output.as_output_ref())
+                .collect::<Vec<_>>();
+
+            module.run_tick(t, &input_refs, &mut output_refs)
+        }));
+
+        let elapsed = start.elapsed();
+
+        let done = Done {
+            module_id,
+            module,
+            outputs,
+            indication,
+            worker: index,
+            elapsed,
+        };
+
+        if done_tx.send(done).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use mixlab_util::time::MediaDuration;
+
+const SAMPLE_RATES: &[usize] = &[8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000];
+const MAX_TICK_RATE: usize = 1000;
+
+/// The sample rate the engine runs at and how many ticks it divides each
+/// second into. These are settings of the project, and are fixed for as long
+/// as the project is open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rate {
+    sample_rate: usize,
+    tick_rate: usize,
+}
+
+#[derive(Debug)]
+pub enum RateError {
+    UnsupportedSampleRate(usize),
+    UnsupportedTickRate(usize),
+    // every tick must be a whole number of samples long:
+    UnevenTicks { sample_rate: usize, tick_rate: usize },
+}
+
+impl Default for Rate {
+    fn default() -> Self {
+        Rate {
+            sample_rate: 44100,
+            tick_rate: 60,
+        }
+    }
+}
+
+impl Rate {
+    pub fn new(sample_rate: usize, tick_rate: usize) -> Result<Self, RateError> {
+        if !SAMPLE_RATES.contains(&sample_rate) {
+            return Err(RateError::UnsupportedSampleRate(sample_rate));
+        }
+
+        if tick_rate == 0 || tick_rate > MAX_TICK_RATE {
+            return Err(RateError::UnsupportedTickRate(tick_rate));
+        }
+
+        if sample_rate % tick_rate != 0 {
+            return Err(RateError::UnevenTicks { sample_rate, tick_rate });
+        }
+
+        Ok(Rate { sample_rate, tick_rate })
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    pub fn tick_rate(&self) -> usize {
+        self.tick_rate
+    }
+
+    pub fn samples_per_tick(&self) -> usize {
+        self.sample_rate / self.tick_rate
+    }
+
+    pub fn tick_budget(&self) -> Duration {
+        Duration::from_micros(1_000_000 / self.tick_rate as u64)
+    }
+
+    /// Time from the start of the engine to the start of `tick`. Calculated
+    /// from the tick number each time rather than by summing tick budgets,
+    /// so as not to drift from the wall clock over time
+    pub fn tick_start(&self, tick: u64) -> Duration {
+        Duration::from_micros((tick * 1_000_000) / self.tick_rate as u64)
+    }
+
+    /// Duration of `samples` samples per channel
+    pub fn duration(&self, samples: usize) -> MediaDuration {
+        MediaDuration::new(samples as i64, self.sample_rate as i64)
+    }
+
+    pub fn tick_duration(&self) -> MediaDuration {
+        self.duration(self.samples_per_tick())
+    }
+}
@@ -0,0 +1,110 @@
+use crate::engine::Rate;
+
+// long enough that changing a param does not click, short enough that
+// controls still feel immediate:
+const RAMP_MILLIS: usize = 20;
+
+/// A numeric param which ramps to new values over a few milliseconds rather
+/// than jumping straight to them, which would cause audible clicks and zipper
+/// noise while the param is being changed.
+///
+/// Changes can be placed at a sample offset into the next tick, so that a run
+/// of changes made in quick succession - dragging a knob, say - keeps its
+/// timing rather than every change landing on a tick boundary.
+#[derive(Debug, Clone)]
+pub struct Smoothed {
+    value: f64,
+    target: f64,
+    step: f64,
+    steps_left: usize,
+    ramp_len: usize,
+    // changes to start ramping to during the next tick, ordered by offset:
+    scheduled: Vec<(usize, f64)>,
+}
+
+impl Smoothed {
+    pub fn new(value: f64, rate: Rate) -> Self {
+        Smoothed {
+            value,
+            target: value,
+            step: 0.0,
+            steps_left: 0,
+            ramp_len: (rate.sample_rate() * RAMP_MILLIS / 1000).max(1),
+            scheduled: Vec::new(),
+        }
+    }
+
+    /// The value the param is settling on
+    pub fn target(&self) -> f64 {
+        self.scheduled.last()
+            .map(|(_, target)| *target)
+            .unwrap_or(self.target)
+    }
+
+    /// Ramps to `target` from the start of the next tick
+    pub fn set(&mut self, target: f64) {
+        self.set_at(0, target);
+    }
+
+    /// Ramps to `target` from `offset` samples into the next tick. Offsets
+    /// past the end of the tick take effect on its last sample
+    pub fn set_at(&mut self, offset: usize, target: f64) {
+        // a change supersedes any made before it which were to happen later:
+        self.scheduled.retain(|(at, _)| *at < offset);
+        self.scheduled.push((offset, target));
+    }
+
+    /// The value of the param for each of the `len` samples of the next tick
+    pub fn tick(&mut self, len: usize) -> Ramp<'_> {
+        let last = len.saturating_sub(1);
+
+        for (at, _) in self.scheduled.iter_mut() {
+            *at = (*at).min(last);
+        }
+
+        Ramp { smoothed: self, index: 0, len }
+    }
+
+    fn next_value(&mut self, index: usize) -> f64 {
+        while self.scheduled.first().map(|(at, _)| *at <= index).unwrap_or(false) {
+            let (_, target) = self.scheduled.remove(0);
+            self.target = target;
+            self.steps_left = self.ramp_len;
+            self.step = (target - self.value) / self.ramp_len as f64;
+        }
+
+        if self.steps_left > 0 {
+            self.steps_left -= 1;
+
+            // land exactly on the target rather than wherever accumulated
+            // rounding error puts us:
+            self.value = if self.steps_left == 0 {
+                self.target
+            } else {
+                self.value + self.step
+            };
+        }
+
+        self.value
+    }
+}
+
+pub struct Ramp<'a> {
+    smoothed: &'a mut Smoothed,
+    index: usize,
+    len: usize,
+}
+
+impl<'a> Iterator for Ramp<'a> {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<f64> {
+        if self.index == self.len {
+            return None;
+        }
+
+        let value = self.smoothed.next_value(self.index);
+        self.index += 1;
+        Some(value)
+    }
+}
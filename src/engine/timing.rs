@@ -3,23 +3,28 @@ use std::time::{Instant, Duration};
 
 use mixlab_protocol::{ModuleId, PerformanceInfo, PerformanceAccount, PerformanceMetric, Microseconds};
 
-use crate::engine;
+use crate::engine::Rate;
 use crate::util;
 
-pub const TICK_BUDGET: Duration = Duration::from_micros(1_000_000 / engine::TICKS_PER_SECOND as u64);
-
 pub struct EngineStat {
+    rate: Rate,
     is_realtime: bool,
     last_lagged: Option<Instant>,
+    last_tick: Duration,
     accounts: HashMap<PerformanceAccount, Stat>,
+    // time each worker spent running modules in the last tick:
+    workers: Vec<Duration>,
 }
 
 impl EngineStat {
-    pub fn new() -> Self {
+    pub fn new(rate: Rate, workers: usize) -> Self {
         EngineStat {
+            rate,
             is_realtime: false,
             last_lagged: None,
+            last_tick: Duration::from_micros(0),
             accounts: HashMap::new(),
+            workers: vec![Duration::from_micros(0); workers],
         }
     }
 
@@ -32,10 +37,13 @@ impl EngineStat {
         tick.stat.is_realtime = end < scheduled_tick_end;
 
         let tick_time = end - start;
+        tick.stat.last_tick = tick_time;
+
+        let tick_budget = tick.stat.rate.tick_budget();
 
-        if tick_time > TICK_BUDGET {
+        if tick_time > tick_budget {
             tick.stat.last_lagged = Some(Instant::now());
-            eprintln!("WARNING: tick ran over time! elapsed: {} us, budget: {} us", tick_time.as_micros(), TICK_BUDGET.as_micros());
+            eprintln!("WARNING: tick ran over time! elapsed: {} us, budget: {} us", tick_time.as_micros(), tick_budget.as_micros());
         }
 
         tick.stat.add_sample(PerformanceAccount::Engine, tick_time - tick.modules_accounted_for);
@@ -49,13 +57,19 @@ impl EngineStat {
         PerformanceInfo {
             realtime: self.is_realtime,
             lag: util::temporal_warning(time_since_lag),
-            tick_rate: engine::TICKS_PER_SECOND,
-            tick_budget: Microseconds(TICK_BUDGET.as_micros() as u64),
+            tick_rate: self.rate.tick_rate(),
+            tick_budget: Microseconds(self.rate.tick_budget().as_micros() as u64),
+            tick_time: Microseconds(self.last_tick.as_micros() as u64),
             accounts: self.accounts.iter().map(|(account, stat)| {
                 (*account, PerformanceMetric {
                     last: Microseconds(stat.last().as_micros() as u64),
                 })
-            }).collect()
+            }).collect(),
+            workers: self.workers.iter().map(|busy| {
+                PerformanceMetric {
+                    last: Microseconds(busy.as_micros() as u64),
+                }
+            }).collect(),
         }
     }
 
@@ -72,26 +86,38 @@ impl EngineStat {
 
 pub struct TickStat<'a> {
     stat: &'a mut EngineStat,
+    // time the engine thread spent waiting on workers to run modules:
     modules_accounted_for: Duration,
 }
 
 impl<'a> TickStat<'a> {
     fn new(stat: &'a mut EngineStat) -> Self {
+        for busy in &mut stat.workers {
+            *busy = Duration::from_micros(0);
+        }
+
         TickStat {
             stat,
             modules_accounted_for: Duration::from_micros(0),
         }
     }
 
-    pub fn record_module<T>(&mut self, module_id: ModuleId, f: impl FnOnce() -> T) -> T {
+    pub fn record_wait<T>(&mut self, f: impl FnOnce() -> T) -> T {
         let start = Instant::now();
         let retn = f();
-        let end = Instant::now();
-        let elapsed_time = end - start;
-        self.modules_accounted_for += elapsed_time;
-        self.stat.add_sample(PerformanceAccount::Module(module_id), elapsed_time);
+        self.modules_accounted_for += start.elapsed();
         retn
     }
+
+    /// Records the time a module took to run on a worker. Modules run in
+    /// parallel, so these can add up to more than the tick took.
+    pub fn record_module(&mut self, module_id: ModuleId, worker: usize, elapsed: Duration) {
+        if let Some(busy) = self.stat.workers.get_mut(worker) {
+            *busy += elapsed;
+        }
+
+        self.stat.add_sample(PerformanceAccount::Module(module_id), elapsed);
+    }
 }
 
 struct Stat {
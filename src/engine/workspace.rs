@@ -1,10 +1,12 @@
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 use tokio::sync::watch;
 
 use mixlab_protocol::{ModuleId, InputId, OutputId, TerminalId, WindowGeometry, Indication, LineType};
 
+use crate::engine::{EngineMode, Output};
 use crate::engine::module::{self, DynModuleHost};
 use crate::persist;
 use crate::project::ProjectBaseRef;
@@ -16,17 +18,20 @@ pub struct Workspace {
     pub(in crate::engine) geometry: HashMap<ModuleId, WindowGeometry>,
     pub(in crate::engine) connections: HashMap<InputId, OutputId>,
     pub(in crate::engine) indications: HashMap<ModuleId, Indication>,
+    // outputs from the last tick which feed feedback connections:
+    pub(in crate::engine) delayed: HashMap<OutputId, Arc<Output>>,
 }
 
 impl Workspace {
-    pub fn from_persist(save: &persist::Workspace, base: ProjectBaseRef) -> Self {
+    pub fn from_persist(save: &persist::Workspace, base: ProjectBaseRef, mode: EngineMode) -> Self {
         let mut modules = HashMap::new();
         let mut geometry = HashMap::new();
         let mut indications = HashMap::new();
 
         // load modules and geometry
         for (module_id, saved_module) in &save.modules {
-            let (module, indication) = module::host(saved_module.params.clone(), base.clone());
+            let (mut module, indication) = module::host(saved_module.params.clone(), base.clone(), mode);
+            module.set_modulation(saved_module.modulation.clone());
             modules.insert(*module_id, module);
             geometry.insert(*module_id, saved_module.geometry.clone());
             indications.insert(*module_id, indication);
@@ -38,6 +43,7 @@ impl Workspace {
             geometry,
             connections: HashMap::new(),
             indications,
+            delayed: HashMap::new(),
         };
 
         // load connections after loading all modules
@@ -75,6 +81,7 @@ impl Workspace {
                         params,
                         geometry,
                         inputs,
+                        modulation: module.modulation().to_vec(),
                     })
                 })
                 .collect()
@@ -136,7 +143,7 @@ impl WorkspaceEmbryo {
     }
 
     pub fn spawn(self, base: ProjectBaseRef) -> SyncWorkspace {
-        let workspace = Workspace::from_persist(&self.workspace, base);
+        let workspace = Workspace::from_persist(&self.workspace, base, EngineMode::Realtime);
 
         SyncWorkspace {
             workspace,
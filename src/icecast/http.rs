@@ -1,8 +1,9 @@
+use std::fmt::{self, Debug};
 use std::str;
 
 use derive_more::From;
 use httparse::Request;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use crate::listen::PeekTcpStream;
 
@@ -14,6 +15,7 @@ pub enum Error {
     HeadersTooLong,
     NoPath,
     NoContentType,
+    BadAuthorization,
 }
 
 #[derive(Debug)]
@@ -25,9 +27,30 @@ pub enum ContentType {
 pub struct RequestInfo {
     pub path: String,
     pub content_type: Option<ContentType>,
+    pub credentials: Option<Credentials>,
     pub stream_data: Vec<u8>,
 }
 
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Credentials {{ username: {:?}, .. }}", self.username)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Refusal {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    UnsupportedMediaType,
+}
+
 pub async fn parse(stream: &mut PeekTcpStream) -> Result<RequestInfo, Error> {
     let mut buff = [0u8; 4096];
     let mut buff_offset = 0;
@@ -68,11 +91,59 @@ pub async fn parse(stream: &mut PeekTcpStream) -> Result<RequestInfo, Error> {
             _ => None,
         };
 
+        let credentials = request.headers.iter()
+            .find(|header| header.name.eq_ignore_ascii_case("authorization"))
+            .map(|header| parse_basic_auth(header.value))
+            .transpose()?;
+
         Ok(RequestInfo {
             path: request.path.ok_or(Error::NoPath)?.to_owned(),
             content_type,
+            credentials,
             stream_data: stream_data.to_vec(),
         })
     }
 }
 
+fn parse_basic_auth(value: &[u8]) -> Result<Credentials, Error> {
+    let value = str::from_utf8(value)
+        .map_err(|_| Error::BadAuthorization)?;
+
+    let mut parts = value.trim().splitn(2, ' ');
+
+    // splitn always returns at least one item:
+    let scheme = parts.next().unwrap();
+
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return Err(Error::BadAuthorization);
+    }
+
+    let encoded = parts.next().ok_or(Error::BadAuthorization)?;
+
+    let decoded = base64::decode(encoded.trim())
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .ok_or(Error::BadAuthorization)?;
+
+    let mut parts = decoded.splitn(2, ':');
+    let username = parts.next().unwrap().to_owned();
+    let password = parts.next().ok_or(Error::BadAuthorization)?.to_owned();
+
+    Ok(Credentials { username, password })
+}
+
+/// Writes an error response to the source client. Icecast sources are
+/// expected to understand plain HTTP status codes, with 401 prompting for
+/// credentials.
+pub async fn refuse(stream: &mut PeekTcpStream, refusal: Refusal) -> Result<(), tokio::io::Error> {
+    let response = match refusal {
+        Refusal::BadRequest => "HTTP/1.0 400 Bad Request\r\n\r\n",
+        Refusal::Unauthorized => "HTTP/1.0 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"mixlab\"\r\n\r\n",
+        Refusal::Forbidden => "HTTP/1.0 403 Forbidden\r\n\r\n",
+        Refusal::NotFound => "HTTP/1.0 404 Not Found\r\n\r\n",
+        Refusal::UnsupportedMediaType => "HTTP/1.0 415 Unsupported Media Type\r\n\r\n",
+    };
+
+    stream.write_all(response.as_bytes()).await
+}
+
@@ -8,25 +8,31 @@ use derive_more::From;
 use tokio::io::AsyncWriteExt;
 
 use mixlab_codec::ogg::{self, OggStream};
+use mixlab_codec::resample::Resampler;
 use mixlab_codec::{AudioStream, StreamRead, StreamError};
-use mixlab_util::time::{MediaTime, MediaDuration};
+use mixlab_protocol::StreamProtocol;
+use mixlab_util::time::MediaTime;
 
-use crate::engine::SAMPLE_RATE;
+use crate::engine::Rate;
 use crate::listen::PeekTcpStream;
-use crate::source::{Registry, ListenError, SourceRecv, SourceSend};
+use crate::source::{Registry, ConnectError, ListenError, SourceRecv, SourceSend, StreamInfo};
 use crate::throttle::AudioThrottle;
 use crate::util::SyncRead;
 
-use self::http::ContentType;
+use self::http::{ContentType, Refusal};
 
 lazy_static::lazy_static! {
-    static ref MOUNTPOINTS: Registry = Registry::new();
+    static ref MOUNTPOINTS: Registry = Registry::new(StreamProtocol::Icecast);
 }
 
-pub async fn accept(mut stream: PeekTcpStream) {
+pub async fn accept(mut stream: PeekTcpStream, rate: Rate) {
     let req = match http::parse(&mut stream).await {
         Ok(req) => req,
-        Err(_) => { return; }
+        Err(http::Error::Io(_)) | Err(http::Error::Eof) => { return; }
+        Err(_) => {
+            let _ = http::refuse(&mut stream, Refusal::BadRequest).await;
+            return;
+        }
     };
 
     // any partial stream data which we might have caught in the http::parse above
@@ -35,14 +41,26 @@ pub async fn accept(mut stream: PeekTcpStream) {
     let content_type = if let Some(ty) = req.content_type {
         ty
     } else {
-        // unknown content type
+        let _ = http::refuse(&mut stream, Refusal::UnsupportedMediaType).await;
         return;
     };
 
-    let send = match MOUNTPOINTS.connect(&req.path) {
+    // icecast sources authenticate with basic auth, conventionally with
+    // username "source". only the password is checked against the secret:
+    let secret = req.credentials.as_ref().map(|credentials| credentials.password.as_str());
+
+    let send = match MOUNTPOINTS.connect(&req.path, secret) {
         Ok(send) => send,
         Err(e) => {
             eprintln!("could not connect to icecast mountpoint: {:?}", e);
+
+            let refusal = match e {
+                ConnectError::NoMountpoint => Refusal::NotFound,
+                ConnectError::AlreadyConnected => Refusal::Forbidden,
+                ConnectError::Unauthorized => Refusal::Unauthorized,
+            };
+
+            let _ = http::refuse(&mut stream, refusal).await;
             return;
         }
     };
@@ -53,7 +71,7 @@ pub async fn accept(mut stream: PeekTcpStream) {
     thread::spawn(move || {
         let stream = stream_data.chain(SyncRead(stream));
 
-        match run_decode_thread(send, stream, content_type) {
+        match run_decode_thread(send, stream, content_type, rate) {
             Ok(()) => {}
             Err(e) => {
                 eprintln!("error in decode thread: {:?}", e);
@@ -62,8 +80,12 @@ pub async fn accept(mut stream: PeekTcpStream) {
     });
 }
 
-pub fn listen(mountpoint: &str) -> Result<SourceRecv, ListenError> {
-    MOUNTPOINTS.listen(mountpoint)
+pub fn listen(mountpoint: &str, secret: Option<String>) -> Result<SourceRecv, ListenError> {
+    MOUNTPOINTS.listen(mountpoint, secret)
+}
+
+pub fn registry() -> &'static Registry {
+    &MOUNTPOINTS
 }
 
 #[derive(From, Debug)]
@@ -73,16 +95,22 @@ enum DecodeThreadError {
     Io(io::Error),
 }
 
-fn run_decode_thread(mut send: SourceSend, stream: impl io::Read, content_type: ContentType)
+fn run_decode_thread(mut send: SourceSend, stream: impl io::Read, content_type: ContentType, rate: Rate)
     -> Result<(), DecodeThreadError>
 {
-    let mut audio = match content_type {
+    let (mut audio, codec) = match content_type {
         ContentType::Ogg => {
             let ogg = OggStream::new(stream)?;
-            Box::new(ogg) as Box<dyn AudioStream>
+            (Box::new(ogg) as Box<dyn AudioStream>, "Vorbis")
         }
     };
 
+    send.set_stream_info(StreamInfo {
+        codec: Some(codec.to_owned()),
+        resolution: None,
+        bitrate_kbps: None,
+    });
+
     let channels = audio.channels();
 
     if channels == 0 {
@@ -91,13 +119,11 @@ fn run_decode_thread(mut send: SourceSend, stream: impl io::Read, content_type:
         return Ok(());
     }
 
-    if audio.sample_rate() != SAMPLE_RATE {
-        // not much we can do for now. TODO implement resampling
-        return Ok(());
-    }
+    // the icecast source always outputs stereo at the engine sample rate:
+    let mut resampler = Resampler::new(2, audio.sample_rate(), rate.sample_rate());
 
     let mut timestamp = MediaTime::zero();
-    let mut throttle = AudioThrottle::new();
+    let mut throttle = AudioThrottle::new(rate.sample_rate());
 
     while let Some(packet) = audio.read().transpose() {
         match packet {
@@ -122,11 +148,16 @@ fn run_decode_thread(mut send: SourceSend, stream: impl io::Read, content_type:
                     }
                 }
 
-                send.write_audio(timestamp, samples)
+                let mut resampled = Vec::with_capacity(samples.len());
+                resampler.process(&samples, &mut resampled);
+
+                let resampled_count = resampled.len() / 2;
+
+                send.write_audio(timestamp, resampled)
                     .map_err(|()| DecodeThreadError::ListenerDisconnected)?;
 
-                timestamp += MediaDuration::new(sample_count as i64, audio.sample_rate() as i64);
-                throttle.send_samples(sample_count);
+                timestamp += rate.duration(resampled_count);
+                throttle.send_samples(resampled_count);
             }
             Ok(StreamRead::Metadata(_)) => {
                 // ignore metadata for now
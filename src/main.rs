@@ -1,9 +1,11 @@
+mod cli;
 mod db;
 mod engine;
 mod icecast;
 mod listen;
 mod persist;
 mod project;
+mod render;
 mod rtmp;
 mod server;
 mod source;
@@ -14,12 +16,24 @@ mod video;
 #[macro_use]
 mod module;
 
+use std::process;
+
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
-struct Opts {
-    #[structopt(flatten)]
-    run: server::RunOpts,
+enum Opts {
+    /// Start the server
+    Run(server::RunOpts),
+    /// Render the workspace to a .wav or .mp4 file faster than realtime
+    Render(render::RenderOpts),
+    /// Manage the media library
+    Media(cli::MediaCommand),
+    /// Inspect, replace, export or import the workspace
+    Workspace(cli::WorkspaceCommand),
+    /// Maintain the project database
+    Db(cli::DbCommand),
+    /// Show or change the project's engine settings
+    Settings(cli::SettingsCommand),
 }
 
 fn main() {
@@ -33,5 +47,26 @@ fn main() {
         .build()
         .unwrap();
 
-    runtime.block_on(server::run(opts.run));
+    match opts {
+        Opts::Run(opts) => {
+            runtime.block_on(server::run(opts));
+        }
+        Opts::Render(opts) => {
+            if let Err(e) = runtime.block_on(render::run(opts)) {
+                eprintln!("render failed: {:?}", e);
+                process::exit(1);
+            }
+        }
+        Opts::Media(command) => exit_on_error(runtime.block_on(cli::media(command))),
+        Opts::Workspace(command) => exit_on_error(runtime.block_on(cli::workspace(command))),
+        Opts::Db(command) => exit_on_error(runtime.block_on(cli::db(command))),
+        Opts::Settings(command) => exit_on_error(runtime.block_on(cli::settings(command))),
+    }
+}
+
+fn exit_on_error(result: Result<(), cli::CliError>) {
+    if let Err(e) = result {
+        eprintln!("error: {:?}", e);
+        process::exit(1);
+    }
 }
@@ -1,4 +1,4 @@
-use crate::engine::{self, Sample, InputRef, OutputRef};
+use crate::engine::{self, Sample, InputRef, OutputRef, Smoothed, CHANNELS};
 use crate::module::{ModuleT, LineType, Terminal};
 
 use mixlab_protocol::AmplifierParams;
@@ -6,6 +6,8 @@ use mixlab_protocol::AmplifierParams;
 #[derive(Debug)]
 pub struct Amplifier {
     params: AmplifierParams,
+    amplitude: Smoothed,
+    mod_depth: Smoothed,
     inputs: Vec<Terminal>,
     outputs: Vec<Terminal>,
 }
@@ -15,8 +17,12 @@ impl ModuleT for Amplifier {
     type Indication = ();
     type Event = ();
 
-    fn create(params: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+    fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+        let rate = ctx.rate();
+
         (Self {
+            amplitude: Smoothed::new(params.amplitude, rate),
+            mod_depth: Smoothed::new(params.mod_depth, rate),
             params,
             inputs: vec![
                 LineType::Stereo.labeled("Input"),
@@ -31,13 +37,17 @@ impl ModuleT for Amplifier {
     }
 
     fn update(&mut self, params: Self::Params) -> Option<Self::Indication> {
+        self.update_at(params, 0)
+    }
+
+    fn update_at(&mut self, params: Self::Params, offset: usize) -> Option<Self::Indication> {
+        self.amplitude.set_at(offset, params.amplitude);
+        self.mod_depth.set_at(offset, params.mod_depth);
         self.params = params;
         None
     }
 
     fn run_tick(&mut self, _t: u64, inputs: &[InputRef], outputs: &mut [OutputRef]) -> Option<Self::Indication> {
-        let AmplifierParams {mod_depth, amplitude} = self.params;
-
         let input = inputs[0].expect_stereo();
         let mod_input = if inputs[1].connected() {
             Some(inputs[1].expect_mono())
@@ -47,13 +57,18 @@ impl ModuleT for Amplifier {
 
         let output = outputs[0].expect_stereo();
 
-        let len = input.len();
+        let frames = input.len() / CHANNELS;
+        let params = self.amplitude.tick(frames).zip(self.mod_depth.tick(frames));
 
-        for i in 0..len {
-            // mod input is a mono channel and so half the length:
-            let mod_value = mod_input.map(|buff| buff[i / 2] as f64).unwrap_or(1.0);
+        for (i, (amplitude, mod_depth)) in params.enumerate() {
+            // mod input is a mono channel and so has one sample per frame:
+            let mod_value = mod_input.map(|buff| buff[i] as f64).unwrap_or(1.0);
+            let gain = depth(mod_value, mod_depth) * amplitude;
 
-            output[i] = (input[i] as f64 * depth(mod_value, mod_depth) * amplitude) as Sample;
+            for ch in 0..CHANNELS {
+                let index = i * CHANNELS + ch;
+                output[index] = (input[index] as f64 * gain) as Sample;
+            }
         }
 
         None
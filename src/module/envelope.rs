@@ -1,4 +1,4 @@
-use crate::engine::{self, InputRef, OutputRef, SAMPLE_RATE};
+use crate::engine::{self, InputRef, OutputRef};
 use crate::module::{ModuleT, LineType, Terminal};
 
 use mixlab_protocol::EnvelopeParams;
@@ -13,8 +13,8 @@ enum EnvelopeState {
 }
 
 type Ms = f64;
-fn sample_seq_duration_ms(first: SampleSeq, last: SampleSeq) -> Ms {
-    (last - first) as f64 / SAMPLE_RATE as f64 * 1000.0
+fn sample_seq_duration_ms(first: SampleSeq, last: SampleSeq, sample_rate: usize) -> Ms {
+    (last - first) as f64 / sample_rate as f64 * 1000.0
 }
 
 fn clamp(x: f64) -> f64 {
@@ -31,11 +31,11 @@ fn invert(x: f64) -> f64 {
     1.0 - x
 }
 
-fn amplitude(params: &EnvelopeParams, state: &EnvelopeState, t: SampleSeq) -> f64 {
+fn amplitude(params: &EnvelopeParams, state: &EnvelopeState, t: SampleSeq, sample_rate: usize) -> f64 {
     match state {
         EnvelopeState::Initial => 0.0,
         EnvelopeState::TriggerOn {on} => {
-            let ms_since_on = sample_seq_duration_ms(*on, t);
+            let ms_since_on = sample_seq_duration_ms(*on, t, sample_rate);
 
             if ms_since_on < params.attack_ms {
                 // Currently in attack phase
@@ -49,7 +49,7 @@ fn amplitude(params: &EnvelopeParams, state: &EnvelopeState, t: SampleSeq) -> f6
             }
         }
         EnvelopeState::TriggerOff {off, off_amplitude} => {
-            let ms_since_off = sample_seq_duration_ms(*off, t);
+            let ms_since_off = sample_seq_duration_ms(*off, t, sample_rate);
             let release_amplitude = invert(clamp(1.0 / params.release_ms * ms_since_off));
 
             off_amplitude * release_amplitude
@@ -61,6 +61,7 @@ fn amplitude(params: &EnvelopeParams, state: &EnvelopeState, t: SampleSeq) -> f6
 pub struct Envelope {
     params: EnvelopeParams,
     state: EnvelopeState,
+    sample_rate: usize,
     inputs: Vec<Terminal>,
     outputs: Vec<Terminal>,
 }
@@ -70,10 +71,11 @@ impl ModuleT for Envelope {
     type Indication = ();
     type Event = ();
 
-    fn create(params: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+    fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
         (Self {
             params,
             state: EnvelopeState::Initial,
+            sample_rate: ctx.rate().sample_rate(),
             inputs: vec![LineType::Mono.unlabeled()],
             outputs: vec![LineType::Mono.unlabeled()],
         }, ())
@@ -107,13 +109,13 @@ impl ModuleT for Envelope {
                     if input[i] == 0.0 {
                         self.state = EnvelopeState::TriggerOff {
                             off: sample_seq,
-                            off_amplitude: amplitude(&self.params, &self.state, sample_seq)
+                            off_amplitude: amplitude(&self.params, &self.state, sample_seq, self.sample_rate)
                         };
                     }
                 }
             }
             // Then set output
-            output[i] = amplitude(&self.params, &self.state, sample_seq) as f32;
+            output[i] = amplitude(&self.params, &self.state, sample_seq, self.sample_rate) as f32;
         }
 
         None
@@ -2,7 +2,7 @@ use std::f64;
 
 use mixlab_protocol::EqThreeParams;
 
-use crate::engine::{self, InputRef, OutputRef, SAMPLE_RATE};
+use crate::engine::{self, InputRef, OutputRef, Smoothed};
 use crate::module::{ModuleT, LineType, Terminal};
 
 const FREQ_LO: f64 = 420.0;
@@ -14,6 +14,11 @@ const VSA: f64 = 1.0 / 4294967295.0; // Very small amount (Denormal Fix)
 pub struct EqThree {
     params: EqThreeParams,
 
+    // linear gain of each band
+    gain_lo: Smoothed,
+    gain_mid: Smoothed,
+    gain_hi: Smoothed,
+
     // filter 1 (low band)
     lo: LowPass,
     hi: LowPass,
@@ -30,11 +35,16 @@ impl ModuleT for EqThree {
     type Indication = ();
     type Event = ();
 
-    fn create(params: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
-        let lo = LowPass::new(FREQ_LO);
-        let hi = LowPass::new(FREQ_HI);
+    fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+        let rate = ctx.rate();
+        let sample_rate = rate.sample_rate();
+        let lo = LowPass::new(FREQ_LO, sample_rate);
+        let hi = LowPass::new(FREQ_HI, sample_rate);
 
         let eq_three = Self {
+            gain_lo: Smoothed::new(params.gain_lo.to_linear(), rate),
+            gain_mid: Smoothed::new(params.gain_mid.to_linear(), rate),
+            gain_hi: Smoothed::new(params.gain_hi.to_linear(), rate),
             params,
             lo,
             hi,
@@ -51,6 +61,13 @@ impl ModuleT for EqThree {
     }
 
     fn update(&mut self, params: Self::Params) -> Option<Self::Indication> {
+        self.update_at(params, 0)
+    }
+
+    fn update_at(&mut self, params: Self::Params, offset: usize) -> Option<Self::Indication> {
+        self.gain_lo.set_at(offset, params.gain_lo.to_linear());
+        self.gain_mid.set_at(offset, params.gain_mid.to_linear());
+        self.gain_hi.set_at(offset, params.gain_hi.to_linear());
         self.params = params;
         None
     }
@@ -59,11 +76,13 @@ impl ModuleT for EqThree {
         let input = inputs[0].expect_mono();
         let output = outputs[0].expect_mono();
 
-        let gain_lo = self.params.gain_lo.to_linear();
-        let gain_mid = self.params.gain_mid.to_linear();
-        let gain_hi = self.params.gain_hi.to_linear();
+        let len = input.len();
 
-        for (input, output) in input.iter().copied().zip(output.iter_mut()) {
+        let gains = self.gain_lo.tick(len)
+            .zip(self.gain_mid.tick(len))
+            .zip(self.gain_hi.tick(len));
+
+        for ((input, output), ((gain_lo, gain_mid), gain_hi)) in input.iter().copied().zip(output.iter_mut()).zip(gains) {
             let sample = input as f64;
 
             let lo = self.lo.pump(sample);
@@ -104,14 +123,14 @@ struct LowPass {
 }
 
 impl LowPass {
-    pub fn new(freq: f64) -> Self {
+    pub fn new(freq: f64, sample_rate: usize) -> Self {
         let mut filter = LowPass { freq: 0.0, poles: [0.0, 0.0, 0.0, 0.0] };
-        filter.set_freq(freq);
+        filter.set_freq(freq, sample_rate);
         filter
     }
 
-    pub fn set_freq(&mut self, freq: f64) {
-        self.freq = 2.0 * f64::sin(f64::consts::PI * freq / (SAMPLE_RATE as f64));
+    pub fn set_freq(&mut self, freq: f64, sample_rate: usize) {
+        self.freq = 2.0 * f64::sin(f64::consts::PI * freq / (sample_rate as f64));
     }
 
     pub fn pump(&mut self, sample: f64) -> f64 {
@@ -126,7 +145,9 @@ impl LowPass {
 
 #[cfg(test)]
 mod tests {
+    use crate::engine::{EngineMode, ModuleCtx, Rate};
     use crate::module::{ModuleT, InputRef, OutputRef};
+    use crate::project::ProjectBase;
     use mixlab_protocol::{Decibel, EqThreeParams};
     use super::EqThree;
 
@@ -147,19 +168,27 @@ mod tests {
         bytes
     }
 
-    #[test]
-    fn basic_smoke_test() {
+    #[tokio::test]
+    async fn basic_smoke_test() {
         let input = bytes_to_f32s(include_bytes!("../../fixtures/module/eq_three/chronos.f32.raw"));
 
+        let rate = Rate::default();
+        let ctx = ModuleCtx::detached(ProjectBase::in_memory(rate), EngineMode::Offline);
+
         let (mut eq, _) = EqThree::create(EqThreeParams {
             gain_lo: Decibel(4.0),
             gain_mid: Decibel(0.0),
             gain_hi: Decibel(4.0),
-        });
+        }, ctx);
 
         let mut output = vec![0.0; input.len()];
 
-        eq.run_tick(0, &[InputRef::Mono(&input)], &mut [OutputRef::Mono(&mut output)]);
+        // gains start out at their params rather than ramping to them, so
+        // smoothing must leave the fixture output as it was before. run tick
+        // by tick as the engine would, in case ramps carry across ticks:
+        for (input, output) in input.chunks(rate.samples_per_tick()).zip(output.chunks_mut(rate.samples_per_tick())) {
+            eq.run_tick(0, &[InputRef::Mono(input)], &mut [OutputRef::Mono(output)]);
+        }
 
         let expected_output = bytes_to_f32s(include_bytes!("../../fixtures/module/eq_three/chronos-eq.f32.raw"));
 
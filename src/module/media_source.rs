@@ -1,20 +1,23 @@
 use std::collections::VecDeque;
-use std::sync::mpsc::{self, SyncSender, Receiver, TryRecvError};
+use std::iter;
+use std::mem;
+use std::sync::mpsc::{self, Sender, SyncSender, Receiver, TryRecvError};
 use std::thread;
 
 use derive_more::From;
-use mixlab_codec::ffmpeg::media::Video;
+use futures::executor::block_on;
+use mixlab_codec::ffmpeg::media::{Audio, Video};
 use mixlab_codec::ffmpeg::codec::{self, CodecBuilder, RecvFrameError, Decode};
-use mixlab_codec::ffmpeg::{AvError, AvIoError, AvIoReader, IoReader, InputContainer};
-use mixlab_protocol::{MediaId, MediaSourceParams};
+use mixlab_codec::ffmpeg::{AvError, AvIoError, AvIoReader, AvPacket, IoReader, InputContainer};
+use mixlab_codec::resample::Resampler;
+use mixlab_protocol::{MediaId, MediaSourceParams, MediaSourceIndication, Microseconds, PlaybackMode};
 use mixlab_util::time::{MediaTime, MediaDuration, TimeBase};
 
-use crate::engine::{InputRef, OutputRef, VideoFrame, ModuleCtx, SAMPLE_RATE, TICKS_PER_SECOND};
+use crate::engine::{EngineMode, InputRef, OutputRef, VideoFrame, ModuleCtx, Rate, Sample, CHANNELS};
 use crate::module::{ModuleT, LineType, Terminal};
 use crate::project::media;
 use crate::project::ProjectBaseRef;
 use crate::project::stream::ReadStream;
-use crate::throttle::MediaThrottle;
 use crate::video;
 
 #[derive(Debug)]
@@ -22,6 +25,7 @@ pub struct MediaSource {
     ctx: ModuleCtx<Self>,
     params: MediaSourceParams,
     media: Option<OpenMedia>,
+    indication: MediaSourceIndication,
     inputs: Vec<Terminal>,
     outputs: Vec<Terminal>,
 }
@@ -31,17 +35,56 @@ pub enum MediaSourceEvent {
     SetMedia(Option<OpenMedia>),
 }
 
+// how far ahead of the playhead frames are pulled from the decode thread.
+// this gives frames of both streams time to arrive before they're due,
+// regardless of how the audio and video packets are interleaved in the
+// container. the decode thread blocks once its channel is full, so this also
+// limits how far ahead of the playhead it runs:
+const DECODE_AHEAD_MILLIS: i64 = 500;
+
+// the playhead moves every tick, there's no need to tell clients about it
+// anywhere near that often:
+const PLAYHEAD_RESOLUTION_MICROS: i64 = 100_000;
+
+// audio frames are appended back to back as long as their timestamps line up
+// with the end of the buffer to within this many samples. beyond that (eg. at
+// loop points) the buffer is padded or trimmed to keep in sync with video:
+const AUDIO_DRIFT_TOLERANCE: i64 = 64;
+
 #[derive(Debug)]
 pub struct OpenMedia {
     media_id: MediaId,
-    rx: Receiver<Frame>,
-    epoch: Option<MediaTime>,
-    video_buffer: VecDeque<Frame>,
+    rate: Rate,
+    mode: EngineMode,
+    rx: Receiver<DecodeMsg>,
+    control: Sender<Control>,
+    // incremented on every seek. frames decoded before the most recent seek
+    // may still be in the channel, these are discarded:
+    generation: u64,
+    duration: Option<MediaDuration>,
+    // playback has reached the out point in one-shot mode:
+    ended: bool,
+    // playhead on the decode thread's timeline, in samples. None until the
+    // first frame after opening or seeking arrives:
+    clock: Option<i64>,
+    received_until: Option<MediaTime>,
+    video_buffer: VecDeque<(MediaTime, video::Frame)>,
+    // most recently output frame, held on screen while paused:
+    last_video: Option<video::Frame>,
+    // interleaved stereo samples, starting at `audio_start` on the decode
+    // thread's timeline:
+    audio_buffer: VecDeque<Sample>,
+    audio_start: i64,
+    // timeline offset currently in effect, see Frame::offset:
+    offset: MediaDuration,
+    // offset changes yet to take effect, and the pts they take effect at:
+    pending_offsets: VecDeque<(MediaTime, MediaDuration)>,
+    last_received_offset: Option<MediaDuration>,
 }
 
 impl ModuleT for MediaSource {
     type Params = MediaSourceParams;
-    type Indication = ();
+    type Indication = MediaSourceIndication;
     type Event = MediaSourceEvent;
 
     fn create(params: Self::Params, ctx: ModuleCtx<Self>) -> (Self, Self::Indication) {
@@ -49,15 +92,18 @@ impl ModuleT for MediaSource {
             ctx,
             params: MediaSourceParams::default(),
             media: None,
+            indication: MediaSourceIndication::default(),
             inputs: vec![],
             outputs: vec![
-                LineType::Video.unlabeled(),
+                LineType::Video.labeled("Video"),
+                LineType::Stereo.labeled("Audio"),
             ],
         };
 
         module.update(params);
 
-        (module, ())
+        let indication = module.indication.clone();
+        (module, indication)
     }
 
     fn params(&self) -> Self::Params {
@@ -65,88 +111,406 @@ impl ModuleT for MediaSource {
     }
 
     fn update(&mut self, params: Self::Params) -> Option<Self::Indication> {
-        if self.params.media_id != params.media_id {
-            self.params.media_id = params.media_id;
+        let old_params = mem::replace(&mut self.params, params);
+        let settings = PlaybackSettings::from_params(&self.params);
 
+        if old_params.media_id != self.params.media_id {
+            let media_id = self.params.media_id;
             let project = self.ctx.project();
+            let mode = self.ctx.mode();
 
-            self.ctx.spawn_async(async move {
-                let media = match params.media_id {
-                    Some(media_id) => open_media(project, media_id).await,
+            let open = async move {
+                match media_id {
+                    Some(media_id) => open_media(project, media_id, settings, mode).await,
                     None => None,
-                };
+                }
+            };
 
-                MediaSourceEvent::SetMedia(media)
-            });
+            match mode {
+                EngineMode::Realtime => {
+                    self.ctx.spawn_async(async move {
+                        MediaSourceEvent::SetMedia(open.await)
+                    });
+                }
+                EngineMode::Offline => {
+                    // the media must be there from the very first tick for
+                    // renders to come out the same every time:
+                    self.receive_event(MediaSourceEvent::SetMedia(block_on(open)));
+                }
+            }
+
+            return None;
+        }
+
+        if let Some(media) = &mut self.media {
+            if settings != PlaybackSettings::from_params(&old_params) {
+                media.set_settings(settings);
+            }
+
+            if old_params.seek_seq != self.params.seek_seq {
+                media.seek(micros_to_media_time(self.params.seek_position));
+            } else if self.params.playing && !old_params.playing && media.ended {
+                // pressing play once a one-shot has finished plays it again
+                // from the in point
+                media.seek(settings.in_point);
+            }
         }
+
         None
     }
 
     fn receive_event(&mut self, event: MediaSourceEvent) {
         match event {
             MediaSourceEvent::SetMedia(media) => {
+                // settings may have changed while media was opening:
+                if let Some(media) = &media {
+                    media.set_settings(PlaybackSettings::from_params(&self.params));
+                }
+
                 self.media = media;
             }
         }
     }
 
-    fn run_tick(&mut self, t: u64, _: &[InputRef], outputs: &mut [OutputRef]) -> Option<Self::Indication> {
-        let start_of_frame = MediaTime::new(t as i64, SAMPLE_RATE as i64);
-        let end_of_frame = start_of_frame + MediaDuration::new(1, TICKS_PER_SECOND as i64);
+    fn run_tick(&mut self, _: u64, _: &[InputRef], outputs: &mut [OutputRef]) -> Option<Self::Indication> {
+        let indication = match &mut self.media {
+            Some(media) => {
+                media.receive();
+                media.play_tick(self.params.playing, outputs);
+                media.indication()
+            }
+            None => MediaSourceIndication::default(),
+        };
 
-        if let Some(media) = &mut self.media {
-            match media.rx.try_recv() {
-                Err(TryRecvError::Empty) => {}
+        if indication != self.indication {
+            self.indication = indication.clone();
+            Some(indication)
+        } else {
+            None
+        }
+    }
+
+    fn inputs(&self) -> &[Terminal] {
+        &self.inputs
+    }
+
+    fn outputs(&self)-> &[Terminal] {
+        &self.outputs
+    }
+}
+
+impl OpenMedia {
+    fn set_settings(&self, settings: PlaybackSettings) {
+        // send only fails if the decode thread has gone away, which is
+        // noticed when receiving frames
+        let _ = self.control.send(Control::Settings(settings));
+    }
+
+    fn seek(&mut self, position: MediaTime) {
+        self.generation += 1;
+
+        let _ = self.control.send(Control::Seek {
+            generation: self.generation,
+            position,
+        });
+
+        self.ended = false;
+        self.clock = None;
+        self.received_until = None;
+        self.video_buffer.clear();
+        self.last_video = None;
+        self.audio_buffer.clear();
+        self.pending_offsets.clear();
+        self.last_received_offset = None;
+    }
+
+    fn receive(&mut self) {
+        loop {
+            if let (Some(clock), Some(received_until)) = (self.clock, self.received_until) {
+                let decode_ahead = MediaTime::new(clock, self.rate.sample_rate() as i64)
+                    + MediaDuration::new(DECODE_AHEAD_MILLIS, 1000);
+
+                if received_until > decode_ahead {
+                    break;
+                }
+            }
+
+            let msg = match self.mode {
+                EngineMode::Realtime => self.rx.try_recv(),
+                EngineMode::Offline => {
+                    // nothing more is coming once playback has ended:
+                    if self.ended {
+                        break;
+                    }
+
+                    // rather than play whatever happens to have been decoded
+                    // by now, wait until the decode thread is far enough
+                    // ahead, so that every render comes out the same:
+                    self.rx.recv().map_err(|_| TryRecvError::Disconnected)
+                }
+            };
+
+            match msg {
+                Err(TryRecvError::Empty) => { break; }
                 Err(TryRecvError::Disconnected) => {
-                    eprintln!("media_source: decode thread died");
+                    // decode thread has exited and reported why, all we can
+                    // do is play out what we have
+                    self.ended = true;
+                    break;
                 }
-                Ok(frame) => {
-                    let epoch = *media.epoch.get_or_insert(start_of_frame);
+                Ok(DecodeMsg::Opened { duration }) => {
+                    self.duration = duration;
+                }
+                Ok(DecodeMsg::End { generation }) => {
+                    if generation == self.generation {
+                        self.ended = true;
+                    }
+                }
+                Ok(DecodeMsg::Frame(frame)) => {
+                    if frame.generation == self.generation {
+                        self.receive_frame(frame);
+                    }
+                }
+            }
+        }
+    }
 
-                    media.video_buffer.push_back(Frame {
-                        pts: frame.pts.add_epoch(epoch),
-                        frame: frame.frame,
-                    });
+    fn receive_frame(&mut self, frame: Frame) {
+        // the decode thread carries on after ending if switched to loop mode:
+        self.ended = false;
+
+        if self.clock.is_none() {
+            self.clock = Some(frame.pts.round_to_base(self.rate.sample_rate() as i64));
+        }
+
+        if self.received_until.map(|until| until < frame.pts).unwrap_or(true) {
+            self.received_until = Some(frame.pts);
+        }
+
+        if self.last_received_offset != Some(frame.offset) {
+            self.pending_offsets.push_back((frame.pts, frame.offset));
+            self.last_received_offset = Some(frame.offset);
+        }
+
+        match frame.data {
+            FrameData::Video(video_frame) => {
+                self.video_buffer.push_back((frame.pts, video_frame));
+            }
+            FrameData::Audio(samples) => {
+                let pts = frame.pts.round_to_base(self.rate.sample_rate() as i64);
+
+                if self.audio_buffer.is_empty() {
+                    self.audio_start = pts;
+                    self.audio_buffer.extend(samples);
+                    return;
+                }
+
+                let buffer_end = self.audio_start + (self.audio_buffer.len() / CHANNELS) as i64;
+                let drift = pts - buffer_end;
+
+                if drift > AUDIO_DRIFT_TOLERANCE {
+                    let gap = drift as usize * CHANNELS;
+                    self.audio_buffer.extend(iter::repeat(0.0).take(gap));
+                    self.audio_buffer.extend(samples);
+                } else if drift < -AUDIO_DRIFT_TOLERANCE {
+                    let overlap = ((-drift) as usize * CHANNELS).min(samples.len());
+                    self.audio_buffer.extend(&samples[overlap..]);
+                } else {
+                    self.audio_buffer.extend(samples);
                 }
             }
+        }
+    }
 
-            if let Some(frame) = media.video_buffer.front() {
-                if frame.pts < end_of_frame {
-                    *outputs[0].expect_video() = Some(VideoFrame {
-                        data: frame.frame.clone(),
-                        tick_offset: frame.pts - start_of_frame,
-                    });
+    fn play_tick(&mut self, playing: bool, outputs: &mut [OutputRef]) {
+        let clock = match self.clock {
+            Some(clock) => clock,
+            None => { return; }
+        };
 
-                    media.video_buffer.pop_front();
+        let tick_duration = self.rate.tick_duration();
+        let start_of_tick = MediaTime::new(clock, self.rate.sample_rate() as i64);
+        let end_of_tick = start_of_tick + tick_duration;
+
+        while let Some((pts, offset)) = self.pending_offsets.front() {
+            if *pts >= end_of_tick {
+                break;
+            }
+
+            self.offset = *offset;
+            self.pending_offsets.pop_front();
+        }
+
+        if !playing {
+            // straight after a seek there is no current frame yet, cue up the
+            // first frame at the new position so that it shows while paused
+            if self.last_video.is_none() {
+                if let Some((_, frame)) = self.video_buffer.pop_front() {
+                    self.last_video = Some(frame);
                 }
             }
+
+            if let Some(frame) = &self.last_video {
+                *outputs[0].expect_video() = Some(VideoFrame {
+                    data: video::Frame {
+                        decoded: frame.decoded.clone(),
+                        duration_hint: tick_duration,
+                    },
+                    tick_offset: MediaDuration::zero(),
+                });
+            }
+
+            return;
         }
 
-        None
+        // when the source frame rate is higher than the tick rate, or we have
+        // fallen behind, several frames can be due in one tick. only the
+        // latest of them is shown, the rest are skipped:
+        let mut due_video = None;
+
+        while let Some((pts, _)) = self.video_buffer.front() {
+            if *pts >= end_of_tick {
+                break;
+            }
+
+            due_video = self.video_buffer.pop_front();
+        }
+
+        if let Some((pts, frame)) = due_video {
+            let tick_offset = if pts > start_of_tick {
+                pts - start_of_tick
+            } else {
+                MediaDuration::zero()
+            };
+
+            *outputs[0].expect_video() = Some(VideoFrame {
+                data: frame.clone(),
+                tick_offset,
+            });
+
+            self.last_video = Some(frame);
+        }
+
+        self.read_audio(clock, outputs[1].expect_stereo());
+
+        // once a one-shot has played out the playhead stays at the end
+        let drained = self.video_buffer.is_empty() && self.audio_buffer.is_empty();
+
+        if !(self.ended && drained) {
+            self.clock = Some(clock + self.rate.samples_per_tick() as i64);
+        }
     }
 
-    fn inputs(&self) -> &[Terminal] {
-        &self.inputs
+    fn read_audio(&mut self, clock: i64, out: &mut [Sample]) {
+        // drop any samples which were due before this tick
+        if self.audio_start < clock {
+            let late = ((clock - self.audio_start) as usize * CHANNELS).min(self.audio_buffer.len());
+            self.audio_buffer.drain(0..late);
+            self.audio_start += (late / CHANNELS) as i64;
+
+            if self.audio_buffer.is_empty() {
+                return;
+            }
+        }
+
+        let offset = (self.audio_start - clock) as usize;
+        let samples_per_tick = self.rate.samples_per_tick();
+
+        if offset >= samples_per_tick {
+            return;
+        }
+
+        let count = (samples_per_tick - offset).min(self.audio_buffer.len() / CHANNELS);
+        let out = &mut out[(offset * CHANNELS)..((offset + count) * CHANNELS)];
+
+        for (out, sample) in out.iter_mut().zip(self.audio_buffer.drain(0..(count * CHANNELS))) {
+            *out = sample;
+        }
+
+        self.audio_start += count as i64;
     }
 
-    fn outputs(&self)-> &[Terminal] {
-        &self.outputs
+    fn indication(&self) -> MediaSourceIndication {
+        let playhead = self.clock.map(|clock| {
+            let position = MediaTime::new(clock, self.rate.sample_rate() as i64) - self.offset;
+            let micros = position.round_to_base(1_000_000).max(0);
+            Microseconds((micros - micros % PLAYHEAD_RESOLUTION_MICROS) as u64)
+        });
+
+        let duration = self.duration.map(|duration| {
+            Microseconds(duration.round_to_base(1_000_000).max(0) as u64)
+        });
+
+        MediaSourceIndication {
+            playhead,
+            duration,
+            ended: self.ended,
+        }
     }
 }
 
-async fn open_media(project: ProjectBaseRef, media_id: MediaId) -> Option<OpenMedia> {
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PlaybackSettings {
+    mode: PlaybackMode,
+    in_point: MediaTime,
+    out_point: Option<MediaTime>,
+}
+
+impl PlaybackSettings {
+    fn from_params(params: &MediaSourceParams) -> Self {
+        let in_point = params.in_point
+            .map(micros_to_media_time)
+            .unwrap_or(MediaTime::zero());
+
+        // an out point at or before the in point would leave nothing to play,
+        // ignore it rather than spin:
+        let out_point = params.out_point
+            .map(micros_to_media_time)
+            .filter(|out_point| *out_point > in_point);
+
+        PlaybackSettings {
+            mode: params.mode,
+            in_point,
+            out_point,
+        }
+    }
+}
+
+fn micros_to_media_time(micros: Microseconds) -> MediaTime {
+    MediaTime::new(micros.0 as i64, 1_000_000)
+}
+
+async fn open_media(project: ProjectBaseRef, media_id: MediaId, settings: PlaybackSettings, mode: EngineMode) -> Option<OpenMedia> {
+    let rate = project.rate();
+
     match media::open(project, media_id).await {
         Ok(Some(stream)) => {
-            let (tx, rx) = mpsc::sync_channel(2);
+            let (tx, rx) = mpsc::sync_channel(16);
+            let (control_tx, control_rx) = mpsc::channel();
+
             thread::spawn(move || {
-                let result = run_decode_thread(stream, tx);
+                let result = run_decode_thread(stream, rate, tx, control_rx, settings);
                 println!("decode thread said: {:?}", result);
             });
+
             Some(OpenMedia {
                 media_id,
+                rate,
+                mode,
                 rx,
-                epoch: None,
+                control: control_tx,
+                generation: 0,
+                duration: None,
+                ended: false,
+                clock: None,
+                received_until: None,
                 video_buffer: VecDeque::new(),
+                last_video: None,
+                audio_buffer: VecDeque::new(),
+                audio_start: 0,
+                offset: MediaDuration::zero(),
+                pending_offsets: VecDeque::new(),
+                last_received_offset: None,
             })
         }
         Ok(None) => None,
@@ -157,17 +521,45 @@ async fn open_media(project: ProjectBaseRef, media_id: MediaId) -> Option<OpenMe
     }
 }
 
+#[derive(Debug)]
+enum DecodeMsg {
+    Opened { duration: Option<MediaDuration> },
+    Frame(Frame),
+    // playback has reached the out point in one-shot mode:
+    End { generation: u64 },
+}
+
 #[derive(Debug)]
 struct Frame {
+    generation: u64,
+    // timestamp on the decode thread's timeline. this keeps on increasing
+    // when playback loops, so the module can play straight through loop
+    // points without any special handling:
     pts: MediaTime,
-    frame: video::Frame,
+    // difference between pts and the position of this frame in the media:
+    offset: MediaDuration,
+    data: FrameData,
+}
+
+#[derive(Debug)]
+enum FrameData {
+    Video(video::Frame),
+    // interleaved stereo at engine sample rate:
+    Audio(Vec<Sample>),
+}
+
+#[derive(Debug)]
+enum Control {
+    Settings(PlaybackSettings),
+    Seek { generation: u64, position: MediaTime },
 }
 
 #[derive(Debug, From)]
 enum DecodeError {
     CodecBuild(codec::BuildError),
     CodecOpen(codec::OpenError),
-    NoFrames,
+    NoStreams,
+    UnsupportedSampleFormat,
     RecvFrame(RecvFrameError),
     Av(AvError),
     Io(<ReadStream as IoReader>::Error),
@@ -182,7 +574,13 @@ impl From<AvIoError<ReadStream>> for DecodeError {
     }
 }
 
-fn run_decode_thread(stream: ReadStream, tx: SyncSender<Frame>) -> Result<(), DecodeError> {
+fn run_decode_thread(
+    stream: ReadStream,
+    rate: Rate,
+    tx: SyncSender<DecodeMsg>,
+    control: Receiver<Control>,
+    settings: PlaybackSettings,
+) -> Result<(), DecodeError> {
     let container = InputContainer::open(AvIoReader::new(stream))?;
 
     for (idx, stream) in container.streams().iter().enumerate() {
@@ -190,103 +588,437 @@ fn run_decode_thread(stream: ReadStream, tx: SyncSender<Frame>) -> Result<(), De
         // println!("            Time base: {}", stream.time_base());
     }
 
-    let video_stream = &container.streams()[0];
-    let video_time_base = video_stream.time_base();
-    let video_codec_params = video_stream.codec_parameters();
+    // play the first video stream and the first audio stream, if present:
+
+    let video = match container.streams().iter().position(|stream| stream.is_media_type::<Video>()) {
+        Some(index) => {
+            let stream = &container.streams()[index];
+            let time_base = stream.time_base();
+            let codec_params = stream.codec_parameters();
 
-    let video_decode = CodecBuilder::<Video>::new(video_codec_params.codec_id, video_time_base)?
-        .with_parameters(video_codec_params)
-        .open_decoder()?;
+            let decode = CodecBuilder::<Video>::new(codec_params.codec_id, time_base)?
+                .with_parameters(codec_params)
+                .open_decoder()?;
+
+            Some(VideoTrack { index, time_base, decode })
+        }
+        None => None,
+    };
+
+    let audio = match container.streams().iter().position(|stream| stream.is_media_type::<Audio>()) {
+        Some(index) => {
+            let stream = &container.streams()[index];
+            let time_base = stream.time_base();
+            let codec_params = stream.codec_parameters();
+
+            let decode = CodecBuilder::<Audio>::new(codec_params.codec_id, time_base)?
+                .with_parameters(codec_params)
+                .open_decoder()?;
+
+            Some(AudioTrack { index, time_base, decode, output_rate: rate.sample_rate(), resampler: None })
+        }
+        None => None,
+    };
+
+    if video.is_none() && audio.is_none() {
+        return Err(DecodeError::NoStreams);
+    }
+
+    // media duration is that of the longest stream we're playing:
+    let duration = video.as_ref().map(|track| track.index).into_iter()
+        .chain(audio.as_ref().map(|track| track.index))
+        .filter_map(|index| container.streams()[index].duration())
+        .max();
+
+    if tx.send(DecodeMsg::Opened { duration }).is_err() {
+        return Ok(());
+    }
 
     let mut play = PlaybackContext {
         container,
-        video_decode,
-        video_time_base,
-        throttle: MediaThrottle::new(),
+        video,
+        audio,
         tx,
+        control,
+        settings,
+        generation: 0,
+        offset: MediaDuration::zero(),
+        skip_until: None,
     };
 
-    let mut iter_start = MediaTime::zero();
-
-    while let Some(iter_end) = play_once(&mut play, iter_start)? {
-        play.video_decode.flush_buffers();
-        play.container.seek(MediaTime::zero())?;
-        iter_start = iter_end;
+    if !settings.in_point.is_zero() {
+        play.seek(settings.in_point)?;
     }
 
-    Ok(())
+    loop {
+        match play_once(&mut play)? {
+            Played::Disconnected => {
+                return Ok(());
+            }
+            Played::Seeked => {}
+            Played::End(end) => {
+                let in_point = play.settings.in_point;
+
+                // an empty region has nothing to loop, looping it would only
+                // spin. it ends like a one-shot instead:
+                if play.settings.mode == PlaybackMode::Loop && end > in_point {
+                    play.offset = play.offset + (end - in_point);
+                    play.seek(in_point)?;
+                    continue;
+                }
+
+                if play.tx.send(DecodeMsg::End { generation: play.generation }).is_err() {
+                    return Ok(());
+                }
+
+                // nothing more to do until we're told to seek, or to loop:
+                loop {
+                    match play.control.recv() {
+                        Ok(control) => {
+                            let was_looping = play.settings.mode == PlaybackMode::Loop;
+
+                            if play.apply(control)? {
+                                break;
+                            }
+
+                            if play.settings.mode == PlaybackMode::Loop && !was_looping {
+                                // carry on from the end, as if we had been
+                                // looping all along:
+                                let in_point = play.settings.in_point;
+
+                                if end > in_point {
+                                    play.offset = play.offset + (end - in_point);
+                                }
+
+                                play.seek(in_point)?;
+                                break;
+                            }
+                        }
+                        Err(_) => {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
 struct PlaybackContext {
     container: InputContainer<ReadStream>,
-    video_decode: Decode<Video>,
-    video_time_base: TimeBase,
-    throttle: MediaThrottle,
-    tx: SyncSender<Frame>,
+    video: Option<VideoTrack>,
+    audio: Option<AudioTrack>,
+    tx: SyncSender<DecodeMsg>,
+    control: Receiver<Control>,
+    settings: PlaybackSettings,
+    generation: u64,
+    // added to media timestamps to place frames on the module's timeline,
+    // grows by the length of the played region every time playback loops:
+    offset: MediaDuration,
+    // seeks land on the keyframe before the requested position. frames
+    // ending before the requested position are decoded but not sent:
+    skip_until: Option<MediaTime>,
 }
 
-fn play_once(play: &mut PlaybackContext, iter_start: MediaTime) -> Result<Option<MediaTime>, DecodeError> {
-    let mut iter_end = None;
+impl PlaybackContext {
+    // returns true if control caused a seek
+    fn apply(&mut self, control: Control) -> Result<bool, DecodeError> {
+        match control {
+            Control::Settings(settings) => {
+                self.settings = settings;
+                Ok(false)
+            }
+            Control::Seek { generation, position } => {
+                self.generation = generation;
+                self.seek(position)?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn seek(&mut self, position: MediaTime) -> Result<(), DecodeError> {
+        if let Some(video) = &mut self.video {
+            video.decode.flush_buffers();
+        }
+
+        if let Some(audio) = &mut self.audio {
+            audio.decode.flush_buffers();
+            // filter history from before the seek is no longer relevant:
+            audio.resampler = None;
+        }
+
+        self.container.seek(position)?;
+        self.skip_until = Some(position);
+
+        Ok(())
+    }
+}
+
+struct VideoTrack {
+    index: usize,
+    time_base: TimeBase,
+    decode: Decode<Video>,
+}
+
+struct AudioTrack {
+    index: usize,
+    time_base: TimeBase,
+    decode: Decode<Audio>,
+    // engine sample rate, which audio is resampled to:
+    output_rate: usize,
+    // created lazily once we know the decoded sample rate:
+    resampler: Option<(usize, Resampler)>,
+}
+
+enum Played {
+    // reached the out point or end of media, at the given position:
+    End(MediaTime),
+    Seeked,
+    Disconnected,
+}
+
+fn play_once(play: &mut PlaybackContext) -> Result<Played, DecodeError> {
+    let mut end = None;
     let mut reached_end_of_stream = false;
 
+    // tracks are finished once they reach end of stream or the out point:
+    let mut video_finished = play.video.is_none();
+    let mut audio_finished = play.audio.is_none();
+
     loop {
+        loop {
+            match play.control.try_recv() {
+                Ok(control) => {
+                    if play.apply(control)? {
+                        return Ok(Played::Seeked);
+                    }
+                }
+                Err(TryRecvError::Empty) => { break; }
+                Err(TryRecvError::Disconnected) => { return Ok(Played::Disconnected); }
+            }
+        }
+
         // read packet and send to decoder
         if !reached_end_of_stream {
             match play.container.read_packet()? {
                 Some(pkt) => {
-                    if pkt.stream_index() != 0 {
-                        continue;
-                    }
-
-                    play.video_decode.send_packet(&pkt)?;
+                    send_packet(play, &pkt)?;
                 }
                 None => {
-                    play.video_decode.end_of_stream()?;
+                    if let Some(video) = &mut play.video {
+                        video.decode.end_of_stream()?;
+                    }
+
+                    if let Some(audio) = &mut play.audio {
+                        audio.decode.end_of_stream()?;
+                    }
+
                     reached_end_of_stream = true;
                 }
             }
         }
 
-        // receive decoded frame from codec if ready
-        match play.video_decode.recv_frame() {
+        // receive all decoded frames from codecs
+        let mut out = FrameOutput {
+            tx: &play.tx,
+            generation: play.generation,
+            offset: play.offset,
+            skip_until: play.skip_until,
+            out_point: play.settings.out_point,
+            end: &mut end,
+        };
+
+        if let Some(track) = &mut play.video {
+            match recv_video(track, &mut out)? {
+                Drained::NeedMoreInput => {}
+                Drained::Finished => { video_finished = true; }
+                Drained::Disconnected => { return Ok(Played::Disconnected); }
+            }
+        }
+
+        if let Some(track) = &mut play.audio {
+            match recv_audio(track, &mut out)? {
+                Drained::NeedMoreInput => {}
+                Drained::Finished => { audio_finished = true; }
+                Drained::Disconnected => { return Ok(Played::Disconnected); }
+            }
+        }
+
+        if video_finished && audio_finished {
+            break;
+        }
+    }
+
+    // nothing plays when seeking to or past the end of the media, or when
+    // the in and out points leave nothing between them. playback ends where
+    // it started:
+    let start = play.skip_until.unwrap_or_else(MediaTime::zero);
+    Ok(Played::End(end.unwrap_or(start)))
+}
+
+fn send_packet(play: &mut PlaybackContext, pkt: &AvPacket) -> Result<(), DecodeError> {
+    let stream_index = pkt.stream_index() as usize;
+
+    if let Some(video) = &mut play.video {
+        if video.index == stream_index {
+            video.decode.send_packet(pkt)?;
+        }
+    }
+
+    if let Some(audio) = &mut play.audio {
+        if audio.index == stream_index {
+            audio.decode.send_packet(pkt)?;
+        }
+    }
+
+    Ok(())
+}
+
+enum Drained {
+    NeedMoreInput,
+    Finished,
+    Disconnected,
+}
+
+fn recv_video(track: &mut VideoTrack, out: &mut FrameOutput) -> Result<Drained, DecodeError> {
+    let mut past_out_point = false;
+
+    loop {
+        match track.decode.recv_frame() {
             Ok(decoded) => {
                 // TODO what to do if packet duration is ever 0? some container
                 // formats do not encode frame duration. assert for now and
                 // deal with it later
                 assert!(decoded.packet_duration() != 0);
 
-                let pts = play.video_time_base
-                    .scale_timestamp(decoded.presentation_timestamp())
-                    .add_epoch(iter_start);
+                let position = track.time_base
+                    .scale_timestamp(decoded.presentation_timestamp());
 
-                let duration = play.video_time_base
+                let duration = track.time_base
                     .scale_duration(decoded.packet_duration());
 
-                iter_end = Some(pts + duration);
+                let data = FrameData::Video(video::Frame {
+                    decoded,
+                    duration_hint: duration,
+                });
 
-                let frame = Frame {
-                    pts: pts,
-                    frame: video::Frame {
-                        decoded,
-                        duration_hint: duration,
-                    },
-                };
+                // keep draining the decoder past the out point so that it
+                // continues to accept packets while the other track catches up
+                match out.send(position, duration, data) {
+                    Sent::Ok => {}
+                    Sent::PastOutPoint => { past_out_point = true; }
+                    Sent::Disconnected => { return Ok(Drained::Disconnected); }
+                }
+            }
+            Err(RecvFrameError::NeedMoreInput) if past_out_point => { return Ok(Drained::Finished); }
+            Err(RecvFrameError::NeedMoreInput) => { return Ok(Drained::NeedMoreInput); }
+            Err(RecvFrameError::Eof) => { return Ok(Drained::Finished); }
+            Err(e) => { return Err(e.into()); }
+        }
+    }
+}
 
-                match play.tx.send(frame) {
-                    Ok(()) => {}
-                    Err(_) => {
-                        // receiver disconnected
-                        return Ok(None);
-                    }
+fn recv_audio(track: &mut AudioTrack, out: &mut FrameOutput) -> Result<Drained, DecodeError> {
+    let mut past_out_point = false;
+
+    loop {
+        match track.decode.recv_frame() {
+            Ok(decoded) => {
+                let position = track.time_base
+                    .scale_timestamp(decoded.presentation_timestamp());
+
+                let sample_rate = decoded.sample_rate();
+                let duration = MediaDuration::new(decoded.sample_count() as i64, sample_rate as i64);
+
+                let samples = decoded.interleaved_samples()
+                    .ok_or(DecodeError::UnsupportedSampleFormat)?;
+
+                let samples = to_stereo(&samples, decoded.channels());
+
+                let resampler_rate = track.resampler.as_ref().map(|(rate, _)| *rate);
+
+                if resampler_rate != Some(sample_rate) {
+                    track.resampler = Some((sample_rate, Resampler::new(CHANNELS, sample_rate, track.output_rate)));
                 }
 
-                play.throttle.wait_until(pts);
+                let (_, resampler) = track.resampler.as_mut().unwrap();
+
+                let mut resampled = Vec::with_capacity(samples.len());
+                resampler.process(&samples, &mut resampled);
+
+                match out.send(position, duration, FrameData::Audio(resampled)) {
+                    Sent::Ok => {}
+                    Sent::PastOutPoint => { past_out_point = true; }
+                    Sent::Disconnected => { return Ok(Drained::Disconnected); }
+                }
             }
-            Err(RecvFrameError::NeedMoreInput) => { continue; }
-            Err(RecvFrameError::Eof) => { break; }
+            Err(RecvFrameError::NeedMoreInput) if past_out_point => { return Ok(Drained::Finished); }
+            Err(RecvFrameError::NeedMoreInput) => { return Ok(Drained::NeedMoreInput); }
+            Err(RecvFrameError::Eof) => { return Ok(Drained::Finished); }
             Err(e) => { return Err(e.into()); }
         }
     }
+}
+
+struct FrameOutput<'a> {
+    tx: &'a SyncSender<DecodeMsg>,
+    generation: u64,
+    offset: MediaDuration,
+    skip_until: Option<MediaTime>,
+    out_point: Option<MediaTime>,
+    end: &'a mut Option<MediaTime>,
+}
 
-    Ok(Some(iter_end.ok_or(DecodeError::NoFrames)?))
+enum Sent {
+    Ok,
+    PastOutPoint,
+    Disconnected,
+}
+
+impl<'a> FrameOutput<'a> {
+    fn send(&mut self, position: MediaTime, duration: MediaDuration, data: FrameData) -> Sent {
+        if let Some(out_point) = self.out_point {
+            if position >= out_point {
+                return Sent::PastOutPoint;
+            }
+        }
+
+        let mut frame_end = position + duration;
+
+        if let Some(skip_until) = self.skip_until {
+            if frame_end <= skip_until {
+                return Sent::Ok;
+            }
+        }
+
+        if let Some(out_point) = self.out_point {
+            frame_end = frame_end.min(out_point);
+        }
+
+        if self.end.map(|end| end < frame_end).unwrap_or(true) {
+            *self.end = Some(frame_end);
+        }
+
+        let frame = Frame {
+            generation: self.generation,
+            pts: position + self.offset,
+            offset: self.offset,
+            data,
+        };
+
+        match self.tx.send(DecodeMsg::Frame(frame)) {
+            Ok(()) => Sent::Ok,
+            Err(_) => Sent::Disconnected,
+        }
+    }
+}
+
+fn to_stereo(samples: &[f32], channels: usize) -> Vec<Sample> {
+    match channels {
+        1 => samples.iter().flat_map(|sample| vec![*sample, *sample]).collect(),
+        2 => samples.to_vec(),
+        // TODO - proper downmix of surround sound. for now take front left
+        // and front right only
+        _ => samples.chunks_exact(channels).flat_map(|frame| vec![frame[0], frame[1]]).collect(),
+    }
 }
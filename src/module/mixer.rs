@@ -1,13 +1,15 @@
-use mixlab_protocol::{MixerParams, LineType, Terminal};
+use mixlab_protocol::{MixerParams, MixerChannelParams, LineType, Terminal};
 
-use crate::engine::{self, Sample, InputRef, OutputRef};
+use crate::engine::{self, Sample, InputRef, OutputRef, Rate, Smoothed, CHANNELS};
 use crate::module::ModuleT;
 use crate::util;
 
 #[derive(Debug)]
 pub struct Mixer {
     params: MixerParams,
-    ctx: Option<engine::ModuleCtx<Self>>,
+    rate: Rate,
+    // linear gain of each channel, fader and gain combined:
+    gains: Vec<Smoothed>,
     inputs: Vec<Terminal>,
     outputs: Vec<Terminal>,
 }
@@ -18,16 +20,19 @@ impl ModuleT for Mixer {
     type Event = ();
 
     fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+        let rate = ctx.rate();
+
         let mixer = Mixer {
-            inputs: params.channels.iter().enumerate().map(|(i, _)| {
-                LineType::Stereo.labeled(&(i+1).to_string())
-            }).collect(),
+            inputs: channel_inputs(params.channels.len()),
             outputs: vec![
                 LineType::Stereo.labeled("Master"),
                 LineType::Stereo.labeled("Cue"),
             ],
+            gains: params.channels.iter()
+                .map(|channel| Smoothed::new(channel_gain(channel), rate))
+                .collect(),
+            rate,
             params,
-            ctx: Some(ctx),
         };
 
         (mixer, ())
@@ -38,8 +43,29 @@ impl ModuleT for Mixer {
     }
 
     fn update(&mut self, params: Self::Params) -> Option<Self::Indication> {
-        let (new, _) = Self::create(params, self.ctx.take().unwrap());
-        *self = new;
+        self.update_at(params, 0)
+    }
+
+    fn update_at(&mut self, params: Self::Params, offset: usize) -> Option<Self::Indication> {
+        if params.channels.len() != self.gains.len() {
+            self.inputs = channel_inputs(params.channels.len());
+
+            // new channels start at their gain rather than ramping up to it:
+            let rate = self.rate;
+            let new_channels = params.channels.iter()
+                .skip(self.gains.len())
+                .map(|channel| Smoothed::new(channel_gain(channel), rate))
+                .collect::<Vec<_>>();
+
+            self.gains.truncate(params.channels.len());
+            self.gains.extend(new_channels);
+        }
+
+        for (gain, channel) in self.gains.iter_mut().zip(params.channels.iter()) {
+            gain.set_at(offset, channel_gain(channel));
+        }
+
+        self.params = params;
         None
     }
 
@@ -49,20 +75,21 @@ impl ModuleT for Mixer {
             _ => unreachable!(),
         };
 
-        let len = master.len();
+        let frames = master.len() / CHANNELS;
 
         util::zero(master);
         util::zero(cue);
 
-        for (ch, channel) in self.params.channels.iter().enumerate() {
+        for (ch, (channel, gain)) in self.params.channels.iter().zip(self.gains.iter_mut()).enumerate() {
             let input = inputs[ch].expect_stereo();
-            let channel_gain = channel.fader * channel.gain.to_linear();
 
-            for i in 0..len {
-                master[i] += (input[i] as f64 * channel_gain) as Sample;
+            for (frame, channel_gain) in gain.tick(frames).enumerate() {
+                for i in (frame * CHANNELS)..((frame + 1) * CHANNELS) {
+                    master[i] += (input[i] as f64 * channel_gain) as Sample;
 
-                if channel.cue {
-                    cue[i] += input[i];
+                    if channel.cue {
+                        cue[i] += input[i];
+                    }
                 }
             }
         }
@@ -78,3 +105,13 @@ impl ModuleT for Mixer {
         &self.outputs
     }
 }
+
+fn channel_inputs(count: usize) -> Vec<Terminal> {
+    (0..count)
+        .map(|i| LineType::Stereo.labeled(&(i+1).to_string()))
+        .collect()
+}
+
+fn channel_gain(channel: &MixerChannelParams) -> f64 {
+    channel.fader * channel.gain.to_linear()
+}
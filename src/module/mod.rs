@@ -4,7 +4,8 @@ use mixlab_protocol::{Terminal, LineType};
 
 use crate::engine::{InputRef, OutputRef, ModuleCtx};
 
-pub trait ModuleT: Any + Sized {
+// modules are run on the engine's worker threads, and so must be Send:
+pub trait ModuleT: Any + Send + Sized {
     type Params;
     type Indication;
     type Event: Send;
@@ -13,6 +14,13 @@ pub trait ModuleT: Any + Sized {
     fn params(&self) -> Self::Params;
     fn receive_event(&mut self, _: Self::Event) {}
     fn update(&mut self, new_params: Self::Params) -> Option<Self::Indication>;
+    /// Updates params as of `offset` samples into the next tick. Modules
+    /// which smooth their params with `engine::Smoothed` override this to
+    /// place the change within the tick, everything else applies it at the
+    /// start of the tick
+    fn update_at(&mut self, new_params: Self::Params, _offset: usize) -> Option<Self::Indication> {
+        self.update(new_params)
+    }
     fn run_tick(&mut self, t: u64, inputs: &[InputRef], outputs: &mut [OutputRef]) -> Option<Self::Indication>;
     fn inputs(&self) -> &[Terminal];
     fn outputs(&self) -> &[Terminal];
@@ -37,6 +45,7 @@ macro_rules! enumerate_modules {
             oscillator::Oscillator,
             output_device::OutputDevice,
             plotter::Plotter,
+            recorder::Recorder,
             stereo_panner::StereoPanner,
             stereo_splitter::StereoSplitter,
             stream_input::StreamInput,
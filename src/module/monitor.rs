@@ -14,7 +14,7 @@ use mixlab_mux::mp4::{Mp4Params, TrackData, AdtsFrame, AvcFrame};
 use mixlab_protocol::{LineType, Terminal, MonitorIndication, MonitorTransportPacket};
 use mixlab_util::time::MediaTime;
 
-use crate::engine::{self, InputRef, OutputRef, SAMPLE_RATE};
+use crate::engine::{self, InputRef, OutputRef};
 use crate::module::ModuleT;
 use crate::video::encode::{EncodeStream, AudioCtx, AudioParams, VideoCtx, VideoParams, StreamSegment, Profile};
 
@@ -75,6 +75,7 @@ async fn send_packet(websocket: &mut WebSocket, packet: MonitorTransportPacket)
 
 #[derive(Debug)]
 pub struct Monitor {
+    sample_rate: usize,
     epoch: Option<MediaTime>,
     socket_id: Uuid,
     codec: AsyncCodec,
@@ -86,11 +87,13 @@ impl ModuleT for Monitor {
     type Indication = MonitorIndication;
     type Event = ();
 
-    fn create(_: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+    fn create(_: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+        let sample_rate = ctx.rate().sample_rate();
         let socket_id = Uuid::new_v4();
-        let codec = AsyncCodec::start(socket_id);
+        let codec = AsyncCodec::start(socket_id, sample_rate);
 
         let module = Monitor {
+            sample_rate,
             epoch: None,
             socket_id,
             codec,
@@ -117,7 +120,7 @@ impl ModuleT for Monitor {
             _ => unreachable!()
         };
 
-        let absolute_timestamp = MediaTime::new(time as i64, SAMPLE_RATE as i64);
+        let absolute_timestamp = MediaTime::new(time as i64, self.sample_rate as i64);
         let epoch = *self.epoch.get_or_insert(absolute_timestamp);
         let timestamp = absolute_timestamp.remove_epoch(epoch);
 
@@ -150,9 +153,9 @@ struct AsyncCodec {
 }
 
 impl AsyncCodec {
-    pub fn start(socket_id: Uuid) -> AsyncCodec {
+    pub fn start(socket_id: Uuid, sample_rate: usize) -> AsyncCodec {
         let (codec_tx, codec_rx) = mpsc::sync_channel(2);
-        thread::spawn(move || run_codec_thread(socket_id, codec_rx));
+        thread::spawn(move || run_codec_thread(socket_id, sample_rate, codec_rx));
 
         AsyncCodec {
             codec_tx,
@@ -182,19 +185,19 @@ struct Tick {
     video: Option<engine::VideoFrame>,
 }
 
-fn run_codec_thread(socket_id: Uuid, rx: mpsc::Receiver<Tick>) {
+fn run_codec_thread(socket_id: Uuid, sample_rate: usize, rx: mpsc::Receiver<Tick>) {
     // create encoders
     let audio_ctx = AudioCtx::new(AudioParams {
         bit_rate: aac::BitRate::VbrVeryHigh,
-        sample_rate: SAMPLE_RATE,
+        sample_rate,
         transport: aac::Transport::Adts,
-    });
+    }).expect("AudioCtx::new");
 
     let video_ctx = VideoCtx::new(VideoParams {
         picture: PictureSettings::yuv420p(MONITOR_WIDTH, MONITOR_HEIGHT),
-        time_base: SAMPLE_RATE,
+        time_base: sample_rate,
         profile: Profile::Monitor,
-    });
+    }).expect("VideoCtx::new");
 
     // mp4 params placeholder
     let mp4_params = {
@@ -203,7 +206,8 @@ fn run_codec_thread(socket_id: Uuid, rx: mpsc::Receiver<Tick>) {
         dcr.write_to(&mut dcr_bytes);
 
         Mp4Params {
-            timescale: SAMPLE_RATE as u32,
+            timescale: sample_rate as u32,
+            sample_rate: sample_rate as u32,
             width: MONITOR_WIDTH as u32,
             height: MONITOR_HEIGHT as u32,
             dcr: Cow::Owned(dcr_bytes),
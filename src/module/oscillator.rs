@@ -2,12 +2,13 @@ use std::f64;
 
 use mixlab_protocol::{OscillatorParams, Waveform, LineType, Terminal};
 
-use crate::engine::{self, InputRef, OutputRef, SAMPLE_RATE};
+use crate::engine::{self, InputRef, OutputRef};
 use crate::module::ModuleT;
 
 #[derive(Debug)]
 pub struct Oscillator {
     params: OscillatorParams,
+    sample_rate: usize,
     inputs: Vec<Terminal>,
     outputs: Vec<Terminal>,
 }
@@ -41,9 +42,10 @@ impl ModuleT for Oscillator {
     type Indication = ();
     type Event = ();
 
-    fn create(params: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+    fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
         (Self {
             params,
+            sample_rate: ctx.rate().sample_rate(),
             inputs: vec![],
             outputs: vec![
                 LineType::Mono.labeled("Mono"),
@@ -71,7 +73,7 @@ impl ModuleT for Oscillator {
         let len = mono.len();
 
         for i in 0..len {
-            let t0 = (t + i as u64) as f64 / SAMPLE_RATE as f64;
+            let t0 = (t + i as u64) as f64 / self.sample_rate as f64;
             let n = t0 * self.params.freq as f64;
 
             let sample: f32 = match &self.params.waveform {
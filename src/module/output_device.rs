@@ -2,6 +2,8 @@ use std::f32;
 use std::fmt::{self, Debug};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
 use std::time::Instant;
 
 use cpal::traits::{HostTrait, DeviceTrait, StreamTrait};
@@ -15,7 +17,7 @@ use crate::util;
 
 pub struct OutputDevice {
     params: OutputDeviceParams,
-    host: cpal::Host,
+    sample_rate: usize,
     scratch: Vec<Sample>,
     stream: Option<OutputStream>,
     last_clip: Option<Instant>,
@@ -29,9 +31,10 @@ pub struct OutputDevice {
 struct OutputStream {
     tx: Producer<f32>,
     config: cpal::StreamConfig,
-    // this field is never used directly but must not be dropped for the
-    // stream to continue playing:
-    _stream: cpal::Stream,
+    // cpal streams can't be moved between threads, but modules can be run on
+    // any of the engine's workers. the stream lives on a thread of its own
+    // instead, which stops it once this is dropped:
+    _stop: mpsc::SyncSender<()>,
 }
 
 impl Debug for OutputDevice {
@@ -45,7 +48,7 @@ impl ModuleT for OutputDevice {
     type Indication = OutputDeviceIndication;
     type Event = ();
 
-    fn create(params: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+    fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
         let host = cpal::default_host();
 
         // TODO - see if we can update devices as they are added/removed from host
@@ -71,7 +74,7 @@ impl ModuleT for OutputDevice {
 
         let device = OutputDevice {
             params,
-            host,
+            sample_rate: ctx.rate().sample_rate(),
             scratch: Vec::new(),
             stream: None,
             last_clip: None,
@@ -93,59 +96,15 @@ impl ModuleT for OutputDevice {
         let OutputDeviceParams { device, left, right } = new_params;
 
         if self.params.device != device {
-            let output_device = self.host.output_devices()
-                .ok()
-                .and_then(|devices| {
-                    devices.into_iter().find(|dev| dev.name().map(|dev| Some(dev) == device).unwrap_or(false))
-                });
-
-            if let Some(output_device) = output_device {
-                let config = output_device.default_output_config()
-                    .expect("default_output_format");
-
-                let (tx, mut rx) = RingBuffer::<f32>::new(65536).split();
-
-                let stream = output_device.build_output_stream(
-                        &config.config(),
-                        {
-                            let lag_flag = self.lag_flag.clone();
-                            let mut backoff_ticks = 0;
-                            move |data: &mut [f32], _info| {
-                                // TOOD info param contains timestamp for sample block
-                                // consider how we might be able to use this
-
-                                if backoff_ticks > 0 {
-                                    backoff_ticks -= 1;
-                                    util::zero(data);
-                                    return;
-                                }
-
-                                let bytes = rx.pop_slice(data);
-
-                                if bytes < data.len() {
-                                    lag_flag.store(true, Ordering::Relaxed);
-                                    backoff_ticks += 3;
-                                    util::zero(&mut data[bytes..])
-                                }
-                            }
-                        },
-                        |err| {
-                            eprintln!("output stream error! {:?}", err);
-                        })
-                    .expect("build_output_stream");
-
-                stream.play().expect("stream.play");
-
-                let stream = OutputStream {
-                    tx,
-                    config: config.config(),
-                    _stream: stream,
-                };
+            // drop any existing stream before opening the new one:
+            self.stream = None;
 
+            let stream = device.clone()
+                .and_then(|name| open_stream(name, self.sample_rate, self.lag_flag.clone()));
+
+            if let Some(stream) = stream {
                 self.params.device = device.clone();
                 self.stream = Some(stream);
-            } else {
-                self.stream = None;
             }
         }
 
@@ -253,3 +212,91 @@ impl ModuleT for OutputDevice {
         &self.outputs
     }
 }
+
+fn open_stream(device_name: String, sample_rate: usize, lag_flag: Arc<AtomicBool>) -> Option<OutputStream> {
+    let (tx, mut rx) = RingBuffer::<f32>::new(65536).split();
+    let (config_tx, config_rx) = mpsc::sync_channel(1);
+    let (stop_tx, stop_rx) = mpsc::sync_channel::<()>(0);
+
+    thread::spawn(move || {
+        let output_device = cpal::default_host().output_devices()
+            .ok()
+            .and_then(|devices| {
+                devices.into_iter().find(|dev| dev.name().map(|dev| dev == device_name).unwrap_or(false))
+            });
+
+        let output_device = match output_device {
+            Some(output_device) => output_device,
+            None => {
+                let _ = config_tx.send(None);
+                return;
+            }
+        };
+
+        let default_config = output_device.default_output_config()
+            .expect("default_output_format");
+
+        // samples are played out as the engine produces them, so the device
+        // must run at the engine's sample rate for them to play at the right
+        // speed. fall back to the default if the device can't:
+        let engine_rate = cpal::SampleRate(sample_rate as u32);
+
+        let config = output_device.supported_output_configs()
+            .ok()
+            .and_then(|mut configs| configs.find(|range| {
+                range.channels() == default_config.channels()
+                    && range.sample_format() == cpal::SampleFormat::F32
+                    && range.min_sample_rate() <= engine_rate
+                    && range.max_sample_rate() >= engine_rate
+            }))
+            .map(|range| range.with_sample_rate(engine_rate))
+            .unwrap_or_else(|| {
+                eprintln!("output device {:?} does not support {} Hz, using its default", device_name, sample_rate);
+                default_config
+            });
+
+        let stream = output_device.build_output_stream(
+                &config.config(),
+                {
+                    let mut backoff_ticks = 0;
+                    move |data: &mut [f32], _info| {
+                        // TOOD info param contains timestamp for sample block
+                        // consider how we might be able to use this
+
+                        if backoff_ticks > 0 {
+                            backoff_ticks -= 1;
+                            util::zero(data);
+                            return;
+                        }
+
+                        let bytes = rx.pop_slice(data);
+
+                        if bytes < data.len() {
+                            lag_flag.store(true, Ordering::Relaxed);
+                            backoff_ticks += 3;
+                            util::zero(&mut data[bytes..])
+                        }
+                    }
+                },
+                |err| {
+                    eprintln!("output stream error! {:?}", err);
+                })
+            .expect("build_output_stream");
+
+        stream.play().expect("stream.play");
+
+        let _ = config_tx.send(Some(config.config()));
+
+        // nothing is ever sent, this returns once the module drops its end
+        // of the channel. the stream stops when dropped along with the thread:
+        let _ = stop_rx.recv();
+    });
+
+    let config = config_rx.recv().ok().flatten()?;
+
+    Some(OutputStream {
+        tx,
+        config,
+        _stop: stop_tx,
+    })
+}
@@ -0,0 +1,584 @@
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write, BufWriter};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use derive_more::From;
+use fdk_aac::enc as aac;
+use tokio::runtime;
+
+use mixlab_mux::mp4::{Mp4Mux, Mp4Index, Mp4Params, TrackData, AdtsFrame, AvcFrame};
+use mixlab_protocol::{RecorderParams, RecorderIndication, RecordingFile, RecordingTarget, StreamEncoderSettings, Microseconds, LineType, Terminal};
+use mixlab_util::time::{MediaTime, MediaDuration};
+
+use crate::engine::{self, InputRef, OutputRef, Rate};
+use crate::module::ModuleT;
+use crate::project::ProjectBaseRef;
+use crate::project::media::{MediaRecording, RecordingInfo, UploadError};
+use crate::video::encode::{EncodeStream, EncodeError, FramePacer, AudioCtx, AudioParams, VideoCtx, VideoParams, StreamSegment};
+
+// duration is reported at this resolution to avoid sending an indication
+// every single tick:
+const DURATION_RESOLUTION_MICROS: i64 = 100_000;
+
+#[derive(Debug)]
+pub struct Recorder {
+    base: ProjectBaseRef,
+    params: RecorderParams,
+    recording: Option<RecordingTask>,
+    file: Option<RecordingFile>,
+    error: Option<String>,
+    dropped_ticks: u64,
+    inputs: Vec<Terminal>,
+    indication: RecorderIndication,
+}
+
+impl ModuleT for Recorder {
+    type Params = RecorderParams;
+    type Indication = RecorderIndication;
+    type Event = ();
+
+    fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+        let mut module = Recorder {
+            base: ctx.project(),
+            params,
+            recording: None,
+            file: None,
+            error: None,
+            dropped_ticks: 0,
+            inputs: vec![
+                LineType::Video.labeled("Video"),
+                LineType::Stereo.labeled("Audio"),
+            ],
+            indication: RecorderIndication {
+                recording: false,
+                file: None,
+                error: None,
+                dropped_ticks: 0,
+            },
+        };
+
+        module.indicate();
+
+        let indication = module.indication.clone();
+        (module, indication)
+    }
+
+    fn params(&self) -> Self::Params {
+        self.params.clone()
+    }
+
+    fn update(&mut self, new_params: Self::Params) -> Option<Self::Indication> {
+        if new_params.seq <= self.params.seq {
+            // out of date update, reject
+            return None;
+        }
+
+        if let Some(recording) = &mut self.recording {
+            if new_params.stop_seq == new_params.seq {
+                // dropping the task finishes off the current file
+                self.recording = None;
+                self.indicate()
+            } else if new_params.split_seq == new_params.seq {
+                recording.split();
+                None
+            } else {
+                // cannot change params while recording
+                None
+            }
+        } else {
+            self.params = new_params;
+
+            if self.params.start_seq == self.params.seq {
+                match validate(&self.params, self.base.rate()) {
+                    Ok(()) => {
+                        let output = match self.params.target {
+                            RecordingTarget::File => {
+                                OutputTarget::File(self.base.directory().join(&self.params.path))
+                            }
+                            RecordingTarget::MediaLibrary => {
+                                OutputTarget::MediaLibrary(self.base.clone(), library_label(&self.params.path))
+                            }
+                        };
+
+                        self.recording = Some(RecordingTask::start(output, self.params.encoder.clone(), self.base.rate()));
+                        self.file = None;
+                        self.error = None;
+                        self.dropped_ticks = 0;
+                    }
+                    Err(e) => {
+                        self.error = Some(e);
+                    }
+                }
+            }
+
+            self.indicate()
+        }
+    }
+
+    fn run_tick(&mut self, engine_time: u64, inputs: &[InputRef], _: &mut [OutputRef]) -> Option<Self::Indication> {
+        let (video, audio) = match inputs {
+            [video, audio] => (video.expect_video(), audio.expect_stereo()),
+            _ => unreachable!()
+        };
+
+        if let Some(recording) = &mut self.recording {
+            let timestamp = MediaTime::new(engine_time as i64, self.base.rate().sample_rate() as i64);
+
+            let msg = RecordingMsg::Tick {
+                timestamp,
+                audio: audio.to_vec(),
+                video: video.cloned(),
+            };
+
+            let mut stopped = recording.send(msg).is_err();
+            self.dropped_ticks = recording.dropped_ticks;
+
+            while let Some(event) = recording.recv_event() {
+                match event {
+                    RecordingEvent::Opened(name) => {
+                        self.file = Some(RecordingFile {
+                            name,
+                            duration: Microseconds(0),
+                            bytes_written: 0,
+                        });
+                    }
+                    RecordingEvent::Progress { duration, bytes_written } => {
+                        if let Some(file) = &mut self.file {
+                            let duration = quantize_duration(duration);
+
+                            if duration != file.duration {
+                                file.duration = duration;
+                                file.bytes_written = bytes_written;
+                            }
+                        }
+                    }
+                    RecordingEvent::Failed(error) => {
+                        eprintln!("Recorder failed: {}", error);
+                        self.error = Some(error);
+                        stopped = true;
+                    }
+                }
+            }
+
+            if stopped {
+                self.recording = None;
+            }
+        }
+
+        self.indicate()
+    }
+
+    fn inputs(&self) -> &[Terminal] {
+        &self.inputs
+    }
+
+    fn outputs(&self) -> &[Terminal] {
+        &[]
+    }
+}
+
+impl Recorder {
+    fn indicate(&mut self) -> Option<RecorderIndication> {
+        let new_indication = RecorderIndication {
+            recording: self.recording.is_some(),
+            file: self.file.clone(),
+            error: self.error.clone(),
+            dropped_ticks: self.dropped_ticks,
+        };
+
+        if new_indication == self.indication {
+            // don't send duplicate indication
+            None
+        } else {
+            self.indication = new_indication.clone();
+            Some(new_indication)
+        }
+    }
+}
+
+fn validate(params: &RecorderParams, rate: Rate) -> Result<(), String> {
+    params.encoder.validate(rate.tick_rate())?;
+
+    let is_mp4 = Path::new(&params.path).extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.eq_ignore_ascii_case("mp4"))
+        .unwrap_or(false);
+
+    if !is_mp4 {
+        return Err("Recording path must end in .mp4".to_owned());
+    }
+
+    // paths come from clients, and must not lead out of the project
+    // directory they are resolved against:
+    let within_project = Path::new(&params.path).components()
+        .all(|component| match component {
+            Component::Normal(_) | Component::CurDir => true,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => false,
+        });
+
+    if !within_project {
+        return Err("Recording path must be relative and within the project directory".to_owned());
+    }
+
+    Ok(())
+}
+
+// library items are named after the file stem of the configured path:
+fn library_label(path: &str) -> String {
+    Path::new(path).file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "Recording".to_owned())
+}
+
+fn quantize_duration(duration: MediaDuration) -> Microseconds {
+    let micros = duration.round_to_base(1_000_000).max(0);
+    Microseconds((micros - micros % DURATION_RESOLUTION_MICROS) as u64)
+}
+
+#[derive(Debug)]
+struct RecordingTask {
+    tx: mpsc::SyncSender<RecordingMsg>,
+    events: mpsc::Receiver<RecordingEvent>,
+    // a split which could not be sent yet because the recording thread is
+    // lagging. it goes ahead of the next tick there is room for:
+    split_pending: bool,
+    dropped_ticks: u64,
+}
+
+enum RecordingMsg {
+    Tick { timestamp: MediaTime, audio: Vec<engine::Sample>, video: Option<engine::VideoFrame> },
+    Split,
+}
+
+#[derive(Debug)]
+enum RecordingEvent {
+    Opened(String),
+    Progress { duration: MediaDuration, bytes_written: u64 },
+    Failed(String),
+}
+
+#[derive(Debug, From)]
+enum RecordError {
+    Io(io::Error),
+    Encode(EncodeError),
+    Upload(UploadError),
+}
+
+// where recorded files end up
+enum OutputTarget {
+    File(PathBuf),
+    MediaLibrary(ProjectBaseRef, String),
+}
+
+impl RecordingTask {
+    pub fn start(target: OutputTarget, settings: StreamEncoderSettings, rate: Rate) -> Self {
+        let (tx, rx) = mpsc::sync_channel(100);
+        let (events_tx, events) = mpsc::channel();
+
+        // writing to the media library goes through the project database,
+        // which requires the tokio runtime context to be entered
+        let tokio_runtime = runtime::Handle::current();
+
+        thread::spawn(move || {
+            tokio_runtime.enter(|| {
+                if let Err(e) = run_recording(&target, &settings, rate, rx, &events_tx) {
+                    let _ = events_tx.send(RecordingEvent::Failed(format!("{:?}", e)));
+                }
+            })
+        });
+
+        RecordingTask { tx, events, split_pending: false, dropped_ticks: 0 }
+    }
+
+    pub fn split(&mut self) {
+        // this runs on the engine thread, which must never block on the
+        // recording thread. the split is sent along with the next tick:
+        self.split_pending = true;
+    }
+
+    pub fn recv_event(&mut self) -> Option<RecordingEvent> {
+        self.events.try_recv().ok()
+    }
+
+    pub fn send(&mut self, msg: RecordingMsg) -> Result<(), ()> {
+        use mpsc::TrySendError;
+
+        if self.split_pending {
+            match self.tx.try_send(RecordingMsg::Split) {
+                Ok(()) => { self.split_pending = false; }
+                Err(TrySendError::Full(_)) => {
+                    // the tick can't go ahead of the split, drop it too
+                    self.dropped_ticks += 1;
+                    return Ok(());
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(());
+                }
+            }
+        }
+
+        match self.tx.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                // recording thread is lagging, drop the tick rather than
+                // holding up the engine. the recording thread sees the gap
+                // in timestamps and pads it out
+                self.dropped_ticks += 1;
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(())
+            }
+        }
+    }
+}
+
+fn run_recording(
+    target: &OutputTarget,
+    settings: &StreamEncoderSettings,
+    rate: Rate,
+    rx: mpsc::Receiver<RecordingMsg>,
+    events: &mpsc::Sender<RecordingEvent>,
+) -> Result<(), RecordError> {
+    // files are opened on the first tick after starting or splitting so
+    // that each begins at time zero:
+    let mut file: Option<FileWriter> = None;
+
+    while let Ok(msg) = rx.recv() {
+        match msg {
+            RecordingMsg::Tick { timestamp, audio, video } => {
+                if file.is_none() {
+                    let writer = FileWriter::create(target, settings, rate, timestamp)?;
+                    let _ = events.send(RecordingEvent::Opened(writer.out.name()));
+                    file = Some(writer);
+                }
+
+                let writer = file.as_mut().expect("file open");
+
+                writer.tick(timestamp, audio, video)?;
+
+                let _ = events.send(RecordingEvent::Progress {
+                    duration: writer.duration,
+                    bytes_written: writer.bytes_written,
+                });
+            }
+            RecordingMsg::Split => {
+                if let Some(writer) = file.take() {
+                    writer.finish()?;
+                }
+            }
+        }
+    }
+
+    // recording was stopped:
+    if let Some(writer) = file {
+        writer.finish()?;
+    }
+
+    Ok(())
+}
+
+struct FileWriter {
+    out: RecordingOutput,
+    rate: Rate,
+    bytes_written: u64,
+    duration: MediaDuration,
+    epoch: MediaTime,
+    encode: EncodeStream,
+    pacer: FramePacer,
+    mux: Mp4Mux,
+    index: Mp4Index,
+}
+
+impl FileWriter {
+    pub fn create(target: &OutputTarget, settings: &StreamEncoderSettings, rate: Rate, epoch: MediaTime) -> Result<Self, RecordError> {
+        let audio_ctx = AudioCtx::new(AudioParams {
+            bit_rate: aac::BitRate::Cbr((settings.audio_bitrate_kbps * 1000) as u32),
+            sample_rate: rate.sample_rate(),
+            transport: aac::Transport::Adts,
+        })?;
+
+        let video_ctx = VideoCtx::new(VideoParams::from_settings(settings, rate.sample_rate()))?;
+
+        let mp4_params = {
+            let dcr = video_ctx.decoder_configuration_record();
+            let mut dcr_bytes = vec![];
+            dcr.write_to(&mut dcr_bytes);
+
+            Mp4Params {
+                timescale: rate.sample_rate() as u32,
+                sample_rate: rate.sample_rate() as u32,
+                width: settings.width as u32,
+                height: settings.height as u32,
+                dcr: Cow::Owned(dcr_bytes),
+            }
+        };
+
+        let mut out = RecordingOutput::open(target)?;
+
+        let (mux, init) = Mp4Mux::new(mp4_params);
+        out.write_all(&init)?;
+
+        Ok(FileWriter {
+            out,
+            rate,
+            bytes_written: init.len() as u64,
+            duration: MediaDuration::zero(),
+            epoch,
+            encode: EncodeStream::new(audio_ctx, video_ctx),
+            pacer: FramePacer::new(settings.frame_rate),
+            mux,
+            index: Mp4Index::new(),
+        })
+    }
+
+    pub fn tick(&mut self, timestamp: MediaTime, audio: Vec<engine::Sample>, video: Option<engine::VideoFrame>) -> Result<(), RecordError> {
+        let timestamp = timestamp.remove_epoch(self.epoch);
+        let tick_end = timestamp + self.rate.tick_duration();
+
+        self.pad(timestamp);
+
+        self.encode.send_audio(&audio);
+
+        if let Some(video_frame) = video {
+            let frame_timestamp = timestamp + video_frame.tick_offset;
+            self.pacer.send_video(frame_timestamp, video_frame.data.duration_hint, video_frame.data.decoded);
+        }
+
+        self.pacer.advance(&mut self.encode, tick_end);
+
+        while let Some(segment) = self.encode.recv_segment() {
+            self.write_segment(segment)?;
+        }
+
+        self.duration = tick_end - MediaTime::zero();
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), RecordError> {
+        // the encoder holds back the last segments of each track until the
+        // stream ends, and they must be in the file before its index:
+        for segment in self.encode.drain() {
+            self.write_segment(segment)?;
+        }
+
+        let index = self.index.finish();
+        self.out.write_all(&index)?;
+        self.out.finish()
+    }
+
+    // fills any ticks dropped between the end of the last tick written and
+    // `timestamp` with silence and a held frame, so that audio stays in sync
+    // with video through the rest of the file:
+    fn pad(&mut self, timestamp: MediaTime) {
+        let mut padded = MediaTime::zero() + self.duration;
+
+        if padded >= timestamp {
+            return;
+        }
+
+        let silence = vec![0.0; self.rate.samples_per_tick() * engine::CHANNELS];
+
+        self.pacer.hold(timestamp);
+
+        while padded < timestamp {
+            padded += self.rate.tick_duration();
+            self.encode.send_audio(&silence);
+            self.pacer.advance(&mut self.encode, padded);
+        }
+    }
+
+    fn write_segment(&mut self, segment: StreamSegment) -> Result<(), RecordError> {
+        let (duration, data) = match segment {
+            StreamSegment::Audio(audio) => {
+                (audio.duration, TrackData::Audio(AdtsFrame(audio.frame)))
+            }
+            StreamSegment::Video(video) => {
+                (video.duration, TrackData::Video(AvcFrame {
+                    is_key_frame: video.frame.is_key_frame,
+                    composition_time: video.frame.composition_time,
+                    data: video.frame.data,
+                }))
+            }
+        };
+
+        let bytes = self.mux.write_track_indexed(duration, &data, self.bytes_written, &mut self.index);
+        self.out.write_all(&bytes)?;
+        self.bytes_written += bytes.len() as u64;
+
+        Ok(())
+    }
+}
+
+enum RecordingOutput {
+    File(PathBuf, BufWriter<File>),
+    MediaLibrary(MediaRecording),
+}
+
+impl RecordingOutput {
+    pub fn open(target: &OutputTarget) -> Result<Self, RecordError> {
+        match target {
+            OutputTarget::File(path) => {
+                let (path, file) = create_numbered(path)?;
+                Ok(RecordingOutput::File(path, BufWriter::new(file)))
+            }
+            OutputTarget::MediaLibrary(base, label) => {
+                let recording = MediaRecording::create(base.clone(), RecordingInfo {
+                    label: label.clone(),
+                    extension: "mp4".to_owned(),
+                    kind: "video/mp4".to_owned(),
+                })?;
+
+                Ok(RecordingOutput::MediaLibrary(recording))
+            }
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            RecordingOutput::File(path, _) => path.to_string_lossy().into_owned(),
+            RecordingOutput::MediaLibrary(recording) => recording.name().to_owned(),
+        }
+    }
+
+    pub fn write_all(&mut self, bytes: &[u8]) -> Result<(), RecordError> {
+        match self {
+            RecordingOutput::File(_, file) => Ok(file.write_all(bytes)?),
+            RecordingOutput::MediaLibrary(recording) => Ok(recording.write(bytes)?),
+        }
+    }
+
+    pub fn finish(self) -> Result<(), RecordError> {
+        match self {
+            RecordingOutput::File(_, mut file) => Ok(file.flush()?),
+            RecordingOutput::MediaLibrary(recording) => Ok(recording.finalize()?),
+        }
+    }
+}
+
+// creates a new file at path, adding a number to the file name if a file of
+// that name already exists
+fn create_numbered(path: &Path) -> Result<(PathBuf, File), io::Error> {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = path.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default();
+
+    for number in 1.. {
+        let candidate = if number == 1 {
+            path.to_owned()
+        } else {
+            path.with_file_name(format!("{}-{}.{}", stem, number, extension))
+        };
+
+        match OpenOptions::new().write(true).create_new(true).open(&candidate) {
+            Ok(file) => { return Ok((candidate, file)); }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => { continue; }
+            Err(e) => { return Err(e); }
+        }
+    }
+
+    unreachable!()
+}
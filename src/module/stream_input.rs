@@ -3,7 +3,7 @@ use std::cmp;
 use mixlab_protocol::{StreamInputParams, LineType, Terminal, StreamProtocol};
 use mixlab_util::time::{MediaTime, MediaDuration};
 
-use crate::engine::{self, InputRef, OutputRef, Sample, VideoFrame, SAMPLE_RATE};
+use crate::engine::{self, InputRef, OutputRef, Rate, Sample, VideoFrame};
 use crate::icecast;
 use crate::module::ModuleT;
 use crate::rtmp;
@@ -13,6 +13,7 @@ use crate::util;
 #[derive(Debug)]
 pub struct StreamInput {
     params: StreamInputParams,
+    rate: Rate,
     recv: Option<SourceRecv>,
     source: Option<SourceTiming>,
     audio_frame: Option<Frame<AudioData>>,
@@ -32,11 +33,12 @@ impl ModuleT for StreamInput {
     type Indication = ();
     type Event = ();
 
-    fn create(params: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+    fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
         let recv = listen_mountpoint(&params);
 
         let module = StreamInput {
             params,
+            rate: ctx.rate(),
             recv,
             source: None,
             audio_frame: None,
@@ -62,6 +64,10 @@ impl ModuleT for StreamInput {
         if current_mountpoint != new_mountpoint || self.params.protocol != new_params.protocol {
             // TODO - tell the user about this one too
             self.recv = listen_mountpoint(&new_params);
+        } else if self.params.secret != new_params.secret {
+            if let Some(recv) = &self.recv {
+                recv.set_secret(new_params.secret.clone());
+            }
         }
 
         self.params = new_params;
@@ -70,14 +76,14 @@ impl ModuleT for StreamInput {
     }
 
     fn run_tick(&mut self, engine_time: u64, _: &[InputRef], outputs: &mut [OutputRef]) -> Option<Self::Indication> {
-        let engine_time = MediaTime::new(engine_time as i64, SAMPLE_RATE as i64);
+        let engine_time = MediaTime::new(engine_time as i64, self.rate.sample_rate() as i64);
 
         let (video_out, mut audio_out) = match outputs {
             [video, audio] => (video.expect_video(), audio.expect_stereo()),
             _ => unimplemented!(),
         };
 
-        let tick_duration = MediaDuration::new(audio_out.len() as i64 / 2, SAMPLE_RATE as i64);
+        let tick_duration = self.rate.tick_duration();
 
         let video_frame = self.video_frame.take()
             .or_else(|| {
@@ -158,9 +164,11 @@ impl ModuleT for StreamInput {
 fn listen_mountpoint(params: &StreamInputParams) -> Option<SourceRecv> {
     let mountpoint = params.mountpoint.as_ref()?;
 
+    let secret = params.secret.clone();
+
     match params.protocol? {
-        StreamProtocol::Icecast => icecast::listen(mountpoint).ok(),
-        StreamProtocol::Rtmp => rtmp::listen(mountpoint).ok(),
+        StreamProtocol::Icecast => icecast::listen(mountpoint, secret).ok(),
+        StreamProtocol::Rtmp => rtmp::listen(mountpoint, secret).ok(),
     }
 }
 
@@ -1,7 +1,7 @@
 use std::sync::mpsc;
 use std::thread;
 
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use derive_more::From;
 use fdk_aac::enc as aac;
 use rml_rtmp::time::RtmpTimestamp;
@@ -9,24 +9,25 @@ use tokio::net::TcpStream;
 use tokio::runtime;
 use tokio::sync::oneshot;
 
-use mixlab_codec::ffmpeg::PictureSettings;
-use mixlab_protocol::{StreamOutputParams, LineType, Terminal, StreamOutputIndication, StreamOutputLiveStatus};
-use mixlab_util::time::MediaTime;
+use mixlab_protocol::{StreamOutputParams, StreamDestination, ReconnectPolicy, StreamEncoderSettings, LineType, Terminal, StreamOutputIndication, StreamDestinationIndication, StreamOutputLiveStatus};
+use mixlab_util::time::{MediaTime, MediaDuration};
 
-use crate::engine::{self, InputRef, OutputRef, SAMPLE_RATE};
+use crate::engine::{self, InputRef, OutputRef, Rate};
 use crate::module::ModuleT;
 use crate::rtmp;
 use crate::rtmp::packet::{AudioPacket, VideoPacket, VideoFrameType, VideoPacketType};
-use crate::rtmp::client::{self, StreamMetadata, PublishInfo, PublishClient};
-use crate::video::encode::{EncodeStream, AudioCtx, AudioParams, VideoCtx, VideoParams, StreamSegment, Profile};
-
-const OUTPUT_WIDTH: usize = 1120;
-const OUTPUT_HEIGHT: usize = 700;
+use crate::rtmp::client::{self, StreamMetadata, PublishInfo, PublishClient, PublishError};
+use crate::video::encode::{EncodeStream, EncodeError, FramePacer, AudioCtx, AudioParams, VideoCtx, VideoParams, StreamSegment};
 
 #[derive(Debug)]
 pub struct StreamOutput {
     params: StreamOutputParams,
-    connection: Connection,
+    rate: Rate,
+    // started once the first destination goes live, and stopped once no
+    // destinations remain active:
+    live: Option<LiveOutputTask>,
+    destinations: Vec<Destination>,
+    encoder_error: Option<String>,
     inputs: Vec<Terminal>,
     indication: StreamOutputIndication,
 }
@@ -36,23 +37,36 @@ impl ModuleT for StreamOutput {
     type Indication = StreamOutputIndication;
     type Event = ();
 
-    fn create(params: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
-        let indic = StreamOutputIndication {
-            live: StreamOutputLiveStatus::Offline,
-            error: false,
-        };
+    fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+        let destinations = params.destinations.iter()
+            .map(|_| Destination::Offline)
+            .collect();
 
-        let module = StreamOutput {
+        let rate = ctx.rate();
+        let encoder_error = params.encoder.validate(rate.tick_rate()).err();
+
+        let mut module = StreamOutput {
             params,
-            connection: Connection::Offline,
+            rate,
+            live: None,
+            destinations,
+            encoder_error,
             inputs: vec![
                 LineType::Video.labeled("Video"),
                 LineType::Stereo.labeled("Audio"),
             ],
-            indication: indic.clone(),
+            indication: StreamOutputIndication {
+                live: StreamOutputLiveStatus::Offline,
+                error: false,
+                destinations: vec![],
+                encoder_error: None,
+            },
         };
 
-        (module, indic)
+        module.indicate();
+
+        let indication = module.indication.clone();
+        (module, indication)
     }
 
     fn params(&self) -> Self::Params {
@@ -65,42 +79,36 @@ impl ModuleT for StreamOutput {
             return None;
         }
 
-        if self.connection.is_active() {
+        if self.is_active() {
             if new_params.disconnect_seq == new_params.seq {
-                self.connection = Connection::Offline;
+                self.live = None;
 
-                Some(StreamOutputIndication {
-                    live: StreamOutputLiveStatus::Offline,
-                    error: false,
-                })
+                for destination in &mut self.destinations {
+                    *destination = Destination::Offline;
+                }
+
+                self.indicate()
             } else {
                 // cannot change params on a live stream output
                 None
             }
         } else {
             self.params = new_params;
+            self.encoder_error = self.params.encoder.validate(self.rate.tick_rate()).err();
 
-            if self.params.connect_seq == self.params.seq {
-                // connect with current details
-                let (completion_tx, completion_rx) = oneshot::channel();
+            if self.params.connect_seq == self.params.seq && self.encoder_error.is_none() {
+                // connect each destination with current details
+                let rate = self.rate;
+                let encoder = &self.params.encoder;
 
-                // spawn task to connect to RTMP
-                tokio::spawn({
-                    let params = self.params.clone();
-                    async move {
-                        let _ = completion_tx.send(connect_rtmp(params.clone()).await);
-                    }
-                });
-
-                self.connection = Connection::Connecting(completion_rx);
-
-                Some(StreamOutputIndication {
-                    live: StreamOutputLiveStatus::Connecting,
-                    error: false,
-                })
+                self.destinations = self.params.destinations.iter()
+                    .map(|destination| Destination::connect(destination, encoder, rate, 0))
+                    .collect();
             } else {
-                None
+                self.destinations.resize_with(self.params.destinations.len(), || Destination::Offline);
             }
+
+            self.indicate()
         }
     }
 
@@ -110,61 +118,99 @@ impl ModuleT for StreamOutput {
             _ => unreachable!()
         };
 
-        let timestamp = MediaTime::new(engine_time as i64, SAMPLE_RATE as i64);
-
-        let live = match &mut self.connection {
-            Connection::Offline => {
-                return self.indicate();
-            }
-            Connection::Failed(_) => {
-                return self.indicate();
-            }
-            Connection::Connecting(completion) => {
-                use oneshot::error::TryRecvError;
+        let rate = self.rate;
+        let timestamp = MediaTime::new(engine_time as i64, rate.sample_rate() as i64);
 
-                match completion.try_recv() {
-                    Ok(Ok(publish)) => {
-                        self.connection = Connection::Live(LiveOutputTask::start(timestamp, publish));
+        for (index, destination) in self.destinations.iter_mut().enumerate() {
+            match destination {
+                Destination::Connecting { completion, attempt } => {
+                    use oneshot::error::TryRecvError;
 
-                        match &mut self.connection {
-                            Connection::Live(live) => live,
-                            _ => unreachable!(),
+                    match completion.try_recv() {
+                        Ok(Ok(publish)) => {
+                            let encoder = &self.params.encoder;
+                            let live = self.live.get_or_insert_with(|| LiveOutputTask::start(timestamp, encoder.clone(), rate));
+                            live.add_destination(index, publish);
+                            *destination = Destination::Live;
+                        }
+                        Ok(Err(e)) => {
+                            // failed to connect
+                            eprintln!("StreamOutput failed to connect: {:?}", e);
+                            let error = format!("{:?}", e);
+
+                            *destination = if *attempt == 0 {
+                                // don't retry initial connection failures,
+                                // they are most likely misconfiguration
+                                Destination::Failed(error)
+                            } else {
+                                Destination::retry(&self.params.reconnect, *attempt + 1, timestamp, error)
+                            };
+                        }
+                        Err(TryRecvError::Empty) => {
+                            // not yet ready
+                        }
+                        Err(TryRecvError::Closed) => {
+                            // failed to connect
+                            *destination = Destination::Offline;
                         }
                     }
-                    Ok(Err(e)) => {
-                        // failed to connect
-                        eprintln!("StreamOutput failed to connect: {:?}", e);
-                        self.connection = Connection::Failed(Some(e));
-                        return self.indicate();
-                    }
-                    Err(TryRecvError::Empty) => {
-                        // not yet ready
-                        return self.indicate();
+                }
+                Destination::Backoff { attempt, retry_at, .. } if *retry_at <= timestamp => {
+                    *destination = Destination::connect(&self.params.destinations[index], &self.params.encoder, rate, *attempt);
+                }
+                _ => {}
+            }
+        }
+
+        let mut encoder_stopped = false;
+
+        if let Some(live) = &mut self.live {
+            while let Some(event) = live.recv_event() {
+                match event {
+                    LiveOutputEvent::Failed { index, error } => {
+                        eprintln!("StreamOutput destination {} failed: {}", index, error);
+                        self.destinations[index] = Destination::retry(&self.params.reconnect, 1, timestamp, error);
                     }
-                    Err(TryRecvError::Closed) => {
-                        // failed to connect
-                        self.connection = Connection::Offline;
-                        return self.indicate();
+                    LiveOutputEvent::EncoderFailed(error) => {
+                        // nothing can go out without an encoder, so take
+                        // every destination offline
+                        eprintln!("StreamOutput encoder failed: {}", error);
+                        self.encoder_error = Some(error);
+
+                        for destination in &mut self.destinations {
+                            *destination = Destination::Offline;
+                        }
                     }
                 }
             }
-            Connection::Live(live) => live,
-        };
 
-        let msg = LiveOutputMsg::Tick {
-            timestamp,
-            audio: audio.to_vec(),
-            video: video.cloned(),
-        };
+            let msg = LiveOutputMsg::Tick {
+                timestamp,
+                audio: audio.to_vec(),
+                video: video.cloned(),
+            };
+
+            encoder_stopped = live.send(msg).is_err();
+        }
+
+        if encoder_stopped {
+            // the live output task has gone away along with every publish
+            // client handed to it. a new task is started as destinations
+            // reconnect, so none of them may be added to this one:
+            self.live = None;
 
-        match live.send(msg) {
-            Ok(()) => {}
-            Err(()) => {
-                self.connection = Connection::Failed(None);
+            for destination in &mut self.destinations {
+                if let Destination::Live = destination {
+                    *destination = Destination::retry(&self.params.reconnect, 1, timestamp, "Encoder stopped".to_owned());
+                }
             }
         }
 
-        return self.indicate();
+        if !self.is_active() {
+            self.live = None;
+        }
+
+        self.indicate()
     }
 
     fn inputs(&self) -> &[Terminal] {
@@ -185,8 +231,9 @@ enum RtmpConnectError {
     Client(client::Error),
 }
 
-async fn connect_rtmp(params: StreamOutputParams) -> Result<PublishClient, RtmpConnectError> {
-    let url = url::Url::parse(&params.rtmp_url)?;
+// settings must have been validated, they are advertised to the server as is:
+async fn connect_rtmp(destination: StreamDestination, settings: StreamEncoderSettings, rate: Rate) -> Result<PublishClient, RtmpConnectError> {
+    let url = url::Url::parse(&destination.rtmp_url)?;
 
     if url.scheme() != "rtmp" {
         return Err(RtmpConnectError::UnsupportedScheme);
@@ -207,16 +254,16 @@ async fn connect_rtmp(params: StreamOutputParams) -> Result<PublishClient, RtmpC
         .await?
         .publish(PublishInfo {
             app_name: app_name.to_owned(),
-            stream_key: params.rtmp_stream_key.to_owned(),
+            stream_key: destination.rtmp_stream_key.to_owned(),
             meta: StreamMetadata {
-                video_width: Some(OUTPUT_WIDTH as u32),
-                video_height: Some(OUTPUT_HEIGHT as u32),
+                video_width: Some(settings.width as u32),
+                video_height: Some(settings.height as u32),
                 video_codec: Some("avc1".to_owned()),
-                video_frame_rate: Some(30.0),
-                video_bitrate_kbps: None, //Some(2500),
+                video_frame_rate: Some(settings.frame_rate as f32),
+                video_bitrate_kbps: Some(settings.video_bitrate_kbps as u32),
                 audio_codec: Some("aac1".to_owned()),
-                audio_bitrate_kbps: Some(160),
-                audio_sample_rate: Some(SAMPLE_RATE as u32),
+                audio_bitrate_kbps: Some(settings.audio_bitrate_kbps as u32),
+                audio_sample_rate: Some(rate.sample_rate() as u32),
                 audio_channels: Some(2),
                 audio_is_stereo: Some(true),
                 encoder: Some("Mixlab".to_owned()),
@@ -228,24 +275,39 @@ async fn connect_rtmp(params: StreamOutputParams) -> Result<PublishClient, RtmpC
 }
 
 impl StreamOutput {
+    fn is_active(&self) -> bool {
+        self.destinations.iter().any(Destination::is_active)
+    }
+
     fn indicate(&mut self) -> Option<StreamOutputIndication> {
-        let new_indication = match &self.connection {
-            Connection::Offline => StreamOutputIndication {
-                live: StreamOutputLiveStatus::Offline,
-                error: false,
-            },
-            Connection::Failed(_) => StreamOutputIndication {
-                live: StreamOutputLiveStatus::Offline,
-                error: true,
-            },
-            Connection::Connecting(_) => StreamOutputIndication {
-                live: StreamOutputLiveStatus::Connecting,
-                error: false,
-            },
-            Connection::Live(_) => StreamOutputIndication {
-                live: StreamOutputLiveStatus::Live,
-                error: false,
-            },
+        let destinations = self.destinations.iter()
+            .map(Destination::indication)
+            .collect::<Vec<_>>();
+
+        let any_status = |status| destinations.iter().any(|dest| dest.live == status);
+
+        let reconnect_attempt = destinations.iter()
+            .filter_map(|dest| match dest.live {
+                StreamOutputLiveStatus::Reconnecting { attempt } => Some(attempt),
+                _ => None,
+            })
+            .max();
+
+        let live = if any_status(StreamOutputLiveStatus::Live) {
+            StreamOutputLiveStatus::Live
+        } else if let Some(attempt) = reconnect_attempt {
+            StreamOutputLiveStatus::Reconnecting { attempt }
+        } else if any_status(StreamOutputLiveStatus::Connecting) {
+            StreamOutputLiveStatus::Connecting
+        } else {
+            StreamOutputLiveStatus::Offline
+        };
+
+        let new_indication = StreamOutputIndication {
+            live,
+            error: self.encoder_error.is_some() || destinations.iter().any(|dest| dest.error.is_some()),
+            destinations,
+            encoder_error: self.encoder_error.clone(),
         };
 
         if new_indication == self.indication {
@@ -259,43 +321,123 @@ impl StreamOutput {
 }
 
 #[derive(Debug)]
-enum Connection {
+enum Destination {
     Offline,
-    Failed(Option<RtmpConnectError>),
-    Connecting(oneshot::Receiver<Result<PublishClient, RtmpConnectError>>),
-    Live(LiveOutputTask),
+    Failed(String),
+    Connecting {
+        completion: oneshot::Receiver<Result<PublishClient, RtmpConnectError>>,
+        // zero for the initial connection:
+        attempt: usize,
+    },
+    // waiting to make the next reconnect attempt:
+    Backoff {
+        attempt: usize,
+        retry_at: MediaTime,
+        error: String,
+    },
+    // publish client has been handed off to the live output task:
+    Live,
 }
 
-impl Connection {
-    pub fn is_active(&self) -> bool {
+impl Destination {
+    fn connect(destination: &StreamDestination, settings: &StreamEncoderSettings, rate: Rate, attempt: usize) -> Self {
+        let (completion_tx, completion_rx) = oneshot::channel();
+
+        // spawn task to connect to RTMP
+        tokio::spawn({
+            let destination = destination.clone();
+            let settings = settings.clone();
+            async move {
+                let _ = completion_tx.send(connect_rtmp(destination, settings, rate).await);
+            }
+        });
+
+        Destination::Connecting { completion: completion_rx, attempt }
+    }
+
+    // schedules reconnect attempt number `attempt` if the policy allows it
+    fn retry(policy: &ReconnectPolicy, attempt: usize, now: MediaTime, error: String) -> Self {
+        if attempt > policy.max_attempts {
+            return Destination::Failed(error);
+        }
+
+        // delay doubles with each attempt: 1s, 2s, 4s...
+        let delay_secs = 1usize.checked_shl((attempt - 1) as u32)
+            .unwrap_or(usize::max_value())
+            .min(policy.max_delay_secs);
+
+        Destination::Backoff {
+            attempt,
+            retry_at: now + MediaDuration::new(delay_secs as i64, 1),
+            error,
+        }
+    }
+
+    fn is_active(&self) -> bool {
         match self {
-            Connection::Offline => false,
-            Connection::Failed(_) => false,
-            Connection::Connecting(_) => true,
-            Connection::Live(_) => true,
+            Destination::Offline => false,
+            Destination::Failed(_) => false,
+            Destination::Connecting { .. } => true,
+            Destination::Backoff { .. } => true,
+            Destination::Live => true,
         }
     }
+
+    fn indication(&self) -> StreamDestinationIndication {
+        let (live, error) = match self {
+            Destination::Offline => (StreamOutputLiveStatus::Offline, None),
+            Destination::Failed(error) => (StreamOutputLiveStatus::Offline, Some(error.clone())),
+            Destination::Connecting { attempt: 0, .. } => (StreamOutputLiveStatus::Connecting, None),
+            Destination::Connecting { attempt, .. } => (StreamOutputLiveStatus::Reconnecting { attempt: *attempt }, None),
+            Destination::Backoff { attempt, error, .. } => (StreamOutputLiveStatus::Reconnecting { attempt: *attempt }, Some(error.clone())),
+            Destination::Live => (StreamOutputLiveStatus::Live, None),
+        };
+
+        StreamDestinationIndication { live, error }
+    }
 }
 
 #[derive(Debug)]
 struct LiveOutputTask {
     tx: mpsc::SyncSender<LiveOutputMsg>,
+    // destinations are sent on their own unbounded channel so that they can
+    // never be dropped like ticks are when the encoder thread lags:
+    destination_tx: mpsc::Sender<(usize, PublishClient)>,
+    events: mpsc::Receiver<LiveOutputEvent>,
 }
 
 enum LiveOutputMsg {
     Tick { timestamp: MediaTime, audio: Vec<engine::Sample>, video: Option<engine::VideoFrame> }
 }
 
+#[derive(Debug)]
+enum LiveOutputEvent {
+    Failed { index: usize, error: String },
+    EncoderFailed(String),
+}
+
 impl LiveOutputTask {
-    pub fn start(epoch: MediaTime, publish: PublishClient) -> Self {
+    pub fn start(epoch: MediaTime, settings: StreamEncoderSettings, rate: Rate) -> Self {
         let runtime = runtime::Handle::current();
         let (tx, rx) = mpsc::sync_channel(100);
+        let (destination_tx, destination_rx) = mpsc::channel();
+        let (events_tx, events) = mpsc::channel();
 
         thread::spawn(move || {
             runtime.enter(move || {
-                let mut live = LiveOutput::start(epoch, publish);
+                let mut live = match LiveOutput::start(epoch, &settings, rate, events_tx.clone()) {
+                    Ok(live) => live,
+                    Err(e) => {
+                        let _ = events_tx.send(LiveOutputEvent::EncoderFailed(format!("{:?}", e)));
+                        return;
+                    }
+                };
 
                 while let Ok(msg) = rx.recv() {
+                    while let Ok((index, publish)) = destination_rx.try_recv() {
+                        live.add_destination(index, publish);
+                    }
+
                     match msg {
                         LiveOutputMsg::Tick { timestamp, audio, video } => {
                             live.tick(timestamp, audio, video);
@@ -305,7 +447,16 @@ impl LiveOutputTask {
             });
         });
 
-        LiveOutputTask { tx }
+        LiveOutputTask { tx, destination_tx, events }
+    }
+
+    pub fn add_destination(&mut self, index: usize, publish: PublishClient) {
+        // if the encoder thread has gone away we will find out on next send
+        let _ = self.destination_tx.send((index, publish));
+    }
+
+    pub fn recv_event(&mut self) -> Option<LiveOutputEvent> {
+        self.events.try_recv().ok()
     }
 
     pub fn send(&mut self, msg: LiveOutputMsg) -> Result<(), ()> {
@@ -328,80 +479,160 @@ impl LiveOutputTask {
 #[derive(Debug)]
 struct LiveOutput {
     epoch: MediaTime,
+    rate: Rate,
     encode: EncodeStream,
+    pacer: FramePacer,
+    // sequence headers, sent to each destination as it is added:
+    audio_config: Bytes,
+    video_config: Bytes,
+    destinations: Vec<LiveDestination>,
+    events: mpsc::Sender<LiveOutputEvent>,
+}
+
+#[derive(Debug)]
+struct LiveDestination {
+    index: usize,
     publish: PublishClient,
+    // destinations may join part way through the stream. nothing is sent to
+    // a destination until the next keyframe, and its timestamps are relative
+    // to that keyframe:
+    epoch: Option<MediaTime>,
 }
 
 impl LiveOutput {
-    pub fn start(epoch: MediaTime, mut publish: PublishClient) -> Self {
+    pub fn start(epoch: MediaTime, settings: &StreamEncoderSettings, rate: Rate, events: mpsc::Sender<LiveOutputEvent>) -> Result<Self, EncodeError> {
         let audio_ctx = AudioCtx::new(AudioParams {
-            bit_rate: aac::BitRate::Cbr(160000),
-            sample_rate: SAMPLE_RATE,
+            bit_rate: aac::BitRate::Cbr((settings.audio_bitrate_kbps * 1000) as u32),
+            sample_rate: rate.sample_rate(),
             transport: aac::Transport::Raw,
-        });
+        })?;
 
         // configuration buffer is ASC when raw transport is in use:
-        let asc = audio_ctx.configuration_data();
-        publish.publish_audio(AudioPacket::AacSequenceHeader(asc), RtmpTimestamp::new(0)).expect("TODO");
-
-        let video_ctx = VideoCtx::new(VideoParams {
-            picture: PictureSettings::yuv420p(OUTPUT_WIDTH, OUTPUT_HEIGHT),
-            time_base: SAMPLE_RATE,
-            profile: Profile::Stream,
-        });
+        let audio_config = audio_ctx.configuration_data();
 
-        let mut dsc = BytesMut::new();
-        video_ctx.decoder_configuration_record().write_to(&mut dsc);
-        let dsc = dsc.freeze();
+        let video_ctx = VideoCtx::new(VideoParams::from_settings(settings, rate.sample_rate()))?;
 
-        publish.publish_video(VideoPacket {
-            frame_type: VideoFrameType::KeyFrame,
-            packet_type: VideoPacketType::SequenceHeader,
-            composition_time: 0,
-            data: dsc,
-        }, RtmpTimestamp::new(0)).expect("TODO");
+        let mut dcr = BytesMut::new();
+        video_ctx.decoder_configuration_record().write_to(&mut dcr);
+        let video_config = dcr.freeze();
 
         let encode = EncodeStream::new(audio_ctx, video_ctx);
 
-        LiveOutput {
+        Ok(LiveOutput {
             epoch,
+            rate,
             encode,
-            publish,
+            pacer: FramePacer::new(settings.frame_rate),
+            audio_config,
+            video_config,
+            destinations: Vec::new(),
+            events,
+        })
+    }
+
+    pub fn add_destination(&mut self, index: usize, mut publish: PublishClient) {
+        let result = publish.publish_audio(AudioPacket::AacSequenceHeader(self.audio_config.clone()), RtmpTimestamp::new(0))
+            .and_then(|()| publish.publish_video(VideoPacket {
+                frame_type: VideoFrameType::KeyFrame,
+                packet_type: VideoPacketType::SequenceHeader,
+                composition_time: 0,
+                data: self.video_config.clone(),
+            }, RtmpTimestamp::new(0)));
+
+        match result {
+            Ok(()) => {
+                // destination starts receiving video from the next keyframe,
+                // so make sure that comes along promptly:
+                self.encode.force_keyframe();
+
+                self.destinations.push(LiveDestination {
+                    index,
+                    publish,
+                    epoch: None,
+                });
+            }
+            Err(e) => {
+                self.fail(index, e);
+            }
         }
     }
 
     pub fn tick(&mut self, timestamp: MediaTime, audio: Vec<engine::Sample>, video: Option<engine::VideoFrame>) {
+        let timestamp = timestamp.remove_epoch(self.epoch);
+        let tick_end = timestamp + self.rate.tick_duration();
+
         self.encode.send_audio(&audio);
 
         if let Some(video_frame) = video {
-            let frame = video_frame.data.decoded.clone();
-            let frame_timestamp = timestamp.remove_epoch(self.epoch) + video_frame.tick_offset;
-
-            self.encode.send_video(frame_timestamp, video_frame.data.duration_hint, frame);
+            let frame_timestamp = timestamp + video_frame.tick_offset;
+            self.pacer.send_video(frame_timestamp, video_frame.data.duration_hint, video_frame.data.decoded.clone());
         }
 
-        self.encode.barrier(timestamp.remove_epoch(self.epoch));
+        self.pacer.advance(&mut self.encode, tick_end);
 
         while let Some(segment) = self.encode.recv_segment() {
-            match segment {
-                StreamSegment::Audio(audio) => {
-                    let timestamp = RtmpTimestamp::new(audio.decode_timestamp.round_to_base(rtmp::TIME_BASE.into()) as u32);
-                    self.publish.publish_audio(AudioPacket::AacRawData(audio.frame), timestamp).expect("TODO");
-                }
-                StreamSegment::Video(video) => {
-                    let timestamp = RtmpTimestamp::new(video.decode_timestamp.round_to_base(rtmp::TIME_BASE.into()) as u32);
-                    self.publish.publish_video(VideoPacket {
-                        frame_type: if video.frame.is_key_frame {
-                            VideoFrameType::KeyFrame
-                        } else {
-                            VideoFrameType::InterFrame
-                        },
-                        packet_type: VideoPacketType::Nalu,
-                        composition_time: video.frame.composition_time.round_to_base(rtmp::TIME_BASE.into()) as u32,
-                        data: video.frame.data,
-                    }, timestamp).expect("TODO");
+            // one destination failing must not affect any of the others:
+            let mut i = 0;
+
+            while i < self.destinations.len() {
+                match self.destinations[i].publish_segment(&segment) {
+                    Ok(()) => { i += 1; }
+                    Err(e) => {
+                        let destination = self.destinations.remove(i);
+                        self.fail(destination.index, e);
+                    }
                 }
             }
         }
     }
+
+    fn fail(&mut self, index: usize, e: PublishError) {
+        let _ = self.events.send(LiveOutputEvent::Failed {
+            index,
+            error: format!("{:?}", e),
+        });
+    }
+}
+
+impl LiveDestination {
+    fn publish_segment(&mut self, segment: &StreamSegment) -> Result<(), PublishError> {
+        match segment {
+            StreamSegment::Audio(audio) => {
+                let epoch = match self.epoch {
+                    Some(epoch) if audio.decode_timestamp >= epoch => epoch,
+                    _ => { return Ok(()); }
+                };
+
+                let timestamp = rtmp_timestamp(audio.decode_timestamp.remove_epoch(epoch));
+                self.publish.publish_audio(AudioPacket::AacRawData(audio.frame.clone()), timestamp)
+            }
+            StreamSegment::Video(video) => {
+                let epoch = match self.epoch {
+                    Some(epoch) => epoch,
+                    None if video.frame.is_key_frame => {
+                        self.epoch = Some(video.decode_timestamp);
+                        video.decode_timestamp
+                    }
+                    None => { return Ok(()); }
+                };
+
+                let timestamp = rtmp_timestamp(video.decode_timestamp.remove_epoch(epoch));
+
+                self.publish.publish_video(VideoPacket {
+                    frame_type: if video.frame.is_key_frame {
+                        VideoFrameType::KeyFrame
+                    } else {
+                        VideoFrameType::InterFrame
+                    },
+                    packet_type: VideoPacketType::Nalu,
+                    composition_time: video.frame.composition_time.round_to_base(rtmp::TIME_BASE.into()) as u32,
+                    data: video.frame.data.clone(),
+                }, timestamp)
+            }
+        }
+    }
+}
+
+fn rtmp_timestamp(time: MediaTime) -> RtmpTimestamp {
+    RtmpTimestamp::new(time.round_to_base(rtmp::TIME_BASE.into()) as u32)
 }
@@ -5,7 +5,7 @@ use mixlab_codec::ffmpeg::{AvFrame, PictureSettings, PixelFormat};
 use mixlab_protocol::{VideoMixerParams, LineType, Terminal, VIDEO_MIXER_CHANNELS};
 use mixlab_util::time::{MediaTime, MediaDuration};
 
-use crate::engine::{self, InputRef, OutputRef, SAMPLE_RATE, TICKS_PER_SECOND};
+use crate::engine::{self, InputRef, OutputRef, Rate};
 use crate::module::ModuleT;
 use crate::video;
 use crate::video::encode::DynamicScaler;
@@ -13,6 +13,7 @@ use crate::video::encode::DynamicScaler;
 #[derive(Debug)]
 pub struct VideoMixer {
     params: VideoMixerParams,
+    rate: Rate,
     inputs: Vec<Terminal>,
     outputs: Vec<Terminal>,
     channels: Vec<Channel>,
@@ -36,9 +37,10 @@ impl ModuleT for VideoMixer {
     type Indication = ();
     type Event = ();
 
-    fn create(params: Self::Params, _: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
+    fn create(params: Self::Params, ctx: engine::ModuleCtx<Self>) -> (Self, Self::Indication) {
         let mixer = VideoMixer {
             params,
+            rate: ctx.rate(),
             inputs: (0..VIDEO_MIXER_CHANNELS).map(|i|
                 LineType::Video.labeled(&(i + 1).to_string())
             ).collect(),
@@ -89,7 +91,7 @@ impl ModuleT for VideoMixer {
                 .cloned();
         }
 
-        let absolute_timestamp = MediaTime::new(t as i64, SAMPLE_RATE as i64);
+        let absolute_timestamp = MediaTime::new(t as i64, self.rate.sample_rate() as i64);
 
         // expire stored frames
         for channel in &mut self.channels {
@@ -241,7 +243,7 @@ impl ModuleT for VideoMixer {
         *out = Some(engine::VideoFrame {
             data: video::Frame {
                 decoded: output_frame,
-                duration_hint: MediaDuration::new(1, TICKS_PER_SECOND as i64), // TODO this assumes 1 output frame per tick
+                duration_hint: self.rate.tick_duration(), // TODO this assumes 1 output frame per tick
             },
             tick_offset: MediaDuration::new(0, 1),
         });
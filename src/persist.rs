@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use serde::{Serialize, Deserialize};
 
-use mixlab_protocol::{ModuleId, ModuleParams, OutputId, WindowGeometry};
+use mixlab_protocol::{ModuleId, ModuleParams, OutputId, WindowGeometry, Modulation};
 
 use crate::util::Sequence;
 
@@ -17,4 +17,62 @@ pub struct Module {
     pub params: ModuleParams,
     pub geometry: WindowGeometry,
     pub inputs: Vec<Option<OutputId>>,
+    // workspaces saved before modulation was added have none:
+    #[serde(default)]
+    pub modulation: Vec<Modulation>,
+}
+
+/// Params of the modules in a workspace, without geometry or connections.
+/// Only modules with numeric params - levels, EQ, faders and the like - are
+/// part of a scene. Recalling the params of anything else would also recall
+/// transport state and device choices, seeking and stopping media on air
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Scene {
+    pub params: HashMap<ModuleId, ModuleParams>,
+}
+
+impl Scene {
+    pub fn includes(params: &ModuleParams) -> bool {
+        !params.modulatable_params().is_empty()
+    }
+}
+
+impl Workspace {
+    pub fn to_scene(&self) -> Scene {
+        Scene {
+            params: self.modules.iter()
+                .filter(|(_, module)| Scene::includes(&module.params))
+                .map(|(module_id, module)| (*module_id, module.params.clone()))
+                .collect(),
+        }
+    }
+
+    /// Gives every module a new ID from `module_seq`, so that this workspace
+    /// can be merged into another. Connections between modules are rewritten
+    /// to match. Modules are returned in the order of their original IDs.
+    pub fn remap_modules(&self, module_seq: &mut Sequence) -> Vec<(ModuleId, Module)> {
+        let mut old_ids = self.modules.keys().copied().collect::<Vec<_>>();
+        old_ids.sort();
+
+        let new_ids = old_ids.iter()
+            .map(|old_id| (*old_id, ModuleId(module_seq.next())))
+            .collect::<HashMap<_, _>>();
+
+        old_ids.iter().map(|old_id| {
+            let module = &self.modules[old_id];
+
+            let inputs = module.inputs.iter()
+                .map(|output| output.and_then(|OutputId(module_id, index)| {
+                    new_ids.get(&module_id).map(|new_id| OutputId(*new_id, index))
+                }))
+                .collect();
+
+            (new_ids[old_id], Module {
+                params: module.params.clone(),
+                geometry: module.geometry.clone(),
+                inputs,
+                modulation: module.modulation.clone(),
+            })
+        }).collect()
+    }
 }
@@ -1,6 +1,7 @@
 use std::fmt::{self, Debug};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 use derive_more::From;
 use futures::stream::{Stream, StreamExt};
@@ -12,11 +13,16 @@ use mixlab_protocol as protocol;
 use mixlab_protocol::{WorkspaceState, PerformanceInfo};
 
 use crate::db;
-use crate::engine::{self, EngineHandle, EngineEvents, EngineError, EngineSession, WorkspaceEmbryo};
+use crate::engine::{self, EngineHandle, EngineEvents, EngineError, EngineSession, Rate, RateError, WorkspaceEmbryo};
 use crate::persist;
 
 pub mod stream;
 pub mod media;
+pub mod probe;
+pub mod preview;
+pub mod upload;
+pub mod bundle;
+pub mod snapshot;
 
 #[derive(Clone)]
 pub struct ProjectHandle {
@@ -33,6 +39,14 @@ pub struct ProjectBase {
     // in the blocking context and pass it as an Arc rather than a reference
     database: Arc<std::sync::Mutex<Connection>>,
 
+    // read once when the project is opened, the engine can't change rate
+    // while it is running:
+    rate: Rate,
+
+    open_streams: stream::OpenStreams,
+    vacuum: watch::Sender<()>,
+    previews: watch::Sender<()>,
+
     notify: NotifyTx,
 }
 
@@ -49,10 +63,21 @@ pub enum OpenError {
     Io(io::Error),
     Json(serde_json::Error),
     Database(rusqlite::Error),
+    Rate(RateError),
     NotDirectory,
 }
 
 impl ProjectBase {
+    /// Directory containing the project, which relative paths given in
+    /// module params are resolved against
+    pub fn directory(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    pub fn rate(&self) -> Rate {
+        self.rate
+    }
+
     #[allow(unused)]
     pub fn with_database_in_blocking_context<T>(&self, f: impl FnOnce(&mut Connection) -> T) -> T {
         f(&mut self.database.lock().expect("lock sqlite connection"))
@@ -65,14 +90,51 @@ impl ProjectBase {
         }).await.expect("blocking database section")
     }
 
-    async fn attach(path: PathBuf, notify: NotifyTx) -> Result<Self, rusqlite::Error> {
-        let mut sqlite_path = path.clone();
-        sqlite_path.set_extension("mixlab");
-        let database = db::attach(sqlite_path).await?;
+    /// Asks the background vacuum to delete unreferenced streams. Does nothing
+    /// if the project was opened without one.
+    pub fn request_vacuum(&self) {
+        let _ = self.vacuum.broadcast(());
+    }
+
+    /// Asks the background preview generator to look for media without
+    /// previews. Does nothing if the project was opened without one.
+    pub fn request_previews(&self) {
+        let _ = self.previews.broadcast(());
+    }
+
+    /// A project with an empty in-memory database and no background tasks,
+    /// for unit tests
+    #[cfg(test)]
+    pub fn in_memory(rate: Rate) -> ProjectBaseRef {
+        let database = Connection::open_in_memory().expect("open in-memory database");
+
+        Arc::new(ProjectBase {
+            path: PathBuf::new(),
+            database: Arc::new(std::sync::Mutex::new(database)),
+            rate,
+            open_streams: stream::OpenStreams::default(),
+            vacuum: watch::channel(()).0,
+            previews: watch::channel(()).0,
+            notify: notify().0,
+        })
+    }
+
+    async fn attach(path: PathBuf, notify: NotifyTx, vacuum: watch::Sender<()>, previews: watch::Sender<()>) -> Result<Self, OpenError> {
+        let database = db::attach(database_path(&path)).await?;
+        let database = Arc::new(std::sync::Mutex::new(database));
+
+        let rate = task::spawn_blocking({
+            let database = database.clone();
+            move || read_rate(&database.lock().expect("lock sqlite connection"))
+        }).await.expect("blocking database section")?;
 
         Ok(ProjectBase {
             path,
-            database: Arc::new(std::sync::Mutex::new(database)),
+            database,
+            rate,
+            open_streams: stream::OpenStreams::default(),
+            vacuum,
+            previews,
             notify,
         })
     }
@@ -108,11 +170,66 @@ impl ProjectBase {
 
 pub async fn open_or_create(path: PathBuf) -> Result<ProjectHandle, OpenError> {
     let (notify_tx, notify_rx) = notify();
-    let base = ProjectBase::attach(path, notify_tx).await?;
+    let (vacuum_tx, mut vacuum_rx) = watch::channel(());
+    let (previews_tx, mut previews_rx) = watch::channel(());
+    let base = ProjectBase::attach(path, notify_tx, vacuum_tx, previews_tx).await?;
     let workspace = base.read_workspace().await?;
 
     let base = Arc::new(base);
 
+    // probe media added before probing was introduced
+    task::spawn({
+        let base = base.clone();
+        async move {
+            if let Err(e) = media::probe_unprobed(&base).await {
+                eprintln!("project: could not probe media: {:?}", e);
+            }
+        }
+    });
+
+    // start background vacuum. the first recv returns immediately, so
+    // anything left behind by an earlier run is cleaned up on open
+    task::spawn({
+        let base = base.clone();
+        async move {
+            // expire abandoned uploads first, so their streams are vacuumed
+            match upload::expire(&base).await {
+                Ok(0) => {}
+                Ok(expired) => {
+                    println!("project: expired {} abandoned uploads", expired);
+                }
+                Err(e) => {
+                    eprintln!("project: could not expire uploads: {:?}", e);
+                }
+            }
+
+            while let Some(()) = vacuum_rx.recv().await {
+                match stream::vacuum(&base).await {
+                    Ok(0) => {}
+                    Ok(deleted) => {
+                        println!("project: vacuumed {} unreferenced streams", deleted);
+                    }
+                    Err(e) => {
+                        eprintln!("project: could not vacuum: {:?}", e);
+                    }
+                }
+            }
+        }
+    });
+
+    // start background preview generation. as with vacuum, the first recv
+    // returns immediately so media added by an earlier run is caught up on
+    task::spawn({
+        let base = base.clone();
+        async move {
+            while let Some(()) = previews_rx.recv().await {
+                if let Err(e) = media::generate_previews(&base).await {
+                    eprintln!("project: could not generate previews: {:?}", e);
+                }
+            }
+        }
+    });
+
     // start engine update thread
     let (embryo, mut persist_rx) = WorkspaceEmbryo::new(workspace);
     let engine = engine::start(runtime::Handle::current(), embryo, base.clone());
@@ -138,7 +255,68 @@ pub async fn open_or_create(path: PathBuf) -> Result<ProjectHandle, OpenError> {
     })
 }
 
+fn read_rate(conn: &Connection) -> Result<Rate, OpenError> {
+    let settings = conn.query_row("SELECT sample_rate, tick_rate FROM settings WHERE rowid = 1", rusqlite::NO_PARAMS,
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))).optional()?;
+
+    match settings {
+        Some((sample_rate, tick_rate)) => Ok(Rate::new(sample_rate as usize, tick_rate as usize)?),
+        None => Ok(Rate::default()),
+    }
+}
+
+/// Path of the SQLite database a project is stored in
+pub fn database_path(path: &Path) -> PathBuf {
+    path.with_extension("mixlab")
+}
+
+/// Opens a project without starting an engine or any background tasks for
+/// it, for administering it from the command line. Vacuums and preview
+/// generation requested through the returned base do not happen until the
+/// project is next opened in a server.
+pub async fn open_base(path: PathBuf) -> Result<ProjectBaseRef, OpenError> {
+    let (notify_tx, _) = notify();
+    let (vacuum_tx, _) = watch::channel(());
+    let (previews_tx, _) = watch::channel(());
+    let base = ProjectBase::attach(path, notify_tx, vacuum_tx, previews_tx).await?;
+
+    Ok(Arc::new(base))
+}
+
+/// Opens a project and reads its workspace without starting an engine for it.
+/// Nothing is persisted back to the project.
+pub async fn open_workspace(path: PathBuf) -> Result<(ProjectBaseRef, persist::Workspace), OpenError> {
+    let base = open_base(path).await?;
+    let workspace = base.read_workspace().await?;
+
+    Ok((base, workspace))
+}
+
+/// Replaces the persisted workspace of a project which is not open in a
+/// running server
+pub async fn write_workspace(base: &ProjectBaseRef, workspace: &persist::Workspace) -> Result<(), rusqlite::Error> {
+    base.write_workspace(workspace).await
+}
+
+/// Changes the sample and tick rate of a project which is not open in a
+/// running server. Takes effect the next time the project is opened
+pub async fn write_rate(base: &ProjectBaseRef, rate: Rate) -> Result<(), rusqlite::Error> {
+    base.with_database(move |conn| -> Result<(), rusqlite::Error> {
+        conn.execute(r"
+                INSERT INTO settings (rowid, sample_rate, tick_rate) VALUES (1, ?, ?)
+                ON CONFLICT (rowid) DO UPDATE SET sample_rate = excluded.sample_rate, tick_rate = excluded.tick_rate
+            ",
+            &[rate.sample_rate() as i64, rate.tick_rate() as i64])?;
+
+        Ok(())
+    }).await
+}
+
 impl ProjectHandle {
+    pub fn rate(&self) -> Rate {
+        self.base.rate()
+    }
+
     pub async fn connect_engine(&self) -> Result<(WorkspaceState, EngineEvents, EngineSession), EngineError> {
         self.engine.connect().await
     }
@@ -146,41 +324,147 @@ impl ProjectHandle {
     pub fn notifications(&self) -> impl Stream<Item = Notification> {
         let perf_info = self.engine.performance_info().map(Notification::PerformanceInfo);
         let media = self.notify.media.clone().map(|()| Notification::MediaLibrary);
-        futures::stream::select(perf_info, media)
+        let snapshots = self.notify.snapshots.clone().map(|()| Notification::Snapshots);
+        futures::stream::select(perf_info, futures::stream::select(media, snapshots))
     }
 
     pub async fn begin_media_upload(&self, info: media::UploadInfo) -> Result<media::MediaUpload, media::UploadError> {
         media::MediaUpload::new(self.base.clone(), info).await
     }
 
+    pub async fn export_bundle(&self, include_media: bool) -> Result<bundle::Bundle, bundle::BundleError> {
+        let workspace = self.engine.export_workspace().await?;
+        bundle::export(&self.base, workspace, include_media).await
+    }
+
+    /// Imports a bundle into the live workspace, returning the IDs of the
+    /// modules added
+    pub async fn import_bundle(&self, bundle: bundle::Bundle) -> Result<Vec<protocol::ModuleId>, bundle::BundleError> {
+        let workspace = bundle::import_media(&self.base, bundle).await?;
+        Ok(self.engine.import_workspace(workspace).await?)
+    }
+
+    pub async fn create_upload(&self, info: media::UploadInfo, length: Option<u64>) -> Result<upload::UploadId, rusqlite::Error> {
+        upload::create(&self.base, info, length).await
+    }
+
+    pub async fn upload_status(&self, id: upload::UploadId) -> Result<upload::UploadStatus, upload::UploadError> {
+        upload::status(&self.base, id).await
+    }
+
+    pub async fn resume_upload(&self, id: upload::UploadId, offset: u64) -> Result<upload::UploadChunk, upload::UploadError> {
+        upload::resume(&self.base, id, offset).await
+    }
+
+    pub async fn finalize_upload(&self, id: upload::UploadId, sha256: Option<&str>) -> Result<(), upload::UploadError> {
+        upload::finalize(&self.base, id, sha256).await
+    }
+
+    pub async fn cancel_upload(&self, id: upload::UploadId) -> Result<(), upload::UploadError> {
+        upload::cancel(&self.base, id).await
+    }
+
     pub async fn fetch_media_library(&self) -> Result<protocol::MediaLibrary, rusqlite::Error> {
         media::library(&self.base).await
     }
+
+    pub async fn rename_media(&self, id: protocol::MediaId, name: String) -> Result<(), media::MediaError> {
+        media::rename(&self.base, id, name).await
+    }
+
+    /// Deletes a media item, unless a media source in the live workspace
+    /// refers to it, whether or not it is playing
+    pub async fn delete_media(&self, id: protocol::MediaId) -> Result<(), media::MediaError> {
+        let workspace = self.engine.export_workspace().await?;
+
+        let referenced = workspace.modules.values().any(|module| {
+            match &module.params {
+                protocol::ModuleParams::MediaSource(params) => params.media_id == Some(id),
+                _ => false,
+            }
+        });
+
+        if referenced {
+            return Err(protocol::MediaOpError::InUse(id).into());
+        }
+
+        media::delete(&self.base, id).await
+    }
+
+    pub async fn set_media_tags(&self, id: protocol::MediaId, tags: Vec<String>) -> Result<(), media::MediaError> {
+        media::set_tags(&self.base, id, tags).await
+    }
+
+    pub async fn search_media(&self, query: String) -> Result<Vec<protocol::MediaId>, rusqlite::Error> {
+        media::search(&self.base, query).await
+    }
+
+    pub async fn media_thumbnail(&self, id: protocol::MediaId) -> Result<Option<Vec<u8>>, rusqlite::Error> {
+        media::thumbnail(&self.base, id).await
+    }
+
+    pub async fn media_waveform(&self, id: protocol::MediaId) -> Result<Option<Vec<u8>>, rusqlite::Error> {
+        media::waveform(&self.base, id).await
+    }
+
+    pub async fn list_snapshots(&self) -> Result<Vec<protocol::SnapshotInfo>, rusqlite::Error> {
+        snapshot::list(&self.base).await
+    }
+
+    pub async fn save_snapshot(&self, name: String, kind: protocol::SnapshotKind) -> Result<protocol::SnapshotId, snapshot::SnapshotError> {
+        let workspace = self.engine.export_workspace().await?;
+        snapshot::save(&self.base, name, kind, &workspace).await
+    }
+
+    /// Recalls a snapshot into the live workspace. Numeric params of scenes
+    /// are crossfaded to if a duration is given.
+    pub async fn recall_snapshot(&self, id: protocol::SnapshotId, crossfade: Option<Duration>) -> Result<(), snapshot::SnapshotError> {
+        match snapshot::load(&self.base, id).await? {
+            snapshot::Snapshot::Workspace(workspace) => {
+                self.engine.recall_workspace(workspace).await?;
+            }
+            snapshot::Snapshot::Scene(scene) => {
+                self.engine.recall_scene(scene, crossfade).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete_snapshot(&self, id: protocol::SnapshotId) -> Result<(), snapshot::SnapshotError> {
+        snapshot::delete(&self.base, id).await
+    }
 }
 
 pub enum Notification {
     PerformanceInfo(Arc<PerformanceInfo>),
     MediaLibrary,
+    Snapshots,
 }
 
 pub struct NotifyTx {
     media: watch::Sender<()>,
+    snapshots: watch::Sender<()>,
 }
 
 #[derive(Clone)]
 pub struct NotifyRx {
     media: watch::Receiver<()>,
+    snapshots: watch::Receiver<()>,
 }
 
 pub fn notify() -> (NotifyTx, NotifyRx) {
     let (media_tx, media_rx) = watch::channel(());
+    let (snapshots_tx, snapshots_rx) = watch::channel(());
 
     let tx = NotifyTx {
         media: media_tx,
+        snapshots: snapshots_tx,
     };
 
     let rx = NotifyRx {
         media: media_rx,
+        snapshots: snapshots_rx,
     };
 
     (tx, rx)
@@ -0,0 +1,280 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use derive_more::From;
+use rusqlite::{params, OptionalExtension};
+use serde::{Serialize, Deserialize};
+use tokio::task;
+
+use mixlab_codec::ffmpeg::IoReader;
+use mixlab_protocol::{MediaId, ModuleParams};
+
+use crate::engine::EngineError;
+use crate::persist;
+use crate::project::{self, ProjectBaseRef, OpenError};
+use crate::project::media::{self, MediaError, MediaUpload, UploadError, UploadInfo};
+use crate::project::stream::ReadStream;
+
+pub const BUNDLE_VERSION: u32 = 1;
+
+// bundles are a single JSON document held in memory whole on both export and
+// import, so the media carried in one is limited. workspaces with more media
+// than this must be exported without it, and the media added separately:
+pub const MAX_MEDIA_BYTES: usize = 64 * 1024 * 1024;
+
+/// Largest bundle accepted for import: room for the maximum amount of media
+/// once base64 encoded, plus the workspace itself
+pub const MAX_BUNDLE_BYTES: u64 = (MAX_MEDIA_BYTES as u64 / 3 + 1) * 4 + 8 * 1024 * 1024;
+
+/// A workspace exported from a project as standalone JSON, along with the
+/// media its modules refer to, so that it can be imported into another.
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    pub workspace: persist::Workspace,
+    pub media: Vec<BundleMedia>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BundleMedia {
+    // ID in the exporting project, as referred to by module params:
+    pub id: MediaId,
+    pub name: String,
+    pub kind: String,
+    pub tags: Vec<String>,
+    // base64 encoded. bundles exported without media only describe it, and
+    // importing them refers to media of the same name in the library instead:
+    pub data: Option<String>,
+}
+
+#[derive(Debug, From)]
+pub enum BundleError {
+    Open(OpenError),
+    Engine(EngineError),
+    Database(rusqlite::Error),
+    Upload(UploadError),
+    Media(MediaError),
+    Base64(base64::DecodeError),
+    Io(io::Error),
+    Json(serde_json::Error),
+    #[from(ignore)]
+    UnsupportedVersion(u32),
+    #[from(ignore)]
+    MediaTooLarge { name: String, limit: usize },
+    #[from(ignore)]
+    BundleTooLarge { limit: u64 },
+}
+
+pub async fn export(base: &ProjectBaseRef, mut workspace: persist::Workspace, include_media: bool) -> Result<Bundle, BundleError> {
+    let mut media = Vec::new();
+    let mut media_bytes = 0;
+
+    for media_id in referenced_media(&workspace) {
+        let media_id = MediaId(media_id);
+
+        let described = base.with_database(move |conn| -> Result<_, rusqlite::Error> {
+            let item = conn.query_row("SELECT name, kind FROM media WHERE id = ?",
+                params![media_id.0],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            ).optional()?;
+
+            let tags = conn.prepare("SELECT tag FROM media_tags WHERE media_id = ? ORDER BY tag")?
+                .query_map(params![media_id.0], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+
+            Ok(item.map(|(name, kind)| (name, kind, tags)))
+        }).await?;
+
+        // modules may refer to media which has since been deleted
+        let (name, kind, tags) = match described {
+            Some(described) => described,
+            None => { continue; }
+        };
+
+        let data = if include_media {
+            let limit = MAX_MEDIA_BYTES - media_bytes;
+
+            match read_media(base, media_id, limit).await? {
+                ReadMedia::Data(data) => {
+                    media_bytes += data.len();
+                    Some(base64::encode(data))
+                }
+                ReadMedia::Missing => None,
+                ReadMedia::TooLarge => {
+                    return Err(BundleError::MediaTooLarge { name, limit: MAX_MEDIA_BYTES });
+                }
+            }
+        } else {
+            None
+        };
+
+        media.push(BundleMedia { id: media_id, name, kind, tags, data });
+    }
+
+    // secrets stay in the project they were set in. imported modules which
+    // had one are left without, and it must be set again:
+    for module in workspace.modules.values_mut() {
+        module.params = module.params.redacted();
+    }
+
+    Ok(Bundle {
+        version: BUNDLE_VERSION,
+        workspace,
+        media,
+    })
+}
+
+/// Adds the media carried by a bundle to the library, and returns the
+/// bundle's workspace with Media Source params rewritten to refer to media in
+/// this project. The workspace's modules still need remapping before they can
+/// be merged into another workspace, see `persist::Workspace::remap_modules`.
+pub async fn import_media(base: &ProjectBaseRef, bundle: Bundle) -> Result<persist::Workspace, BundleError> {
+    if bundle.version != BUNDLE_VERSION {
+        return Err(BundleError::UnsupportedVersion(bundle.version));
+    }
+
+    let mut media_ids = HashMap::new();
+
+    for media in bundle.media {
+        let imported_id = match media.data {
+            Some(data) => {
+                let data = base64::decode(&data)?;
+                import_one(base, media.name, media.kind, media.tags, &data).await?
+            }
+            None => find_by_name(base, media.name, media.kind).await?,
+        };
+
+        media_ids.insert(media.id, imported_id);
+    }
+
+    let mut workspace = bundle.workspace;
+
+    for module in workspace.modules.values_mut() {
+        module.params.restore_secrets(None);
+
+        if let ModuleParams::MediaSource(params) = &mut module.params {
+            params.media_id = params.media_id
+                .and_then(|media_id| media_ids.get(&media_id).copied().flatten());
+        }
+    }
+
+    Ok(workspace)
+}
+
+/// Exports the workspace of a project on disk to a bundle file
+pub async fn export_file(project_path: PathBuf, output: PathBuf, include_media: bool) -> Result<(), BundleError> {
+    let (base, workspace) = project::open_workspace(project_path).await?;
+    let bundle = export(&base, workspace, include_media).await?;
+
+    task::spawn_blocking(move || -> Result<(), BundleError> {
+        let mut file = BufWriter::new(File::create(output)?);
+        serde_json::to_writer(&mut file, &bundle)?;
+        Ok(file.flush()?)
+    }).await.expect("write bundle task")
+}
+
+/// Imports a bundle file into the workspace of a project on disk, returning
+/// the number of modules added. Must not be used on a project which is open
+/// in a running server, which would overwrite the imported modules.
+pub async fn import_file(project_path: PathBuf, input: PathBuf) -> Result<usize, BundleError> {
+    let bundle = task::spawn_blocking(move || -> Result<Bundle, BundleError> {
+        let file = File::open(input)?;
+
+        if file.metadata()?.len() > MAX_BUNDLE_BYTES {
+            return Err(BundleError::BundleTooLarge { limit: MAX_BUNDLE_BYTES });
+        }
+
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }).await.expect("read bundle task")?;
+
+    let (base, mut workspace) = project::open_workspace(project_path).await?;
+    let imported = import_media(&base, bundle).await?;
+
+    let modules = imported.remap_modules(&mut workspace.module_seq);
+    let count = modules.len();
+    workspace.modules.extend(modules);
+
+    base.write_workspace(&workspace).await?;
+
+    Ok(count)
+}
+
+// IDs of media referred to by modules, in order and without duplicates
+fn referenced_media(workspace: &persist::Workspace) -> BTreeSet<i64> {
+    workspace.modules.values()
+        .filter_map(|module| match &module.params {
+            ModuleParams::MediaSource(params) => params.media_id,
+            _ => None,
+        })
+        .map(|media_id| media_id.0)
+        .collect()
+}
+
+enum ReadMedia {
+    Data(Vec<u8>),
+    Missing,
+    // more than the limit given to read_media:
+    TooLarge,
+}
+
+// reads media into memory whole, giving up once more than `limit` bytes
+// have been read
+async fn read_media(base: &ProjectBaseRef, media_id: MediaId, limit: usize) -> Result<ReadMedia, rusqlite::Error> {
+    let mut stream = match media::open(base.clone(), media_id).await? {
+        Some(stream) => stream,
+        None => { return Ok(ReadMedia::Missing); }
+    };
+
+    task::spawn_blocking(move || {
+        let mut data = Vec::new();
+        let mut buff = vec![0u8; <ReadStream as IoReader>::BUFFER_SIZE];
+
+        loop {
+            let len = stream.read(&mut buff)?;
+
+            if len == 0 {
+                break;
+            }
+
+            if data.len() + len > limit {
+                return Ok(ReadMedia::TooLarge);
+            }
+
+            data.extend_from_slice(&buff[..len]);
+        }
+
+        Ok(ReadMedia::Data(data))
+    }).await.expect("read media task")
+}
+
+// media which can't be played is left out rather than failing the import
+async fn import_one(base: &ProjectBaseRef, name: String, kind: String, tags: Vec<String>, data: &[u8]) -> Result<Option<MediaId>, BundleError> {
+    let mut upload = MediaUpload::new(base.clone(), UploadInfo { name, kind }).await?;
+    upload.receive_bytes(data).await?;
+
+    let media_id = match upload.finalize().await {
+        Ok(media_id) => media_id,
+        Err(UploadError::Unplayable(reason)) => {
+            eprintln!("bundle: skipping media: {}", reason);
+            return Ok(None);
+        }
+        Err(e) => { return Err(e.into()); }
+    };
+
+    if !tags.is_empty() {
+        media::set_tags(base, media_id, tags).await?;
+    }
+
+    Ok(Some(media_id))
+}
+
+async fn find_by_name(base: &ProjectBaseRef, name: String, kind: String) -> Result<Option<MediaId>, rusqlite::Error> {
+    base.with_database(move |conn| {
+        conn.query_row("SELECT id FROM media WHERE name = ? AND kind = ? ORDER BY id DESC LIMIT 1",
+            params![name, kind],
+            |row| Ok(MediaId(row.get(0)?))
+        ).optional()
+    }).await
+}
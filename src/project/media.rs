@@ -1,11 +1,16 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
 
 use derive_more::From;
-use mixlab_protocol::MediaId;
+use mixlab_protocol::{MediaId, MediaOpError, MediaInfo, MediaStreamInfo, MediaStreamKind, Microseconds};
 use mixlab_protocol as protocol;
-use rusqlite::{params, OptionalExtension};
+use rusqlite::{params, Connection, OptionalExtension};
+use tokio::task;
 
+use crate::engine::EngineError;
 use crate::project::ProjectBaseRef;
+use crate::project::preview;
+use crate::project::probe::{self, Probe};
 use crate::project::stream::{self, ReadStream, WriteStream, StreamId};
 
 pub struct UploadInfo {
@@ -22,6 +27,63 @@ pub struct MediaUpload {
 #[derive(From, Debug)]
 pub enum UploadError {
     Database(rusqlite::Error),
+    // media could not be probed, or has nothing a Media Source can play:
+    #[from(ignore)]
+    Unplayable(String),
+}
+
+#[derive(From, Debug)]
+pub enum MediaError {
+    Database(rusqlite::Error),
+    Engine(EngineError),
+    Op(MediaOpError),
+}
+
+/// Describes media produced on the server itself, rather than uploaded
+pub struct RecordingInfo {
+    // media items are named after this and their stream id, eg. "Recording 12.mp4"
+    pub label: String,
+    pub extension: String,
+    pub kind: String,
+}
+
+/// Streams media produced on the server, such as the encoded output of a
+/// module, into a new media library item. Methods block, so this is for use
+/// from a thread of its own which has entered the tokio runtime.
+pub struct MediaRecording {
+    upload: MediaUpload,
+}
+
+impl MediaRecording {
+    pub fn create(base: ProjectBaseRef, info: RecordingInfo) -> Result<Self, UploadError> {
+        futures::executor::block_on(async move {
+            let stream = stream::create(base.clone()).await?;
+
+            let info = UploadInfo {
+                name: format!("{} {}.{}", info.label, stream.id().0, info.extension),
+                kind: info.kind,
+            };
+
+            Ok(MediaRecording {
+                upload: MediaUpload { base, stream, info },
+            })
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.upload.info.name
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), UploadError> {
+        futures::executor::block_on(self.upload.receive_bytes(bytes))
+    }
+
+    /// Registers the media item and notifies clients of the new addition to
+    /// the library
+    pub fn finalize(self) -> Result<(), UploadError> {
+        futures::executor::block_on(self.upload.finalize())?;
+        Ok(())
+    }
 }
 
 impl MediaUpload {
@@ -40,21 +102,43 @@ impl MediaUpload {
         Ok(())
     }
 
-    pub async fn finalize(self) -> Result<(), UploadError> {
+    pub async fn finalize(mut self) -> Result<MediaId, UploadError> {
         let stream_id = self.stream.finalize().await?;
         let info = self.info;
 
-        self.base.with_database(move |conn| -> Result<(), rusqlite::Error> {
-            conn.execute(
+        // media is probed before it is added to the library, so unplayable
+        // files are rejected here rather than failing later in a Media Source
+        let probe = match probe_stream(&self.base, stream_id).await? {
+            Some(probe) if probe.playable => probe,
+            Some(_) => {
+                self.base.request_vacuum();
+                return Err(UploadError::Unplayable(format!("{} has no playable audio or video streams", info.name)));
+            }
+            None => {
+                self.base.request_vacuum();
+                return Err(UploadError::Unplayable(format!("{} is not a recognised media file", info.name)));
+            }
+        };
+
+        let media_id = self.base.with_database(move |conn| -> Result<MediaId, rusqlite::Error> {
+            let txn = conn.transaction()?;
+
+            txn.execute(
                     "INSERT INTO media (name, kind, stream_id) VALUES (?, ?, ?)",
                     params![info.name, info.kind, stream_id.0])?;
 
-            Ok(())
+            let media_id = MediaId(txn.last_insert_rowid());
+            store_probe(&txn, media_id, Some(&probe.info))?;
+
+            txn.commit()?;
+
+            Ok(media_id)
         }).await?;
 
         let _ = self.base.notify.media.broadcast(());
+        self.base.request_previews();
 
-        Ok(())
+        Ok(media_id)
     }
 }
 
@@ -68,20 +152,87 @@ pub async fn library(base: &ProjectBaseRef) -> Result<protocol::MediaLibrary, ru
     }
 
     let items = base.with_database(|conn| -> Result<Vec<protocol::MediaItem>, rusqlite::Error> {
-        conn.prepare(r"
-                SELECT media.id, media.name, media.kind, streams.size FROM media
+        let mut items = conn.prepare(r"
+                SELECT media.id, media.name, media.kind, streams.size, media.format, media.duration_us,
+                    media_previews.thumbnail IS NOT NULL, media_previews.waveform IS NOT NULL
+                FROM media
                 INNER JOIN streams ON streams.id = media.stream_id
+                LEFT JOIN media_previews ON media_previews.media_id = media.id
                 ORDER BY media.id DESC
             ")?
-            .query_map(rusqlite::NO_PARAMS,
-                |row| Ok(protocol::MediaItem {
+            .query_map(rusqlite::NO_PARAMS, |row| {
+                // format is only set once media has been successfully probed:
+                let info = match row.get::<_, Option<String>>(4)? {
+                    Some(format) => Some(MediaInfo {
+                        format,
+                        duration: row.get::<_, Option<i64>>(5)?.map(|micros| Microseconds(micros as u64)),
+                        streams: Vec::new(),
+                    }),
+                    None => None,
+                };
+
+                Ok(protocol::MediaItem {
                     id: protocol::MediaId(row.get(0)?),
                     name: row.get(1)?,
                     kind: row.get(2)?,
                     size: row.get::<_, i64>(3)?.try_into().unwrap(),
+                    tags: Vec::new(),
+                    info,
+                    thumbnail: row.get(6)?,
+                    waveform: row.get(7)?,
                 })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut streams = HashMap::<i64, Vec<MediaStreamInfo>>::new();
+
+        let rows = conn.prepare(r"
+                SELECT media_id, kind, codec, width, height, frame_rate, sample_rate, channels
+                FROM media_streams
+                ORDER BY media_id, stream_index
+            ")?
+            .query_map(rusqlite::NO_PARAMS, |row| {
+                let resolution = match (row.get::<_, Option<i64>>(3)?, row.get::<_, Option<i64>>(4)?) {
+                    (Some(width), Some(height)) => Some((width as u32, height as u32)),
+                    _ => None,
+                };
+
+                Ok((row.get::<_, i64>(0)?, MediaStreamInfo {
+                    kind: parse_stream_kind(&row.get::<_, String>(1)?),
+                    codec: row.get(2)?,
+                    resolution,
+                    frame_rate: row.get(5)?,
+                    sample_rate: row.get::<_, Option<i64>>(6)?.map(|rate| rate as u32),
+                    channels: row.get::<_, Option<i64>>(7)?.map(|channels| channels as u32),
+                }))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (media_id, stream) in rows {
+            streams.entry(media_id).or_default().push(stream);
+        }
+
+        let mut tags = HashMap::<i64, Vec<String>>::new();
+
+        let rows = conn.prepare("SELECT media_id, tag FROM media_tags ORDER BY tag")?
+            .query_map(rusqlite::NO_PARAMS,
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
             )?
-            .collect()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (media_id, tag) in rows {
+            tags.entry(media_id).or_default().push(tag);
+        }
+
+        for item in &mut items {
+            item.tags = tags.remove(&item.id.0).unwrap_or_default();
+
+            if let Some(info) = &mut item.info {
+                info.streams = streams.remove(&item.id.0).unwrap_or_default();
+            }
+        }
+
+        Ok(items)
     }).await?;
 
     Ok(protocol::MediaLibrary { items })
@@ -100,3 +251,307 @@ pub async fn open(base: ProjectBaseRef, media_id: MediaId) -> Result<Option<Read
         None => Ok(None),
     }
 }
+
+pub async fn rename(base: &ProjectBaseRef, media_id: MediaId, name: String) -> Result<(), MediaError> {
+    let name = name.trim().to_owned();
+
+    if name.is_empty() {
+        return Err(MediaOpError::InvalidName.into());
+    }
+
+    let updated = base.with_database(move |conn| {
+        conn.execute("UPDATE media SET name = ? WHERE id = ?",
+            params![name, media_id.0])
+    }).await?;
+
+    if updated == 0 {
+        return Err(MediaOpError::NotFound(media_id).into());
+    }
+
+    let _ = base.notify.media.broadcast(());
+
+    Ok(())
+}
+
+/// Deletes a media item, unless it is being played. Media referred to by
+/// modules should be checked for before calling, see `Project::delete_media`.
+/// Its stream is left for the vacuum to clean up.
+pub async fn delete(base: &ProjectBaseRef, media_id: MediaId) -> Result<(), MediaError> {
+    base.with_database({
+        let base = base.clone();
+        move |conn| -> Result<(), MediaError> {
+            let txn = conn.transaction()?;
+
+            let stream_id = txn.query_row(r"SELECT stream_id FROM media WHERE id = ?",
+                params![media_id.0],
+                |row| Ok(StreamId(row.get(0)?))
+            ).optional()?;
+
+            let stream_id = stream_id.ok_or(MediaOpError::NotFound(media_id))?;
+
+            // streams are only opened with the database lock held, so nothing
+            // can begin playing this media before the delete is committed:
+            if base.open_streams.is_open(stream_id) {
+                return Err(MediaOpError::InUse(media_id).into());
+            }
+
+            txn.execute("DELETE FROM media_tags WHERE media_id = ?", params![media_id.0])?;
+            txn.execute("DELETE FROM media_streams WHERE media_id = ?", params![media_id.0])?;
+            txn.execute("DELETE FROM media_previews WHERE media_id = ?", params![media_id.0])?;
+            txn.execute("DELETE FROM media WHERE id = ?", params![media_id.0])?;
+            txn.commit()?;
+
+            Ok(())
+        }
+    }).await?;
+
+    let _ = base.notify.media.broadcast(());
+    base.request_vacuum();
+
+    Ok(())
+}
+
+/// Replaces all tags of a media item. Tags are trimmed, and empty or
+/// duplicate tags are dropped.
+pub async fn set_tags(base: &ProjectBaseRef, media_id: MediaId, tags: Vec<String>) -> Result<(), MediaError> {
+    let mut tags = tags.iter()
+        .map(|tag| tag.trim().to_owned())
+        .filter(|tag| !tag.is_empty())
+        .collect::<Vec<_>>();
+
+    tags.sort();
+    tags.dedup();
+
+    base.with_database(move |conn| -> Result<(), MediaError> {
+        let txn = conn.transaction()?;
+
+        let exists = txn.query_row(r"SELECT 1 FROM media WHERE id = ?",
+            params![media_id.0],
+            |_| Ok(())
+        ).optional()?;
+
+        if exists.is_none() {
+            return Err(MediaOpError::NotFound(media_id).into());
+        }
+
+        txn.execute("DELETE FROM media_tags WHERE media_id = ?", params![media_id.0])?;
+
+        for tag in &tags {
+            txn.execute("INSERT INTO media_tags (media_id, tag) VALUES (?, ?)",
+                params![media_id.0, tag])?;
+        }
+
+        txn.commit()?;
+
+        Ok(())
+    }).await?;
+
+    let _ = base.notify.media.broadcast(());
+
+    Ok(())
+}
+
+/// Finds media whose name or tags contain every whitespace separated term in
+/// query, ignoring case. Results are in the same order as the library.
+pub async fn search(base: &ProjectBaseRef, query: String) -> Result<Vec<MediaId>, rusqlite::Error> {
+    let mut sql = "SELECT media.id FROM media WHERE 1".to_owned();
+    let mut patterns = Vec::new();
+
+    for term in query.split_whitespace() {
+        sql.push_str(r"
+            AND (media.name LIKE ? ESCAPE '\' OR EXISTS (
+                SELECT 1 FROM media_tags
+                WHERE media_tags.media_id = media.id AND media_tags.tag LIKE ? ESCAPE '\'
+            ))");
+
+        let pattern = format!("%{}%", escape_like(term));
+        patterns.push(pattern.clone());
+        patterns.push(pattern);
+    }
+
+    sql.push_str(" ORDER BY media.id DESC");
+
+    base.with_database(move |conn| {
+        conn.prepare(&sql)?
+            .query_map(patterns, |row| Ok(MediaId(row.get(0)?)))?
+            .collect()
+    }).await
+}
+
+fn escape_like(term: &str) -> String {
+    let mut escaped = String::with_capacity(term.len());
+
+    for c in term.chars() {
+        if c == '\\' || c == '%' || c == '_' {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Probes all media which has not been probed yet, such as media uploaded
+/// before probing was introduced. Media which cannot be probed is left
+/// without info.
+pub async fn probe_unprobed(base: &ProjectBaseRef) -> Result<(), rusqlite::Error> {
+    let unprobed = base.with_database(|conn| -> Result<Vec<(MediaId, StreamId)>, rusqlite::Error> {
+        conn.prepare("SELECT id, stream_id FROM media WHERE probed = 0")?
+            .query_map(rusqlite::NO_PARAMS, |row| Ok((MediaId(row.get(0)?), StreamId(row.get(1)?))))?
+            .collect()
+    }).await?;
+
+    if unprobed.is_empty() {
+        return Ok(());
+    }
+
+    for (media_id, stream_id) in unprobed {
+        let probe = probe_stream(base, stream_id).await?;
+
+        base.with_database(move |conn| -> Result<(), rusqlite::Error> {
+            let txn = conn.transaction()?;
+            store_probe(&txn, media_id, probe.as_ref().map(|probe| &probe.info))?;
+            txn.commit()
+        }).await?;
+    }
+
+    let _ = base.notify.media.broadcast(());
+    base.request_previews();
+
+    Ok(())
+}
+
+/// Generates previews for all probed media which does not have them yet.
+/// Media which previews cannot be generated for is not retried.
+pub async fn generate_previews(base: &ProjectBaseRef) -> Result<(), rusqlite::Error> {
+    let missing = base.with_database(|conn| -> Result<Vec<(MediaId, StreamId)>, rusqlite::Error> {
+        conn.prepare(r"
+                SELECT id, stream_id FROM media
+                WHERE format IS NOT NULL AND id NOT IN (SELECT media_id FROM media_previews)
+            ")?
+            .query_map(rusqlite::NO_PARAMS, |row| Ok((MediaId(row.get(0)?), StreamId(row.get(1)?))))?
+            .collect()
+    }).await?;
+
+    for (media_id, stream_id) in missing {
+        let stream = match ReadStream::open(base.clone(), stream_id).await? {
+            Some(stream) => stream,
+            None => { continue; }
+        };
+
+        let previews = task::spawn_blocking(move || preview::generate(stream)).await
+            .expect("preview task");
+
+        let previews = match previews {
+            Ok(previews) => previews,
+            Err(e) => {
+                eprintln!("media: could not generate previews for {:?}: {:?}", media_id, e);
+                preview::Previews { thumbnail: None, waveform: None }
+            }
+        };
+
+        base.with_database(move |conn| {
+            // media may have been deleted while previews were generated:
+            conn.execute(r"
+                    INSERT INTO media_previews (media_id, thumbnail, waveform)
+                    SELECT id, ?, ? FROM media WHERE id = ?
+                ",
+                params![previews.thumbnail, previews.waveform, media_id.0])
+        }).await?;
+
+        // let clients show each preview as soon as it is ready:
+        let _ = base.notify.media.broadcast(());
+    }
+
+    Ok(())
+}
+
+pub async fn thumbnail(base: &ProjectBaseRef, media_id: MediaId) -> Result<Option<Vec<u8>>, rusqlite::Error> {
+    preview_column(base, media_id, "thumbnail").await
+}
+
+pub async fn waveform(base: &ProjectBaseRef, media_id: MediaId) -> Result<Option<Vec<u8>>, rusqlite::Error> {
+    preview_column(base, media_id, "waveform").await
+}
+
+async fn preview_column(base: &ProjectBaseRef, media_id: MediaId, column: &'static str) -> Result<Option<Vec<u8>>, rusqlite::Error> {
+    base.with_database(move |conn| {
+        conn.query_row(&format!("SELECT {} FROM media_previews WHERE media_id = ?", column),
+            params![media_id.0],
+            |row| row.get::<_, Option<Vec<u8>>>(0)
+        ).optional().map(Option::flatten)
+    }).await
+}
+
+// returns None if the stream is not a media file ffmpeg can read
+async fn probe_stream(base: &ProjectBaseRef, stream_id: StreamId) -> Result<Option<Probe>, rusqlite::Error> {
+    let stream = match ReadStream::open(base.clone(), stream_id).await? {
+        Some(stream) => stream,
+        None => { return Ok(None); }
+    };
+
+    let result = task::spawn_blocking(move || probe::probe(stream)).await
+        .expect("probe task");
+
+    match result {
+        Ok(probe) => Ok(Some(probe)),
+        Err(e) => {
+            eprintln!("media: could not probe {:?}: {:?}", stream_id, e);
+            Ok(None)
+        }
+    }
+}
+
+fn store_probe(conn: &Connection, media_id: MediaId, info: Option<&MediaInfo>) -> Result<(), rusqlite::Error> {
+    let format = info.map(|info| info.format.clone());
+    let duration = info.and_then(|info| info.duration).map(|duration| duration.0 as i64);
+
+    conn.execute("UPDATE media SET probed = 1, format = ?, duration_us = ? WHERE id = ?",
+        params![format, duration, media_id.0])?;
+
+    conn.execute("DELETE FROM media_streams WHERE media_id = ?", params![media_id.0])?;
+
+    let streams = info.map(|info| info.streams.as_slice()).unwrap_or_default();
+
+    for (index, stream) in streams.iter().enumerate() {
+        let (width, height) = match stream.resolution {
+            Some((width, height)) => (Some(width), Some(height)),
+            None => (None, None),
+        };
+
+        conn.execute(r"
+                INSERT INTO media_streams (media_id, stream_index, kind, codec, width, height, frame_rate, sample_rate, channels)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ",
+            params![
+                media_id.0,
+                index as i64,
+                stream_kind_name(stream.kind),
+                stream.codec,
+                width,
+                height,
+                stream.frame_rate,
+                stream.sample_rate,
+                stream.channels,
+            ])?;
+    }
+
+    Ok(())
+}
+
+fn stream_kind_name(kind: MediaStreamKind) -> &'static str {
+    match kind {
+        MediaStreamKind::Video => "video",
+        MediaStreamKind::Audio => "audio",
+        MediaStreamKind::Other => "other",
+    }
+}
+
+fn parse_stream_kind(name: &str) -> MediaStreamKind {
+    match name {
+        "video" => MediaStreamKind::Video,
+        "audio" => MediaStreamKind::Audio,
+        _ => MediaStreamKind::Other,
+    }
+}
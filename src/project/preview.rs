@@ -0,0 +1,257 @@
+use std::cmp;
+use std::slice;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use derive_more::From;
+use mixlab_codec::ffmpeg::codec::{self, CodecBuilder, Decode, RecvFrameError};
+use mixlab_codec::ffmpeg::media::{Audio, MediaType, Video};
+use mixlab_codec::ffmpeg::{AvError, AvFrame, AvIoError, AvIoReader, InputContainer, InputStream, IoReader, PictureSettings, SwsContext};
+use mixlab_util::time::MediaTime;
+
+use crate::project::stream::ReadStream;
+
+// thumbnails are scaled to fit within these bounds:
+const THUMBNAIL_WIDTH: usize = 160;
+const THUMBNAIL_HEIGHT: usize = 90;
+
+const WAVEFORM_POINTS: usize = 256;
+
+pub struct Previews {
+    // poster frame of the first video stream, as a BMP image:
+    pub thumbnail: Option<Vec<u8>>,
+    // peak amplitude of the first audio stream over each of WAVEFORM_POINTS
+    // equal sections of the media, scaled to 0-255:
+    pub waveform: Option<Vec<u8>>,
+}
+
+#[derive(Debug, From)]
+pub enum PreviewError {
+    CodecBuild(codec::BuildError),
+    CodecOpen(codec::OpenError),
+    RecvFrame(RecvFrameError),
+    Av(AvError),
+    Io(<ReadStream as IoReader>::Error),
+}
+
+impl From<AvIoError<ReadStream>> for PreviewError {
+    fn from(e: AvIoError<ReadStream>) -> PreviewError {
+        match e {
+            AvIoError::Av(e) => PreviewError::Av(e),
+            AvIoError::Io(e) => PreviewError::Io(e),
+        }
+    }
+}
+
+/// Decodes a stored stream to generate its previews. Blocks, so must be run
+/// on a blocking thread.
+pub fn generate(stream: ReadStream) -> Result<Previews, PreviewError> {
+    let mut container = InputContainer::open(AvIoReader::new(stream))?;
+
+    // waveform reads through the whole input, thumbnail seeks afterwards:
+    let waveform = waveform(&mut container)?;
+    let thumbnail = thumbnail(&mut container)?;
+
+    Ok(Previews { thumbnail, waveform })
+}
+
+fn open_decoder<Mt: MediaType>(stream: &InputStream) -> Result<Decode<Mt>, PreviewError> {
+    let params = stream.codec_parameters();
+
+    Ok(CodecBuilder::<Mt>::new(params.codec_id, stream.time_base())?
+        .with_parameters(params)
+        .open_decoder()?)
+}
+
+fn waveform(container: &mut InputContainer<ReadStream>) -> Result<Option<Vec<u8>>, PreviewError> {
+    let index = match container.streams().iter().position(|stream| stream.is_media_type::<Audio>()) {
+        Some(index) => index,
+        None => { return Ok(None); }
+    };
+
+    let mut decode = open_decoder::<Audio>(&container.streams()[index])?;
+
+    // peak of each decoded frame. these are reduced to WAVEFORM_POINTS once
+    // we know how many frames there are:
+    let mut frame_peaks = Vec::new();
+    let mut reached_end_of_stream = false;
+
+    loop {
+        if !reached_end_of_stream {
+            match container.read_packet()? {
+                Some(pkt) if pkt.stream_index() as usize == index => {
+                    decode.send_packet(&pkt)?;
+                }
+                Some(_) => { continue; }
+                None => {
+                    decode.end_of_stream()?;
+                    reached_end_of_stream = true;
+                }
+            }
+        }
+
+        loop {
+            match decode.recv_frame() {
+                Ok(frame) => {
+                    if let Some(samples) = frame.interleaved_samples() {
+                        frame_peaks.push(samples.iter().fold(0f32, |peak, sample| peak.max(sample.abs())));
+                    }
+                }
+                Err(RecvFrameError::NeedMoreInput) => { break; }
+                Err(RecvFrameError::Eof) => {
+                    if frame_peaks.is_empty() {
+                        return Ok(None);
+                    }
+
+                    return Ok(Some(reduce_peaks(&frame_peaks)));
+                }
+                Err(e) => { return Err(e.into()); }
+            }
+        }
+    }
+}
+
+fn reduce_peaks(peaks: &[f32]) -> Vec<u8> {
+    (0..WAVEFORM_POINTS).map(|point| {
+        let start = point * peaks.len() / WAVEFORM_POINTS;
+        let end = cmp::max(start + 1, (point + 1) * peaks.len() / WAVEFORM_POINTS);
+        let peak = peaks[start..end].iter().fold(0f32, |a, b| a.max(*b));
+        (peak.min(1.0) * 255.0).round() as u8
+    }).collect()
+}
+
+fn thumbnail(container: &mut InputContainer<ReadStream>) -> Result<Option<Vec<u8>>, PreviewError> {
+    let index = match container.streams().iter().position(|stream| stream.is_media_type::<Video>()) {
+        Some(index) => index,
+        None => { return Ok(None); }
+    };
+
+    let mut decode = open_decoder::<Video>(&container.streams()[index])?;
+
+    // a frame a little way in is more representative than the first, which
+    // is often black:
+    let position = match container.duration() {
+        Some(duration) => MediaTime::new(duration.round_to_base(1000) / 10, 1000),
+        None => MediaTime::zero(),
+    };
+
+    if container.seek(position).is_err() {
+        container.seek(MediaTime::zero())?;
+    }
+
+    let mut reached_end_of_stream = false;
+
+    loop {
+        if !reached_end_of_stream {
+            match container.read_packet()? {
+                Some(pkt) if pkt.stream_index() as usize == index => {
+                    decode.send_packet(&pkt)?;
+                }
+                Some(_) => { continue; }
+                None => {
+                    decode.end_of_stream()?;
+                    reached_end_of_stream = true;
+                }
+            }
+        }
+
+        match decode.recv_frame() {
+            Ok(frame) => { return Ok(Some(render_thumbnail(&frame))); }
+            Err(RecvFrameError::NeedMoreInput) => {}
+            Err(RecvFrameError::Eof) => { return Ok(None); }
+            Err(e) => { return Err(e.into()); }
+        }
+    }
+}
+
+fn render_thumbnail(frame: &AvFrame<Video>) -> Vec<u8> {
+    let input = frame.picture_settings();
+
+    // fit within thumbnail bounds, preserving aspect ratio:
+    let (width, height) = if input.width * THUMBNAIL_HEIGHT > input.height * THUMBNAIL_WIDTH {
+        (THUMBNAIL_WIDTH, cmp::max(1, input.height * THUMBNAIL_WIDTH / input.width))
+    } else {
+        (cmp::max(1, input.width * THUMBNAIL_HEIGHT / input.height), THUMBNAIL_HEIGHT)
+    };
+
+    let output = PictureSettings::rgb24(width, height);
+    let mut scaled = AvFrame::blank(&output);
+
+    SwsContext::new(input, output)
+        .process(&frame.frame_data(), &mut scaled.frame_data_mut());
+
+    let data = scaled.frame_data();
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        let row = unsafe {
+            slice::from_raw_parts(data.data(0).add(y * data.stride(0)), width * 3)
+        };
+
+        rgb.extend_from_slice(row);
+    }
+
+    encode_bmp(width, height, &rgb)
+}
+
+// encodes packed RGB pixels as a 24 bit BMP, which every browser can display
+fn encode_bmp(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    const HEADER_SIZE: usize = 14 + 40;
+
+    // rows are padded to a multiple of 4 bytes:
+    let row_size = (width * 3 + 3) & !3;
+    let image_size = row_size * height;
+
+    let mut bmp = Vec::with_capacity(HEADER_SIZE + image_size);
+
+    // BITMAPFILEHEADER. writes to a Vec can not fail:
+    bmp.extend_from_slice(b"BM");
+    bmp.write_u32::<LittleEndian>((HEADER_SIZE + image_size) as u32).unwrap();
+    bmp.write_u32::<LittleEndian>(0).unwrap();
+    bmp.write_u32::<LittleEndian>(HEADER_SIZE as u32).unwrap();
+
+    // BITMAPINFOHEADER:
+    bmp.write_u32::<LittleEndian>(40).unwrap();
+    bmp.write_i32::<LittleEndian>(width as i32).unwrap();
+    bmp.write_i32::<LittleEndian>(height as i32).unwrap();
+    bmp.write_u16::<LittleEndian>(1).unwrap(); // planes
+    bmp.write_u16::<LittleEndian>(24).unwrap(); // bits per pixel
+    bmp.write_u32::<LittleEndian>(0).unwrap(); // BI_RGB
+    bmp.write_u32::<LittleEndian>(image_size as u32).unwrap();
+    bmp.write_i32::<LittleEndian>(2835).unwrap(); // 72 dpi
+    bmp.write_i32::<LittleEndian>(2835).unwrap();
+    bmp.write_u32::<LittleEndian>(0).unwrap();
+    bmp.write_u32::<LittleEndian>(0).unwrap();
+
+    // pixel rows are stored bottom to top, in BGR order:
+    for y in (0..height).rev() {
+        let row = &rgb[y * width * 3..][..width * 3];
+
+        for pixel in row.chunks_exact(3) {
+            bmp.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+        }
+
+        bmp.resize(bmp.len() + row_size - width * 3, 0);
+    }
+
+    bmp
+}
+
+/// Renders waveform peaks as an SVG image
+pub fn waveform_svg(peaks: &[u8]) -> String {
+    const HEIGHT: usize = 64;
+
+    let mut path = String::new();
+
+    for (x, peak) in peaks.iter().enumerate() {
+        // always draw at least a pixel so silence is visible:
+        let half = cmp::max(1, *peak as usize * HEIGHT / 2 / 255);
+        path += &format!("M{} {}V{}", x, HEIGHT / 2 - half, HEIGHT / 2 + half);
+    }
+
+    format!(concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}" preserveAspectRatio="none">"#,
+            r##"<path d="{}" stroke="#8d8bb0" stroke-width="1" fill="none"/>"##,
+            "</svg>",
+        ),
+        peaks.len(), HEIGHT, path)
+}
@@ -0,0 +1,88 @@
+use mixlab_codec::ffmpeg::media::{Audio, Video};
+use mixlab_codec::ffmpeg::{AvIoError, AvIoReader, InputContainer};
+use mixlab_protocol::{MediaInfo, MediaStreamInfo, MediaStreamKind, Microseconds};
+
+use crate::project::stream::ReadStream;
+
+pub struct Probe {
+    pub info: MediaInfo,
+    // a Media Source can only play media with either a video or an audio
+    // stream, and it plays the first of each:
+    pub playable: bool,
+}
+
+pub type ProbeError = AvIoError<ReadStream>;
+
+/// Reads container and stream parameters from a stored stream. Blocks, so
+/// must be run on a blocking thread.
+pub fn probe(stream: ReadStream) -> Result<Probe, ProbeError> {
+    let mut container = InputContainer::open(AvIoReader::new(stream))?;
+    container.find_stream_info()?;
+
+    let streams = container.streams().iter().map(|stream| {
+        let params = stream.codec_parameters();
+
+        let kind = if stream.is_media_type::<Video>() {
+            MediaStreamKind::Video
+        } else if stream.is_media_type::<Audio>() {
+            MediaStreamKind::Audio
+        } else {
+            MediaStreamKind::Other
+        };
+
+        let (resolution, frame_rate) = match kind {
+            MediaStreamKind::Video => {
+                let resolution = if params.width > 0 && params.height > 0 {
+                    Some((params.width as u32, params.height as u32))
+                } else {
+                    None
+                };
+
+                (resolution, stream.frame_rate())
+            }
+            _ => (None, None),
+        };
+
+        let (sample_rate, channels) = match kind {
+            MediaStreamKind::Audio => {
+                (positive(params.sample_rate), positive(params.channels))
+            }
+            _ => (None, None),
+        };
+
+        MediaStreamInfo {
+            kind,
+            codec: stream.codec_short_name().to_owned(),
+            resolution,
+            frame_rate,
+            sample_rate,
+            channels,
+        }
+    }).collect::<Vec<_>>();
+
+    let first_video = container.streams().iter().find(|stream| stream.is_media_type::<Video>());
+    let first_audio = container.streams().iter().find(|stream| stream.is_media_type::<Audio>());
+
+    let playable = (first_video.is_some() || first_audio.is_some())
+        && first_video.into_iter().chain(first_audio)
+            .all(|stream| stream.codec_name().is_some());
+
+    let duration = container.duration()
+        .map(|duration| Microseconds(duration.round_to_base(1_000_000).max(0) as u64));
+
+    let info = MediaInfo {
+        format: container.format_name().unwrap_or("unknown").to_owned(),
+        duration,
+        streams,
+    };
+
+    Ok(Probe { info, playable })
+}
+
+fn positive(value: i32) -> Option<u32> {
+    if value > 0 {
+        Some(value as u32)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,116 @@
+use derive_more::From;
+use rusqlite::{params, OptionalExtension};
+
+use mixlab_protocol::{SnapshotId, SnapshotInfo, SnapshotKind, SnapshotOpError};
+
+use crate::engine::EngineError;
+use crate::persist;
+use crate::project::ProjectBaseRef;
+
+#[derive(From, Debug)]
+pub enum SnapshotError {
+    Database(rusqlite::Error),
+    Engine(EngineError),
+    Json(serde_json::Error),
+    Op(SnapshotOpError),
+}
+
+pub enum Snapshot {
+    Workspace(persist::Workspace),
+    Scene(persist::Scene),
+}
+
+pub async fn list(base: &ProjectBaseRef) -> Result<Vec<SnapshotInfo>, rusqlite::Error> {
+    base.with_database(|conn| {
+        conn.prepare("SELECT id, name, kind, created_at FROM snapshots ORDER BY name")?
+            .query_map(rusqlite::NO_PARAMS, |row| {
+                Ok(SnapshotInfo {
+                    id: SnapshotId(row.get(0)?),
+                    name: row.get(1)?,
+                    kind: parse_kind(&row.get::<_, String>(2)?),
+                    created_at: row.get::<_, i64>(3)? as u64,
+                })
+            })?
+            .collect()
+    }).await
+}
+
+/// Saves a snapshot of a workspace, replacing any existing snapshot of the
+/// same name
+pub async fn save(base: &ProjectBaseRef, name: String, kind: SnapshotKind, workspace: &persist::Workspace) -> Result<SnapshotId, SnapshotError> {
+    let name = name.trim().to_owned();
+
+    if name.is_empty() {
+        return Err(SnapshotOpError::InvalidName.into());
+    }
+
+    let serialized = match kind {
+        SnapshotKind::Workspace => serde_json::to_string(workspace)?,
+        SnapshotKind::Scene => serde_json::to_string(&workspace.to_scene())?,
+    };
+
+    let snapshot_id = base.with_database(move |conn| -> Result<SnapshotId, rusqlite::Error> {
+        conn.execute(r"
+                INSERT INTO snapshots (name, kind, serialized) VALUES (?, ?, ?)
+                ON CONFLICT (name) DO UPDATE SET
+                    kind = excluded.kind,
+                    serialized = excluded.serialized,
+                    created_at = excluded.created_at
+            ",
+            params![name, kind_name(kind), serialized])?;
+
+        // last_insert_rowid is not updated when an existing row is replaced:
+        conn.query_row("SELECT id FROM snapshots WHERE name = ?",
+            params![name],
+            |row| Ok(SnapshotId(row.get(0)?)))
+    }).await?;
+
+    let _ = base.notify.snapshots.broadcast(());
+
+    Ok(snapshot_id)
+}
+
+pub async fn load(base: &ProjectBaseRef, snapshot_id: SnapshotId) -> Result<Snapshot, SnapshotError> {
+    let row = base.with_database(move |conn| {
+        conn.query_row("SELECT kind, serialized FROM snapshots WHERE id = ?",
+            params![snapshot_id.0],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        ).optional()
+    }).await?;
+
+    let (kind, serialized) = row.ok_or(SnapshotOpError::NotFound(snapshot_id))?;
+
+    Ok(match parse_kind(&kind) {
+        SnapshotKind::Workspace => Snapshot::Workspace(serde_json::from_str(&serialized)?),
+        SnapshotKind::Scene => Snapshot::Scene(serde_json::from_str(&serialized)?),
+    })
+}
+
+pub async fn delete(base: &ProjectBaseRef, snapshot_id: SnapshotId) -> Result<(), SnapshotError> {
+    let deleted = base.with_database(move |conn| {
+        conn.execute("DELETE FROM snapshots WHERE id = ?", params![snapshot_id.0])
+    }).await?;
+
+    if deleted == 0 {
+        return Err(SnapshotOpError::NotFound(snapshot_id).into());
+    }
+
+    let _ = base.notify.snapshots.broadcast(());
+
+    Ok(())
+}
+
+fn kind_name(kind: SnapshotKind) -> &'static str {
+    match kind {
+        SnapshotKind::Workspace => "workspace",
+        SnapshotKind::Scene => "scene",
+    }
+}
+
+// the kind column is constrained to the names above
+fn parse_kind(name: &str) -> SnapshotKind {
+    match name {
+        "scene" => SnapshotKind::Scene,
+        _ => SnapshotKind::Workspace,
+    }
+}
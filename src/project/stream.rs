@@ -1,22 +1,69 @@
 use std::cmp;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::io::SeekFrom;
 use std::mem;
+use std::sync::Mutex;
 
-use rusqlite::{params, OptionalExtension, types::ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, types::ValueRef};
 use mixlab_codec::ffmpeg;
 
 use crate::project::ProjectBaseRef;
 
 const STREAM_BLOB_SIZE: usize = 1024 * 1024;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct StreamId(pub i64);
 
+/// Counts the readers and writers each stream currently has. Open streams are
+/// never vacuumed, and media is considered in use while its stream is open.
+///
+/// Streams are only ever opened while the database lock is held, so holding
+/// the database lock is enough to ensure no stream is opened concurrently.
+#[derive(Debug, Default)]
+pub struct OpenStreams {
+    streams: Mutex<HashMap<StreamId, usize>>,
+}
+
+impl OpenStreams {
+    fn acquire(&self, id: StreamId) {
+        let mut streams = self.streams.lock().expect("lock open streams");
+        *streams.entry(id).or_insert(0) += 1;
+    }
+
+    fn release(&self, id: StreamId) {
+        let mut streams = self.streams.lock().expect("lock open streams");
+
+        if let Some(count) = streams.get_mut(&id) {
+            *count -= 1;
+
+            if *count == 0 {
+                streams.remove(&id);
+            }
+        }
+    }
+
+    pub fn is_open(&self, id: StreamId) -> bool {
+        self.streams.lock().expect("lock open streams").contains_key(&id)
+    }
+}
+
+/// Inserts a new empty stream without opening it. The caller must make sure
+/// something refers to the stream before the database lock is released, or it
+/// may be vacuumed.
+pub fn insert(conn: &Connection) -> Result<StreamId, rusqlite::Error> {
+    conn.execute("INSERT INTO streams (size) VALUES (0)", rusqlite::NO_PARAMS)?;
+    Ok(StreamId(conn.last_insert_rowid()))
+}
+
 pub async fn create(base: ProjectBaseRef) -> Result<WriteStream, rusqlite::Error> {
-    let stream_id = base.with_database(|conn| -> Result<StreamId, rusqlite::Error> {
-        conn.execute("INSERT INTO streams (size) VALUES (0)", rusqlite::NO_PARAMS)?;
-        Ok(StreamId(conn.last_insert_rowid()))
+    let stream_id = base.with_database({
+        let base = base.clone();
+        move |conn| -> Result<StreamId, rusqlite::Error> {
+            let stream_id = insert(conn)?;
+            base.open_streams.acquire(stream_id);
+            Ok(stream_id)
+        }
     }).await?;
 
     Ok(WriteStream {
@@ -24,18 +71,98 @@ pub async fn create(base: ProjectBaseRef) -> Result<WriteStream, rusqlite::Error
         id: stream_id,
         offset: 0,
         buff: Vec::with_capacity(STREAM_BLOB_SIZE),
+        finalized: false,
     })
 }
 
-// TODO - automatically clean up write stream on drop if not explicitly finalized
+/// Reopens an existing stream to append to it. Returns None if the stream is
+/// currently open for reading or writing, as a stream only ever has one writer
+/// and data must not change under its readers.
+pub async fn resume(base: ProjectBaseRef, stream_id: StreamId) -> Result<Option<WriteStream>, rusqlite::Error> {
+    let size = base.with_database({
+        let base = base.clone();
+        move |conn| -> Result<Option<i64>, rusqlite::Error> {
+            if base.open_streams.is_open(stream_id) {
+                return Ok(None);
+            }
+
+            let size = conn.query_row(
+                r"SELECT size FROM streams WHERE id = ?",
+                params![stream_id.0],
+                |row| row.get(0)
+            )?;
+
+            base.open_streams.acquire(stream_id);
+
+            Ok(Some(size))
+        }
+    }).await?;
+
+    Ok(size.map(|size| WriteStream {
+        base,
+        id: stream_id,
+        offset: size,
+        buff: Vec::with_capacity(STREAM_BLOB_SIZE),
+        finalized: false,
+    }))
+}
+
+/// Deletes all streams which are neither referred to by any media or upload
+/// nor currently open, along with their blobs. Returns the number of streams
+/// deleted.
+pub async fn vacuum(base: &ProjectBaseRef) -> Result<usize, rusqlite::Error> {
+    base.with_database({
+        let base = base.clone();
+        move |conn| -> Result<usize, rusqlite::Error> {
+            let txn = conn.transaction()?;
+
+            let unreferenced = txn.prepare(r"
+                    SELECT id FROM streams
+                    WHERE id NOT IN (SELECT stream_id FROM media)
+                    AND id NOT IN (SELECT stream_id FROM uploads)
+                ")?
+                .query_map(rusqlite::NO_PARAMS, |row| Ok(StreamId(row.get(0)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let mut deleted = 0;
+
+            for stream_id in unreferenced {
+                if base.open_streams.is_open(stream_id) {
+                    continue;
+                }
+
+                txn.execute("DELETE FROM blobs WHERE stream_id = ?", params![stream_id.0])?;
+                txn.execute("DELETE FROM streams WHERE id = ?", params![stream_id.0])?;
+                deleted += 1;
+            }
+
+            txn.commit()?;
+
+            Ok(deleted)
+        }
+    }).await
+}
+
+/// A stream being written. If dropped without being finalized, the stream is
+/// left for the vacuum to clean up.
 pub struct WriteStream {
     base: ProjectBaseRef,
     id: StreamId,
     offset: i64,
     buff: Vec<u8>,
+    finalized: bool,
 }
 
 impl WriteStream {
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+
+    /// Size of the stream, including any data not yet written out
+    pub fn size(&self) -> u64 {
+        (self.offset + self.buff.len() as i64) as u64
+    }
+
     pub async fn write(&mut self, mut bytes: &[u8]) -> Result<(), rusqlite::Error> {
         while !bytes.is_empty() {
             let take = cmp::min(bytes.len(), STREAM_BLOB_SIZE - self.buff.len());
@@ -54,8 +181,11 @@ impl WriteStream {
         Ok(())
     }
 
-    pub async fn finalize(mut self) -> Result<StreamId, rusqlite::Error> {
+    /// Writes out any buffered data. The stream remains open until dropped,
+    /// so the caller has a chance to refer to it before it can be vacuumed.
+    pub async fn finalize(&mut self) -> Result<StreamId, rusqlite::Error> {
         self.flush().await?;
+        self.finalized = true;
         Ok(self.id)
     }
 
@@ -66,14 +196,18 @@ impl WriteStream {
             let buff_len = i64::try_from(self.buff.len()).expect("buff.len as i64");
             let buff = mem::take(&mut self.buff);
 
+            // size must always agree with the blobs written, as resuming a
+            // stream appends at its recorded size:
             self.base.with_database(move |conn| -> Result<(), rusqlite::Error> {
-                conn.execute(r"INSERT INTO blobs (stream_id, offset, data) VALUES (?, ?, ?)",
+                let txn = conn.transaction()?;
+
+                txn.execute(r"INSERT INTO blobs (stream_id, offset, data) VALUES (?, ?, ?)",
                     params![id.0, offset, &buff])?;
 
-                conn.execute(r"UPDATE streams SET size = ? WHERE id = ?",
+                txn.execute(r"UPDATE streams SET size = ? WHERE id = ?",
                     params![offset + buff_len, id.0])?;
 
-                Ok(())
+                txn.commit()
             }).await?;
 
             self.offset += buff_len;
@@ -83,6 +217,16 @@ impl WriteStream {
     }
 }
 
+impl Drop for WriteStream {
+    fn drop(&mut self) {
+        self.base.open_streams.release(self.id);
+
+        if !self.finalized {
+            self.base.request_vacuum();
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ReadStream {
     base: ProjectBaseRef,
@@ -93,12 +237,21 @@ pub struct ReadStream {
 
 impl ReadStream {
     pub async fn open(base: ProjectBaseRef, stream_id: StreamId) -> Result<Option<Self>, rusqlite::Error> {
-        Ok(base.with_database(move |conn| {
-            conn.query_row(
-                r"SELECT size FROM streams WHERE rowid = ?",
-                &[stream_id.0],
-                |row| row.get(0)
-            ).optional()
+        Ok(base.with_database({
+            let base = base.clone();
+            move |conn| -> Result<Option<i64>, rusqlite::Error> {
+                let size = conn.query_row(
+                    r"SELECT size FROM streams WHERE rowid = ?",
+                    &[stream_id.0],
+                    |row| row.get(0)
+                ).optional()?;
+
+                if size.is_some() {
+                    base.open_streams.acquire(stream_id);
+                }
+
+                Ok(size)
+            }
         }).await?.map(|size| {
             ReadStream {
                 base,
@@ -110,6 +263,12 @@ impl ReadStream {
     }
 }
 
+impl Drop for ReadStream {
+    fn drop(&mut self) {
+        self.base.open_streams.release(self.stream_id);
+    }
+}
+
 impl ffmpeg::IoReader for ReadStream {
     type Error = rusqlite::Error;
     const BUFFER_SIZE: usize = STREAM_BLOB_SIZE;
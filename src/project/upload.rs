@@ -0,0 +1,290 @@
+use rusqlite::{params, OptionalExtension};
+use sha2::{Digest, Sha256};
+use tokio::task;
+
+use mixlab_codec::ffmpeg::IoReader;
+
+use crate::project::ProjectBaseRef;
+use crate::project::media::{self, MediaUpload, UploadInfo};
+use crate::project::stream::{self, ReadStream, WriteStream, StreamId};
+
+// uploads not written to for this long are assumed to be abandoned:
+const EXPIRE_AFTER_SECS: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy)]
+pub struct UploadId(pub i64);
+
+/// Resumable uploads are created up front, have their bytes appended by one
+/// or more requests, and only become media once finalized. The data received
+/// so far survives dropped connections and server restarts.
+#[derive(Debug)]
+pub enum UploadError {
+    Database(rusqlite::Error),
+    Media(media::UploadError),
+    NotFound,
+    // another request is currently writing to or finalizing the upload:
+    Busy,
+    // client is out of sync, and should resume from the offset given:
+    OffsetMismatch(u64),
+    // client sent more bytes than the length it declared:
+    TooLarge,
+    // upload was finalized before all of its declared length was received:
+    Incomplete { offset: u64, length: u64 },
+    InvalidChecksum,
+    ChecksumMismatch,
+}
+
+impl From<rusqlite::Error> for UploadError {
+    fn from(e: rusqlite::Error) -> UploadError {
+        UploadError::Database(e)
+    }
+}
+
+impl From<media::UploadError> for UploadError {
+    fn from(e: media::UploadError) -> UploadError {
+        UploadError::Media(e)
+    }
+}
+
+pub struct UploadStatus {
+    pub offset: u64,
+    pub length: Option<u64>,
+}
+
+struct Upload {
+    info: UploadInfo,
+    stream_id: StreamId,
+    offset: u64,
+    length: Option<u64>,
+}
+
+pub async fn create(base: &ProjectBaseRef, info: UploadInfo, length: Option<u64>) -> Result<UploadId, rusqlite::Error> {
+    base.with_database(move |conn| -> Result<UploadId, rusqlite::Error> {
+        let txn = conn.transaction()?;
+
+        let stream_id = stream::insert(&txn)?;
+
+        txn.execute(
+            "INSERT INTO uploads (name, kind, stream_id, length) VALUES (?, ?, ?, ?)",
+            params![info.name, info.kind, stream_id.0, length.map(|length| length as i64)])?;
+
+        let upload_id = UploadId(txn.last_insert_rowid());
+
+        txn.commit()?;
+
+        Ok(upload_id)
+    }).await
+}
+
+pub async fn status(base: &ProjectBaseRef, upload_id: UploadId) -> Result<UploadStatus, UploadError> {
+    let upload = find(base, upload_id).await?.ok_or(UploadError::NotFound)?;
+
+    Ok(UploadStatus {
+        offset: upload.offset,
+        length: upload.length,
+    })
+}
+
+/// Appends bytes to an upload. The offset given must be the current offset
+/// of the upload, so that clients which have lost track of how much was
+/// received find out rather than corrupting the upload.
+pub async fn resume(base: &ProjectBaseRef, upload_id: UploadId, offset: u64) -> Result<UploadChunk, UploadError> {
+    let (upload, stream) = open(base, upload_id).await?;
+
+    if stream.size() != offset {
+        return Err(UploadError::OffsetMismatch(stream.size()));
+    }
+
+    Ok(UploadChunk {
+        base: base.clone(),
+        upload_id,
+        stream,
+        length: upload.length,
+    })
+}
+
+/// Turns a complete upload into a media item. If a SHA-256 is given, the data
+/// received must match it. Uploads which fail verification are discarded, as
+/// resuming them could never succeed.
+pub async fn finalize(base: &ProjectBaseRef, upload_id: UploadId, sha256: Option<&str>) -> Result<(), UploadError> {
+    let expected = match sha256 {
+        Some(hex) => Some(parse_sha256(hex).ok_or(UploadError::InvalidChecksum)?),
+        None => None,
+    };
+
+    let (upload, stream) = open(base, upload_id).await?;
+
+    if let Some(length) = upload.length {
+        if stream.size() != length {
+            return Err(UploadError::Incomplete { offset: stream.size(), length });
+        }
+    }
+
+    // the stream is closed for writing as soon as MediaUpload is done with it,
+    // but must stay busy until the upload is deleted, see `open`
+    let guard = ReadStream::open(base.clone(), upload.stream_id).await?
+        .ok_or(UploadError::NotFound)?;
+
+    let guard = match expected {
+        Some(expected) => {
+            let (actual, guard) = task::spawn_blocking(move || hash_stream(guard)).await
+                .expect("hash stream task")?;
+
+            if actual != expected {
+                delete(base, upload_id).await?;
+                return Err(UploadError::ChecksumMismatch);
+            }
+
+            guard
+        }
+        None => guard,
+    };
+
+    let result = MediaUpload { base: base.clone(), stream, info: upload.info }
+        .finalize().await;
+
+    match result {
+        // stream is now referred to by media, or has been rejected
+        Ok(_) | Err(media::UploadError::Unplayable(_)) => {
+            delete(base, upload_id).await?;
+        }
+        // leave the upload in place so finalizing can be retried
+        Err(media::UploadError::Database(_)) => {}
+    }
+
+    drop(guard);
+    base.request_vacuum();
+
+    result?;
+    Ok(())
+}
+
+/// Abandons an upload, discarding everything received for it
+pub async fn cancel(base: &ProjectBaseRef, upload_id: UploadId) -> Result<(), UploadError> {
+    // open the stream so an upload can't be cancelled from under a request
+    // writing to it. dropping the stream unfinalized has it vacuumed:
+    let (_, _stream) = open(base, upload_id).await?;
+    delete(base, upload_id).await?;
+    Ok(())
+}
+
+/// Deletes uploads which have not been written to for a while, leaving their
+/// streams for the vacuum. Returns the number of uploads deleted.
+pub async fn expire(base: &ProjectBaseRef) -> Result<usize, rusqlite::Error> {
+    base.with_database(|conn| {
+        conn.execute("DELETE FROM uploads WHERE updated_at < strftime('%s', 'now') - ?",
+            params![EXPIRE_AFTER_SECS])
+    }).await
+}
+
+pub struct UploadChunk {
+    base: ProjectBaseRef,
+    upload_id: UploadId,
+    stream: WriteStream,
+    length: Option<u64>,
+}
+
+impl UploadChunk {
+    pub async fn write(&mut self, bytes: &[u8]) -> Result<(), UploadError> {
+        if let Some(length) = self.length {
+            if self.stream.size() + bytes.len() as u64 > length {
+                return Err(UploadError::TooLarge);
+            }
+        }
+
+        self.stream.write(bytes).await?;
+        Ok(())
+    }
+
+    /// Writes out everything received, returning the new offset of the
+    /// upload. Should be called even if the request fails part way, so that
+    /// the client can resume from where it got to.
+    pub async fn finish(mut self) -> Result<u64, UploadError> {
+        self.stream.finalize().await?;
+
+        let upload_id = self.upload_id;
+
+        self.base.with_database(move |conn| {
+            conn.execute("UPDATE uploads SET updated_at = strftime('%s', 'now') WHERE id = ?",
+                params![upload_id.0])
+        }).await?;
+
+        Ok(self.stream.size())
+    }
+}
+
+async fn find(base: &ProjectBaseRef, upload_id: UploadId) -> Result<Option<Upload>, rusqlite::Error> {
+    base.with_database(move |conn| {
+        conn.query_row(r"
+                SELECT uploads.name, uploads.kind, uploads.stream_id, streams.size, uploads.length
+                FROM uploads
+                INNER JOIN streams ON streams.id = uploads.stream_id
+                WHERE uploads.id = ?
+            ",
+            params![upload_id.0],
+            |row| Ok(Upload {
+                info: UploadInfo {
+                    name: row.get(0)?,
+                    kind: row.get(1)?,
+                },
+                stream_id: StreamId(row.get(2)?),
+                offset: row.get::<_, i64>(3)? as u64,
+                length: row.get::<_, Option<i64>>(4)?.map(|length| length as u64),
+            })
+        ).optional()
+    }).await
+}
+
+// opens the stream of an upload for writing. only one request may have an
+// upload's stream open at a time, others get UploadError::Busy
+async fn open(base: &ProjectBaseRef, upload_id: UploadId) -> Result<(Upload, WriteStream), UploadError> {
+    let upload = find(base, upload_id).await?.ok_or(UploadError::NotFound)?;
+
+    let stream = stream::resume(base.clone(), upload.stream_id).await?
+        .ok_or(UploadError::Busy)?;
+
+    // the upload may have been finalized or cancelled between looking it up
+    // and opening its stream, in which case the stream is no longer ours:
+    if find(base, upload_id).await?.is_none() {
+        return Err(UploadError::NotFound);
+    }
+
+    Ok((upload, stream))
+}
+
+async fn delete(base: &ProjectBaseRef, upload_id: UploadId) -> Result<(), rusqlite::Error> {
+    base.with_database(move |conn| {
+        conn.execute("DELETE FROM uploads WHERE id = ?", params![upload_id.0])
+    }).await?;
+
+    Ok(())
+}
+
+fn hash_stream(mut stream: ReadStream) -> Result<(Vec<u8>, ReadStream), rusqlite::Error> {
+    let mut hasher = Sha256::default();
+    let mut buff = vec![0u8; <ReadStream as IoReader>::BUFFER_SIZE];
+
+    loop {
+        let len = stream.read(&mut buff)?;
+
+        if len == 0 {
+            break;
+        }
+
+        hasher.input(&buff[..len]);
+    }
+
+    Ok((hasher.result().to_vec(), stream))
+}
+
+fn parse_sha256(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..32)
+        .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok())
+        .collect()
+}
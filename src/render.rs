@@ -0,0 +1,272 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write, BufWriter};
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use derive_more::From;
+use fdk_aac::enc as aac;
+use structopt::StructOpt;
+use tokio::{runtime, task};
+
+use mixlab_codec::ffmpeg::PictureSettings;
+use mixlab_mux::mp4::{Mp4Mux, Mp4Params, TrackData, AdtsFrame, AvcFrame};
+use mixlab_protocol::{ModuleId, ModuleParams};
+use mixlab_util::time::MediaTime;
+
+use crate::engine::{OfflineEngine, OfflineTick, Sample, CHANNELS};
+use crate::project::{self, OpenError};
+use crate::video::encode::{EncodeError, EncodeStream, AudioCtx, AudioParams, VideoCtx, VideoParams, StreamSegment, Profile};
+
+const RENDER_WIDTH: usize = 1280;
+const RENDER_HEIGHT: usize = 720;
+
+#[derive(StructOpt)]
+pub struct RenderOpts {
+    pub workspace_path: PathBuf,
+
+    /// File to render to, either .wav or .mp4
+    pub output: PathBuf,
+
+    /// Length of render in seconds
+    #[structopt(long, default_value = "10")]
+    pub seconds: f64,
+}
+
+#[derive(Debug, From)]
+pub enum RenderError {
+    Open(OpenError),
+    Io(io::Error),
+    Encode(EncodeError),
+    UnknownFormat,
+    #[from(ignore)]
+    LiveModule(ModuleId),
+    #[from(ignore)]
+    TooLong { max_seconds: u64 },
+}
+
+enum Format {
+    Wav,
+    Mp4,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "wav" => Some(Format::Wav),
+            "mp4" => Some(Format::Mp4),
+            _ => None,
+        }
+    }
+}
+
+/// Renders a project's workspace to a file as fast as the engine can run,
+/// rather than at wall-clock pace. The stereo and video signals arriving at
+/// terminal modules (eg. Output Device, Monitor) are what gets rendered.
+pub async fn run(opts: RenderOpts) -> Result<(), RenderError> {
+    let format = Format::from_path(&opts.output)
+        .ok_or(RenderError::UnknownFormat)?;
+
+    let (base, workspace) = project::open_workspace(opts.workspace_path).await?;
+
+    // stream inputs play whatever a remote source happens to be sending, so
+    // what they contribute to each tick varies from one render to the next:
+    let live_module = workspace.modules.iter()
+        .filter(|(_, module)| match module.params {
+            ModuleParams::StreamInput(_) => true,
+            _ => false,
+        })
+        .map(|(id, _)| *id)
+        .min();
+
+    if let Some(module_id) = live_module {
+        return Err(RenderError::LiveModule(module_id));
+    }
+
+    let ticks = (opts.seconds * base.rate().tick_rate() as f64).ceil() as u64;
+
+    if let Format::Wav = format {
+        // sizes in the RIFF header are 32 bit:
+        let max_data_bytes = (u32::max_value() - WAV_HEADER_SIZE) as u64;
+        let bytes_per_tick = (base.rate().samples_per_tick() * CHANNELS * WAV_BYTES_PER_SAMPLE) as u64;
+        let max_ticks = max_data_bytes / bytes_per_tick;
+
+        if ticks > max_ticks {
+            let max_seconds = max_ticks / base.rate().tick_rate() as u64;
+            return Err(RenderError::TooLong { max_seconds });
+        }
+    }
+    let output = opts.output;
+
+    // modules require a tokio runtime context to be entered so they can
+    // spawn async tasks, just like on the realtime engine thread
+    let tokio_runtime = runtime::Handle::current();
+
+    task::spawn_blocking(move || {
+        tokio_runtime.enter(|| {
+            let mut engine = OfflineEngine::new(&workspace, base);
+            let file = BufWriter::new(File::create(&output)?);
+
+            match format {
+                Format::Wav => render_wav(&mut engine, ticks, file),
+                Format::Mp4 => render_mp4(&mut engine, ticks, file),
+            }
+        })
+    }).await.expect("render task")
+}
+
+fn render_wav(engine: &mut OfflineEngine, ticks: u64, out: impl Write + Seek) -> Result<(), RenderError> {
+    let mut wav = WavWriter::new(out, engine.rate().sample_rate())?;
+
+    for _ in 0..ticks {
+        let OfflineTick { audio, .. } = engine.run_tick();
+        wav.write_samples(&audio)?;
+    }
+
+    Ok(wav.finalize()?)
+}
+
+fn render_mp4(engine: &mut OfflineEngine, ticks: u64, mut out: impl Write) -> Result<(), RenderError> {
+    let rate = engine.rate();
+
+    let audio_ctx = AudioCtx::new(AudioParams {
+        bit_rate: aac::BitRate::VbrVeryHigh,
+        sample_rate: rate.sample_rate(),
+        transport: aac::Transport::Adts,
+    })?;
+
+    let video_ctx = VideoCtx::new(VideoParams {
+        picture: PictureSettings::yuv420p(RENDER_WIDTH, RENDER_HEIGHT),
+        time_base: rate.sample_rate(),
+        profile: Profile::Stream,
+    })?;
+
+    let mp4_params = {
+        let dcr = video_ctx.decoder_configuration_record();
+        let mut dcr_bytes = vec![];
+        dcr.write_to(&mut dcr_bytes);
+
+        Mp4Params {
+            timescale: rate.sample_rate() as u32,
+            sample_rate: rate.sample_rate() as u32,
+            width: RENDER_WIDTH as u32,
+            height: RENDER_HEIGHT as u32,
+            dcr: Cow::Owned(dcr_bytes),
+        }
+    };
+
+    let (mut mux, init) = Mp4Mux::new(mp4_params);
+    out.write_all(&init)?;
+
+    let mut encode = EncodeStream::new(audio_ctx, video_ctx);
+
+    for tick in 0..ticks {
+        let OfflineTick { audio, video } = engine.run_tick();
+
+        let timestamp = MediaTime::new((tick * rate.samples_per_tick() as u64) as i64, rate.sample_rate() as i64);
+
+        encode.send_audio(&audio);
+
+        if let Some(video_frame) = video {
+            let frame_timestamp = timestamp + video_frame.tick_offset;
+            encode.send_video(frame_timestamp, video_frame.data.duration_hint, video_frame.data.decoded);
+        }
+
+        encode.barrier(timestamp);
+
+        while let Some(segment) = encode.recv_segment() {
+            write_segment(&mut mux, &mut out, segment)?;
+        }
+    }
+
+    // the encoder holds back the last segments of each track until the
+    // stream ends:
+    for segment in encode.drain() {
+        write_segment(&mut mux, &mut out, segment)?;
+    }
+
+    Ok(out.flush()?)
+}
+
+fn write_segment(mux: &mut Mp4Mux, out: &mut impl Write, segment: StreamSegment) -> Result<(), io::Error> {
+    let bytes = match segment {
+        StreamSegment::Audio(audio) => {
+            mux.write_track(audio.duration, &TrackData::Audio(AdtsFrame(audio.frame)))
+        }
+        StreamSegment::Video(video) => {
+            mux.write_track(video.duration, &TrackData::Video(AvcFrame {
+                is_key_frame: video.frame.is_key_frame,
+                composition_time: video.frame.composition_time,
+                data: video.frame.data,
+            }))
+        }
+    };
+
+    out.write_all(&bytes)
+}
+
+struct WavWriter<W: Write + Seek> {
+    out: W,
+    sample_rate: usize,
+    data_bytes: u32,
+}
+
+const WAV_BITS_PER_SAMPLE: u16 = 16;
+const WAV_BYTES_PER_SAMPLE: usize = WAV_BITS_PER_SAMPLE as usize / 8;
+const WAV_HEADER_SIZE: u32 = 44;
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: usize) -> Result<Self, io::Error> {
+        // header is written with zero lengths and patched up in finalize
+        write_wav_header(&mut out, sample_rate, 0)?;
+        Ok(WavWriter { out, sample_rate, data_bytes: 0 })
+    }
+
+    pub fn write_samples(&mut self, samples: &[Sample]) -> Result<(), io::Error> {
+        for sample in samples {
+            let sample = if *sample > 1.0 {
+                1.0
+            } else if *sample < -1.0 {
+                -1.0
+            } else {
+                *sample
+            };
+
+            self.out.write_i16::<LittleEndian>((sample * i16::max_value() as f32) as i16)?;
+        }
+
+        self.data_bytes += (samples.len() * WAV_BYTES_PER_SAMPLE) as u32;
+
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> Result<(), io::Error> {
+        self.out.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.out, self.sample_rate, self.data_bytes)?;
+        self.out.flush()
+    }
+}
+
+fn write_wav_header(out: &mut impl Write, sample_rate: usize, data_bytes: u32) -> Result<(), io::Error> {
+    let block_align = CHANNELS as u16 * WAV_BITS_PER_SAMPLE / 8;
+
+    out.write_all(b"RIFF")?;
+    out.write_u32::<LittleEndian>(WAV_HEADER_SIZE - 8 + data_bytes)?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_u32::<LittleEndian>(16)?;
+    out.write_u16::<LittleEndian>(1)?; // PCM
+    out.write_u16::<LittleEndian>(CHANNELS as u16)?;
+    out.write_u32::<LittleEndian>(sample_rate as u32)?;
+    out.write_u32::<LittleEndian>(sample_rate as u32 * block_align as u32)?;
+    out.write_u16::<LittleEndian>(block_align)?;
+    out.write_u16::<LittleEndian>(WAV_BITS_PER_SAMPLE)?;
+
+    out.write_all(b"data")?;
+    out.write_u32::<LittleEndian>(data_bytes)?;
+
+    Ok(())
+}
@@ -7,6 +7,7 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 use crate::listen::PeekTcpStream;
 use crate::rtmp::RtmpError;
+use crate::source::{Registry, SourceSend, ConnectError};
 
 pub async fn handshake(stream: &mut PeekTcpStream, buff: &mut [u8]) -> Result<(Handshake, Vec<u8>), RtmpError> {
     println!("RTMP incoming!");
@@ -88,19 +89,46 @@ pub async fn handle_new_client(
     }
 }
 
+/// Connects the client to the mountpoint named by its app name, using its
+/// stream key as the mountpoint secret. If the mountpoint refuses the
+/// connection, the publish request is rejected and `None` is returned.
 pub async fn accept_publish(
     stream: &mut PeekTcpStream,
     session: &mut ServerSession,
     publish: &PublishInfo,
-) -> Result<(), RtmpError> {
+    mountpoints: &Registry,
+) -> Result<Option<SourceSend>, RtmpError> {
+    let source = match mountpoints.connect(&publish.app_name, Some(&publish.stream_key)) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("rtmp: refusing publish on {:?}: {:?}", publish.app_name, e);
+
+            let (code, description) = match e {
+                ConnectError::NoMountpoint => ("NetStream.Publish.Denied", "No such mountpoint"),
+                ConnectError::Unauthorized => ("NetStream.Publish.Denied", "Invalid stream key"),
+                ConnectError::AlreadyConnected => ("NetStream.Publish.BadName", "Mountpoint already in use"),
+            };
+
+            let results = session.reject_request(publish.request_id, code, description)?;
+            send_request_results(stream, results).await?;
+            return Ok(None);
+        }
+    };
+
     let results = session.accept_request(publish.request_id)?;
+    send_request_results(stream, results).await?;
+
+    Ok(Some(source))
+}
 
+async fn send_request_results(stream: &mut PeekTcpStream, results: Vec<ServerSessionResult>) -> Result<(), RtmpError> {
     for result in results {
         if let ServerSessionResult::OutboundResponse(resp) = result {
             stream.write_all(&resp.bytes).await?;
         } else {
-            // accept_request never returns any variant of ServerSessionResult other than OutboundReponse:
-            panic!("rtmp: unexpected result from accept_request: {:?}", result);
+            // accept_request and reject_request never return any variant of
+            // ServerSessionResult other than OutboundReponse:
+            panic!("rtmp: unexpected result from request: {:?}", result);
         }
     }
 
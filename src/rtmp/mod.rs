@@ -1,5 +1,4 @@
 use std::io;
-use std::mem;
 use std::thread;
 
 use bytes::Bytes;
@@ -15,10 +14,13 @@ use mixlab_codec::aac;
 use mixlab_codec::ffmpeg::media::Video;
 use mixlab_codec::ffmpeg::codec::{self, CodecBuilder, Decode, RecvFrameError};
 use mixlab_codec::ffmpeg::{AvError, AvPacketRef, PacketInfo};
+use mixlab_codec::resample::Resampler;
+use mixlab_protocol::StreamProtocol;
 use mixlab_util::time::{MediaDuration, MediaTime, TimeBase};
 
+use crate::engine::Rate;
 use crate::listen::PeekTcpStream;
-use crate::source::{Registry, ConnectError, SourceRecv, SourceSend, ListenError};
+use crate::source::{Registry, SourceRecv, SourceSend, StreamInfo, ListenError};
 use crate::video;
 
 pub mod client;
@@ -28,15 +30,15 @@ pub mod packet;
 use packet::{AudioPacket, VideoPacket, VideoPacketType};
 
 lazy_static::lazy_static! {
-    static ref MOUNTPOINTS: Registry = {
-        let reg = Registry::new();
-        mem::forget(reg.listen("my_stream_endpoint"));
-        reg
-    };
+    static ref MOUNTPOINTS: Registry = Registry::new(StreamProtocol::Rtmp);
+}
+
+pub fn listen(mountpoint: &str, secret: Option<String>) -> Result<SourceRecv, ListenError> {
+    MOUNTPOINTS.listen(mountpoint, secret)
 }
 
-pub fn listen(mountpoint: &str) -> Result<SourceRecv, ListenError> {
-    MOUNTPOINTS.listen(mountpoint)
+pub fn registry() -> &'static Registry {
+    &MOUNTPOINTS
 }
 
 pub const TIME_BASE: i32 = 1000;
@@ -46,7 +48,6 @@ pub enum RtmpError {
     Io(io::Error),
     Handshake(HandshakeError),
     Session(ServerSessionError),
-    SourceConnect(ConnectError),
     MetadataNotYetSent,
     UnsupportedStream,
     SourceSend,
@@ -56,7 +57,7 @@ pub enum RtmpError {
     AvCodec(AvError),
 }
 
-pub async fn accept(mut stream: PeekTcpStream) -> Result<(), RtmpError> {
+pub async fn accept(mut stream: PeekTcpStream, rate: Rate) -> Result<(), RtmpError> {
     let mut buff = vec![0u8; 4096];
 
     let (_, remaining_bytes) = incoming::handshake(&mut stream, &mut buff).await?;
@@ -65,16 +66,12 @@ pub async fn accept(mut stream: PeekTcpStream) -> Result<(), RtmpError> {
 
     let source = match publish {
         Some(publish) => {
-            println!("rtmp: client wants to publish on {:?} with stream_key {:?}",
-                publish.app_name, publish.stream_key);
-
-            // TODO handle stream keys
-
-            let source = MOUNTPOINTS.connect(&publish.app_name)?;
-
-            incoming::accept_publish(&mut stream, &mut session, &publish).await?;
+            println!("rtmp: client wants to publish on {:?}", publish.app_name);
 
-            source
+            match incoming::accept_publish(&mut stream, &mut session, &publish, &MOUNTPOINTS).await? {
+                Some(source) => source,
+                None => { return Ok(()); }
+            }
         }
         None => { return Ok(()); }
     };
@@ -89,9 +86,11 @@ pub async fn accept(mut stream: PeekTcpStream) -> Result<(), RtmpError> {
         stream,
         session,
         source,
+        rate,
         meta: None,
         audio_codec,
         audio_asc: None,
+        audio_resampler: None,
         audio_timestamp: MediaTime::new(0, 1),
         video_codec: None,
     };
@@ -107,9 +106,11 @@ struct ReceiveContext {
     stream: PeekTcpStream,
     session: ServerSession,
     source: SourceSend,
+    rate: Rate,
     meta: Option<StreamMeta>,
     audio_codec: fdk_aac::dec::Decoder,
     audio_asc: Option<aac::AudioSpecificConfiguration>,
+    audio_resampler: Option<(usize, Resampler)>,
     audio_timestamp: MediaTime,
     video_codec: Option<Decode<Video>>,
 }
@@ -120,6 +121,12 @@ struct StreamMeta {
 
 fn run_receive_thread(ctx: &mut ReceiveContext, mut buff: Vec<u8>) -> Result<(), RtmpError> {
     loop {
+        if !ctx.source.connected() {
+            // source was cut off by a listener claiming the mountpoint with
+            // a secret that this client did not present
+            return Ok(());
+        }
+
         match block_on(ctx.stream.read(&mut buff))? {
             0 => {
                 return Ok(());
@@ -180,6 +187,22 @@ fn handle_event(
                 video_frame_duration,
             });
 
+            let resolution = metadata.video_width.and_then(|width| {
+                metadata.video_height.map(|height| (width, height))
+            });
+
+            let bitrate_kbps = match (metadata.video_bitrate_kbps, metadata.audio_bitrate_kbps) {
+                (None, None) => None,
+                (video, audio) => Some(video.unwrap_or(0) + audio.unwrap_or(0)),
+            };
+
+            // we only support receiving H.264 and AAC over RTMP:
+            ctx.source.set_stream_info(StreamInfo {
+                codec: Some("H.264/AAC".to_owned()),
+                resolution,
+                bitrate_kbps,
+            });
+
             Ok(())
         }
         _ => {
@@ -224,21 +247,29 @@ fn receive_audio_packet(
 
             match ctx.audio_codec.decode_frame(&mut pcm_buffer) {
                 Ok(()) => {
-                    let sample_rate = ctx.audio_codec.stream_info().sampleRate;
+                    let sample_rate = ctx.audio_codec.stream_info().sampleRate as usize;
 
-                    if sample_rate != 44100 {
-                        // TODO fix me
-                        panic!("expected stream sample rate to be 44100");
+                    pcm_buffer.truncate(ctx.audio_codec.decoded_frame_size());
+                    // println!("decoded frame! timestamp: {:?}, frame size: {}", timestamp, pcm_buffer.len());
+
+                    // (re)create resampler if the stream sample rate changes.
+                    // decoder always mixes down to stereo, see above:
+                    let resampler_rate = ctx.audio_resampler.as_ref().map(|(rate, _)| *rate);
+
+                    if resampler_rate != Some(sample_rate) {
+                        ctx.audio_resampler = Some((sample_rate, Resampler::new(2, sample_rate, ctx.rate.sample_rate())));
                     }
 
-                    let frame_time = MediaDuration::new(pcm_buffer.len() as i64 / 2, sample_rate as i64);
+                    let (_, resampler) = ctx.audio_resampler.as_mut().unwrap();
 
-                    pcm_buffer.truncate(ctx.audio_codec.decoded_frame_size());
-                    // println!("decoded frame! timestamp: {:?}, frame size: {}", timestamp, pcm_buffer.len());
+                    let mut resampled = Vec::with_capacity(pcm_buffer.len());
+                    resampler.process(&pcm_buffer, &mut resampled);
+
+                    let frame_time = ctx.rate.duration(resampled.len() / 2);
 
                     // TODO do we use ctx.audio_timestamp or the rtmp timestamp here?
 
-                    ctx.source.write_audio(ctx.audio_timestamp, pcm_buffer)
+                    ctx.source.write_audio(ctx.audio_timestamp, resampled)
                         .map_err(|()| RtmpError::SourceSend)?;
 
                     ctx.audio_timestamp += frame_time;
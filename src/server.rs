@@ -1,7 +1,9 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use bytes::Buf;
 use derive_more::From;
@@ -13,21 +15,25 @@ use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use uuid::Uuid;
 use warp::Filter;
+use warp::http::{HeaderValue, StatusCode};
 use warp::reply::{self, Reply};
 use warp::ws::{self, Ws, WebSocket};
 
-use mixlab_protocol::{ClientMessage, ServerMessage};
+use mixlab_protocol::{ClientMessage, ServerMessage, ServerUpdate, WorkspaceState, Rejection, LiveSource, MediaId, MediaOp, MediaSearchResults, SnapshotOp};
 
-use crate::engine::EngineEvent;
+use crate::engine::{EngineEvent, EngineError};
 use crate::listen::{self, Disambiguation};
 use crate::project::{self, ProjectHandle, Notification};
+use crate::project::bundle::{self, Bundle, BundleError};
+use crate::project::snapshot::SnapshotError;
+use crate::project::upload::{UploadId, UploadError as UploadOpError};
 use crate::{icecast, module, rtmp};
 
 #[derive(StructOpt)]
 pub struct RunOpts {
     #[structopt(short, long, default_value = "127.0.0.1:8000")]
     listen: SocketAddr,
-    workspace_path: PathBuf,
+    pub workspace_path: PathBuf,
 }
 
 struct Server {
@@ -48,6 +54,10 @@ pub async fn run(opts: RunOpts) {
     let project = project::open_or_create(opts.workspace_path).await
         .expect("create_or_open_project");
 
+    // streams received over icecast and rtmp are resampled to the rate the
+    // engine runs at:
+    let rate = project.rate();
+
     let server = Arc::new(Server::new(project));
 
     let index = warp::path::end()
@@ -105,13 +115,187 @@ pub async fn run(opts: RunOpts) {
                         kind,
                     };
 
-                    handle_upload(params, stream, server).await
-                        .map(|()| warp::reply::reply())
-                        .map_err(|e| {
-                            eprintln!("upload failed: {:?}", e);
-                            // TODO - internal server error?
-                            warp::reject::not_found()
-                        })
+                    Ok::<_, warp::Rejection>(match handle_upload(params, stream, server).await {
+                        Ok(()) => reply::with_status(String::new(), StatusCode::OK).into_response(),
+                        Err(e) => e.into_response(),
+                    })
+                }
+            }
+        });
+
+    // resumable uploads. an upload is created with a POST, has its bytes sent
+    // in one or more PUTs, each starting at the offset the previous one
+    // reached, and is turned into media with a POST to finalize. if a PUT
+    // fails, the client asks for the upload's offset with a GET and resumes
+    // from there:
+    let upload_create = warp::post()
+        .and(warp::path!("_uploads" / String)
+            .map(|filename: String| percent_decode(filename.as_bytes()).decode_utf8_lossy().into_owned()))
+        .and(warp::header::<String>("content-type"))
+        .and(warp::header::optional::<u64>("upload-length"))
+        .and_then({
+            let server = server.clone();
+            move |name, kind, length| {
+                let server = server.clone();
+                async move {
+                    let info = project::media::UploadInfo { name, kind };
+
+                    Ok::<_, warp::Rejection>(match server.project.create_upload(info, length).await {
+                        Ok(UploadId(id)) => reply::with_status(id.to_string(), StatusCode::CREATED).into_response(),
+                        Err(e) => UploadError::from(UploadOpError::from(e)).into_response(),
+                    })
+                }
+            }
+        });
+
+    let upload_status = warp::get()
+        .and(warp::path!("_uploads" / i64))
+        .and_then({
+            let server = server.clone();
+            move |id| {
+                let server = server.clone();
+                async move {
+                    Ok::<_, warp::Rejection>(match server.project.upload_status(UploadId(id)).await {
+                        Ok(status) => {
+                            let mut response = offset_reply(status.offset,
+                                reply::with_status(status.offset.to_string(), StatusCode::OK));
+
+                            if let Some(length) = status.length {
+                                response.headers_mut().insert("upload-length", HeaderValue::from(length));
+                            }
+
+                            response
+                        }
+                        Err(e) => UploadError::from(e).into_response(),
+                    })
+                }
+            }
+        });
+
+    let upload_chunk = warp::put()
+        .and(warp::path!("_uploads" / i64))
+        .and(warp::header::<u64>("upload-offset"))
+        .and(warp::filters::body::stream())
+        .and_then({
+            let server = server.clone();
+            move |id, offset, stream| {
+                let server = server.clone();
+                async move {
+                    Ok::<_, warp::Rejection>(match handle_upload_chunk(UploadId(id), offset, stream, server).await {
+                        Ok(offset) => offset_reply(offset, reply::with_status(offset.to_string(), StatusCode::OK)),
+                        Err(e) => e.into_response(),
+                    })
+                }
+            }
+        });
+
+    let upload_finalize = warp::post()
+        .and(warp::path!("_uploads" / i64 / "finalize"))
+        .and(warp::header::optional::<String>("upload-sha256"))
+        .and_then({
+            let server = server.clone();
+            move |id, sha256: Option<String>| {
+                let server = server.clone();
+                async move {
+                    Ok::<_, warp::Rejection>(match server.project.finalize_upload(UploadId(id), sha256.as_deref()).await {
+                        Ok(()) => reply::with_status(String::new(), StatusCode::OK).into_response(),
+                        Err(e) => UploadError::from(e).into_response(),
+                    })
+                }
+            }
+        });
+
+    let upload_cancel = warp::delete()
+        .and(warp::path!("_uploads" / i64))
+        .and_then({
+            let server = server.clone();
+            move |id| {
+                let server = server.clone();
+                async move {
+                    Ok::<_, warp::Rejection>(match server.project.cancel_upload(UploadId(id)).await {
+                        Ok(()) => reply::with_status(String::new(), StatusCode::NO_CONTENT).into_response(),
+                        Err(e) => UploadError::from(e).into_response(),
+                    })
+                }
+            }
+        });
+
+    let media_thumbnail = warp::get()
+        .and(warp::path!("_media" / i64 / "thumbnail"))
+        .and_then({
+            let server = server.clone();
+            move |id| {
+                let server = server.clone();
+                async move {
+                    match server.project.media_thumbnail(MediaId(id)).await {
+                        Ok(Some(bmp)) => Ok(content("image/bmp", bmp)),
+                        Ok(None) => Err(warp::reject::not_found()),
+                        Err(e) => {
+                            eprintln!("could not read thumbnail: {:?}", e);
+                            Err(warp::reject::not_found())
+                        }
+                    }
+                }
+            }
+        });
+
+    let media_waveform = warp::get()
+        .and(warp::path!("_media" / i64 / "waveform"))
+        .and_then({
+            let server = server.clone();
+            move |id| {
+                let server = server.clone();
+                async move {
+                    match server.project.media_waveform(MediaId(id)).await {
+                        Ok(Some(peaks)) => Ok(content("image/svg+xml", project::preview::waveform_svg(&peaks))),
+                        Ok(None) => Err(warp::reject::not_found()),
+                        Err(e) => {
+                            eprintln!("could not read waveform: {:?}", e);
+                            Err(warp::reject::not_found())
+                        }
+                    }
+                }
+            }
+        });
+
+    // bundles are the workspace as standalone JSON, optionally with the media
+    // it refers to, for moving patches between projects:
+    let workspace_export = warp::get()
+        .and(warp::path!("_workspace" / "export"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then({
+            let server = server.clone();
+            move |query: HashMap<String, String>| {
+                let server = server.clone();
+                async move {
+                    let include_media = query.get("media").map(|value| value == "1" || value == "true").unwrap_or(false);
+
+                    Ok::<_, warp::Rejection>(match server.project.export_bundle(include_media).await {
+                        Ok(bundle) => {
+                            reply::with_header(reply::json(&bundle),
+                                "content-disposition", "attachment; filename=\"workspace.json\"").into_response()
+                        }
+                        Err(e) => bundle_error_response(e),
+                    })
+                }
+            }
+        });
+
+    let workspace_import = warp::post()
+        .and(warp::path!("_workspace" / "import"))
+        .and(warp::body::content_length_limit(bundle::MAX_BUNDLE_BYTES))
+        .and(warp::body::json())
+        .and_then({
+            let server = server.clone();
+            move |bundle: Bundle| {
+                let server = server.clone();
+                async move {
+                    Ok::<_, warp::Rejection>(match server.project.import_bundle(bundle).await {
+                        Ok(modules) => {
+                            reply::with_status(format!("imported {} modules", modules.len()), StatusCode::OK).into_response()
+                        }
+                        Err(e) => bundle_error_response(e),
+                    })
                 }
             }
         });
@@ -120,6 +304,15 @@ pub async fn run(opts: RunOpts) {
         .or(websocket)
         .or(monitor_socket)
         .or(media_upload)
+        .or(upload_create)
+        .or(upload_status)
+        .or(upload_chunk)
+        .or(upload_finalize)
+        .or(upload_cancel)
+        .or(workspace_export)
+        .or(workspace_import)
+        .or(media_thumbnail)
+        .or(media_waveform)
         .with(warp::log("mixlab-http"));
 
     let warp = warp::serve(routes);
@@ -141,11 +334,11 @@ pub async fn run(opts: RunOpts) {
                     }
                 }
                 Disambiguation::Icecast(conn) => {
-                    tokio::spawn(icecast::accept(conn));
+                    tokio::spawn(icecast::accept(conn, rate));
                 }
                 Disambiguation::Rtmp(conn) => {
                     tokio::spawn(async move {
-                        match rtmp::accept(conn).await {
+                        match rtmp::accept(conn, rate).await {
                             Ok(()) => {}
                             Err(e) => { eprintln!("rtmp: {:?}", e); }
                         }
@@ -206,7 +399,7 @@ async fn session(websocket: WebSocket, server: ServerRef) {
     let library = server.project.fetch_media_library().await
         .expect("fetch_media_library");
 
-    tx.send(ServerMessage::WorkspaceState(state))
+    tx.send(ServerMessage::WorkspaceState(redact_state(state)))
         .await
         .expect("tx.send WorkspaceState");
 
@@ -214,17 +407,35 @@ async fn session(websocket: WebSocket, server: ServerRef) {
         .await
         .expect("tx.send MediaLibrary");
 
+    tx.send(ServerMessage::LiveSources(live_sources()))
+        .await
+        .expect("tx.send LiveSources");
+
+    let snapshots = server.project.list_snapshots().await
+        .expect("list_snapshots");
+
+    tx.send(ServerMessage::Snapshots(snapshots))
+        .await
+        .expect("tx.send Snapshots");
+
     enum Event {
         ClientMessage(Result<ws::Message, warp::Error>),
         Engine(Result<EngineEvent, broadcast::RecvError>),
         Notification(Notification),
+        LiveSources,
     }
 
+    let live_source_changes = stream::select(
+        icecast::registry().changes(),
+        rtmp::registry().changes());
+
     let mut events = stream::select(
         rx.map(Event::ClientMessage),
         stream::select(
-            engine_ops.map(Event::Engine),
-            notifications.map(Event::Notification)));
+            stream::select(
+                engine_ops.map(Event::Engine),
+                notifications.map(Event::Notification)),
+            live_source_changes.map(|()| Event::LiveSources)));
 
     while let Some(event) = events.next().await {
         match event {
@@ -246,6 +457,28 @@ async fn session(websocket: WebSocket, server: ServerRef) {
                             println!("Engine update failed: {:?}", e);
                         }
                     }
+                    ClientMessage::Media(op) => {
+                        if let Some(msg) = media_op(&server, op).await {
+                            match tx.send(msg).await {
+                                Ok(()) => {}
+                                Err(_) => {
+                                    // client disconnected
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    ClientMessage::Snapshot(op) => {
+                        if let Some(msg) = snapshot_op(&server, op).await {
+                            match tx.send(msg).await {
+                                Ok(()) => {}
+                                Err(_) => {
+                                    // client disconnected
+                                    return;
+                                }
+                            }
+                        }
+                    }
                 }
             }
             Event::Engine(Err(broadcast::RecvError::Lagged(skipped))) => {
@@ -259,7 +492,7 @@ async fn session(websocket: WebSocket, server: ServerRef) {
             Event::Engine(Ok(event)) => {
                 // sequence is only applicable if it belongs to this session:
                 let msg = match event {
-                    EngineEvent::ServerUpdate(update) => Some(ServerMessage::Update(update)),
+                    EngineEvent::ServerUpdate(update) => Some(ServerMessage::Update(redact_update(update))),
                     EngineEvent::Sync(clock) => {
                         if clock.0 == engine.session_id() {
                             Some(ServerMessage::Sync(clock.1))
@@ -267,6 +500,13 @@ async fn session(websocket: WebSocket, server: ServerRef) {
                             None
                         }
                     }
+                    EngineEvent::Rejected(clock, rejection) => {
+                        if clock.0 == engine.session_id() {
+                            Some(ServerMessage::Rejected(clock.1, redact_rejection(rejection)))
+                        } else {
+                            None
+                        }
+                    }
                 };
 
                 if let Some(msg) = msg {
@@ -293,6 +533,15 @@ async fn session(websocket: WebSocket, server: ServerRef) {
                             }
                         }
                     }
+                    Notification::Snapshots => {
+                        match server.project.list_snapshots().await {
+                            Ok(snapshots) => Some(ServerMessage::Snapshots(snapshots)),
+                            Err(e) => {
+                                eprintln!("failed to query snapshots: {:?}", e);
+                                None
+                            }
+                        }
+                    }
                 };
 
                 if let Some(msg) = msg {
@@ -305,14 +554,184 @@ async fn session(websocket: WebSocket, server: ServerRef) {
                     }
                 }
             }
+            Event::LiveSources => {
+                match tx.send(ServerMessage::LiveSources(live_sources())).await {
+                    Ok(()) => {}
+                    Err(_) => {
+                        // client disconnected
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// applies a media op, returning the reply for the client which sent it, if
+// any. all clients are notified of changes to the library separately
+async fn media_op(server: &Server, op: MediaOp) -> Option<ServerMessage<'static>> {
+    let result = match op {
+        MediaOp::Rename(id, name) => server.project.rename_media(id, name).await,
+        MediaOp::Delete(id) => server.project.delete_media(id).await,
+        MediaOp::SetTags(id, tags) => server.project.set_media_tags(id, tags).await,
+        MediaOp::Search(query) => {
+            return match server.project.search_media(query.clone()).await {
+                Ok(items) => Some(ServerMessage::MediaSearch(MediaSearchResults { query, items })),
+                Err(e) => {
+                    eprintln!("failed to search media library: {:?}", e);
+                    None
+                }
+            };
+        }
+    };
+
+    match result {
+        Ok(()) => None,
+        Err(project::media::MediaError::Op(e)) => Some(ServerMessage::MediaOpFailed(e)),
+        Err(e) => {
+            eprintln!("media op failed: {:?}", e);
+            None
+        }
+    }
+}
+
+async fn snapshot_op(server: &Server, op: SnapshotOp) -> Option<ServerMessage<'static>> {
+    let result = match op {
+        SnapshotOp::Save(name, kind) => server.project.save_snapshot(name, kind).await.map(|_| ()),
+        SnapshotOp::Recall(id, crossfade) => {
+            let crossfade = crossfade.map(|micros| Duration::from_micros(micros.0));
+            server.project.recall_snapshot(id, crossfade).await
+        }
+        SnapshotOp::Delete(id) => server.project.delete_snapshot(id).await,
+    };
+
+    match result {
+        Ok(()) => None,
+        Err(SnapshotError::Op(e)) => Some(ServerMessage::SnapshotOpFailed(e)),
+        Err(e) => {
+            eprintln!("snapshot op failed: {:?}", e);
+            None
+        }
+    }
+}
+
+// secrets in module params stay on the server, clients only get to know
+// whether they are set:
+fn redact_state(mut state: WorkspaceState) -> WorkspaceState {
+    for (_, params) in &mut state.modules {
+        *params = params.redacted();
+    }
+
+    state
+}
+
+fn redact_update(update: ServerUpdate) -> ServerUpdate {
+    match update {
+        ServerUpdate::CreateModule { id, params, geometry, indication, inputs, outputs, modulation } => {
+            ServerUpdate::CreateModule { id, params: params.redacted(), geometry, indication, inputs, outputs, modulation }
+        }
+        ServerUpdate::UpdateModuleParams(module_id, params, version) => {
+            ServerUpdate::UpdateModuleParams(module_id, params.redacted(), version)
         }
+        update => update,
     }
 }
 
+fn redact_rejection(rejection: Rejection) -> Rejection {
+    match rejection {
+        Rejection::StaleParams(module_id, params, version) => {
+            Rejection::StaleParams(module_id, params.redacted(), version)
+        }
+    }
+}
+
+fn live_sources() -> Vec<LiveSource> {
+    let mut sources = icecast::registry().sources();
+    sources.extend(rtmp::registry().sources());
+    sources
+}
+
 #[derive(From, Debug)]
 enum UploadError {
     Warp(warp::Error),
     Upload(project::media::UploadError),
+    Resumable(UploadOpError),
+}
+
+impl UploadError {
+    fn into_response(self) -> reply::Response {
+        let (status, reason) = match self {
+            UploadError::Warp(e) => {
+                // most likely the client went away part way through
+                (StatusCode::BAD_REQUEST, format!("could not receive upload: {}", e))
+            }
+            UploadError::Upload(project::media::UploadError::Unplayable(reason)) => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, reason)
+            }
+            UploadError::Upload(project::media::UploadError::Database(e)) |
+            UploadError::Resumable(UploadOpError::Database(e)) => {
+                eprintln!("upload failed: {:?}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_owned())
+            }
+            UploadError::Resumable(UploadOpError::Media(e)) => {
+                return UploadError::Upload(e).into_response();
+            }
+            UploadError::Resumable(UploadOpError::NotFound) => {
+                (StatusCode::NOT_FOUND, "no such upload".to_owned())
+            }
+            UploadError::Resumable(UploadOpError::Busy) => {
+                (StatusCode::CONFLICT, "upload is busy with another request".to_owned())
+            }
+            UploadError::Resumable(UploadOpError::OffsetMismatch(offset)) => {
+                let reply = reply::with_status(
+                    format!("upload is at offset {}", offset),
+                    StatusCode::CONFLICT);
+
+                return offset_reply(offset, reply);
+            }
+            UploadError::Resumable(UploadOpError::TooLarge) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, "upload is larger than its declared length".to_owned())
+            }
+            UploadError::Resumable(UploadOpError::Incomplete { offset, length }) => {
+                (StatusCode::CONFLICT, format!("upload is incomplete, received {} of {} bytes", offset, length))
+            }
+            UploadError::Resumable(UploadOpError::InvalidChecksum) => {
+                (StatusCode::BAD_REQUEST, "upload-sha256 must be 64 hex digits".to_owned())
+            }
+            UploadError::Resumable(UploadOpError::ChecksumMismatch) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "upload does not match its checksum and has been discarded".to_owned())
+            }
+        };
+
+        reply::with_status(reason, status).into_response()
+    }
+}
+
+fn bundle_error_response(e: BundleError) -> reply::Response {
+    let (status, reason) = match e {
+        BundleError::UnsupportedVersion(version) => {
+            (StatusCode::BAD_REQUEST, format!("unsupported bundle version {}", version))
+        }
+        BundleError::MediaTooLarge { name, limit } => {
+            (StatusCode::PAYLOAD_TOO_LARGE, format!("media {:?} would take the bundle over the limit of {} MB, export without media instead", name, limit / (1024 * 1024)))
+        }
+        BundleError::Base64(_) => {
+            (StatusCode::BAD_REQUEST, "bundle contains invalid media data".to_owned())
+        }
+        BundleError::Engine(EngineError::Busy) => {
+            (StatusCode::SERVICE_UNAVAILABLE, "engine is busy, try again".to_owned())
+        }
+        e => {
+            eprintln!("bundle failed: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "internal error".to_owned())
+        }
+    };
+
+    reply::with_status(reason, status).into_response()
+}
+
+fn offset_reply(offset: u64, reply: impl Reply) -> reply::Response {
+    reply::with_header(reply, "upload-offset", offset.to_string()).into_response()
 }
 
 struct UploadParams {
@@ -341,6 +760,31 @@ async fn handle_upload(
     Ok(())
 }
 
+async fn handle_upload_chunk(
+    id: UploadId,
+    offset: u64,
+    stream: impl Stream<Item = Result<impl Buf, warp::Error>>,
+    server: ServerRef,
+) -> Result<u64, UploadError> {
+    futures::pin_mut!(stream);
+
+    let mut chunk = server.project.resume_upload(id, offset).await?;
+
+    let received: Result<(), UploadError> = async {
+        while let Some(buf) = stream.next().await {
+            chunk.write(buf?.bytes()).await?;
+        }
+
+        Ok(())
+    }.await;
+
+    // keep whatever arrived before any error, so the client can resume
+    // from there rather than resending the whole chunk
+    let offset = chunk.finish().await?;
+
+    received.map(|()| offset)
+}
+
 #[derive(Debug, From)]
 pub enum TxError {
     Warp(warp::Error),
@@ -5,7 +5,9 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use ringbuf::{RingBuffer, Producer, Consumer};
+use tokio::sync::watch;
 
+use mixlab_protocol::{LiveSource, StreamProtocol};
 use mixlab_util::time::MediaTime;
 
 use crate::util::Sequence;
@@ -14,16 +16,38 @@ use crate::video;
 #[derive(Clone)]
 pub struct Registry {
     inner: Arc<Mutex<RegistryInner>>,
+    changes: watch::Receiver<()>,
 }
 
 struct RegistryInner {
+    protocol: StreamProtocol,
     channels: HashMap<String, Source>,
+    changes: watch::Sender<()>,
 }
 
+// senders may connect to channels which nothing is listening on yet, so
+// that live sources can be discovered and then picked from in the UI. this
+// happens before any secret is known to check them against, so only so many
+// of these unclaimed channels are allowed at once:
+const MAX_UNCLAIMED_CHANNELS: usize = 16;
+
+// frames buffered between sender and listener, per stream:
+const BUFFER_FRAMES: usize = 65536;
+
+// a channel exists for as long as it has either a listener or a connected
+// sender.
 struct Source {
     shared: Arc<SourceShared>,
     seq: Sequence,
+    listening: bool,
+    // the buffers are only allocated once something first listens on the
+    // channel, so unclaimed channels cost next to nothing. once allocated,
+    // each half is here while not held by the sender or listener:
     tx: Option<TxPair>,
+    rx: Option<RxPair>,
+    // secret required of senders, set by the listener:
+    secret: Option<String>,
+    sender: Option<SenderInfo>,
 }
 
 struct TxPair {
@@ -31,6 +55,28 @@ struct TxPair {
     video: Producer<Frame<VideoData>>,
 }
 
+struct RxPair {
+    audio: Consumer<Frame<AudioData>>,
+    video: Consumer<Frame<VideoData>>,
+}
+
+struct SenderInfo {
+    // secret presented by the sender when it connected. if the sender
+    // connected before anything was listening, this is checked once a
+    // listener claims the channel:
+    secret: Option<String>,
+    revoked: Arc<AtomicBool>,
+    stream: StreamInfo,
+}
+
+/// Describes the stream a sender is publishing, as far as it is known
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamInfo {
+    pub codec: Option<String>,
+    pub resolution: Option<(u32, u32)>,
+    pub bitrate_kbps: Option<u32>,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct SourceId(NonZeroUsize);
 
@@ -49,14 +95,18 @@ pub enum ListenError {
 pub enum ConnectError {
     NoMountpoint,
     AlreadyConnected,
+    Unauthorized,
 }
 
 pub struct SourceSend {
     registry: Registry,
     shared: Arc<SourceShared>,
     source_id: SourceId,
+    revoked: Arc<AtomicBool>,
     // this is, regrettably, an Option because we need to take the tx pair
-    // and put it back in the mountpoints table on drop:
+    // and put it back in the mountpoints table on drop. it is also None
+    // until the sender has claimed the buffers, which happens the first time
+    // it writes while something is listening:
     tx: Option<TxPair>,
 }
 
@@ -73,115 +123,277 @@ pub struct Frame<T> {
 pub struct SourceRecv {
     registry: Registry,
     shared: Arc<SourceShared>,
-    audio_rx: Consumer<Frame<AudioData>>,
-    video_rx: Consumer<Frame<VideoData>>,
+    // Option for the same reason as SourceSend::tx:
+    rx: Option<RxPair>,
 }
 
 impl Registry {
-    pub fn new() -> Self {
+    pub fn new(protocol: StreamProtocol) -> Self {
+        let (changes_tx, changes_rx) = watch::channel(());
+
         let inner = RegistryInner {
+            protocol,
             channels: HashMap::new(),
+            changes: changes_tx,
         };
 
-        Registry { inner: Arc::new(Mutex::new(inner)) }
+        Registry {
+            inner: Arc::new(Mutex::new(inner)),
+            changes: changes_rx,
+        }
     }
 
-    pub fn listen(&self, channel_name: &str) -> Result<SourceRecv, ListenError> {
+    /// Listens for sources on `channel_name`. If `secret` is set, sources
+    /// must present it in order to connect.
+    pub fn listen(&self, channel_name: &str, secret: Option<String>) -> Result<SourceRecv, ListenError> {
         let mut registry = self.inner.lock()
             .expect("registry lock");
 
-        if registry.channels.contains_key(channel_name) {
+        let source = registry.channel(channel_name);
+
+        if source.listening {
             return Err(ListenError::AlreadyInUse);
         }
 
-        let (audio_tx, audio_rx) = RingBuffer::<Frame<AudioData>>::new(65536).split();
-        let (video_tx, video_rx) = RingBuffer::<Frame<VideoData>>::new(65536).split();
+        if source.rx.is_none() {
+            source.allocate_buffers();
+        }
 
-        let shared = Arc::new(SourceShared {
-            channel_name: channel_name.to_owned(),
-            recv_online: AtomicBool::new(true),
-        });
+        let rx = source.rx.take().expect("buffers allocated");
 
-        let recv = SourceRecv {
-            registry: self.clone(),
-            shared: shared.clone(),
-            audio_rx,
-            video_rx,
-        };
+        if let (Some(secret), Some(sender)) = (&secret, &source.sender) {
+            let presented = sender.secret.as_ref().map(String::as_str).unwrap_or("");
 
-        let source = Source {
-            shared: shared.clone(),
-            seq: Sequence::new(),
-            tx: Some(TxPair {
-                audio: audio_tx,
-                video: video_tx,
-            }),
-        };
+            if !secret_matches(secret, presented) {
+                // sender connected before this channel was claimed and does
+                // not know its secret, cut it off:
+                sender.revoked.store(true, Ordering::Relaxed);
+            }
+        }
 
-        registry.channels.insert(channel_name.to_owned(), source);
+        source.secret = secret;
+        source.listening = true;
+        source.shared.recv_online.store(true, Ordering::Relaxed);
 
-        Ok(recv)
+        let shared = source.shared.clone();
+
+        registry.notify();
+
+        Ok(SourceRecv {
+            registry: self.clone(),
+            shared,
+            rx: Some(rx),
+        })
     }
 
-    pub fn connect(&self, channel_name: &str) -> Result<SourceSend, ConnectError> {
+    pub fn connect(&self, channel_name: &str, secret: Option<&str>) -> Result<SourceSend, ConnectError> {
         let mut registry = self.inner.lock()
             .expect("registry lock");
 
-        let source = match registry.channels.get_mut(channel_name) {
-            None => { return Err(ConnectError::NoMountpoint); }
-            Some(source) => source,
-        };
+        if !registry.channels.contains_key(channel_name) && registry.unclaimed_channels() >= MAX_UNCLAIMED_CHANNELS {
+            return Err(ConnectError::NoMountpoint);
+        }
+
+        let source = registry.channel(channel_name);
+
+        if let Some(expected) = &source.secret {
+            if !secret_matches(expected, secret.unwrap_or("")) {
+                return Err(ConnectError::Unauthorized);
+            }
+        }
+
+        if source.sender.is_some() {
+            return Err(ConnectError::AlreadyConnected);
+        }
+
+        let tx = source.tx.take();
 
         let source_id = SourceId(source.seq.next());
+        let revoked = Arc::new(AtomicBool::new(false));
+
+        source.sender = Some(SenderInfo {
+            secret: secret.map(str::to_owned),
+            revoked: revoked.clone(),
+            stream: StreamInfo::default(),
+        });
+
+        let shared = source.shared.clone();
 
-        let tx = source.tx.take().ok_or(ConnectError::AlreadyConnected)?;
+        registry.notify();
 
         Ok(SourceSend {
             registry: self.clone(),
-            shared: source.shared.clone(),
-            tx: Some(tx),
+            shared,
             source_id,
+            revoked,
+            tx,
         })
     }
+
+    /// Lists all channels which either have a listener or a connected sender
+    pub fn sources(&self) -> Vec<LiveSource> {
+        let registry = self.inner.lock()
+            .expect("registry lock");
+
+        let mut sources = registry.channels.iter()
+            .map(|(channel_name, source)| {
+                let stream = source.sender.as_ref()
+                    .map(|sender| sender.stream.clone())
+                    .unwrap_or_default();
+
+                LiveSource {
+                    channel_name: channel_name.clone(),
+                    protocol: registry.protocol,
+                    listening: source.listening,
+                    connected: source.sender.is_some(),
+                    codec: stream.codec,
+                    resolution: stream.resolution,
+                    bitrate_kbps: stream.bitrate_kbps,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        sources.sort_by(|a, b| a.channel_name.cmp(&b.channel_name));
+        sources
+    }
+
+    /// Yields whenever the result of `sources` may have changed
+    pub fn changes(&self) -> watch::Receiver<()> {
+        self.changes.clone()
+    }
+}
+
+impl RegistryInner {
+    fn channel(&mut self, channel_name: &str) -> &mut Source {
+        self.channels.entry(channel_name.to_owned())
+            .or_insert_with(|| Source {
+                shared: Arc::new(SourceShared {
+                    channel_name: channel_name.to_owned(),
+                    recv_online: AtomicBool::new(false),
+                }),
+                seq: Sequence::new(),
+                listening: false,
+                tx: None,
+                rx: None,
+                secret: None,
+                sender: None,
+            })
+    }
+
+    fn unclaimed_channels(&self) -> usize {
+        self.channels.values()
+            .filter(|source| !source.listening)
+            .count()
+    }
+
+    fn remove_if_unused(&mut self, channel_name: &str) {
+        let unused = self.channels.get(channel_name)
+            .map(|source| !source.listening && source.sender.is_none())
+            .unwrap_or(false);
+
+        if unused {
+            self.channels.remove(channel_name);
+        }
+    }
+
+    fn notify(&self) {
+        // only fails if there are no receivers, but the registry itself
+        // always holds one
+        let _ = self.changes.broadcast(());
+    }
+}
+
+impl Source {
+    fn allocate_buffers(&mut self) {
+        let (audio_tx, audio_rx) = RingBuffer::<Frame<AudioData>>::new(BUFFER_FRAMES).split();
+        let (video_tx, video_rx) = RingBuffer::<Frame<VideoData>>::new(BUFFER_FRAMES).split();
+
+        self.tx = Some(TxPair { audio: audio_tx, video: video_tx });
+        self.rx = Some(RxPair { audio: audio_rx, video: video_rx });
+    }
 }
 
 impl SourceSend {
+    /// Returns false once the sender has been cut off and should disconnect
     pub fn connected(&self) -> bool {
-        self.shared.recv_online.load(Ordering::Relaxed)
+        !self.revoked.load(Ordering::Relaxed)
     }
 
-    pub fn write_audio(&mut self, timestamp: MediaTime, data: AudioData) -> Result<(), ()> {
-        if self.connected() {
-            // tx is always Some for a valid (non-dropped) SourceSend:
-            let tx = self.tx.as_mut().unwrap();
+    pub fn set_stream_info(&self, info: StreamInfo) {
+        let mut registry = self.registry.inner.lock()
+            .expect("registry lock");
 
-            let frame = Frame {
-                source_id: self.source_id,
-                source_time: timestamp,
-                data,
-            };
+        let sender = registry.channels.get_mut(&self.shared.channel_name)
+            .and_then(|source| source.sender.as_mut());
 
-            tx.audio.push(frame).map_err(|_| ())
-        } else {
-            Err(())
+        if let Some(sender) = sender {
+            if sender.stream != info {
+                sender.stream = info;
+                registry.notify();
+            }
         }
     }
 
-    pub fn write_video(&mut self, timestamp: MediaTime, data: VideoData) -> Result<(), ()> {
-        if self.connected() {
-            // tx is always Some for a valid (non-dropped) SourceSend:
-            let tx = self.tx.as_mut().unwrap();
+    // takes the tx pair from the registry if we don't hold it yet. only
+    // returns None if the listener has gone away again in the meantime
+    fn claim_tx(&mut self) -> Option<&mut TxPair> {
+        if self.tx.is_none() {
+            let mut registry = self.registry.inner.lock()
+                .expect("registry lock");
 
-            let frame = Frame {
-                source_id: self.source_id,
-                source_time: timestamp,
-                data,
-            };
+            self.tx = registry.channels.get_mut(&self.shared.channel_name)
+                .and_then(|source| source.tx.take());
+        }
+
+        self.tx.as_mut()
+    }
 
-            tx.video.push(frame).map_err(|_| ())
-        } else {
-            Err(())
+    pub fn write_audio(&mut self, timestamp: MediaTime, data: AudioData) -> Result<(), ()> {
+        if !self.connected() {
+            return Err(());
         }
+
+        if !self.shared.recv_online.load(Ordering::Relaxed) {
+            // nothing is listening, drop the frame on the floor
+            return Ok(());
+        }
+
+        let frame = Frame {
+            source_id: self.source_id,
+            source_time: timestamp,
+            data,
+        };
+
+        let tx = match self.claim_tx() {
+            Some(tx) => tx,
+            None => { return Ok(()); }
+        };
+
+        tx.audio.push(frame).map_err(|_| ())
+    }
+
+    pub fn write_video(&mut self, timestamp: MediaTime, data: VideoData) -> Result<(), ()> {
+        if !self.connected() {
+            return Err(());
+        }
+
+        if !self.shared.recv_online.load(Ordering::Relaxed) {
+            // nothing is listening, drop the frame on the floor
+            return Ok(());
+        }
+
+        let frame = Frame {
+            source_id: self.source_id,
+            source_time: timestamp,
+            data,
+        };
+
+        let tx = match self.claim_tx() {
+            Some(tx) => tx,
+            None => { return Ok(()); }
+        };
+
+        tx.video.push(frame).map_err(|_| ())
     }
 }
 
@@ -196,14 +408,21 @@ impl Drop for SourceSend {
         let mut registry = self.registry.inner.lock()
             .expect("registry lock");
 
-        match registry.channels.get_mut(&self.shared.channel_name) {
-            None => {
-                // receiver has disconnected, there is nothing to do
-            }
-            Some(channel) => {
-                channel.tx = self.tx.take();
+        let channel_name = &self.shared.channel_name;
+
+        // channels are only removed once both sides have gone away, so the
+        // channel is always present here:
+        if let Some(source) = registry.channels.get_mut(channel_name) {
+            // if we never claimed the tx pair, it is still in the registry:
+            if let Some(tx) = self.tx.take() {
+                source.tx = Some(tx);
             }
+
+            source.sender = None;
         }
+
+        registry.remove_if_unused(channel_name);
+        registry.notify();
     }
 }
 
@@ -218,22 +437,67 @@ impl SourceRecv {
         &self.shared.channel_name
     }
 
+    /// Changes the secret required of sources connecting from now on. Does
+    /// not affect any source which is already connected.
+    pub fn set_secret(&self, secret: Option<String>) {
+        let mut registry = self.registry.inner.lock()
+            .expect("registry lock");
+
+        if let Some(source) = registry.channels.get_mut(&self.shared.channel_name) {
+            source.secret = secret;
+        }
+    }
+
     pub fn read_audio(&mut self) -> Option<Frame<AudioData>> {
-        self.audio_rx.pop()
+        // rx is always Some for a valid (non-dropped) SourceRecv:
+        self.rx.as_mut().unwrap().audio.pop()
     }
 
     pub fn read_video(&mut self) -> Option<Frame<VideoData>> {
-        self.video_rx.pop()
+        // rx is always Some for a valid (non-dropped) SourceRecv:
+        self.rx.as_mut().unwrap().video.pop()
     }
 }
 
 impl Drop for SourceRecv {
     fn drop(&mut self) {
-        self.registry.inner.lock()
-            .expect("registry lock")
-            .channels
-            .remove(&self.shared.channel_name);
+        let mut registry = self.registry.inner.lock()
+            .expect("registry lock");
+
+        let channel_name = &self.shared.channel_name;
 
         self.shared.recv_online.store(false, Ordering::Relaxed);
+
+        if let Some(source) = registry.channels.get_mut(channel_name) {
+            let mut rx = self.rx.take();
+
+            // discard anything left unread so the next listener does not
+            // receive stale frames:
+            if let Some(rx) = &mut rx {
+                while rx.audio.pop().is_some() {}
+                while rx.video.pop().is_some() {}
+            }
+
+            source.rx = rx;
+            source.listening = false;
+            source.secret = None;
+        }
+
+        registry.remove_if_unused(channel_name);
+        registry.notify();
+    }
+}
+
+// compares in constant time, so as to not leak the secret through timing
+fn secret_matches(expected: &str, given: &str) -> bool {
+    let expected = expected.as_bytes();
+    let given = given.as_bytes();
+
+    if expected.len() != given.len() {
+        return false;
     }
+
+    expected.iter()
+        .zip(given)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
 }
@@ -3,15 +3,17 @@ use std::collections::VecDeque;
 use std::convert::TryInto;
 
 use bytes::Bytes;
+use derive_more::From;
 use fdk_aac::enc as aac;
 use num_rational::Ratio;
 
 use mixlab_codec::avc::DecoderConfigurationRecord;
-use mixlab_codec::avc::encode::{AvcEncoder, AvcParams, Preset, Tune, RateControl};
+use mixlab_codec::avc::encode::{self as avc, AvcEncoder, AvcParams, Level, Preset, Tune, RateControl};
 use mixlab_codec::ffmpeg::media::Video;
 use mixlab_codec::ffmpeg::sys;
-use mixlab_codec::ffmpeg::{AvFrame, AvPacket, PictureSettings, SwsContext};
+use mixlab_codec::ffmpeg::{AvError, AvFrame, AvPacket, PictureSettings, SwsContext};
 use mixlab_mux::mp4::AvcFrame;
+use mixlab_protocol::{StreamEncoderSettings, EncoderPreset, EncoderProfile};
 use mixlab_util::time::{MediaTime, MediaDuration};
 
 use crate::engine::Sample;
@@ -21,6 +23,12 @@ const SAMPLES_PER_CHANNEL_PER_FRAGMENT: usize = 1024;
 
 const AUDIO_CHANNELS: usize = 2;
 
+#[derive(Debug, From)]
+pub enum EncodeError {
+    Aac(aac::EncoderError),
+    Avc(AvError),
+}
+
 #[derive(Debug)]
 pub struct EncodeStream {
     audio_segments: VecDeque<AudioSegment>,
@@ -28,6 +36,9 @@ pub struct EncodeStream {
     audio_ctx: AudioCtx,
     video_segments: VecDeque<VideoSegment>,
     video_timestamp: MediaTime,
+    // of the last frame sent to the encoder, which packets flushed out of it
+    // at the end of the stream are taken to have too:
+    video_frame_duration: MediaDuration,
     video_ctx: VideoCtx,
 }
 
@@ -39,23 +50,26 @@ impl EncodeStream {
             audio_ctx,
             video_segments: VecDeque::new(),
             video_timestamp: MediaTime::new(0, 1),
+            video_frame_duration: MediaDuration::zero(),
             video_ctx,
         }
     }
 
     pub fn send_audio(&mut self, samples: &[f32]) {
         if let Some((duration, frame)) = self.audio_ctx.send_audio(samples) {
-            let decode_timestamp = self.audio_timestamp;
-            self.audio_timestamp += duration;
+            self.push_audio(duration, frame);
+        }
+    }
 
-            self.audio_segments.push_back(AudioSegment {
-                decode_timestamp,
-                duration,
-                frame,
-            });
+    fn push_audio(&mut self, duration: MediaDuration, frame: Bytes) {
+        let decode_timestamp = self.audio_timestamp;
+        self.audio_timestamp += duration;
 
-            self.audio_timestamp;
-        }
+        self.audio_segments.push_back(AudioSegment {
+            decode_timestamp,
+            duration,
+            frame,
+        });
     }
 
     pub fn send_video(&mut self, timestamp: MediaTime, duration_hint: MediaDuration, frame: AvFrame<Video>) {
@@ -75,6 +89,11 @@ impl EncodeStream {
         self.encode_video(duration, frame);
     }
 
+    /// Makes the next video frame sent to the encoder a keyframe
+    pub fn force_keyframe(&mut self) {
+        self.video_ctx.force_keyframe = true;
+    }
+
     pub fn barrier(&mut self, timestamp: MediaTime) {
         if self.video_timestamp < timestamp {
             let duration = timestamp - self.video_timestamp;
@@ -97,10 +116,17 @@ impl EncodeStream {
         frame.set_presentation_timestamp(frame_start_in_base);
         self.video_ctx.send_frame(frame);
 
+        self.video_frame_duration = MediaDuration::new(duration_in_base, time_base);
+        self.recv_video_packets();
+    }
+
+    fn recv_video_packets(&mut self) {
+        let time_base = self.video_ctx.time_base;
+
         while let Some(packet) = self.video_ctx.recv_packet() {
             self.video_segments.push_back(VideoSegment {
                 decode_timestamp: MediaTime::new(packet.decode_timestamp(), time_base),
-                duration: MediaDuration::new(duration_in_base, time_base),
+                duration: self.video_frame_duration,
                 frame: AvcFrame {
                     is_key_frame: packet.is_key_frame(),
                     composition_time: MediaDuration::new(packet.presentation_timestamp() - packet.decode_timestamp(), time_base),
@@ -111,11 +137,43 @@ impl EncodeStream {
     }
 
     pub fn recv_segment(&mut self) -> Option<StreamSegment> {
+        // the last segment of each track is held back until another arrives,
+        // so that segments always come out in decode order across tracks:
         if self.audio_segments.len() <= 1 || self.video_segments.len() <= 1 {
             return None;
         }
 
-        if self.audio_segments.front().unwrap().decode_timestamp < self.video_segments.front().unwrap().decode_timestamp {
+        self.pop_segment()
+    }
+
+    /// Ends the stream, flushing out everything the encoders are holding
+    /// back. Returns every remaining segment in decode order. Nothing more
+    /// can be sent to the stream afterwards
+    pub fn drain(&mut self) -> Vec<StreamSegment> {
+        for (duration, frame) in self.audio_ctx.flush() {
+            self.push_audio(duration, frame);
+        }
+
+        self.video_ctx.flush();
+        self.recv_video_packets();
+
+        let mut segments = Vec::new();
+
+        while let Some(segment) = self.pop_segment() {
+            segments.push(segment);
+        }
+
+        segments
+    }
+
+    fn pop_segment(&mut self) -> Option<StreamSegment> {
+        let audio_first = match (self.audio_segments.front(), self.video_segments.front()) {
+            (Some(audio), Some(video)) => audio.decode_timestamp < video.decode_timestamp,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+
+        if audio_first {
             self.audio_segments.pop_front().map(StreamSegment::Audio)
         } else {
             self.video_segments.pop_front().map(StreamSegment::Video)
@@ -123,6 +181,66 @@ impl EncodeStream {
     }
 }
 
+/// Feeds video into an `EncodeStream` at a constant frame rate regardless of
+/// how often frames arrive, repeating the most recently received frame until
+/// it expires
+#[derive(Debug)]
+pub struct FramePacer {
+    frame_duration: MediaDuration,
+    next_frame: MediaTime,
+    current_frame: Option<PacedFrame>,
+}
+
+#[derive(Debug)]
+struct PacedFrame {
+    end: MediaTime,
+    frame: AvFrame<Video>,
+}
+
+impl FramePacer {
+    pub fn new(frame_rate: usize) -> Self {
+        FramePacer {
+            frame_duration: MediaDuration::new(1, frame_rate as i64),
+            next_frame: MediaTime::zero(),
+            current_frame: None,
+        }
+    }
+
+    pub fn send_video(&mut self, timestamp: MediaTime, duration_hint: MediaDuration, frame: AvFrame<Video>) {
+        self.current_frame = Some(PacedFrame {
+            end: timestamp + duration_hint,
+            frame,
+        });
+    }
+
+    /// Keeps showing the most recently received frame until at least
+    /// `timestamp`, even if it would have expired before then
+    pub fn hold(&mut self, timestamp: MediaTime) {
+        if let Some(current) = &mut self.current_frame {
+            current.end = current.end.max(timestamp);
+        }
+    }
+
+    /// Encodes every output frame which starts before `timestamp`
+    pub fn advance(&mut self, encode: &mut EncodeStream, timestamp: MediaTime) {
+        while self.next_frame < timestamp {
+            let frame_end = self.next_frame + self.frame_duration;
+
+            match &self.current_frame {
+                Some(current) if self.next_frame < current.end => {
+                    encode.send_video(self.next_frame, self.frame_duration, current.frame.clone());
+                }
+                _ => {
+                    // nothing to show, barrier fills with a blank frame
+                    encode.barrier(frame_end);
+                }
+            }
+
+            self.next_frame = frame_end;
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum StreamSegment {
     Audio(AudioSegment),
@@ -157,7 +275,7 @@ pub struct AudioParams {
 }
 
 impl AudioCtx {
-    pub fn new(params: AudioParams) -> Self {
+    pub fn new(params: AudioParams) -> Result<Self, EncodeError> {
         let sample_rate = params.sample_rate.try_into().expect("sample_rate into u32");
 
         let aac_params = aac::EncoderParams {
@@ -166,13 +284,13 @@ impl AudioCtx {
             transport: params.transport,
         };
 
-        let codec = aac::Encoder::new(aac_params).expect("aac::Encoder::new");
+        let codec = aac::Encoder::new(aac_params)?;
 
-        AudioCtx {
+        Ok(AudioCtx {
             codec,
             pcm_buff: Vec::new(),
             sample_rate: sample_rate.into(),
-        }
+        })
     }
 
     pub fn configuration_data(&self) -> Bytes {
@@ -197,27 +315,50 @@ impl AudioCtx {
         let audio_frame_sample_count = AUDIO_CHANNELS * SAMPLES_PER_CHANNEL_PER_FRAGMENT;
 
         if self.pcm_buff.len() > audio_frame_sample_count {
-            // encode frame
-            let fragment_pcm = &self.pcm_buff[0..audio_frame_sample_count];
+            Some(self.encode_fragment())
+        } else {
+            None
+        }
+    }
 
-            let mut aac_buff = [0u8; 4096];
+    // pads out whatever remains buffered with silence, along with enough
+    // silence after it to push the encoder's lookahead out:
+    fn flush(&mut self) -> Vec<(MediaDuration, Bytes)> {
+        let audio_frame_sample_count = AUDIO_CHANNELS * SAMPLES_PER_CHANNEL_PER_FRAGMENT;
 
-            let encode_result = self.codec.encode(&fragment_pcm, &mut aac_buff)
-                .expect("aac.encode");
+        let delay = self.codec.info()
+            .map(|info| info.nDelay as usize)
+            .unwrap_or(0);
 
-            if encode_result.input_consumed != audio_frame_sample_count {
-                eprintln!("monitor: aac encoder did not consume exactly {} samples (consumed {})",
-                    audio_frame_sample_count, encode_result.input_consumed);
-            }
+        let samples = self.pcm_buff.len() + delay * AUDIO_CHANNELS;
+        let fragments = (samples + audio_frame_sample_count - 1) / audio_frame_sample_count;
 
-            let duration = MediaDuration::new(SAMPLES_PER_CHANNEL_PER_FRAGMENT as i64, self.sample_rate);
-            let frame_data = Bytes::copy_from_slice(&aac_buff[0..encode_result.output_size]);
-            self.pcm_buff.drain(0..audio_frame_sample_count);
+        self.pcm_buff.resize(fragments * audio_frame_sample_count, 0);
 
-            Some((duration, frame_data))
-        } else {
-            None
+        (0..fragments)
+            .map(|_| self.encode_fragment())
+            .collect()
+    }
+
+    fn encode_fragment(&mut self) -> (MediaDuration, Bytes) {
+        let audio_frame_sample_count = AUDIO_CHANNELS * SAMPLES_PER_CHANNEL_PER_FRAGMENT;
+        let fragment_pcm = &self.pcm_buff[0..audio_frame_sample_count];
+
+        let mut aac_buff = [0u8; 4096];
+
+        let encode_result = self.codec.encode(&fragment_pcm, &mut aac_buff)
+            .expect("aac.encode");
+
+        if encode_result.input_consumed != audio_frame_sample_count {
+            eprintln!("monitor: aac encoder did not consume exactly {} samples (consumed {})",
+                audio_frame_sample_count, encode_result.input_consumed);
         }
+
+        let duration = MediaDuration::new(SAMPLES_PER_CHANNEL_PER_FRAGMENT as i64, self.sample_rate);
+        let frame_data = Bytes::copy_from_slice(&aac_buff[0..encode_result.output_size]);
+        self.pcm_buff.drain(0..audio_frame_sample_count);
+
+        (duration, frame_data)
     }
 }
 
@@ -227,6 +368,7 @@ pub struct VideoCtx {
     scaler: DynamicScaler,
     blank_frame: AvFrame<Video>,
     time_base: i64,
+    force_keyframe: bool,
 }
 
 pub struct VideoParams {
@@ -235,13 +377,55 @@ pub struct VideoParams {
     pub profile: Profile,
 }
 
+impl VideoParams {
+    /// Params for the user configurable encoder settings of modules which
+    /// stream or record their output
+    pub fn from_settings(settings: &StreamEncoderSettings, time_base: usize) -> Self {
+        VideoParams {
+            picture: PictureSettings::yuv420p(settings.width, settings.height),
+            time_base,
+            profile: Profile::Custom(EncoderSettings {
+                bitrate: settings.video_bitrate_kbps * 1000,
+                frame_rate: settings.frame_rate,
+                keyframe_interval_secs: settings.keyframe_interval_secs,
+                preset: match settings.preset {
+                    EncoderPreset::Ultrafast => Preset::Ultrafast,
+                    EncoderPreset::Superfast => Preset::Superfast,
+                    EncoderPreset::Veryfast => Preset::Veryfast,
+                    EncoderPreset::Faster => Preset::Faster,
+                    EncoderPreset::Fast => Preset::Fast,
+                    EncoderPreset::Medium => Preset::Medium,
+                    EncoderPreset::Slow => Preset::Slow,
+                    EncoderPreset::Slower => Preset::Slower,
+                    EncoderPreset::Veryslow => Preset::Veryslow,
+                },
+                profile: match settings.profile {
+                    EncoderProfile::Baseline => avc::Profile::Baseline,
+                    EncoderProfile::Main => avc::Profile::Main,
+                    EncoderProfile::High => avc::Profile::High,
+                },
+            }),
+        }
+    }
+}
+
 pub enum Profile {
     Monitor,
     Stream,
+    Custom(EncoderSettings),
+}
+
+/// Video encoder settings for use with `Profile::Custom`
+pub struct EncoderSettings {
+    pub bitrate: usize,
+    pub frame_rate: usize,
+    pub keyframe_interval_secs: usize,
+    pub preset: Preset,
+    pub profile: avc::Profile,
 }
 
 impl VideoCtx {
-    pub fn new(params: VideoParams) -> Self {
+    pub fn new(params: VideoParams) -> Result<Self, EncodeError> {
         let time_base = params.time_base;
         let picture = params.picture;
 
@@ -251,33 +435,45 @@ impl VideoCtx {
             color_space: sys::AVColorSpace_AVCOL_SPC_UNSPECIFIED,
             picture_width: picture.width,
             picture_height: picture.height,
-            rate_control: match params.profile {
+            rate_control: match &params.profile {
                 // cannot use constant bitrate in zero latency mode apparently:
                 Profile::Monitor => RateControl::ConstantQuality { crf: 30 },
                 Profile::Stream => RateControl::ConstantBitRate { bitrate: 1_500_000 },
+                Profile::Custom(settings) => RateControl::ConstantBitRate { bitrate: settings.bitrate },
+            },
+            profile: match &params.profile {
+                Profile::Monitor | Profile::Stream => avc::Profile::High,
+                Profile::Custom(settings) => settings.profile,
+            },
+            level: match &params.profile {
+                Profile::Monitor | Profile::Stream => Level(41),
+                Profile::Custom(settings) => Level::for_picture(picture.width, picture.height, settings.frame_rate),
             },
-            preset: match params.profile {
+            preset: match &params.profile {
                 Profile::Monitor => Preset::Veryfast,
                 Profile::Stream => Preset::Slow,
+                Profile::Custom(settings) => settings.preset,
             },
-            tune: match params.profile {
+            tune: match &params.profile {
                 Profile::Monitor => Some(Tune::Zerolatency),
-                Profile::Stream => Some(Tune::Film),
+                Profile::Stream | Profile::Custom(_) => Some(Tune::Film),
             },
-            gop_size: match params.profile {
+            gop_size: match &params.profile {
                 Profile::Monitor => Some(1), // every frame is key frame
                 Profile::Stream => Some(60),
+                Profile::Custom(settings) => Some(settings.frame_rate * settings.keyframe_interval_secs),
             },
         };
 
-        let codec = AvcEncoder::new(params).unwrap();
+        let codec = AvcEncoder::new(params)?;
 
-        VideoCtx {
+        Ok(VideoCtx {
             codec,
             scaler: DynamicScaler::new(picture.clone()),
             blank_frame: AvFrame::blank(&picture),
             time_base: time_base.try_into().unwrap(),
-        }
+            force_keyframe: false,
+        })
     }
 
     pub fn decoder_configuration_record(&self) -> DecoderConfigurationRecord {
@@ -285,8 +481,13 @@ impl VideoCtx {
     }
 
     pub fn send_frame(&mut self, mut frame: AvFrame<Video>) {
-        // clear picture type so x264 can make its own decisions about keyframes:
-        frame.set_picture_type(mixlab_codec::ffmpeg::sys::AVPictureType_AV_PICTURE_TYPE_NONE);
+        if self.force_keyframe {
+            self.force_keyframe = false;
+            frame.set_picture_type(mixlab_codec::ffmpeg::sys::AVPictureType_AV_PICTURE_TYPE_I);
+        } else {
+            // clear picture type so x264 can make its own decisions about keyframes:
+            frame.set_picture_type(mixlab_codec::ffmpeg::sys::AVPictureType_AV_PICTURE_TYPE_NONE);
+        }
 
         // scale picture to expected size if necessary
         let frame = self.scaler.scale(&mut frame);
@@ -297,11 +498,17 @@ impl VideoCtx {
     pub fn recv_packet(&mut self) -> Option<AvPacket> {
         match self.codec.recv_packet() {
             Ok(pkt) => Some(pkt),
-            Err(e) if e.again() => { return None; }
+            Err(e) if e.again() || e.eof() => { return None; }
             Err(e) => { panic!("recv_packet errored: {:?}", e); }
         }
     }
 
+    /// Signals the end of the stream to the encoder, so that `recv_packet`
+    /// returns the packets it has been holding back
+    pub fn flush(&mut self) {
+        self.codec.send_eof().unwrap();
+    }
+
     pub fn blank_frame(&self) -> AvFrame<Video> {
         self.blank_frame.clone()
     }